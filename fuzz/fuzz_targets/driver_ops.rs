@@ -0,0 +1,11 @@
+//! Reads, writes, fills and bounds checks at arbitrary offsets, compared with a model of the
+//! device.
+
+#![no_main]
+
+use at24cx::fuzzing::{block_on, decode_ops, run};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    block_on(run(&decode_ops(input)));
+});
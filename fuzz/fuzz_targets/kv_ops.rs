@@ -0,0 +1,10 @@
+//! Sets, removes, compactions and remounts of the key-value store, compared with a map.
+
+#![no_main]
+
+use at24cx::fuzzing::{block_on, decode_kv_ops, run_kv};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    block_on(run_kv(&decode_kv_ops(input)));
+});
@@ -0,0 +1,555 @@
+//! Tamper-evident, append-only audit log in a region of the device.
+//!
+//! Records are laid out back to back from the start of the region. Each record is
+//!
+//! | bytes      | content                                          |
+//! |------------|--------------------------------------------------|
+//! | 0..2       | payload length, `0xFFFF` past the last record    |
+//! | 2..6       | sequence number                                  |
+//! | 6          | kind, 0 for a record and 1 for an anchor         |
+//! | 7..7+len   | payload                                          |
+//! | ..+32      | MAC                                              |
+//!
+//! All integers are little endian. The MAC is an HMAC-SHA256 over the record up to the MAC
+//! followed by the MAC of the previous record, so records form a hash chain: changing,
+//! deleting or reordering records breaks the chain at that point, which
+//! [`verify_chain`](AuditLog::verify_chain) reports. The first record of a chain follows
+//! a MAC of all zeros.
+//!
+//! Once the region is full, the log either refuses further records or, with
+//! [`WhenFull::Anchor`], drops every record and starts over with an anchor. The payload of
+//! the anchor is the MAC of the last dropped record, which it follows in the chain, so the
+//! truncation is itself recorded and the new chain continues the sequence numbers of the
+//! old one.
+//!
+//! Dropping the newest records leaves an intact chain. Compare the
+//! [`head_mac`](ChainReport::head_mac) with one kept elsewhere to detect that.
+
+use crate::{EepromPartition, Error};
+use core::cmp::min;
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Bytes of an HMAC-SHA256
+pub const MAC_SIZE: usize = 32;
+/// Bytes of framing added to every payload
+pub const RECORD_OVERHEAD: usize = HEADER_SIZE + MAC_SIZE;
+
+const HEADER_SIZE: usize = 7;
+const CHUNK_SIZE: usize = 64;
+/// Payload length marking the end of the log
+const END: u16 = 0xFFFF;
+const KIND_RECORD: u8 = 0;
+const KIND_ANCHOR: u8 = 1;
+/// Space an anchor takes, its payload is a MAC
+const ANCHOR_SIZE: u32 = (RECORD_OVERHEAD + MAC_SIZE) as u32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What [`append`](AuditLog::append) does once the region is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhenFull {
+    /// Refuse further records with `Full`
+    Stop,
+    /// Drop every record and start a new chain with an anchor
+    Anchor,
+}
+
+/// The last record of a chain that was dropped to make room, carried over by an anchor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub seq: u32,
+    pub mac: [u8; MAC_SIZE],
+}
+
+/// Why [`verify_chain`](AuditLog::verify_chain) stopped at a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    /// The sequence number doesn't follow the previous record, records were deleted or
+    /// reordered
+    Sequence,
+    /// The MAC doesn't match, the record or one before it was changed
+    Mac,
+    /// An anchor after the first record
+    MisplacedAnchor,
+}
+
+/// The first record that doesn't verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainBreak {
+    /// Number of records before it
+    pub index: usize,
+    /// Offset of the record in the region
+    pub offset: u32,
+    pub reason: BreakReason,
+}
+
+/// Result of [`verify_chain`](AuditLog::verify_chain)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainReport {
+    /// Records, not counting an anchor, that verified before the first break
+    pub verified: usize,
+    /// Set if the chain starts with an anchor, after records were dropped
+    pub anchor: Option<Anchor>,
+    /// Sequence number of the last verified record or anchor
+    pub head_seq: Option<u32>,
+    /// MAC of the last verified record or anchor, all zeros if there is none
+    pub head_mac: [u8; MAC_SIZE],
+    /// `None` if the whole chain verified
+    pub first_break: Option<ChainBreak>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RecordHeader {
+    len: u16,
+    seq: u32,
+    kind: u8,
+}
+
+impl RecordHeader {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut out = [0; HEADER_SIZE];
+        out[0..2].copy_from_slice(&self.len.to_le_bytes());
+        out[2..6].copy_from_slice(&self.seq.to_le_bytes());
+        out[6] = self.kind;
+        out
+    }
+
+    fn decode(bytes: &[u8; HEADER_SIZE]) -> Self {
+        Self {
+            len: u16::from_le_bytes([bytes[0], bytes[1]]),
+            seq: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            kind: bytes[6],
+        }
+    }
+
+    /// Space taken on the device
+    fn size(&self) -> u32 {
+        (RECORD_OVERHEAD + self.len as usize) as u32
+    }
+}
+
+/// Audit log over a partition, see the [module docs](self)
+pub struct AuditLog<'a, I2C, D> {
+    region: EepromPartition<'a, I2C, D>,
+    capacity: u32,
+    when_full: WhenFull,
+    /// Where the next record goes
+    head: u32,
+    next_seq: u32,
+    /// MAC of the newest record, all zeros if there is none
+    last_mac: [u8; MAC_SIZE],
+}
+
+impl<'a, I2C, E: Debug, D: DelayNs> AuditLog<'a, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Opens the log in `region`, finding the end of the records on the device. Nothing is
+    /// verified, see [`verify_chain`](Self::verify_chain) for that. Returns
+    /// `InvalidArgument` if the region can't hold an anchor and an empty record.
+    pub async fn mount(
+        region: EepromPartition<'a, I2C, D>,
+        when_full: WhenFull,
+    ) -> Result<Self, Error<E>> {
+        let capacity = region.capacity() as u32;
+        if capacity < ANCHOR_SIZE + RECORD_OVERHEAD as u32 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut log = Self {
+            region,
+            capacity,
+            when_full,
+            head: 0,
+            next_seq: 0,
+            last_mac: [0; MAC_SIZE],
+        };
+        let mut last = None;
+        while let Some(header) = log.read_header(log.head).await? {
+            last = Some((log.head, header));
+            log.head += header.size();
+        }
+        if let Some((position, header)) = last {
+            log.next_seq = header.seq.wrapping_add(1);
+            let mac_offset = position + (HEADER_SIZE + header.len as usize) as u32;
+            log.region.read(mac_offset, &mut log.last_mac).await?;
+        }
+        Ok(log)
+    }
+
+    /// Largest payload [`append`](Self::append) accepts
+    pub fn max_payload(&self) -> usize {
+        let space = self.capacity - ANCHOR_SIZE;
+        min(space as usize, END as usize) - RECORD_OVERHEAD
+    }
+
+    /// Bytes left for records before the region is full
+    pub fn free(&self) -> usize {
+        (self.capacity - self.head) as usize
+    }
+
+    /// Appends a record of `payload`, chained to the previous one with `key`.
+    ///
+    /// If the record doesn't fit, this returns `Full` with [`WhenFull::Stop`]. With
+    /// [`WhenFull::Anchor`] every record is dropped and a new chain is started with an
+    /// anchor, and the last dropped record is returned. Returns `OutOfBounds` if the
+    /// payload is larger than [`max_payload`](Self::max_payload).
+    pub async fn append(&mut self, payload: &[u8], key: &[u8]) -> Result<Option<Anchor>, Error<E>> {
+        if payload.len() > self.max_payload() {
+            return Err(Error::OutOfBounds);
+        }
+        let mut anchor = None;
+        if (RECORD_OVERHEAD + payload.len()) as u32 > self.capacity - self.head {
+            if self.when_full == WhenFull::Stop {
+                return Err(Error::Full);
+            }
+            let dropped = Anchor {
+                seq: self.next_seq.wrapping_sub(1),
+                mac: self.last_mac,
+            };
+            self.head = 0;
+            self.write_record(KIND_ANCHOR, &dropped.mac, key).await?;
+            anchor = Some(dropped);
+        }
+        self.write_record(KIND_RECORD, payload, key).await?;
+        Ok(anchor)
+    }
+
+    /// Walks the records from the start of the region, checking the sequence numbers and
+    /// MACs with `key`, up to the first record that doesn't verify.
+    pub async fn verify_chain(&mut self, key: &[u8]) -> Result<ChainReport, Error<E>> {
+        let mut report = ChainReport {
+            verified: 0,
+            anchor: None,
+            head_seq: None,
+            head_mac: [0; MAC_SIZE],
+            first_break: None,
+        };
+        let mut position = 0;
+        let mut index = 0;
+        while let Some(header) = self.read_header(position).await? {
+            let reason = match (header.kind, report.head_seq) {
+                (KIND_ANCHOR, Some(_)) => Some(BreakReason::MisplacedAnchor),
+                (KIND_ANCHOR, None) => {
+                    // The MAC it carries over is what it follows in the chain
+                    let mut carried = [0; MAC_SIZE];
+                    if header.len as usize == MAC_SIZE {
+                        self.region
+                            .read(position + HEADER_SIZE as u32, &mut carried)
+                            .await?;
+                    }
+                    report.head_mac = carried;
+                    None
+                }
+                (_, Some(seq)) if header.seq != seq.wrapping_add(1) => Some(BreakReason::Sequence),
+                (_, None) if header.seq != 0 => Some(BreakReason::Sequence),
+                _ => None,
+            };
+            let reason = match reason {
+                None if !self
+                    .mac_matches(position, header, &report.head_mac, key)
+                    .await? =>
+                {
+                    Some(BreakReason::Mac)
+                }
+                reason => reason,
+            };
+            if let Some(reason) = reason {
+                report.first_break = Some(ChainBreak {
+                    index,
+                    offset: position,
+                    reason,
+                });
+                break;
+            }
+
+            if header.kind == KIND_ANCHOR {
+                report.anchor = Some(Anchor {
+                    seq: header.seq.wrapping_sub(1),
+                    mac: report.head_mac,
+                });
+            } else {
+                report.verified += 1;
+            }
+            let mac_offset = position + (HEADER_SIZE + header.len as usize) as u32;
+            self.region.read(mac_offset, &mut report.head_mac).await?;
+            report.head_seq = Some(header.seq);
+            position += header.size();
+            index += 1;
+        }
+        Ok(report)
+    }
+
+    /// Writes a record at the head, after marking the end of the log behind it
+    async fn write_record(&mut self, kind: u8, payload: &[u8], key: &[u8]) -> Result<(), Error<E>> {
+        let header = RecordHeader {
+            len: payload.len() as u16,
+            seq: self.next_seq,
+            kind,
+        };
+        let end = self.head + header.size();
+        if end + 2 <= self.capacity {
+            // Whatever follows is left over from before an anchor
+            self.region.write(end, &END.to_le_bytes()).await?;
+        }
+        let header_bytes = header.encode();
+        let mut mac = new_mac(key);
+        mac.update(&header_bytes);
+        mac.update(payload);
+        mac.update(&self.last_mac);
+        let mac: [u8; MAC_SIZE] = mac.finalize().into_bytes().into();
+
+        self.region.write(self.head, &header_bytes).await?;
+        self.region
+            .write(self.head + HEADER_SIZE as u32, payload)
+            .await?;
+        self.region.write(end - MAC_SIZE as u32, &mac).await?;
+        self.head = end;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.last_mac = mac;
+        Ok(())
+    }
+
+    /// Whether the MAC of the record at `position` matches its contents following `previous`
+    async fn mac_matches(
+        &mut self,
+        position: u32,
+        header: RecordHeader,
+        previous: &[u8; MAC_SIZE],
+        key: &[u8],
+    ) -> Result<bool, Error<E>> {
+        let mut mac = new_mac(key);
+        mac.update(&header.encode());
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut done = 0;
+        while done < header.len as usize {
+            let len = min(CHUNK_SIZE, header.len as usize - done);
+            let chunk = &mut chunk[..len];
+            self.region
+                .read(position + (HEADER_SIZE + done) as u32, chunk)
+                .await?;
+            mac.update(chunk);
+            done += len;
+        }
+        mac.update(previous);
+        let mut stored = [0; MAC_SIZE];
+        let mac_offset = position + (HEADER_SIZE + header.len as usize) as u32;
+        self.region.read(mac_offset, &mut stored).await?;
+        Ok(mac.verify_slice(&stored).is_ok())
+    }
+
+    /// Header of the record at `position`, `None` past the last record
+    async fn read_header(&mut self, position: u32) -> Result<Option<RecordHeader>, Error<E>> {
+        if position + HEADER_SIZE as u32 > self.capacity {
+            return Ok(None);
+        }
+        let mut bytes = [0; HEADER_SIZE];
+        self.region.read(position, &mut bytes).await?;
+        let header = RecordHeader::decode(&bytes);
+        let fits = position + header.size() <= self.capacity;
+        let known = header.kind == KIND_RECORD || header.kind == KIND_ANCHOR;
+        Ok((header.len != END && fits && known).then_some(header))
+    }
+}
+
+fn new_mac(key: &[u8]) -> HmacSha256 {
+    // HMAC takes keys of any length
+    HmacSha256::new_from_slice(key).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec::Vec;
+
+    const REGION: core::ops::Range<u32> = 0x3000..0x3100;
+    const KEY: &[u8] = b"metering key";
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn mount(
+        eeprom: &mut At24Cx<SimBus, NoopDelay>,
+        when_full: WhenFull,
+    ) -> AuditLog<'_, SimBus, NoopDelay> {
+        AuditLog::mount(eeprom.partition(REGION).unwrap(), when_full)
+            .await
+            .unwrap()
+    }
+
+    fn payload(seq: u32) -> Vec<u8> {
+        std::vec![seq as u8; 4 + seq as usize % 5]
+    }
+
+    /// Offsets on the device of three appended records and of their end
+    async fn three_records(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> Vec<usize> {
+        let mut log = mount(eeprom, WhenFull::Stop).await;
+        let mut offsets = std::vec![REGION.start as usize];
+        for seq in 0..3 {
+            log.append(&payload(seq), KEY).await.unwrap();
+            offsets.push(REGION.start as usize + log.head as usize);
+        }
+        offsets
+    }
+
+    async fn verify(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> ChainReport {
+        let mut log = mount(eeprom, WhenFull::Stop).await;
+        log.verify_chain(KEY).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn verifies_the_chain() {
+        let mut eeprom = driver();
+        let empty = verify(&mut eeprom).await;
+        assert_eq!(empty.verified, 0);
+        assert_eq!(empty.head_seq, None);
+        assert_eq!(empty.first_break, None);
+
+        three_records(&mut eeprom).await;
+        let mut log = mount(&mut eeprom, WhenFull::Stop).await;
+        // Continues the chain after mounting
+        log.append(&payload(3), KEY).await.unwrap();
+        let report = log.verify_chain(KEY).await.unwrap();
+        assert_eq!(report.verified, 4);
+        assert_eq!(report.head_seq, Some(3));
+        assert_eq!(report.head_mac, log.last_mac);
+        assert_eq!(report.anchor, None);
+        assert_eq!(report.first_break, None);
+
+        let report = log.verify_chain(b"another key").await.unwrap();
+        assert_eq!(report.verified, 0);
+        assert_eq!(
+            report.first_break.map(|b| (b.index, b.reason)),
+            Some((0, BreakReason::Mac))
+        );
+    }
+
+    #[tokio::test]
+    async fn detects_a_modified_record() {
+        let mut eeprom = driver();
+        let offsets = three_records(&mut eeprom).await;
+        eeprom.i2c.memory_mut()[offsets[1] + HEADER_SIZE] ^= 0x01;
+        let report = verify(&mut eeprom).await;
+        assert_eq!(report.verified, 1);
+        assert_eq!(
+            report.first_break,
+            Some(ChainBreak {
+                index: 1,
+                offset: (offsets[1] - REGION.start as usize) as u32,
+                reason: BreakReason::Mac
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn detects_a_deleted_record() {
+        let mut eeprom = driver();
+        let offsets = three_records(&mut eeprom).await;
+        let memory = eeprom.i2c.memory_mut();
+        // Move the last record over the middle one
+        memory.copy_within(offsets[2]..offsets[3], offsets[1]);
+        let end = offsets[1] + offsets[3] - offsets[2];
+        memory[end..end + 2].copy_from_slice(&END.to_le_bytes());
+        let report = verify(&mut eeprom).await;
+        assert_eq!(report.verified, 1);
+        assert_eq!(
+            report.first_break.map(|b| (b.index, b.reason)),
+            Some((1, BreakReason::Sequence))
+        );
+
+        // Also with the sequence number rewritten to follow on
+        let memory = eeprom.i2c.memory_mut();
+        memory[offsets[1] + 2..offsets[1] + 6].copy_from_slice(&1u32.to_le_bytes());
+        let report = verify(&mut eeprom).await;
+        assert_eq!(
+            report.first_break.map(|b| (b.index, b.reason)),
+            Some((1, BreakReason::Mac))
+        );
+    }
+
+    #[tokio::test]
+    async fn detects_reordered_records() {
+        let mut eeprom = driver();
+        let offsets = three_records(&mut eeprom).await;
+        let memory = eeprom.i2c.memory_mut();
+        let first = memory[offsets[0]..offsets[1]].to_vec();
+        let second = memory[offsets[1]..offsets[2]].to_vec();
+        memory[offsets[0]..offsets[0] + second.len()].copy_from_slice(&second);
+        let moved = offsets[0] + second.len();
+        memory[moved..moved + first.len()].copy_from_slice(&first);
+        let report = verify(&mut eeprom).await;
+        assert_eq!(report.verified, 0);
+        assert_eq!(
+            report.first_break.map(|b| (b.index, b.reason)),
+            Some((0, BreakReason::Sequence))
+        );
+
+        // Swapping the sequence numbers back doesn't help
+        memory_swap_seq(&mut eeprom, offsets[0], moved);
+        let report = verify(&mut eeprom).await;
+        assert_eq!(
+            report.first_break.map(|b| (b.index, b.reason)),
+            Some((0, BreakReason::Mac))
+        );
+    }
+
+    fn memory_swap_seq(eeprom: &mut At24Cx<SimBus, NoopDelay>, a: usize, b: usize) {
+        let memory = eeprom.i2c.memory_mut();
+        for i in 2..6 {
+            memory.swap(a + i, b + i);
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_or_anchors_when_full() {
+        let mut eeprom = driver();
+        let mut log = mount(&mut eeprom, WhenFull::Stop).await;
+        assert_eq!(log.max_payload(), 256 - 2 * RECORD_OVERHEAD - MAC_SIZE);
+        let mut seq = 0;
+        while log.free() >= RECORD_OVERHEAD + 8 {
+            log.append(&payload(seq), KEY).await.unwrap();
+            seq += 1;
+        }
+        assert!(matches!(log.append(&[0; 8], KEY).await, Err(Error::Full)));
+        let full = log.verify_chain(KEY).await.unwrap();
+        assert_eq!(full.verified, seq as usize);
+
+        let mut log = mount(&mut eeprom, WhenFull::Anchor).await;
+        let anchor = log.append(&[0xAA; 8], KEY).await.unwrap();
+        assert_eq!(
+            anchor,
+            Some(Anchor {
+                seq: seq - 1,
+                mac: full.head_mac
+            })
+        );
+        log.append(&[0xBB; 8], KEY).await.unwrap();
+        let report = log.verify_chain(KEY).await.unwrap();
+        assert_eq!(report.verified, 2);
+        assert_eq!(report.anchor, anchor);
+        // The anchor takes a sequence number too
+        assert_eq!(report.head_seq, Some(seq + 2));
+        assert_eq!(report.first_break, None);
+
+        // The records left over from the old chain are ignored after mounting again
+        let mut log = mount(&mut eeprom, WhenFull::Anchor).await;
+        assert_eq!(log.next_seq, seq + 3);
+        assert_eq!(log.verify_chain(KEY).await.unwrap(), report);
+
+        // A changed anchor is caught
+        let start = REGION.start as usize;
+        eeprom.i2c.memory_mut()[start + HEADER_SIZE] ^= 0x01;
+        let report = verify(&mut eeprom).await;
+        assert_eq!(
+            report.first_break.map(|b| (b.index, b.reason)),
+            Some((0, BreakReason::Mac))
+        );
+    }
+}
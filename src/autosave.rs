@@ -0,0 +1,437 @@
+//! Settings kept in RAM and saved in the background once changes settle.
+//!
+//! An [`Autosave`] holds the current value of some [`Settings`]. Any task reads it with
+//! [`get`](Autosave::get) and changes it with [`update`](Autosave::update), which only marks
+//! it dirty. [`run`](Autosave::run) saves dirty values in the background, when the first of
+//! these happens:
+//!
+//! - no update came in for [`min_dirty_age_ms`](AutosaveConfig::new), so a knob that is
+//!   being turned is saved once it stops rather than on every step,
+//! - the oldest unsaved change is [`max_interval_ms`](AutosaveConfig::new) old, so a value
+//!   that never settles is still saved,
+//! - [`flush`](Autosave::flush) is called, for example before powering down.
+//!
+//! This gives two guarantees, as long as `run` is running and saves succeed:
+//!
+//! - **Data loss window:** a change is saved at most `max_interval_ms` after it was made.
+//!   A change made while a save is in progress can wait for that save on top.
+//! - **Write rate:** apart from flushes, a save starts at least `min_dirty_age_ms` after the
+//!   previous one ended, so the settings pages see at most one write per `min_dirty_age_ms`.
+//!
+//! Time comes from a [`TickSource`]. With `embassy-time` that is a function returning
+//! `Instant::now().as_millis()`. `run` is generic and can't be an `#[embassy_executor::task]`
+//! itself, wrap it in a task with your concrete types:
+//!
+//! ```ignore
+//! fn now_ms() -> u64 {
+//!     Instant::now().as_millis()
+//! }
+//!
+//! static VOLUME: Autosave<CriticalSectionRawMutex, Volume, fn() -> u64> =
+//!     Autosave::new(Volume::DEFAULT, AutosaveConfig::new(500, 10_000), now_ms);
+//!
+//! #[embassy_executor::task]
+//! async fn autosave(mut eeprom: At24Cx<I2c<'static, Async>, Delay>) -> ! {
+//!     let mut settings = Settings::new(0x100, 1);
+//!     if let Ok(volume) = settings.load(&mut eeprom, &[]).await {
+//!         VOLUME.set_saved(volume);
+//!     }
+//!     VOLUME.run(&mut eeprom, &mut settings).await
+//! }
+//! ```
+
+use crate::settings::Settings;
+use crate::{with_timeout, At24Cx, Error};
+use bytemuck::Pod;
+use core::cell::RefCell;
+use core::fmt::Debug;
+use embassy_sync::blocking_mutex::{raw::RawMutex, Mutex};
+use embassy_sync::signal::Signal;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+/// A monotonic clock in milliseconds
+pub trait TickSource {
+    fn now_ms(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> TickSource for F {
+    fn now_ms(&self) -> u64 {
+        self()
+    }
+}
+
+#[cfg(any(test, feature = "sim"))]
+impl TickSource for crate::sim::SimClock {
+    fn now_ms(&self) -> u64 {
+        self.now() / 1000
+    }
+}
+
+/// When an [`Autosave`] saves, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutosaveConfig {
+    min_dirty_age_ms: u32,
+    max_interval_ms: u32,
+}
+
+impl AutosaveConfig {
+    /// Saves once the value didn't change for `min_dirty_age_ms`, or at the latest
+    /// `max_interval_ms` after the oldest unsaved change. Panics if `min_dirty_age_ms` is
+    /// larger than `max_interval_ms`.
+    pub const fn new(min_dirty_age_ms: u32, max_interval_ms: u32) -> Self {
+        assert!(
+            min_dirty_age_ms <= max_interval_ms,
+            "the minimum dirty age is longer than the maximum interval"
+        );
+        Self {
+            min_dirty_age_ms,
+            max_interval_ms,
+        }
+    }
+
+    pub const fn min_dirty_age_ms(&self) -> u32 {
+        self.min_dirty_age_ms
+    }
+
+    pub const fn max_interval_ms(&self) -> u32 {
+        self.max_interval_ms
+    }
+}
+
+struct State<T> {
+    value: T,
+    /// Time of the oldest unsaved change, `None` if the value is saved
+    dirty_since: Option<u64>,
+    last_update: u64,
+    /// Saves other than flushes don't start before this
+    not_before: u64,
+    /// Bumped by every update, tells whether the value changed during a save
+    generation: u32,
+    flush_requested: bool,
+}
+
+impl<T> State<T> {
+    /// When the dirty value is due to be saved
+    fn deadline(&self, config: &AutosaveConfig, dirty_since: u64) -> u64 {
+        let settled = self.last_update + config.min_dirty_age_ms as u64;
+        let overdue = dirty_since + config.max_interval_ms as u64;
+        settled.min(overdue).max(self.not_before)
+    }
+}
+
+enum Due<T> {
+    Clean,
+    At(u64),
+    Now(T, u32),
+}
+
+/// A value of type `T` that is saved in the background, see the [module docs](self)
+pub struct Autosave<M: RawMutex, T, C> {
+    state: Mutex<M, RefCell<State<T>>>,
+    config: AutosaveConfig,
+    clock: C,
+    /// Wakes `run` after an update or flush request
+    wake: Signal<M, ()>,
+    /// `true` once a requested flush saved the value, `false` if it failed
+    flushed: Signal<M, bool>,
+}
+
+impl<M: RawMutex, T: Pod, C: TickSource> Autosave<M, T, C> {
+    /// Holds `value`, which is taken to be saved already. Usually placed in a `static`.
+    pub const fn new(value: T, config: AutosaveConfig, clock: C) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State {
+                value,
+                dirty_since: None,
+                last_update: 0,
+                not_before: 0,
+                generation: 0,
+                flush_requested: false,
+            })),
+            config,
+            clock,
+            wake: Signal::new(),
+            flushed: Signal::new(),
+        }
+    }
+
+    pub fn config(&self) -> AutosaveConfig {
+        self.config
+    }
+
+    /// Current value, saved or not
+    pub fn get(&self) -> T {
+        self.state.lock(|state| state.borrow().value)
+    }
+
+    /// Whether the value has changes that aren't saved yet
+    pub fn is_dirty(&self) -> bool {
+        self.state
+            .lock(|state| state.borrow().dirty_since.is_some())
+    }
+
+    /// Replaces the value with one that is already saved, like the one loaded at startup.
+    /// Discards unsaved changes.
+    pub fn set_saved(&self, value: T) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.value = value;
+            state.dirty_since = None;
+            state.generation = state.generation.wrapping_add(1);
+        });
+    }
+
+    /// Changes the value with `change` and marks it dirty, returning what `change` returns.
+    /// `change` runs with the lock held, so it must not call back into the autosave.
+    pub fn update<R>(&self, change: impl FnOnce(&mut T) -> R) -> R {
+        let now = self.clock.now_ms();
+        let result = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let result = change(&mut state.value);
+            state.dirty_since.get_or_insert(now);
+            state.last_update = now;
+            state.generation = state.generation.wrapping_add(1);
+            result
+        });
+        self.wake.signal(());
+        result
+    }
+
+    /// Asks [`run`](Self::run) to save the value now if it is dirty, and waits for it.
+    /// Returns `false` if the save failed. Only one task should flush at a time.
+    pub async fn flush(&self) -> bool {
+        self.flushed.reset();
+        self.state
+            .lock(|state| state.borrow_mut().flush_requested = true);
+        self.wake.signal(());
+        self.flushed.wait().await
+    }
+
+    /// Saves the value to `settings` if it is dirty and a save is due. Returns when the
+    /// next save is due if the value is still dirty afterwards.
+    ///
+    /// [`run`](Self::run) calls this whenever something changes, it is public for driving
+    /// the autosave from a loop of your own instead.
+    pub async fn save_if_due<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+        settings: &mut Settings<T>,
+    ) -> Result<Option<u64>, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let now = self.clock.now_ms();
+        let due = self.state.lock(|state| {
+            let state = state.borrow();
+            match state.dirty_since {
+                None => Due::Clean,
+                Some(_) if state.flush_requested => Due::Now(state.value, state.generation),
+                Some(dirty_since) => match state.deadline(&self.config, dirty_since) {
+                    deadline if deadline > now => Due::At(deadline),
+                    _ => Due::Now(state.value, state.generation),
+                },
+            }
+        });
+        let (value, generation) = match due {
+            Due::Clean => {
+                self.finish_flush(true);
+                return Ok(None);
+            }
+            Due::At(deadline) => return Ok(Some(deadline)),
+            Due::Now(value, generation) => (value, generation),
+        };
+
+        let result = settings.save(eeprom, &value).await;
+        let done = self.clock.now_ms();
+        let next = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.not_before = done + self.config.min_dirty_age_ms as u64;
+            if result.is_ok() {
+                // Changed during the save, after `now` at the earliest
+                state.dirty_since = (state.generation != generation).then_some(now);
+            }
+            let dirty_since = state.dirty_since?;
+            Some(state.deadline(&self.config, dirty_since))
+        });
+        self.finish_flush(result.is_ok());
+        result.map(|()| next)
+    }
+
+    /// Saves dirty values in the background, forever. Waits with the driver's delay, so
+    /// `eeprom` is best a handle of its own on a shared bus. Failed saves are retried after
+    /// `min_dirty_age_ms`.
+    pub async fn run<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+        settings: &mut Settings<T>,
+    ) -> !
+    where
+        I2C: I2c<Error = E>,
+    {
+        loop {
+            let next = match self.save_if_due(eeprom, settings).await {
+                Ok(next) => next,
+                Err(_) => Some(self.clock.now_ms() + self.config.min_dirty_age_ms as u64),
+            };
+            let timeout_us = next.map(|deadline| {
+                let wait_ms = deadline.saturating_sub(self.clock.now_ms());
+                wait_ms.saturating_mul(1000).min(u32::MAX as u64) as u32
+            });
+            with_timeout(&mut eeprom.delay, timeout_us, self.wake.wait()).await;
+        }
+    }
+
+    /// Answers a pending flush request
+    fn finish_flush(&self, saved: bool) {
+        let requested = self
+            .state
+            .lock(|state| core::mem::take(&mut state.borrow_mut().flush_requested));
+        if requested {
+            self.flushed.signal(saved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimBus, SimClock, SimDelay};
+    use crate::Address;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embedded_storage_async::nor_flash::NorFlash;
+    use std::vec::Vec;
+
+    const OFFSET: u32 = 0x100;
+    const MIN_DIRTY_AGE_MS: u64 = 100;
+    const MAX_INTERVAL_MS: u64 = 1000;
+    /// Upper bound of how long a save takes on the simulator, one write cycle is 5ms
+    const SAVE_MS: u64 = 10;
+
+    type Volume = Autosave<NoopRawMutex, u32, SimClock>;
+
+    fn driver() -> At24Cx<SimBus, SimDelay> {
+        let bus = SimBus::new(Address(0, 0), 17).with_write_cycle(5000);
+        let delay = SimDelay::new(bus.clock());
+        At24Cx::new(bus, Address(0, 0), 17, delay)
+    }
+
+    fn autosave(clock: SimClock) -> Volume {
+        let config = AutosaveConfig::new(MIN_DIRTY_AGE_MS as u32, MAX_INTERVAL_MS as u32);
+        Autosave::new(0, config, clock)
+    }
+
+    /// Saves so far, each one writes the first byte of one of the two pages
+    fn saves(eeprom: &At24Cx<SimBus, SimDelay>) -> u32 {
+        let page = At24Cx::<SimBus, SimDelay>::ERASE_SIZE;
+        eeprom.i2c.write_count(OFFSET as usize) + eeprom.i2c.write_count(OFFSET as usize + page)
+    }
+
+    /// Drives the autosave every millisecond with `change` called before, returns the start
+    /// times of the saves
+    async fn drive(
+        eeprom: &mut At24Cx<SimBus, SimDelay>,
+        settings: &mut Settings<u32>,
+        volume: &Volume,
+        until_ms: u64,
+        mut change: impl FnMut(u64),
+    ) -> Vec<u64> {
+        let clock = eeprom.i2c.clock();
+        let mut save_times = Vec::new();
+        while clock.now_ms() < until_ms {
+            let now = clock.now_ms();
+            change(now);
+            let before = saves(eeprom);
+            volume.save_if_due(eeprom, settings).await.unwrap();
+            if saves(eeprom) > before {
+                save_times.push(now);
+            }
+            clock.advance(1000);
+        }
+        save_times
+    }
+
+    #[tokio::test]
+    async fn saves_a_burst_once_it_settles() {
+        let mut eeprom = driver();
+        let mut settings = Settings::new(OFFSET, 1);
+        let volume = autosave(eeprom.i2c.clock());
+
+        let mut last_update = 0;
+        let save_times = drive(&mut eeprom, &mut settings, &volume, 3000, |now| {
+            if (1000..1500).contains(&now) && now % 20 == 0 {
+                volume.update(|v| *v += 1);
+                last_update = now;
+            }
+        })
+        .await;
+
+        assert_eq!(save_times, [last_update + MIN_DIRTY_AGE_MS]);
+        assert!(!volume.is_dirty());
+        assert_eq!(settings.load(&mut eeprom, &[]).await.unwrap(), 25);
+    }
+
+    #[tokio::test]
+    async fn bounds_data_loss_and_write_rate() {
+        let mut eeprom = driver();
+        let mut settings = Settings::new(OFFSET, 1);
+        let volume = autosave(eeprom.i2c.clock());
+
+        // Never settles for long enough until 5s, then one more change
+        let mut updates = Vec::new();
+        let save_times = drive(&mut eeprom, &mut settings, &volume, 8000, |now| {
+            if (now < 5000 && now % 50 == 0) || now == 6000 {
+                volume.update(|v| *v = now as u32);
+                updates.push(now);
+            }
+        })
+        .await;
+
+        for update in updates {
+            assert!(
+                save_times
+                    .iter()
+                    .any(|&save| save >= update && save <= update + MAX_INTERVAL_MS + SAVE_MS),
+                "change at {update}ms not saved in time: {save_times:?}"
+            );
+        }
+        for pair in save_times.windows(2) {
+            assert!(pair[1] - pair[0] >= MIN_DIRTY_AGE_MS, "{save_times:?}");
+        }
+        assert!(save_times.len() <= 8000 / MIN_DIRTY_AGE_MS as usize);
+        assert_eq!(save_times.last(), Some(&(6000 + MIN_DIRTY_AGE_MS)));
+        assert_eq!(settings.load(&mut eeprom, &[]).await.unwrap(), 6000);
+    }
+
+    #[tokio::test]
+    async fn run_saves_and_flushes() {
+        let mut eeprom = driver();
+        let clock = eeprom.i2c.clock();
+        let mut settings = Settings::new(OFFSET, 1);
+        let volume = autosave(clock.clone());
+
+        let scenario = async {
+            // Clean, nothing to save
+            assert!(volume.flush().await);
+            volume.update(|v| *v = 7);
+            let start = clock.now_ms();
+            while volume.is_dirty() {
+                tokio::task::yield_now().await;
+            }
+            let took = clock.now_ms() - start;
+            assert!((MIN_DIRTY_AGE_MS..=MIN_DIRTY_AGE_MS + SAVE_MS).contains(&took));
+
+            // Flushes don't wait for the change to settle
+            volume.update(|v| *v = 8);
+            let start = clock.now_ms();
+            assert!(volume.flush().await);
+            assert!(clock.now_ms() - start <= SAVE_MS);
+            assert!(!volume.is_dirty());
+        };
+        tokio::select! {
+            _ = volume.run(&mut eeprom, &mut settings) => unreachable!(),
+            _ = scenario => {}
+        }
+        let mut reloaded = Settings::<u32>::new(OFFSET, 1);
+        assert_eq!(reloaded.load(&mut eeprom, &[]).await.unwrap(), 8);
+    }
+}
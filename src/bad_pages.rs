@@ -0,0 +1,198 @@
+//! Software bad-page management.
+//!
+//! A page known to have failing cells can be listed as bad, optionally with a spare page
+//! that takes its place. Accesses to a remapped page go to the same offset within the
+//! spare page instead, so the remapping is invisible to the caller. Writes to a bad page
+//! without a spare fail with `BadPage`, reads still go to the page itself so its contents
+//! can be salvaged. The list lives in RAM only: persist it yourself and set it again after
+//! every reset, before touching the device.
+
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+use heapless::Vec;
+
+/// Maximum number of entries in the bad-page list
+pub const MAX_BAD_PAGES: usize = 8;
+
+/// A page that shouldn't be written anymore, by index in the driver's
+/// [pages](At24Cx::set_page_size)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadPage {
+    pub page: u32,
+    /// Spare page used in its place
+    pub remap: Option<u32>,
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Replaces the bad-page list. Pages are counted in the current page size, so set the
+    /// page size first.
+    ///
+    /// Pages and spares have to be within the device (`OutOfBounds`), pages may be listed
+    /// only once and a spare can't be bad or used twice (`InvalidArgument`).
+    pub fn set_bad_pages(&mut self, pages: &[BadPage]) -> Result<(), Error<E>> {
+        let page_count = (self.capacity() / self.page_size) as u32;
+        let list = Vec::from_slice(pages).map_err(|_| Error::InvalidArgument)?;
+        for (i, bad) in pages.iter().enumerate() {
+            if bad.page >= page_count || bad.remap.is_some_and(|spare| spare >= page_count) {
+                return Err(Error::OutOfBounds);
+            }
+            let duplicate = pages[..i].iter().any(|other| {
+                other.page == bad.page || (bad.remap.is_some() && other.remap == bad.remap)
+            });
+            let bad_spare = bad
+                .remap
+                .is_some_and(|spare| pages.iter().any(|other| other.page == spare));
+            if duplicate || bad_spare {
+                return Err(Error::InvalidArgument);
+            }
+        }
+        self.bad_pages = list;
+        Ok(())
+    }
+
+    pub fn bad_pages(&self) -> &[BadPage] {
+        &self.bad_pages
+    }
+}
+
+impl<I2C, D> At24Cx<I2C, D> {
+    /// Where a write to `offset` goes, `None` if it's in a bad page without a spare
+    pub(crate) fn remap_write(&self, offset: u32) -> Option<u32> {
+        match self.find_bad_page(offset) {
+            None => Some(offset),
+            Some(BadPage {
+                remap: Some(spare), ..
+            }) => {
+                let page_size = self.page_size as u32;
+                Some(spare * page_size + offset % page_size)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Where a read of `offset` goes
+    pub(crate) fn remap_read(&self, offset: u32) -> u32 {
+        self.remap_write(offset).unwrap_or(offset)
+    }
+
+    fn find_bad_page(&self, offset: u32) -> Option<&BadPage> {
+        let page = offset / self.page_size as u32;
+        self.bad_pages.iter().find(|bad| bad.page == page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, DeviceKind};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_storage_async::nor_flash::NorFlash;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn remaps_writes_and_reads() {
+        let mut eeprom = driver();
+        eeprom
+            .set_bad_pages(&[
+                BadPage {
+                    page: 2,
+                    remap: Some(0x1FF),
+                },
+                BadPage {
+                    page: 4,
+                    remap: None,
+                },
+            ])
+            .unwrap();
+        let data: [u8; 0x200] = core::array::from_fn(|i| i as u8);
+        eeprom.write(0x180, &data).await.unwrap();
+        assert_eq!(eeprom.i2c.memory()[0x180..0x200], data[..0x80]);
+        assert!(eeprom.i2c.memory()[0x200..0x300].iter().all(|b| *b == 0xFF));
+        assert_eq!(eeprom.i2c.memory()[0x1FF00..0x20000], data[0x80..0x180]);
+        assert_eq!(eeprom.i2c.memory()[0x300..0x380], data[0x180..]);
+
+        let mut buf = [0; 0x200];
+        eeprom.read(0x180, &mut buf).await.unwrap();
+        assert_eq!(buf, data);
+
+        assert!(matches!(
+            eeprom.write(0x3F0, &[0; 0x20]).await,
+            Err(Error::BadPage)
+        ));
+        eeprom.i2c.memory_mut()[0x400] = 0x42;
+        eeprom.read(0x400, &mut buf[..1]).await.unwrap();
+        assert_eq!(buf[0], 0x42);
+    }
+
+    #[tokio::test]
+    async fn remaps_small_pages() {
+        let address = Address::pins(1, 1, 1);
+        let bus = SimBus::new(address, 12).with_page_size(32);
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+        let bad = BadPage {
+            page: 2,
+            remap: Some(127),
+        };
+        eeprom.set_bad_pages(&[bad]).unwrap();
+        let data: [u8; 0x40] = core::array::from_fn(|i| i as u8);
+        eeprom.write(0x30, &data).await.unwrap();
+        let memory = eeprom.i2c.memory();
+        assert_eq!(memory[0x30..0x40], data[..0x10]);
+        assert!(memory[0x40..0x60].iter().all(|b| *b == 0xFF));
+        assert_eq!(memory[0x60..0x70], data[0x30..]);
+        assert_eq!(memory[0xFE0..0x1000], data[0x10..0x30]);
+
+        let mut buf = [0; 0x40];
+        eeprom.read(0x30, &mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        assert!(matches!(
+            eeprom.set_bad_pages(&[BadPage {
+                page: 128,
+                remap: None
+            }]),
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn validates_list() {
+        let mut eeprom = driver();
+        let bad = |page, remap| BadPage { page, remap };
+        assert!(matches!(
+            eeprom.set_bad_pages(&[bad(0x200, None)]),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.set_bad_pages(&[bad(1, Some(0x200))]),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.set_bad_pages(&[bad(1, None), bad(1, Some(3))]),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            eeprom.set_bad_pages(&[bad(1, Some(3)), bad(2, Some(3))]),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            eeprom.set_bad_pages(&[bad(1, Some(2)), bad(2, None)]),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            eeprom.set_bad_pages(&[bad(1, None); MAX_BAD_PAGES + 1]),
+            Err(Error::InvalidArgument)
+        ));
+        eeprom.set_bad_pages(&[bad(1, Some(3))]).unwrap();
+        assert_eq!(eeprom.bad_pages(), &[bad(1, Some(3))]);
+    }
+}
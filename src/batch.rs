@@ -0,0 +1,147 @@
+use crate::{check_read, At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Reads several regions, merging the ones that are close together into one transfer.
+    ///
+    /// `ops` is sorted by offset in place. Regions at most the
+    /// [merge gap](Self::set_batch_merge_gap) apart, overlapping ones included, are read
+    /// as a single span of up to [`PAGE_SIZE`] bytes and copied out of it, the gaps are
+    /// read and dropped. A region that isn't merged is read straight into its buffer. All
+    /// bounds are checked before the bus is touched.
+    pub async fn read_batch(&mut self, ops: &mut [(u32, &mut [u8])]) -> Result<(), Error<E>> {
+        for (offset, buf) in ops.iter() {
+            check_read(self, *offset, buf.len()).map_err(Error::from_kind)?;
+        }
+        ops.sort_unstable_by_key(|(offset, _)| *offset);
+
+        let mut scratch = [0; PAGE_SIZE];
+        let mut i = 0;
+        while i < ops.len() {
+            let start = ops[i].0;
+            let mut end = start + ops[i].1.len() as u32;
+            let mut j = i + 1;
+            while let Some((offset, buf)) = ops.get(j) {
+                let merged_end = end.max(offset + buf.len() as u32);
+                if offset.saturating_sub(end) > self.batch_merge_gap as u32
+                    || (merged_end - start) as usize > PAGE_SIZE
+                {
+                    break;
+                }
+                end = merged_end;
+                j += 1;
+            }
+
+            if j == i + 1 {
+                let (offset, buf) = &mut ops[i];
+                self.read(*offset, buf).await?;
+            } else {
+                let span = &mut scratch[..(end - start) as usize];
+                self.read(start, span).await?;
+                for (offset, buf) in &mut ops[i..j] {
+                    let at = (*offset - start) as usize;
+                    buf.copy_from_slice(&span[at..at + buf.len()]);
+                }
+            }
+            i = j;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBus;
+    use crate::test_support::expect_read;
+    use crate::Address;
+    use embedded_hal_mock::eh1::{delay::NoopDelay, i2c::Mock as I2cMock};
+
+    #[tokio::test]
+    async fn merges_close_regions() {
+        let memory: [u8; 0x40] = core::array::from_fn(|i| i as u8);
+        let expectations = [
+            // 0x10..0x14 and 0x18..0x1C, 4 bytes apart, with the gap
+            expect_read(0x50, 0x10, &memory[0x10..0x1C]),
+            // Too far from the others
+            expect_read(0x50, 0x30, &memory[0x30..0x32]),
+            expect_read(0x51, 0x10000, &[0xAA; 3]),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_batch_merge_gap(4);
+        let (mut a, mut b, mut c, mut d) = ([0; 4], [0; 4], [0; 2], [0; 3]);
+        eeprom
+            .read_batch(&mut [
+                (0x10000, &mut d[..]),
+                (0x18, &mut b[..]),
+                (0x30, &mut c[..]),
+                (0x10, &mut a[..]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(a, memory[0x10..0x14]);
+        assert_eq!(b, memory[0x18..0x1C]);
+        assert_eq!(c, memory[0x30..0x32]);
+        assert_eq!(d, [0xAA; 3]);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn merges_overlapping_regions_up_to_a_page() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        for (i, byte) in bus.memory_mut().iter_mut().enumerate() {
+            *byte = (i * 7) as u8;
+        }
+        let memory = bus.memory().to_vec();
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.batch_merge_gap(), 8);
+
+        let (mut a, mut b, mut c, mut d) = ([0; 0x10], [0; 4], [0; 8], [0; 0xF0]);
+        let before = eeprom.i2c.transaction_count();
+        eeprom
+            .read_batch(&mut [
+                (0xFFF8, &mut a[..]),
+                // Within the first one
+                (0xFFFC, &mut b[..]),
+                // Across the 64K boundary, 8 bytes after the first one
+                (0x10010, &mut c[..]),
+                // Close, but would make the span longer than a page
+                (0x1001C, &mut d[..]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(eeprom.i2c.transaction_count() - before, 2);
+        assert_eq!(a, memory[0xFFF8..0x10008]);
+        assert_eq!(b, memory[0xFFFC..0x10000]);
+        assert_eq!(c, memory[0x10010..0x10018]);
+        assert_eq!(d, memory[0x1001C..0x1010C]);
+
+        // Far apart regions each take a transfer
+        let before = eeprom.i2c.transaction_count();
+        eeprom
+            .read_batch(&mut [(0, &mut a[..]), (0x100, &mut b[..]), (0x200, &mut c[..])])
+            .await
+            .unwrap();
+        assert_eq!(eeprom.i2c.transaction_count() - before, 3);
+    }
+
+    #[tokio::test]
+    async fn checks_bounds_before_reading() {
+        let i2c = I2cMock::new(&[]);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        let (mut a, mut b) = ([0; 4], [0; 4]);
+        let result = eeprom
+            .read_batch(&mut [(0x10, &mut a[..]), (0x1FFFE, &mut b[..])])
+            .await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+        eeprom.read_batch(&mut []).await.unwrap();
+        eeprom.i2c.done();
+    }
+}
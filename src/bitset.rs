@@ -0,0 +1,386 @@
+//! Persistent bitset in a region of the device.
+//!
+//! Bit `i` lives in bit `i % 8` of byte `i / 8` of the region. A bit is set when it's
+//! cleared on the device, so a blank region reads as an empty set. Mutations read the byte
+//! first and skip the write if the bit already has the requested value.
+
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+const CHUNK_SIZE: usize = 32;
+
+/// Set of `BITS` bits over a region, see the [module docs](self)
+///
+/// With a cache the whole bitmap is read once on first use and kept in RAM, mutations are
+/// written through to the device. Mutations through the bitset keep the cache up to date,
+/// anything else writing to the region, like another `Bitset` or a raw write, has to be
+/// followed by [`invalidate_cache`](Self::invalidate_cache) or
+/// [`invalidate_page`](Self::invalidate_page).
+pub struct Bitset<'c, const BITS: usize> {
+    region: Range<u32>,
+    cache: Option<&'c mut [u8]>,
+    cache_valid: bool,
+}
+
+impl<'c, const BITS: usize> Bitset<'c, BITS> {
+    /// Bytes of the device, and of the cache, used by the bitset
+    pub const BYTES: usize = BITS.div_ceil(8);
+
+    /// Creates a bitset over `region`, which has to be exactly [`BYTES`](Self::BYTES) long.
+    /// The cache, if any, needs at least as many bytes.
+    ///
+    /// Returns `OutOfBounds` if the region doesn't fit on the device and `InvalidArgument`
+    /// if the region or cache have the wrong size.
+    pub fn new<I2C, E: Debug, D: DelayNs>(
+        eeprom: &At24Cx<I2C, D>,
+        region: Range<u32>,
+        cache: Option<&'c mut [u8]>,
+    ) -> Result<Self, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if region.start > region.end || region.end as usize > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        let cache_too_small = cache.as_ref().is_some_and(|c| c.len() < Self::BYTES);
+        if (region.end - region.start) as usize != Self::BYTES || BITS == 0 || cache_too_small {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(Self {
+            region,
+            cache,
+            cache_valid: false,
+        })
+    }
+
+    /// Makes the next query read the bitmap from the device again
+    pub fn invalidate_cache(&mut self) {
+        self.cache_valid = false;
+    }
+
+    /// Invalidates the cache if the [page](At24Cx::set_page_size) of `eeprom` starting at
+    /// `page_offset` overlaps the region
+    pub fn invalidate_page<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &At24Cx<I2C, D>,
+        page_offset: u32,
+    ) where
+        I2C: I2c<Error = E>,
+    {
+        let page = page_offset..page_offset.saturating_add(eeprom.page_size() as u32);
+        if page.start < self.region.end && self.region.start < page.end {
+            self.invalidate_cache();
+        }
+    }
+
+    /// Adds `index` to the set
+    pub async fn set<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        index: usize,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        self.update(eeprom, index, true).await
+    }
+
+    /// Removes `index` from the set
+    pub async fn clear<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        index: usize,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        self.update(eeprom, index, false).await
+    }
+
+    /// Whether `index` is in the set
+    pub async fn test<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        index: usize,
+    ) -> Result<bool, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if index >= BITS {
+            return Err(Error::OutOfBounds);
+        }
+        let byte = self.byte(eeprom, index / 8).await?;
+        Ok(byte & (1 << (index % 8)) == 0)
+    }
+
+    /// Number of bits in the set
+    pub async fn count_ones<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<usize, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let mut count = 0;
+        let mut from = 0;
+        while let Some(index) = self.next_set(eeprom, from).await? {
+            count += 1;
+            from = index + 1;
+        }
+        Ok(count)
+    }
+
+    /// Streams the bits in the set in ascending order
+    pub fn iter_set<'b, I2C, D>(
+        &'b mut self,
+        eeprom: &'b mut At24Cx<I2C, D>,
+    ) -> SetBits<'b, 'c, I2C, D, BITS> {
+        SetBits {
+            bitset: self,
+            eeprom,
+            from: 0,
+        }
+    }
+
+    /// Smallest bit in the set at or after `from`
+    async fn next_set<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        from: usize,
+    ) -> Result<Option<usize>, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        self.load_cache(eeprom).await?;
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut start = from / 8;
+        while start < Self::BYTES {
+            let len = CHUNK_SIZE.min(Self::BYTES - start);
+            let bytes = match &self.cache {
+                Some(cache) => &cache[start..start + len],
+                None => {
+                    let offset = self.region.start + start as u32;
+                    eeprom.read(offset, &mut chunk[..len]).await?;
+                    &chunk[..len]
+                }
+            };
+            for (i, byte) in bytes.iter().enumerate() {
+                let first = (start + i) * 8;
+                // Bits before `from` in its byte don't count
+                let skip = from.saturating_sub(first).min(8) as u32;
+                let set = !byte & (0xFF << skip);
+                if set != 0 {
+                    let index = first + set.trailing_zeros() as usize;
+                    return Ok((index < BITS).then_some(index));
+                }
+            }
+            start += len;
+        }
+        Ok(None)
+    }
+
+    async fn update<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        index: usize,
+        set: bool,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if index >= BITS {
+            return Err(Error::OutOfBounds);
+        }
+        let old = self.byte(eeprom, index / 8).await?;
+        let mask = 1 << (index % 8);
+        let new = if set { old & !mask } else { old | mask };
+        if new == old {
+            return Ok(());
+        }
+        eeprom
+            .write(self.region.start + (index / 8) as u32, &[new])
+            .await?;
+        if let Some(cache) = &mut self.cache {
+            cache[index / 8] = new;
+        }
+        Ok(())
+    }
+
+    async fn byte<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        index: usize,
+    ) -> Result<u8, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        self.load_cache(eeprom).await?;
+        if let Some(cache) = &self.cache {
+            return Ok(cache[index]);
+        }
+        let mut byte = [0];
+        eeprom
+            .read(self.region.start + index as u32, &mut byte)
+            .await?;
+        Ok(byte[0])
+    }
+
+    async fn load_cache<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if let (Some(cache), false) = (&mut self.cache, self.cache_valid) {
+            eeprom
+                .read(self.region.start, &mut cache[..Self::BYTES])
+                .await?;
+            self.cache_valid = true;
+        }
+        Ok(())
+    }
+}
+
+/// Streaming iterator over the bits in a [`Bitset`], see [`Bitset::iter_set`]
+pub struct SetBits<'b, 'c, I2C, D, const BITS: usize> {
+    bitset: &'b mut Bitset<'c, BITS>,
+    eeprom: &'b mut At24Cx<I2C, D>,
+    from: usize,
+}
+
+impl<I2C, E: Debug, D: DelayNs, const BITS: usize> SetBits<'_, '_, I2C, D, BITS>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Next bit in the set, `None` after the last one
+    pub async fn next(&mut self) -> Result<Option<usize>, Error<E>> {
+        let next = self.bitset.next_set(self.eeprom, self.from).await?;
+        if let Some(index) = next {
+            self.from = index + 1;
+        } else {
+            self.from = BITS;
+        }
+        Ok(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: Range<u32> = 0x800..0xA00;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn collect<I2C, E: Debug, D: DelayNs>(
+        mut bits: SetBits<'_, '_, I2C, D, 4096>,
+    ) -> Vec<usize>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let mut set = Vec::new();
+        while let Some(index) = bits.next().await.unwrap() {
+            set.push(index);
+        }
+        set
+    }
+
+    #[tokio::test]
+    async fn iterates_across_bytes() {
+        let mut eeprom = driver();
+        let mut bitset = Bitset::<4096>::new(&eeprom, REGION, None).unwrap();
+        let expected = [0, 7, 8, 15, 16, 255, 256, 257, 4095];
+        for index in expected {
+            bitset.set(&mut eeprom, index).await.unwrap();
+        }
+        assert_eq!(collect(bitset.iter_set(&mut eeprom)).await, expected);
+        assert_eq!(
+            bitset.count_ones(&mut eeprom).await.unwrap(),
+            expected.len()
+        );
+        assert!(bitset.test(&mut eeprom, 257).await.unwrap());
+        assert!(!bitset.test(&mut eeprom, 258).await.unwrap());
+        assert!(matches!(
+            bitset.set(&mut eeprom, 4096).await,
+            Err(Error::OutOfBounds)
+        ));
+
+        // Bits past BITS in the last byte are never reported
+        let mut eeprom = driver();
+        eeprom.i2c.memory_mut()[0x800] = 0;
+        let mut bitset = Bitset::<5>::new(&eeprom, 0x800..0x801, None).unwrap();
+        assert_eq!(bitset.count_ones(&mut eeprom).await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn cache_stays_coherent() {
+        let mut eeprom = driver();
+        let mut cache = [0; Bitset::<4096>::BYTES];
+        let mut cached = Bitset::<4096>::new(&eeprom, REGION, Some(&mut cache)).unwrap();
+        let mut uncached = Bitset::<4096>::new(&eeprom, REGION, None).unwrap();
+        uncached.set(&mut eeprom, 3).await.unwrap();
+        cached.set(&mut eeprom, 9).await.unwrap();
+        cached.set(&mut eeprom, 10).await.unwrap();
+        cached.clear(&mut eeprom, 3).await.unwrap();
+        cached.clear(&mut eeprom, 9).await.unwrap();
+        assert_eq!(collect(cached.iter_set(&mut eeprom)).await, [10]);
+        assert_eq!(collect(uncached.iter_set(&mut eeprom)).await, [10]);
+        assert_eq!(eeprom.i2c.memory()[0x801], 0b1111_1011);
+
+        // Cached queries don't read the device again
+        eeprom.i2c.memory_mut()[0x800] = 0;
+        assert!(!cached.test(&mut eeprom, 0).await.unwrap());
+        assert_eq!(cached.count_ones(&mut eeprom).await.unwrap(), 1);
+
+        // Until they're told about the out-of-band write
+        cached.invalidate_page(&eeprom, 0x700);
+        assert!(!cached.test(&mut eeprom, 0).await.unwrap());
+        // A 32 byte page ends before the region
+        eeprom.set_page_size(32).unwrap();
+        cached.invalidate_page(&eeprom, 0x7E0);
+        assert!(!cached.test(&mut eeprom, 0).await.unwrap());
+        cached.invalidate_page(&eeprom, 0x9E0);
+        assert!(cached.test(&mut eeprom, 0).await.unwrap());
+        eeprom.i2c.memory_mut()[0x801] = 0xFF;
+        cached.invalidate_cache();
+        assert_eq!(cached.count_ones(&mut eeprom).await.unwrap(), 8);
+    }
+
+    #[tokio::test]
+    async fn skips_unchanged_writes() {
+        let mut eeprom = driver();
+        let mut bitset = Bitset::<4096>::new(&eeprom, REGION, None).unwrap();
+        bitset.clear(&mut eeprom, 20).await.unwrap();
+        assert_eq!(eeprom.i2c.write_count(0x802), 0);
+        bitset.set(&mut eeprom, 20).await.unwrap();
+        bitset.set(&mut eeprom, 20).await.unwrap();
+        assert_eq!(eeprom.i2c.write_count(0x802), 1);
+    }
+
+    #[test]
+    fn checks_region_and_cache() {
+        let eeprom = driver();
+        let mut cache = [0; 511];
+        assert!(matches!(
+            Bitset::<4096>::new(&eeprom, REGION.start..REGION.end - 1, None),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            Bitset::<4096>::new(&eeprom, REGION, Some(&mut cache)),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            Bitset::<4096>::new(&eeprom, 0x1FF00..0x20100, None),
+            Err(Error::OutOfBounds)
+        ));
+    }
+}
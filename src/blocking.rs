@@ -0,0 +1,201 @@
+//! Blocking `embedded-storage` traits over the async driver.
+//!
+//! A [`BlockingFacade`] borrows an async [`At24Cx`] and implements the blocking
+//! [`ReadNorFlash`] and [`NorFlash`] traits for a dependency that only knows those, without
+//! a second driver on the same bus. Every call drives the async operation to completion with
+//! the facade's [`BlockOn`] executor, so the caller's thread is stuck until the bus
+//! transactions and ACK polling are done. **Only call it from a context where blocking is
+//! acceptable**, never from an interrupt handler or from inside an async task that other
+//! tasks on the same executor are waiting on.
+//!
+//! [`Spin`] polls the operation in a loop like `embassy_futures::block_on`. An executor of
+//! the firmware's own is used by implementing [`BlockOn`] for it.
+
+use crate::At24Cx;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use embedded_hal_async::{
+    delay::DelayNs,
+    i2c::{ErrorType as I2cErrorType, I2c},
+};
+use embedded_storage::nor_flash::{ErrorType as StorageErrorType, NorFlash, ReadNorFlash};
+use embedded_storage_async::nor_flash as nor_flash_async;
+
+/// Runs a future to completion on the caller's thread
+pub trait BlockOn {
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output;
+}
+
+/// Busy-polls the future with a waker that does nothing, until it is ready
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Spin;
+
+impl BlockOn for Spin {
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        // SAFETY: the vtable functions do nothing, so any data pointer is fine
+        let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+}
+
+/// The driver behind blocking storage traits, see the [module docs](self). Errors of the
+/// async operations are returned unchanged. The facade isn't `Send`, so it can't be moved
+/// into an interrupt context.
+pub struct BlockingFacade<'a, I2C, D, X = Spin> {
+    eeprom: &'a mut At24Cx<I2C, D>,
+    executor: X,
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl<'a, I2C, D, X: BlockOn> BlockingFacade<'a, I2C, D, X> {
+    /// Wraps `eeprom`, driving its operations with `executor`
+    pub fn new(eeprom: &'a mut At24Cx<I2C, D>, executor: X) -> Self {
+        Self {
+            eeprom,
+            executor,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<I2C: I2cErrorType, D, X> StorageErrorType for BlockingFacade<'_, I2C, D, X> {
+    type Error = crate::Error<I2C::Error>;
+}
+
+impl<I2C: I2c, D: DelayNs, X: BlockOn> ReadNorFlash for BlockingFacade<'_, I2C, D, X> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let read = nor_flash_async::ReadNorFlash::read(self.eeprom, offset, bytes);
+        self.executor.block_on(read)
+    }
+
+    fn capacity(&self) -> usize {
+        nor_flash_async::ReadNorFlash::capacity(self.eeprom)
+    }
+}
+
+impl<I2C: I2c, D: DelayNs, X: BlockOn> NorFlash for BlockingFacade<'_, I2C, D, X> {
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = <At24Cx<I2C, D> as nor_flash_async::NorFlash>::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let erase = nor_flash_async::NorFlash::erase(self.eeprom, from, to);
+        self.executor.block_on(erase)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let write = nor_flash_async::NorFlash::write(self.eeprom, offset, bytes);
+        self.executor.block_on(write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimBus, SimDelay};
+    use crate::{Address, Error};
+    use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind};
+    use std::vec::Vec;
+
+    fn driver() -> At24Cx<SimBus, SimDelay> {
+        let bus = SimBus::new(Address(0, 0), 17).with_write_cycle(5000);
+        let delay = SimDelay::new(bus.clock());
+        At24Cx::new(bus, Address(0, 0), 17, delay)
+    }
+
+    enum Op {
+        Write(u32, Vec<u8>),
+        Read(u32, usize),
+        Erase(u32, u32),
+    }
+
+    type Outcome = Result<Vec<u8>, NorFlashErrorKind>;
+
+    fn ops() -> [Op; 7] {
+        let data = (0..300).map(|i| i as u8).collect();
+        [
+            // Crosses pages and the 64KiB boundary
+            Op::Write(0xFFF0, data),
+            Op::Read(0xFFF0, 300),
+            Op::Erase(0x100, 0x200),
+            Op::Write(0x1FFFF, std::vec![1, 2]),
+            Op::Read(0x20000, 1),
+            Op::Erase(0x80, 0x100),
+            Op::Read(0x1FFFF, 1),
+        ]
+    }
+
+    fn run_blocking(flash: &mut impl NorFlash) -> Vec<Outcome> {
+        let run = |op: Op| match op {
+            Op::Write(offset, data) => flash.write(offset, &data).map(|()| Vec::new()),
+            Op::Read(offset, len) => {
+                let mut buf = std::vec![0; len];
+                flash.read(offset, &mut buf).map(|()| buf)
+            }
+            Op::Erase(from, to) => flash.erase(from, to).map(|()| Vec::new()),
+        };
+        ops()
+            .into_iter()
+            .map(run)
+            .map(|r| r.map_err(|e| e.kind()))
+            .collect()
+    }
+
+    async fn run_async(flash: &mut impl nor_flash_async::NorFlash) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+        for op in ops() {
+            let outcome = match op {
+                Op::Write(offset, data) => flash.write(offset, &data).await.map(|()| Vec::new()),
+                Op::Read(offset, len) => {
+                    let mut buf = std::vec![0; len];
+                    flash.read(offset, &mut buf).await.map(|()| buf)
+                }
+                Op::Erase(from, to) => flash.erase(from, to).await.map(|()| Vec::new()),
+            };
+            outcomes.push(outcome.map_err(|e| e.kind()));
+        }
+        outcomes
+    }
+
+    #[tokio::test]
+    async fn matches_the_async_driver() {
+        let mut blocking = driver();
+        let mut facade = BlockingFacade::new(&mut blocking, Spin);
+        assert_eq!(ReadNorFlash::capacity(&facade), 0x20000);
+        let outcomes = run_blocking(&mut facade);
+
+        let mut expected = driver();
+        assert_eq!(outcomes, run_async(&mut expected).await);
+        assert_eq!(outcomes[1].as_ref().unwrap()[..4], [0, 1, 2, 3]);
+        assert_eq!(outcomes[3], Err(NorFlashErrorKind::OutOfBounds));
+        assert_eq!(outcomes[5], Err(NorFlashErrorKind::NotAligned));
+        assert_eq!(blocking.i2c.memory(), expected.i2c.memory());
+    }
+
+    #[test]
+    fn forwards_errors_unchanged() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut absent = At24Cx::new(bus, Address(1, 1), 17, SimDelay::new(Default::default()));
+        let mut facade = BlockingFacade::new(&mut absent, Spin);
+        assert!(matches!(
+            facade.read(0, &mut [0; 4]),
+            Err(Error::NoAcknowledge(_))
+        ));
+    }
+}
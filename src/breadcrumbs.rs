@@ -0,0 +1,330 @@
+//! Boot-stage breadcrumbs for finding where a unit hangs or resets.
+//!
+//! The firmware [marks](Breadcrumbs::mark) a stage as it boots, and after a reset the
+//! [previous run](Breadcrumbs::last_run) tells how far it got. Every mark and every
+//! [run delimiter](Breadcrumbs::start_new_run) is a single byte, which the device writes
+//! atomically, so a reset can't leave a half written breadcrumb behind.
+//!
+//! The region is a ring of byte cells written one after the other, so a boot loop spreads
+//! its writes over the whole region instead of wearing out one cell. The low 7 bits of a
+//! cell hold a stage up to [`MAX_STAGE`], `0x7E` for the start of a run or `0x7F` for a
+//! cell that was never written. The top bit is the lap: it flips every time the ring wraps,
+//! so the next cell to write is the first one whose lap differs from the first cell's. A
+//! blank region reads as a finished lap of unwritten cells.
+
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Highest stage that can be marked
+pub const MAX_STAGE: u8 = 0x7D;
+/// Longest region that can be used
+pub const MAX_BREADCRUMB_REGION: usize = 256;
+
+const RUN_START: u8 = 0x7E;
+const UNWRITTEN: u8 = 0x7F;
+const LAP: u8 = 0x80;
+
+/// Where the most recent run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreadcrumbReport {
+    /// Last stage the run marked, `None` if it didn't mark any
+    pub last_stage: Option<u8>,
+    /// Number of runs in a row, up to and including the most recent one, that stopped at
+    /// `last_stage`. It only counts runs still in the region, and is 0 if there are none.
+    pub consecutive: u32,
+}
+
+/// Breadcrumb ring over a region, see the [module docs](self)
+///
+/// The position in the ring is cached after the first access, so a `Breadcrumbs` must
+/// always be used with the same device and be the only writer to its region.
+pub struct Breadcrumbs {
+    region: Range<u32>,
+    /// Next cell to write and the lap bit it gets
+    head: Option<(u32, u8)>,
+}
+
+impl Breadcrumbs {
+    /// Creates breadcrumbs over `region`, which needs 2 to [`MAX_BREADCRUMB_REGION`] bytes
+    pub const fn new(region: Range<u32>) -> Self {
+        Self { region, head: None }
+    }
+
+    /// Records that the current run reached `stage`.
+    ///
+    /// Returns `InvalidArgument` if `stage` is above [`MAX_STAGE`] or the region has the
+    /// wrong size, and `OutOfBounds` if it doesn't fit on the device.
+    pub async fn mark<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        stage: u8,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if stage > MAX_STAGE {
+            return Err(Error::InvalidArgument);
+        }
+        self.push(eeprom, stage).await
+    }
+
+    /// Ends the previous run. Call it early on boot, after [`last_run`](Self::last_run).
+    pub async fn start_new_run<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        self.push(eeprom, RUN_START).await
+    }
+
+    /// Where the most recent run stopped and how many runs before it stopped there too.
+    /// A run that completed boots normally stops at the firmware's last stage.
+    pub async fn last_run<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<BreadcrumbReport, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let mut buf = [0; MAX_BREADCRUMB_REGION];
+        let cells = self.load(eeprom, &mut buf).await?;
+        let head = self.head.map_or(0, |(head, _)| head as usize);
+
+        let mut report = BreadcrumbReport {
+            last_stage: None,
+            consecutive: 0,
+        };
+        // Cells before the first run start belong to a run whose start was overwritten
+        let mut run: Option<Option<u8>> = None;
+        let mut finish = |run: Option<Option<u8>>| {
+            if let Some(last_stage) = run {
+                if report.consecutive > 0 && report.last_stage == last_stage {
+                    report.consecutive += 1;
+                } else {
+                    report = BreadcrumbReport {
+                        last_stage,
+                        consecutive: 1,
+                    };
+                }
+            }
+        };
+        for &cell in cells[head..].iter().chain(&cells[..head]) {
+            match cell & !LAP {
+                UNWRITTEN => {}
+                RUN_START => finish(run.replace(None)),
+                stage => run = Some(Some(stage)),
+            }
+        }
+        finish(run);
+        Ok(report)
+    }
+
+    async fn push<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        value: u8,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let (head, lap) = match self.head {
+            Some(head) => head,
+            None => {
+                let mut buf = [0; MAX_BREADCRUMB_REGION];
+                self.load(eeprom, &mut buf).await?;
+                self.head.unwrap_or((0, 0))
+            }
+        };
+        eeprom
+            .write(self.region.start + head, &[value | lap])
+            .await?;
+        let len = self.region.end - self.region.start;
+        self.head = Some(if head + 1 == len {
+            (0, lap ^ LAP)
+        } else {
+            (head + 1, lap)
+        });
+        Ok(())
+    }
+
+    /// Reads the region into `buf` and finds the head if it isn't cached yet
+    async fn load<'b, I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        buf: &'b mut [u8; MAX_BREADCRUMB_REGION],
+    ) -> Result<&'b [u8], Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let len = self.region.end.saturating_sub(self.region.start) as usize;
+        if !(2..=MAX_BREADCRUMB_REGION).contains(&len) {
+            return Err(Error::InvalidArgument);
+        }
+        if self.region.end as usize > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        let cells = &mut buf[..len];
+        eeprom.read(self.region.start, cells).await?;
+        if self.head.is_none() {
+            let lap = cells[0] & LAP;
+            self.head = Some(match cells.iter().position(|cell| cell & LAP != lap) {
+                Some(head) => (head as u32, lap),
+                // A finished lap, blank regions included
+                None => (0, lap ^ LAP),
+            });
+        }
+        Ok(cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: Range<u32> = 0x300..0x310;
+    const CLOCKS_OK: u8 = 1;
+    const RADIO_INIT: u8 = 2;
+    const APP_START: u8 = 3;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn reports_normal_runs() {
+        let mut eeprom = driver();
+        let mut crumbs = Breadcrumbs::new(REGION);
+        assert_eq!(
+            crumbs.last_run(&mut eeprom).await.unwrap(),
+            BreadcrumbReport {
+                last_stage: None,
+                consecutive: 0
+            }
+        );
+        for boot in 1..=2 {
+            // Every boot starts with a fresh instance, as after a reset
+            let mut crumbs = Breadcrumbs::new(REGION);
+            crumbs.start_new_run(&mut eeprom).await.unwrap();
+            for stage in [CLOCKS_OK, RADIO_INIT, APP_START] {
+                crumbs.mark(&mut eeprom, stage).await.unwrap();
+            }
+            assert_eq!(
+                Breadcrumbs::new(REGION)
+                    .last_run(&mut eeprom)
+                    .await
+                    .unwrap(),
+                BreadcrumbReport {
+                    last_stage: Some(APP_START),
+                    consecutive: boot
+                }
+            );
+        }
+        assert!(matches!(
+            crumbs.mark(&mut eeprom, RUN_START).await,
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            Breadcrumbs::new(0x300..0x301).mark(&mut eeprom, 0).await,
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[tokio::test]
+    async fn counts_watchdog_reset_loops() {
+        let mut eeprom = driver();
+        let mut crumbs = Breadcrumbs::new(REGION);
+        crumbs.start_new_run(&mut eeprom).await.unwrap();
+        crumbs.mark(&mut eeprom, CLOCKS_OK).await.unwrap();
+        crumbs.mark(&mut eeprom, APP_START).await.unwrap();
+
+        // The radio hangs from here on, long enough to wrap the ring
+        for resets in 1..=6 {
+            let mut crumbs = Breadcrumbs::new(REGION);
+            crumbs.start_new_run(&mut eeprom).await.unwrap();
+            crumbs.mark(&mut eeprom, CLOCKS_OK).await.unwrap();
+            crumbs.mark(&mut eeprom, RADIO_INIT).await.unwrap();
+            assert_eq!(
+                Breadcrumbs::new(REGION)
+                    .last_run(&mut eeprom)
+                    .await
+                    .unwrap(),
+                BreadcrumbReport {
+                    last_stage: Some(RADIO_INIT),
+                    consecutive: resets
+                }
+            );
+        }
+
+        // Dying before the first mark is a failure of its own
+        Breadcrumbs::new(REGION)
+            .start_new_run(&mut eeprom)
+            .await
+            .unwrap();
+        assert_eq!(
+            Breadcrumbs::new(REGION)
+                .last_run(&mut eeprom)
+                .await
+                .unwrap(),
+            BreadcrumbReport {
+                last_stage: None,
+                consecutive: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rotates_through_the_region() {
+        let mut eeprom = driver();
+        let len = REGION.end - REGION.start;
+        for boot in 0..len {
+            let mut crumbs = Breadcrumbs::new(REGION);
+            crumbs.start_new_run(&mut eeprom).await.unwrap();
+            crumbs.mark(&mut eeprom, (boot % 4) as u8).await.unwrap();
+            crumbs.mark(&mut eeprom, APP_START).await.unwrap();
+        }
+        // 3 laps, every cell written once per lap
+        for offset in REGION {
+            assert_eq!(eeprom.i2c.write_count(offset as usize), 3);
+        }
+        assert_eq!(eeprom.i2c.write_count(REGION.end as usize), 0);
+        // The third lap started in the middle of a run
+        assert_eq!(eeprom.i2c.memory()[REGION.start as usize], APP_START);
+        assert_eq!(
+            Breadcrumbs::new(REGION)
+                .last_run(&mut eeprom)
+                .await
+                .unwrap(),
+            BreadcrumbReport {
+                last_stage: Some(APP_START),
+                consecutive: 6
+            }
+        );
+
+        // Halfway through the next lap the ring starts after the newest cell
+        let mut crumbs = Breadcrumbs::new(REGION);
+        crumbs.start_new_run(&mut eeprom).await.unwrap();
+        crumbs.mark(&mut eeprom, RADIO_INIT).await.unwrap();
+        assert_eq!(
+            eeprom.i2c.memory()[REGION.start as usize + 1],
+            LAP | RADIO_INIT
+        );
+        assert_eq!(
+            Breadcrumbs::new(REGION)
+                .last_run(&mut eeprom)
+                .await
+                .unwrap(),
+            BreadcrumbReport {
+                last_stage: Some(RADIO_INIT),
+                consecutive: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Typed values at fixed offsets.
+//!
+//! A [`Cell`] stores the bytes of a [`Pod`] value as they are in memory, so the layout on
+//! the device follows the target's endianness and the type's `#[repr(C)]` layout. `Pod`
+//! rules out padding bytes, a struct that would need padding has to spell it out as
+//! explicit fields. Changing the type of a cell changes its layout on the device, old
+//! contents can't be loaded as the new type.
+//!
+//! A [`CheckedCell`] appends a CRC-32 to the value, so that a value that was never stored
+//! and one that was torn or corrupted can be told apart.
+//! [`load_config`](At24Cx::load_config) and [`save_config`](At24Cx::save_config) wrap one
+//! for the usual boot configuration, which falls back to defaults when there is no valid
+//! value.
+
+use crate::crc::crc32;
+use crate::{At24Cx, Error};
+use bytemuck::Pod;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// A `T` stored at a fixed offset, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell<T> {
+    offset: u32,
+    _type: PhantomData<T>,
+}
+
+impl<T: Pod> Cell<T> {
+    /// Bytes the cell takes on the device
+    pub const SIZE: usize = size_of::<T>();
+
+    const NON_ZST: () = assert!(size_of::<T>() > 0, "cells can't hold zero-sized types");
+
+    /// Creates a cell at `offset`. Fails to compile for zero-sized types.
+    pub const fn new(offset: u32) -> Self {
+        let () = Self::NON_ZST;
+        Self {
+            offset,
+            _type: PhantomData,
+        }
+    }
+
+    pub const fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Reads the value, returning `OutOfBounds` if the cell doesn't fit on the device
+    pub async fn load<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<T, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let mut value = T::zeroed();
+        eeprom
+            .read(self.offset, bytemuck::bytes_of_mut(&mut value))
+            .await?;
+        Ok(value)
+    }
+
+    /// Writes `value`, returning `OutOfBounds` if the cell doesn't fit on the device
+    pub async fn store<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+        value: &T,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        eeprom.write(self.offset, bytemuck::bytes_of(value)).await
+    }
+}
+
+/// What a [`CheckedCell`] holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checked<T> {
+    /// Every byte, including the CRC, is erased
+    Blank,
+    /// The CRC doesn't match
+    Corrupt,
+    Value(T),
+}
+
+/// A `T` followed by a little endian CRC-32 over its bytes, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckedCell<T> {
+    cell: Cell<T>,
+}
+
+impl<T: Pod> CheckedCell<T> {
+    /// Bytes the cell takes on the device
+    pub const SIZE: usize = size_of::<T>() + 4;
+
+    /// Creates a cell at `offset`. Fails to compile for zero-sized types.
+    pub const fn new(offset: u32) -> Self {
+        Self {
+            cell: Cell::new(offset),
+        }
+    }
+
+    pub const fn offset(&self) -> u32 {
+        self.cell.offset
+    }
+
+    /// Reads the value and checks its CRC.
+    /// Returns `OutOfBounds` if the cell doesn't fit on the device.
+    pub async fn load<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<Checked<T>, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let value = self.cell.load(eeprom).await?;
+        let mut crc = [0; 4];
+        eeprom
+            .read(self.offset() + size_of::<T>() as u32, &mut crc)
+            .await?;
+        let bytes = bytemuck::bytes_of(&value);
+        if crc32(bytes) == u32::from_le_bytes(crc) {
+            Ok(Checked::Value(value))
+        } else if bytes.iter().chain(&crc).all(|b| *b == 0xFF) {
+            Ok(Checked::Blank)
+        } else {
+            Ok(Checked::Corrupt)
+        }
+    }
+
+    /// Writes `value` followed by its CRC.
+    /// Returns `OutOfBounds` if the cell doesn't fit on the device.
+    pub async fn store<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+        value: &T,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let crc_offset = self.offset() + size_of::<T>() as u32;
+        if crc_offset as usize + 4 > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        self.cell.store(eeprom, value).await?;
+        let crc = crc32(bytemuck::bytes_of(value));
+        eeprom.write(crc_offset, &crc.to_le_bytes()).await
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Loads a configuration stored by [`save_config`](Self::save_config) at `offset`.
+    /// Returns `None` if it was never stored or its CRC doesn't match, so the caller can fall
+    /// back to its defaults, and `OutOfBounds` only if it doesn't fit on the device.
+    pub async fn load_config<T: Pod>(&mut self, offset: u32) -> Result<Option<T>, Error<E>> {
+        match CheckedCell::new(offset).load(self).await? {
+            Checked::Value(config) => Ok(Some(config)),
+            Checked::Blank | Checked::Corrupt => Ok(None),
+        }
+    }
+
+    /// Stores `config` at `offset` followed by its CRC, taking
+    /// [`CheckedCell::<T>::SIZE`](CheckedCell::SIZE) bytes
+    pub async fn save_config<T: Pod>(&mut self, offset: u32, config: &T) -> Result<(), Error<E>> {
+        CheckedCell::new(offset).store(self, config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use bytemuck::Zeroable;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+    struct Calibration {
+        gain: f32,
+        offset: i16,
+        channel: u8,
+        flags: u8,
+        serial: u64,
+    }
+
+    const CALIBRATION: Calibration = Calibration {
+        gain: 1.25,
+        offset: -42,
+        channel: 3,
+        flags: 0b1010,
+        serial: 0x0123_4567_89AB_CDEF,
+    };
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn round_trips() {
+        let mut eeprom = driver();
+        let cell = Cell::<Calibration>::new(0x40);
+        cell.store(&mut eeprom, &CALIBRATION).await.unwrap();
+        assert_eq!(cell.load(&mut eeprom).await.unwrap(), CALIBRATION);
+
+        // Straddles the page boundary at 0x1000
+        let cell = Cell::<Calibration>::new(0x1000 - 6);
+        cell.store(&mut eeprom, &CALIBRATION).await.unwrap();
+        assert_eq!(cell.load(&mut eeprom).await.unwrap(), CALIBRATION);
+        assert_eq!(
+            eeprom.i2c.memory()[0xFFA..0x100A],
+            *bytemuck::bytes_of(&CALIBRATION)
+        );
+
+        let cell = Cell::<u32>::new(0x1FFFE);
+        assert!(matches!(
+            cell.store(&mut eeprom, &0).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[tokio::test]
+    async fn checked_cell_tells_blank_from_corrupt() {
+        let mut eeprom = driver();
+        let cell = CheckedCell::<Calibration>::new(0x0FF8);
+        assert_eq!(CheckedCell::<Calibration>::SIZE, 20);
+        assert_eq!(cell.load(&mut eeprom).await.unwrap(), Checked::Blank);
+        cell.store(&mut eeprom, &CALIBRATION).await.unwrap();
+        assert_eq!(
+            cell.load(&mut eeprom).await.unwrap(),
+            Checked::Value(CALIBRATION)
+        );
+        eeprom.i2c.memory_mut()[0x1005] ^= 0x10;
+        assert_eq!(cell.load(&mut eeprom).await.unwrap(), Checked::Corrupt);
+
+        // The CRC wouldn't fit, so nothing is written
+        let cell = CheckedCell::<u32>::new(0x1FFFA);
+        assert!(matches!(
+            cell.store(&mut eeprom, &0).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(eeprom.i2c.memory()[0x1FFFA], 0xFF);
+    }
+
+    #[tokio::test]
+    async fn loads_config_or_falls_back() {
+        let mut eeprom = driver();
+        let defaults = Calibration::zeroed();
+        let config = eeprom.load_config::<Calibration>(0x200).await.unwrap();
+        assert_eq!(config.unwrap_or(defaults), defaults);
+
+        eeprom.save_config(0x200, &CALIBRATION).await.unwrap();
+        let config = eeprom.load_config::<Calibration>(0x200).await.unwrap();
+        assert_eq!(config, Some(CALIBRATION));
+
+        // A torn or corrupted config is no error either
+        eeprom.i2c.memory_mut()[0x210] ^= 0x01;
+        let config = eeprom.load_config::<Calibration>(0x200).await.unwrap();
+        assert_eq!(config, None);
+
+        assert!(matches!(
+            eeprom.load_config::<Calibration>(0x1FFF0).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+}
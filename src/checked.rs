@@ -0,0 +1,183 @@
+use crate::crc::{crc16, crc8};
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+/// Checksum reserved at the end of every page by
+/// [`write_checked_page`](At24Cx::write_checked_page) and
+/// [`read_checked_page`](At24Cx::read_checked_page).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PageChecksum {
+    /// No checksum, the whole page is usable (default)
+    #[default]
+    None,
+    /// Last byte of the page holds a CRC-8
+    Crc8,
+    /// Last two bytes of the page hold a CRC-16
+    Crc16,
+}
+
+impl PageChecksum {
+    /// Number of bytes reserved at the end of each page
+    pub const fn reserved_bytes(self) -> usize {
+        match self {
+            PageChecksum::None => 0,
+            PageChecksum::Crc8 => 1,
+            PageChecksum::Crc16 => 2,
+        }
+    }
+
+    fn compute(self, data: &[u8], out: &mut [u8]) {
+        match self {
+            PageChecksum::None => {}
+            PageChecksum::Crc8 => out[0] = crc8(data),
+            PageChecksum::Crc16 => out.copy_from_slice(&crc16(data).to_be_bytes()),
+        }
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Sets the checksum used by the checked page functions.
+    /// Data written with a different setting will fail to verify.
+    pub fn set_page_checksum(&mut self, checksum: PageChecksum) {
+        self.page_checksum = checksum;
+    }
+
+    /// Number of usable bytes per page for the checked page functions
+    pub fn checked_page_size(&self) -> usize {
+        self.page_size - self.page_checksum.reserved_bytes()
+    }
+
+    /// Writes a whole page with its checksum in the reserved bytes at the end.
+    /// If `data` is shorter than [`checked_page_size`](Self::checked_page_size) the rest
+    /// is padded with 0xFF. Without a configured checksum this is a plain page write.
+    pub async fn write_checked_page(&mut self, page: u32, data: &[u8]) -> Result<(), Error<E>> {
+        let usable = self.checked_page_size();
+        if data.len() > usable {
+            return Err(Error::OutOfBounds);
+        }
+        let mut buf = [0xFF; PAGE_SIZE];
+        let buf = &mut buf[..self.page_size];
+        buf[..data.len()].copy_from_slice(data);
+        let (payload, checksum) = buf.split_at_mut(usable);
+        self.page_checksum.compute(payload, checksum);
+        let address = self.checked_page_address(page)?;
+        self.page_write(address, buf).await
+    }
+
+    /// Reads the usable part of a page into `buf`, returning `CrcMismatch` if the stored
+    /// checksum doesn't match, e.g. because power was lost during the write.
+    pub async fn read_checked_page(&mut self, page: u32, buf: &mut [u8]) -> Result<(), Error<E>> {
+        let usable = self.checked_page_size();
+        if buf.len() > usable {
+            return Err(Error::OutOfBounds);
+        }
+        let address = self.checked_page_address(page)?;
+        let mut raw = [0; PAGE_SIZE];
+        let raw = &mut raw[..self.page_size];
+        self.read(address, raw).await?;
+        let (payload, stored) = raw.split_at(usable);
+        let mut expected = [0; 2];
+        let expected = &mut expected[..stored.len()];
+        self.page_checksum.compute(payload, expected);
+        if stored != expected {
+            return Err(Error::CrcMismatch);
+        }
+        buf.copy_from_slice(&payload[..buf.len()]);
+        Ok(())
+    }
+
+    fn checked_page_address(&self, page: u32) -> Result<u32, Error<E>> {
+        let address = page as usize * self.page_size;
+        if address >= self.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(address as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, DeviceKind};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver(checksum: PageChecksum) -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_page_checksum(checksum);
+        eeprom
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        for checksum in [PageChecksum::None, PageChecksum::Crc8, PageChecksum::Crc16] {
+            let mut eeprom = driver(checksum);
+            let usable = eeprom.checked_page_size();
+            assert_eq!(usable, PAGE_SIZE - checksum.reserved_bytes());
+            let data: [u8; PAGE_SIZE] = core::array::from_fn(|i| i as u8);
+            eeprom.write_checked_page(3, &data[..usable]).await.unwrap();
+            let mut buf = [0; PAGE_SIZE];
+            eeprom
+                .read_checked_page(3, &mut buf[..usable])
+                .await
+                .unwrap();
+            assert_eq!(buf[..usable], data[..usable]);
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_torn_write() {
+        let mut eeprom = driver(PageChecksum::Crc16);
+        eeprom.write_checked_page(1, &[0x42; 100]).await.unwrap();
+        let mut buf = [0; 100];
+        eeprom.read_checked_page(1, &mut buf).await.unwrap();
+        assert_eq!(buf, [0x42; 100]);
+        // Only the first half of the page made it
+        eeprom.i2c.memory_mut()[PAGE_SIZE + 128..2 * PAGE_SIZE].fill(0xFF);
+        let result = eeprom.read_checked_page(1, &mut buf).await;
+        assert!(matches!(result, Err(Error::CrcMismatch)));
+    }
+
+    #[tokio::test]
+    async fn rejects_data_in_reserved_bytes() {
+        let mut eeprom = driver(PageChecksum::Crc8);
+        let result = eeprom.write_checked_page(0, &[0; PAGE_SIZE]).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+        let result = eeprom.write_checked_page(512, &[0; 8]).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+    }
+
+    #[tokio::test]
+    async fn small_pages() {
+        let address = Address::pins(1, 1, 1);
+        let bus = SimBus::new(address, 12).with_page_size(32);
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+        eeprom.set_page_checksum(PageChecksum::Crc16);
+        assert_eq!(eeprom.checked_page_size(), 30);
+        eeprom.write_checked_page(2, &[0x42; 30]).await.unwrap();
+        let mut buf = [0; 30];
+        eeprom.read_checked_page(2, &mut buf).await.unwrap();
+        assert_eq!(buf, [0x42; 30]);
+        // The neighbouring pages are untouched
+        let memory = eeprom.i2c.memory();
+        assert_eq!(memory[0x40..0x5E], [0x42; 30]);
+        assert!(memory[..0x40]
+            .iter()
+            .chain(&memory[0x60..0x80])
+            .all(|&b| b == 0xFF));
+        assert!(matches!(
+            eeprom.write_checked_page(2, &[0; 31]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.write_checked_page(128, &[0; 8]).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,799 @@
+//! The `at24cx` command line tool, for dumping, flashing and checking devices on a Linux
+//! I2C bus.
+//!
+//! ```text
+//! at24cx [--bus PATH] [--address ADDR] [--device PART] COMMAND
+//! ```
+//!
+//! The bus defaults to `/dev/i2c-1`, the address to `0x50` and the part to `at24cm01`.
+//! Numbers are decimal or `0x` hex, ranges `start..end`. See [`USAGE`] for the commands.
+//!
+//! Progress bars are only drawn when standard error is a terminal. The exit code tells
+//! failures apart, see [`Failure::exit_code`]. The commands run over any bus through
+//! [`execute`], which is how they are tested against the simulator.
+
+use crate::linux::{LinuxI2c, StdDelay};
+use crate::{
+    bus_error, probe as probe_address, Address, At24Cx, DeviceAddress, DeviceKind, Error,
+    PageOverwriteToken, ProgramError, ProgramOptions, ProgramReport, PAGE_SIZE,
+};
+use core::fmt::{self, Debug};
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+use std::borrow::ToOwned;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::string::{String, ToString};
+use std::vec::Vec;
+use std::{eprint, eprintln, format, write, writeln};
+
+/// Printed after a usage error
+pub const USAGE: &str = "\
+usage: at24cx [--bus PATH] [--address ADDR] [--device PART] COMMAND
+
+commands:
+  dump --out FILE [--range A..B]            save the range, the whole device by default
+  flash --in FILE [--offset A] [--verify]   program the file, reading it back with --verify
+  hexdump [--range A..B]                    print the range, the whole device by default
+  scan [--addresses A,B,...]                list the addresses that acknowledge, of the
+                                            EEPROM addresses 0x50..0x57 by default
+  probe [--page-size-at A]                  check that the device answers, with
+                                            --page-size-at also overwrite the page at A
+                                            to check the part's page size
+";
+
+/// What the tool was asked to do
+pub struct Options {
+    pub bus: PathBuf,
+    pub address: DeviceAddress,
+    pub device: DeviceKind,
+    pub command: Command,
+}
+
+/// A command and its arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Dump {
+        out: PathBuf,
+        range: Option<Range<u32>>,
+    },
+    Flash {
+        input: PathBuf,
+        offset: u32,
+        verify: bool,
+    },
+    Hexdump {
+        range: Option<Range<u32>>,
+    },
+    Scan {
+        addresses: Option<Vec<u8>>,
+    },
+    Probe {
+        page_size_at: Option<u32>,
+    },
+}
+
+/// Why the tool failed
+#[derive(Debug)]
+pub enum Failure {
+    /// The arguments don't make sense
+    Usage(String),
+    /// The bus or the device failed
+    Device(String),
+    /// The device doesn't hold what was written, or has pages of another size than the part
+    Verify(String),
+    /// Reading or writing a file or the output failed
+    Io(io::Error),
+}
+
+impl Failure {
+    /// 1 for the bus or the device, 2 for the arguments, 3 for a verify failure and 4 for a
+    /// file
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Device(_) => 1,
+            Failure::Usage(_) => 2,
+            Failure::Verify(_) => 3,
+            Failure::Io(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Usage(message) => write!(f, "{message}"),
+            Failure::Device(message) => write!(f, "device error: {message}"),
+            Failure::Verify(message) => write!(f, "verify failed: {message}"),
+            Failure::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+fn usage(message: impl Into<String>) -> Failure {
+    Failure::Usage(message.into())
+}
+
+fn device<E: Debug>(error: Error<E>) -> Failure {
+    Failure::Device(format!("{error:?}"))
+}
+
+/// Runs the tool with the arguments after the program name
+pub fn run(args: impl IntoIterator<Item = String>) -> ExitCode {
+    match parse(args).and_then(|options| run_on_linux(&options)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("at24cx: {failure}");
+            if let Failure::Usage(_) = failure {
+                eprint!("\n{USAGE}");
+            }
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+fn run_on_linux(options: &Options) -> Result<(), Failure> {
+    let i2c = LinuxI2c::open(&options.bus)
+        .map_err(|e| Failure::Device(format!("{}: {:?}", options.bus.display(), e.0)))?;
+    let mut eeprom = At24Cx::with_device(i2c, options.address, options.device, StdDelay)
+        .map_err(|e| usage(format!("{:?} isn't supported: {e:?}", options.device)))?;
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    let progress = io::stderr().is_terminal();
+    runtime.block_on(execute(
+        &mut eeprom,
+        options.device,
+        &options.command,
+        &mut io::stdout().lock(),
+        progress,
+    ))
+}
+
+/// Parses the arguments after the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, Failure> {
+    let mut args = args.into_iter();
+    let mut bus = PathBuf::from("/dev/i2c-1");
+    let mut address = Address(0, 0).into();
+    let mut device = DeviceKind::At24cm01;
+    let mut command = None;
+    let (mut out, mut input, mut range, mut offset) = (None, None, None, None);
+    let (mut verify, mut page_size_at, mut addresses) = (false, None, None);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| usage(format!("{arg} needs a value")))
+        };
+        match arg.as_str() {
+            "--bus" => bus = value()?.into(),
+            "--address" => address = parse_address(&value()?)?,
+            "--device" => device = parse_device(&value()?)?,
+            "--out" => out = Some(PathBuf::from(value()?)),
+            "--in" => input = Some(PathBuf::from(value()?)),
+            "--range" => range = Some(parse_range(&value()?)?),
+            "--offset" => offset = Some(parse_number(&value()?)?),
+            "--page-size-at" => page_size_at = Some(parse_number(&value()?)?),
+            "--addresses" => addresses = Some(parse_addresses(&value()?)?),
+            "--verify" => verify = true,
+            flag if flag.starts_with("--") => return Err(usage(format!("unknown option {flag}"))),
+            name if command.is_none() => command = Some(name.to_owned()),
+            name => return Err(usage(format!("unexpected argument {name}"))),
+        }
+    }
+
+    let name = command.ok_or_else(|| usage("no command given"))?;
+    let command = match name.as_str() {
+        "dump" => Command::Dump {
+            out: out.take().ok_or_else(|| usage("dump needs --out"))?,
+            range: range.take(),
+        },
+        "flash" => Command::Flash {
+            input: input.take().ok_or_else(|| usage("flash needs --in"))?,
+            offset: offset.take().unwrap_or(0),
+            verify: core::mem::take(&mut verify),
+        },
+        "hexdump" => Command::Hexdump {
+            range: range.take(),
+        },
+        "scan" => Command::Scan {
+            addresses: addresses.take(),
+        },
+        "probe" => Command::Probe {
+            page_size_at: page_size_at.take(),
+        },
+        _ => return Err(usage(format!("unknown command {name}"))),
+    };
+    // Whatever wasn't taken by the command doesn't apply to it
+    let unused = [
+        ("--out", out.is_some()),
+        ("--in", input.is_some()),
+        ("--range", range.is_some()),
+        ("--offset", offset.is_some()),
+        ("--verify", verify),
+        ("--page-size-at", page_size_at.is_some()),
+        ("--addresses", addresses.is_some()),
+    ];
+    if let Some((flag, _)) = unused.iter().find(|(_, set)| *set) {
+        return Err(usage(format!("{flag} doesn't apply to {name}")));
+    }
+    Ok(Options {
+        bus,
+        address,
+        device,
+        command,
+    })
+}
+
+fn parse_number(text: &str) -> Result<u32, Failure> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|_| usage(format!("{text} isn't a number")))
+}
+
+fn parse_range(text: &str) -> Result<Range<u32>, Failure> {
+    let (start, end) = text
+        .split_once("..")
+        .ok_or_else(|| usage(format!("{text} isn't a range like 0x100..0x200")))?;
+    let range = parse_number(start)?..parse_number(end)?;
+    if range.start > range.end {
+        return Err(usage(format!("{text} ends before it starts")));
+    }
+    Ok(range)
+}
+
+/// The device address with the A1 and A2 pins, P0 is set by the driver
+fn parse_address(text: &str) -> Result<DeviceAddress, Failure> {
+    let address = parse_number(text)?;
+    if address & !0b110 != 0x50 {
+        return Err(usage(format!(
+            "{text} isn't one of the addresses 0x50, 0x52, 0x54 and 0x56"
+        )));
+    }
+    Ok(Address((address >> 1) as u8 & 1, (address >> 2) as u8 & 1).into())
+}
+
+/// A comma separated list of 7-bit addresses to scan
+fn parse_addresses(text: &str) -> Result<Vec<u8>, Failure> {
+    text.split(',')
+        .map(|address| {
+            u8::try_from(parse_number(address)?)
+                .ok()
+                .and_then(Address::raw)
+                .map(u8::from)
+                .ok_or_else(|| usage(format!("{address} isn't a 7-bit device address")))
+        })
+        .collect()
+}
+
+fn parse_device(text: &str) -> Result<DeviceKind, Failure> {
+    DeviceKind::ALL
+        .into_iter()
+        .find(|kind| format!("{kind:?}").eq_ignore_ascii_case(text))
+        .ok_or_else(|| usage(format!("unknown part {text}")))
+}
+
+/// A progress bar on standard error
+struct Progress {
+    label: &'static str,
+    visible: bool,
+}
+
+impl Progress {
+    const WIDTH: u64 = 40;
+
+    fn update(&self, done: u32, total: u32) {
+        if !self.visible {
+            return;
+        }
+        let filled = (done as u64 * Self::WIDTH / total.max(1) as u64) as usize;
+        let percent = done as u64 * 100 / total.max(1) as u64;
+        eprint!(
+            "\r{:<8}[{}{}] {percent:>3}%",
+            self.label,
+            "#".repeat(filled),
+            " ".repeat(Self::WIDTH as usize - filled)
+        );
+        if done == total {
+            eprintln!();
+        }
+    }
+}
+
+/// Runs `command` on a `kind` part, printing its results to `out`. Progress bars are drawn
+/// if `progress` is set.
+pub async fn execute<I2C, E: Debug, D: DelayNs>(
+    eeprom: &mut At24Cx<I2C, D>,
+    kind: DeviceKind,
+    command: &Command,
+    out: &mut impl Write,
+    progress: bool,
+) -> Result<(), Failure>
+where
+    I2C: I2c<Error = E>,
+{
+    let whole = 0..eeprom.capacity() as u32;
+    let check = |range: &Option<Range<u32>>| match range {
+        Some(range) if range.end > whole.end => Err(usage(format!(
+            "{range:x?} doesn't fit on the {} byte device",
+            whole.end
+        ))),
+        Some(range) => Ok(range.clone()),
+        None => Ok(whole.clone()),
+    };
+    match command {
+        Command::Dump { out: path, range } => {
+            let range = check(range)?;
+            let mut file = BufWriter::new(File::create(path)?);
+            let progress = Progress {
+                label: "dump",
+                visible: progress,
+            };
+            dump(eeprom, range, &mut file, &progress).await?;
+            Ok(file.flush()?)
+        }
+        Command::Flash {
+            input,
+            offset,
+            verify,
+        } => {
+            let image = fs::read(input)?;
+            let progress = Progress {
+                label: "flash",
+                visible: progress,
+            };
+            let report = flash(eeprom, *offset, &image, *verify, &progress).await?;
+            writeln!(
+                out,
+                "{} pages written, {} already equal, {} retried, CRC-32 {:08x}",
+                report.pages_written, report.pages_skipped, report.pages_retried, report.crc
+            )?;
+            Ok(())
+        }
+        Command::Hexdump { range } => hexdump(eeprom, check(range)?, out).await,
+        Command::Scan { addresses } => {
+            let eeprom_addresses: Vec<u8> = (0x50..=0x57).collect();
+            let addresses = addresses.as_deref().unwrap_or(&eeprom_addresses);
+            for address in scan(eeprom, addresses).await? {
+                writeln!(out, "0x{address:02x}")?;
+            }
+            Ok(())
+        }
+        Command::Probe { page_size_at } => probe(eeprom, kind, *page_size_at, out).await,
+    }
+}
+
+/// Copies `range` to `out` a page at a time
+async fn dump<I2C, E: Debug, D: DelayNs>(
+    eeprom: &mut At24Cx<I2C, D>,
+    range: Range<u32>,
+    out: &mut impl Write,
+    progress: &Progress,
+) -> Result<(), Failure>
+where
+    I2C: I2c<Error = E>,
+{
+    let mut buf = [0; PAGE_SIZE];
+    let mut address = range.start;
+    progress.update(0, range.end - range.start);
+    while address < range.end {
+        let len = (range.end - address).min(PAGE_SIZE as u32) as usize;
+        let chunk = &mut buf[..len];
+        eeprom.read(address, chunk).await.map_err(device)?;
+        out.write_all(chunk)?;
+        address += len as u32;
+        progress.update(address - range.start, range.end - range.start);
+    }
+    Ok(())
+}
+
+/// Programs `image` at `offset`, verifying pages and the CRC if `verify` is set
+async fn flash<I2C, E: Debug, D: DelayNs>(
+    eeprom: &mut At24Cx<I2C, D>,
+    offset: u32,
+    image: &[u8],
+    verify: bool,
+    progress: &Progress,
+) -> Result<ProgramReport, Failure>
+where
+    I2C: I2c<Error = E>,
+{
+    eeprom.can_write(offset, image.len()).map_err(|_| {
+        usage(format!(
+            "{} bytes at 0x{offset:x} don't fit on the {} byte device",
+            image.len(),
+            eeprom.capacity()
+        ))
+    })?;
+    let opts = ProgramOptions {
+        verify,
+        final_crc: verify,
+        ..Default::default()
+    };
+    eeprom
+        .program_image(offset, image, opts, |p| progress.update(p.done, p.total))
+        .await
+        .map_err(|e| match e {
+            ProgramError::Eeprom { offset, error } => {
+                Failure::Device(format!("at 0x{offset:x}: {error:?}"))
+            }
+            ProgramError::Verify { offset } => {
+                Failure::Verify(format!("the byte at 0x{offset:x} doesn't read back"))
+            }
+            ProgramError::Crc { expected, actual } => Failure::Verify(format!(
+                "the CRC-32 is {actual:08x} instead of {expected:08x}"
+            )),
+        })
+}
+
+/// Prints `range` 16 bytes a line, reading it a page at a time
+async fn hexdump<I2C, E: Debug, D: DelayNs>(
+    eeprom: &mut At24Cx<I2C, D>,
+    range: Range<u32>,
+    out: &mut impl Write,
+) -> Result<(), Failure>
+where
+    I2C: I2c<Error = E>,
+{
+    let mut buf = [0; PAGE_SIZE];
+    let mut address = range.start;
+    while address < range.end {
+        let len = (range.end - address).min(PAGE_SIZE as u32) as usize;
+        let chunk = &mut buf[..len];
+        eeprom.read(address, chunk).await.map_err(device)?;
+        for line in chunk.chunks(16) {
+            write!(out, "{address:08x} ")?;
+            for byte in line {
+                write!(out, " {byte:02x}")?;
+            }
+            let text: String = line
+                .iter()
+                .map(|&b| match b {
+                    b' '..=b'~' => b as char,
+                    _ => '.',
+                })
+                .collect();
+            writeln!(
+                out,
+                "{:width$}  |{text}|",
+                "",
+                width = 3 * (16 - line.len())
+            )?;
+            address += line.len() as u32;
+        }
+    }
+    Ok(())
+}
+
+/// The `addresses` that acknowledge, probed like the driver ACK polls
+async fn scan<I2C, E: Debug, D: DelayNs>(
+    eeprom: &mut At24Cx<I2C, D>,
+    addresses: &[u8],
+) -> Result<Vec<u8>, Failure>
+where
+    I2C: I2c<Error = E>,
+{
+    let method = eeprom.ack_probe();
+    let mut found = Vec::new();
+    for &address in addresses {
+        let acknowledged = probe_address(&mut eeprom.i2c, address, method)
+            .await
+            .map_err(|e| device(bus_error::<I2C>(e)))?;
+        if acknowledged {
+            found.push(address);
+        }
+    }
+    Ok(found)
+}
+
+/// Checks that the device answers, and with `page_size_at` that it has the pages of `kind`
+async fn probe<I2C, E: Debug, D: DelayNs>(
+    eeprom: &mut At24Cx<I2C, D>,
+    kind: DeviceKind,
+    page_size_at: Option<u32>,
+    out: &mut impl Write,
+) -> Result<(), Failure>
+where
+    I2C: I2c<Error = E>,
+{
+    if !eeprom.is_ready().await.map_err(device)? {
+        return Err(Failure::Device(
+            "the device doesn't acknowledge".to_string(),
+        ));
+    }
+    writeln!(
+        out,
+        "{kind:?} answers: {} bytes in pages of {}",
+        kind.capacity(),
+        kind.page_size()
+    )?;
+    if let Some(offset) = page_size_at {
+        let token = PageOverwriteToken::i_accept_losing_the_page_contents();
+        let size = eeprom
+            .probe_page_size(offset, &token)
+            .await
+            .map_err(device)?;
+        writeln!(out, "probed page size: {size}")?;
+        if size != kind.page_size() {
+            return Err(Failure::Verify(format!(
+                "the part has pages of {size} bytes, not {}",
+                kind.page_size()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBus;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    fn parse_command(line: &str) -> Result<Command, Failure> {
+        parse(args(line)).map(|options| options.command)
+    }
+
+    /// A file in the temporary directory, deleted when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let file = format!("at24cx-cli-{}-{name}", std::process::id());
+            Self(std::env::temp_dir().join(file))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn driver(bus: SimBus) -> At24Cx<SimBus, NoopDelay> {
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn execute_line(
+        eeprom: &mut At24Cx<SimBus, NoopDelay>,
+        line: &str,
+    ) -> Result<String, Failure> {
+        let options = parse(args(line))?;
+        let mut out = Vec::new();
+        execute(eeprom, options.device, &options.command, &mut out, false).await?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let options = parse(args(
+            "--bus /dev/i2c-7 flash --in image.bin --device AT24CM01 --address 0x56 \
+             --offset 0x100 --verify",
+        ))
+        .unwrap();
+        assert_eq!(options.bus, PathBuf::from("/dev/i2c-7"));
+        assert_eq!(u8::from(options.address), 0x56);
+        assert_eq!(options.device, DeviceKind::At24cm01);
+        assert_eq!(
+            options.command,
+            Command::Flash {
+                input: "image.bin".into(),
+                offset: 0x100,
+                verify: true
+            }
+        );
+
+        let options = parse(args("dump --out x --range 16..0x20")).unwrap();
+        assert_eq!(options.bus, PathBuf::from("/dev/i2c-1"));
+        assert_eq!(u8::from(options.address), 0x50);
+        assert_eq!(
+            options.command,
+            Command::Dump {
+                out: "x".into(),
+                range: Some(0x10..0x20)
+            }
+        );
+        assert_eq!(
+            parse_command("hexdump").unwrap(),
+            Command::Hexdump { range: None }
+        );
+        assert_eq!(
+            parse_command("scan").unwrap(),
+            Command::Scan { addresses: None }
+        );
+        assert_eq!(
+            parse_command("scan --addresses 0x1C,0x1d,80").unwrap(),
+            Command::Scan {
+                addresses: Some(std::vec![0x1C, 0x1D, 0x50])
+            }
+        );
+        assert_eq!(
+            parse_command("probe --page-size-at 0x1FF00").unwrap(),
+            Command::Probe {
+                page_size_at: Some(0x1FF00)
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for line in [
+            "",
+            "erase",
+            "dump",
+            "flash --verify",
+            "dump --out x --verify",
+            "scan --range 0..1",
+            "hexdump --range 0x20..0x10",
+            "hexdump --range 0x20",
+            "hexdump --range 0..zz",
+            "scan --address 0x51",
+            "scan --address 0x58",
+            "scan --addresses 0x78",
+            "scan --addresses 0x1C,",
+            "dump --out x --addresses 0x50",
+            "scan --device at24c99",
+            "scan --bus",
+            "scan --frobnicate",
+            "scan scan",
+        ] {
+            let failure = parse_command(line).err().unwrap();
+            assert!(matches!(failure, Failure::Usage(_)), "{line}: {failure:?}");
+            assert_eq!(failure.exit_code(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn flashes_and_dumps_through_files() {
+        let device = TempFile::new("device.bin");
+        let input = TempFile::new("flash.bin");
+        let output = TempFile::new("dump.bin");
+        let image: Vec<u8> = (0..0x345).map(|i| (i * 13) as u8).collect();
+        fs::write(&input.0, &image).unwrap();
+
+        let (bus, _) = SimBus::open_file(&device.0, Address(0, 0), 17).unwrap();
+        let mut eeprom = driver(bus);
+        let line = format!("flash --in {} --offset 0xFFF0 --verify", input.0.display());
+        let report = execute_line(&mut eeprom, &line).await.unwrap();
+        assert_eq!(
+            report,
+            format!(
+                "5 pages written, 0 already equal, 0 retried, CRC-32 {:08x}\n",
+                crate::crc::crc32(&image)
+            )
+        );
+        drop(eeprom);
+
+        // After a reboot of the simulated device
+        let (bus, _) = SimBus::open_file(&device.0, Address(0, 0), 17).unwrap();
+        let mut eeprom = driver(bus);
+        let line = format!("dump --out {} --range 0xFFF0..0x10335", output.0.display());
+        execute_line(&mut eeprom, &line).await.unwrap();
+        assert_eq!(fs::read(&output.0).unwrap(), image);
+
+        let line = format!("dump --out {}", output.0.display());
+        execute_line(&mut eeprom, &line).await.unwrap();
+        assert_eq!(fs::read(&output.0).unwrap(), fs::read(&device.0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn checks_ranges_against_the_device() {
+        let mut eeprom = driver(SimBus::new(Address(0, 0), 17));
+        let input = TempFile::new("large.bin");
+        fs::write(&input.0, vec![0; 0x20]).unwrap();
+        let line = format!("flash --in {} --offset 0x1FFF0", input.0.display());
+        for line in [line.as_str(), "hexdump --range 0x1FFF0..0x20001"] {
+            let failure = execute_line(&mut eeprom, line).await.err().unwrap();
+            assert!(matches!(failure, Failure::Usage(_)), "{line}: {failure:?}");
+        }
+        let failure = execute_line(&mut eeprom, "flash --in /nonexistent/image.bin")
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(failure, Failure::Io(_)));
+        assert_eq!(failure.exit_code(), 4);
+        assert!(eeprom.i2c.memory().iter().all(|b| *b == 0xFF));
+    }
+
+    #[tokio::test]
+    async fn verify_and_bus_failures_exit_differently() {
+        let input = TempFile::new("verify.bin");
+        fs::write(&input.0, [0x55; 0x40]).unwrap();
+        let line = format!("flash --in {} --offset 0x200 --verify", input.0.display());
+
+        let bus = SimBus::builder(Address(0, 0), 17)
+            .bit_error(0x210, 0x04)
+            .persistent()
+            .build();
+        let failure = execute_line(&mut driver(bus), &line).await.err().unwrap();
+        assert!(matches!(failure, Failure::Verify(_)), "{failure:?}");
+        assert_eq!(failure.exit_code(), 3);
+
+        // Without --verify the bit error goes unnoticed
+        let bus = SimBus::builder(Address(0, 0), 17)
+            .bit_error(0x210, 0x04)
+            .persistent()
+            .build();
+        let line = format!("flash --in {} --offset 0x200", input.0.display());
+        execute_line(&mut driver(bus), &line).await.unwrap();
+
+        let bus = SimBus::builder(Address(0, 0), 17)
+            .absent(0, usize::MAX)
+            .build();
+        let failure = execute_line(&mut driver(bus), &line).await.err().unwrap();
+        assert!(matches!(failure, Failure::Device(_)), "{failure:?}");
+        assert_eq!(failure.exit_code(), 1);
+    }
+
+    #[tokio::test]
+    async fn hexdumps_a_range() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        bus.memory_mut()[0x1FFE8..0x1FFF5].copy_from_slice(b"at24cx\x00\x01 test");
+        let mut eeprom = driver(bus);
+        let text = execute_line(&mut eeprom, "hexdump --range 0x1FFE8..0x1FFFA")
+            .await
+            .unwrap();
+        assert_eq!(
+            text,
+            "0001ffe8  61 74 32 34 63 78 00 01 20 74 65 73 74 ff ff ff  |at24cx.. test...|\n\
+             0001fff8  ff ff                                            |..|\n"
+        );
+        assert_eq!(
+            execute_line(&mut eeprom, "hexdump --range 0..0")
+                .await
+                .unwrap(),
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn scans_and_probes() {
+        let mut eeprom = driver(SimBus::new(Address(0, 0), 17));
+        // One AT24CM01 answers at two addresses, one for each half of the array
+        assert_eq!(
+            execute_line(&mut eeprom, "scan").await.unwrap(),
+            "0x50\n0x51\n"
+        );
+        // Behind an address translator
+        let bus = SimBus::new(Address::raw(0x1C).unwrap(), 17);
+        assert_eq!(
+            execute_line(&mut driver(bus), "scan --addresses 0x1C,0x1D,0x1E,0x50")
+                .await
+                .unwrap(),
+            "0x1c\n0x1d\n"
+        );
+        assert_eq!(
+            execute_line(&mut eeprom, "probe").await.unwrap(),
+            "At24cm01 answers: 131072 bytes in pages of 256\n"
+        );
+        let text = execute_line(&mut eeprom, "probe --page-size-at 0x1FF00")
+            .await
+            .unwrap();
+        assert!(text.ends_with("probed page size: 256\n"), "{text}");
+
+        // A smaller part sold as an AT24CM01
+        let bus = SimBus::new(Address(0, 0), 17).with_page_size(64);
+        let failure = execute_line(&mut driver(bus), "probe --page-size-at 0")
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(failure, Failure::Verify(_)), "{failure:?}");
+
+        let bus = SimBus::new(Address(1, 1), 17);
+        let failure = execute_line(&mut driver(bus), "probe").await.err().unwrap();
+        assert!(matches!(failure, Failure::Device(_)), "{failure:?}");
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// What [`clone_to`](At24Cx::clone_to) does when the devices differ in size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CapacityPolicy {
+    /// Fail with `CapacityMismatch` before copying anything
+    #[default]
+    RequireEqual,
+    /// Copy as many bytes as the smaller device holds
+    CopySmaller,
+}
+
+/// How far a [`clone_to`](At24Cx::clone_to) got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloneProgress {
+    /// Bytes written to the destination so far
+    pub copied: u32,
+    /// Bytes that will be copied in total
+    pub total: u32,
+}
+
+/// Why a [`clone_to`](At24Cx::clone_to) stopped
+#[derive(Debug)]
+pub enum CloneError<SE: Debug, DE: Debug> {
+    /// Reading the chunk at `offset` from the source failed
+    Source { offset: u32, error: Error<SE> },
+    /// Writing the chunk at `offset` to the destination, or reading it back, failed
+    Destination { offset: u32, error: Error<DE> },
+    /// The devices differ in size and the policy is `RequireEqual`
+    CapacityMismatch { source: usize, destination: usize },
+    /// The destination byte at `offset` didn't read back as written
+    Verify { offset: u32 },
+    /// The scratch buffer is empty, or has a single byte while verifying
+    ScratchTooSmall,
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Copies this device to `dest` in chunks of `scratch`. With `verify` every chunk is read
+    /// back from `dest`, which takes half of `scratch`. `progress` is called after every
+    /// chunk.
+    pub async fn clone_to<I2C2, E2: Debug, D2: DelayNs>(
+        &mut self,
+        dest: &mut At24Cx<I2C2, D2>,
+        scratch: &mut [u8],
+        verify: bool,
+        policy: CapacityPolicy,
+        mut progress: impl FnMut(CloneProgress),
+    ) -> Result<(), CloneError<E, E2>>
+    where
+        I2C2: I2c<Error = E2>,
+    {
+        let (source, destination) = (self.capacity(), dest.capacity());
+        if source != destination && policy == CapacityPolicy::RequireEqual {
+            return Err(CloneError::CapacityMismatch {
+                source,
+                destination,
+            });
+        }
+        let (buf, readback) = if verify {
+            let half = scratch.len() / 2;
+            scratch.split_at_mut(half)
+        } else {
+            (scratch, &mut [][..])
+        };
+        if buf.is_empty() {
+            return Err(CloneError::ScratchTooSmall);
+        }
+
+        let total = source.min(destination) as u32;
+        let mut offset = 0;
+        while offset < total {
+            let len = buf.len().min((total - offset) as usize);
+            let chunk = &mut buf[..len];
+            self.read(offset, chunk)
+                .await
+                .map_err(|error| CloneError::Source { offset, error })?;
+            dest.write(offset, chunk)
+                .await
+                .map_err(|error| CloneError::Destination { offset, error })?;
+            if verify {
+                let readback = &mut readback[..len];
+                dest.read(offset, readback)
+                    .await
+                    .map_err(|error| CloneError::Destination { offset, error })?;
+                if let Some(i) = readback.iter().zip(chunk.iter()).position(|(a, b)| a != b) {
+                    return Err(CloneError::Verify {
+                        offset: offset + i as u32,
+                    });
+                }
+            }
+            offset += len as u32;
+            progress(CloneProgress {
+                copied: offset,
+                total,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver(address: Address, address_bits: usize) -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(address.0, address.1), address_bits);
+        At24Cx::new(bus, address, address_bits, NoopDelay::new())
+    }
+
+    fn donor() -> At24Cx<SimBus, NoopDelay> {
+        let mut eeprom = driver(Address(0, 0), 17);
+        for (i, byte) in eeprom.i2c.memory_mut().iter_mut().enumerate() {
+            *byte = (i * 13 % 256) as u8;
+        }
+        eeprom
+    }
+
+    #[tokio::test]
+    async fn clones_byte_exact() {
+        let mut source = donor();
+        let mut dest = driver(Address(1, 0), 17);
+        let mut scratch = [0; 600];
+        let mut last = None;
+        source
+            .clone_to(
+                &mut dest,
+                &mut scratch,
+                true,
+                CapacityPolicy::default(),
+                |p| last = Some(p),
+            )
+            .await
+            .unwrap();
+        assert_eq!(dest.i2c.memory(), source.i2c.memory());
+        assert_eq!(
+            last,
+            Some(CloneProgress {
+                copied: 131072,
+                total: 131072
+            })
+        );
+
+        assert!(matches!(
+            source
+                .clone_to(&mut dest, &mut [0], true, CapacityPolicy::default(), |_| {})
+                .await,
+            Err(CloneError::ScratchTooSmall)
+        ));
+    }
+
+    #[tokio::test]
+    async fn applies_the_capacity_policy() {
+        let mut source = donor();
+        let mut dest = driver(Address(0, 0), 16);
+        let mut scratch = [0; 256];
+        assert!(matches!(
+            source
+                .clone_to(
+                    &mut dest,
+                    &mut scratch,
+                    false,
+                    CapacityPolicy::RequireEqual,
+                    |_| {}
+                )
+                .await,
+            Err(CloneError::CapacityMismatch {
+                source: 131072,
+                destination: 65536
+            })
+        ));
+        assert_eq!(dest.i2c.write_count(0), 0);
+
+        source
+            .clone_to(
+                &mut dest,
+                &mut scratch,
+                false,
+                CapacityPolicy::CopySmaller,
+                |_| {},
+            )
+            .await
+            .unwrap();
+        assert_eq!(dest.i2c.memory(), &source.i2c.memory()[..65536]);
+    }
+
+    #[tokio::test]
+    async fn says_where_the_destination_failed() {
+        let mut source = donor();
+        let bus = SimBus::new(Address(0, 0), 17).with_endurance(1);
+        let mut dest = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        // Worn out and stuck at 0xFF
+        dest.i2c.set_write_count(0x1234, 1);
+        let mut scratch = [0; 512];
+        assert!(matches!(
+            source
+                .clone_to(
+                    &mut dest,
+                    &mut scratch,
+                    true,
+                    CapacityPolicy::default(),
+                    |_| {}
+                )
+                .await,
+            Err(CloneError::Verify { offset: 0x1234 })
+        ));
+
+        let mut dest = driver(Address(0, 0), 17);
+        // In the middle of the second chunk
+        dest.i2c.cut_power_after(1000);
+        assert!(matches!(
+            source
+                .clone_to(
+                    &mut dest,
+                    &mut scratch,
+                    false,
+                    CapacityPolicy::default(),
+                    |_| {}
+                )
+                .await,
+            Err(CloneError::Destination { offset: 512, .. })
+        ));
+    }
+}
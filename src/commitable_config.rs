@@ -0,0 +1,541 @@
+//! Two-phase configuration commits that fall back after a reboot unless confirmed.
+//!
+//! A [`CommitableConfig`] takes the [`CONFIG_REGION_SIZE`] bytes starting at its offset: a
+//! state area followed by two slots, each [`PAGE_SIZE`] bytes. Each slot holds a configuration as a
+//! [signed block](crate::signature) with magic `CCFG`. One slot holds the active
+//! configuration and the other a candidate, which goes through these states:
+//!
+//! - [`Idle`](ConfigState::Idle): there is no candidate and the active configuration is
+//!   loaded
+//! - [`Staged`](ConfigState::Staged): [`stage`](CommitableConfig::stage) wrote a candidate.
+//!   The next [`load_active`](CommitableConfig::load_active) returns it and moves on to
+//!   `Trial`
+//! - [`Trial`](ConfigState::Trial): the candidate is running. [`commit`](CommitableConfig::commit)
+//!   makes it the active configuration. Without a commit, the next `load_active` falls back
+//!   to the active configuration and goes back to `Idle`
+//! - [`Committed`](ConfigState::Committed): like `Idle`, after the last candidate was
+//!   committed
+//!
+//! The state is a signed block with magic `CCST` in one of two places 32 bytes apart in the
+//! state area, and the one with the newer sequence number counts. Every transition is a
+//! single write cycle to the other place, so a power loss in the middle of it leaves the
+//! previous state. That needs [pages](At24Cx::set_page_size) of at least 32 bytes, which
+//! every supported part has, the configuration returns `InvalidArgument` otherwise. On parts
+//! with smaller pages than [`PAGE_SIZE`] a slot takes several write cycles, a torn one fails
+//! its CRC and is never made active.
+//!
+//! `load_active` is meant to be called once per boot, a second call without a commit in
+//! between falls back like a reboot would.
+
+use crate::signature::{BlockStatus, SIGNED_BLOCK_OVERHEAD};
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+/// Largest configuration, in bytes, a [`CommitableConfig`] can hold
+pub const MAX_CONFIG_SIZE: usize = PAGE_SIZE - SIGNED_BLOCK_OVERHEAD;
+/// Bytes a [`CommitableConfig`] takes, the state area and two slots
+pub const CONFIG_REGION_SIZE: usize = 3 * PAGE_SIZE;
+
+const CONFIG_MAGIC: [u8; 4] = *b"CCFG";
+const STATE_MAGIC: [u8; 4] = *b"CCST";
+/// Distance between the two places of the state in the state area, the smallest page the
+/// state block is written in a single write cycle with
+const STATE_STRIDE: usize = 32;
+/// Sequence number, state, active slot and candidate slot
+const STATE_SIZE: usize = 7;
+/// Slot number stored for a missing configuration
+const NO_SLOT: u8 = 0xFF;
+
+/// State of the candidate configuration, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigState {
+    Idle,
+    Staged,
+    Trial,
+    Committed,
+}
+
+/// What [`load_active`](CommitableConfig::load_active) read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveConfig {
+    /// Length of the configuration at the start of the buffer
+    pub len: usize,
+    /// The configuration is a candidate on trial, it has to be
+    /// [committed](CommitableConfig::commit) to be loaded again after a reboot
+    pub trial: bool,
+}
+
+/// A configuration committed in two phases, see the [module docs](self)
+pub struct CommitableConfig {
+    offset: u32,
+}
+
+/// The newest state and where it is stored
+#[derive(Clone, Copy)]
+struct Record {
+    place: usize,
+    sequence: u32,
+    state: ConfigState,
+    active: Option<usize>,
+    candidate: Option<usize>,
+}
+
+impl CommitableConfig {
+    /// Creates a configuration stored in the [`CONFIG_REGION_SIZE`] bytes starting at
+    /// `offset`, which has to be page aligned
+    pub const fn new(offset: u32) -> Self {
+        Self { offset }
+    }
+
+    pub const fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Reads the current state, which is `Idle` for a region that was never written
+    pub async fn state<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<ConfigState, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        Ok(self
+            .scan(eeprom)
+            .await?
+            .map_or(ConfigState::Idle, |record| record.state))
+    }
+
+    /// Writes `payload` as the candidate configuration, replacing any earlier candidate.
+    /// The active configuration stays untouched, an earlier candidate is dropped with a
+    /// transition to `Idle` before its slot is overwritten. Returns `OutOfBounds` if the
+    /// payload is larger than [`MAX_CONFIG_SIZE`].
+    pub async fn stage<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+        payload: &[u8],
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if payload.len() > MAX_CONFIG_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+        let mut last = self.scan(eeprom).await?;
+        let active = last.and_then(|record| record.active);
+        if last.is_some_and(|record| record.candidate.is_some()) {
+            // Drop the earlier candidate first, so it's never tried half overwritten
+            last = Some(
+                self.write_state(eeprom, last, ConfigState::Idle, active, None)
+                    .await?,
+            );
+        }
+        let slot = active.map_or(0, |active| 1 - active);
+        eeprom
+            .write_signed_block(self.slot_offset(slot), CONFIG_MAGIC, 0, payload)
+            .await?;
+        self.write_state(eeprom, last, ConfigState::Staged, active, Some(slot))
+            .await?;
+        Ok(())
+    }
+
+    /// Reads the configuration to run with into the start of `buf`.
+    ///
+    /// A staged candidate is returned once, as a trial. Otherwise this is the active
+    /// configuration, after falling back from a trial that wasn't committed. Returns
+    /// `NotFound` if there is no configuration, `CrcMismatch` if the active one is damaged
+    /// and `OutOfBounds` if it doesn't fit in `buf`.
+    pub async fn load_active<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+        buf: &mut [u8],
+    ) -> Result<ActiveConfig, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let Some(record) = self.scan(eeprom).await? else {
+            return Err(Error::NotFound);
+        };
+        match (record.state, record.candidate) {
+            (ConfigState::Staged, Some(candidate)) => {
+                let status = eeprom
+                    .read_signed_block(self.slot_offset(candidate), CONFIG_MAGIC, buf)
+                    .await?;
+                if let BlockStatus::Valid { len, .. } = status {
+                    self.write_state(
+                        eeprom,
+                        Some(record),
+                        ConfigState::Trial,
+                        record.active,
+                        Some(candidate),
+                    )
+                    .await?;
+                    return Ok(ActiveConfig { len, trial: true });
+                }
+                // The candidate was damaged since, drop it
+                self.write_state(eeprom, Some(record), ConfigState::Idle, record.active, None)
+                    .await?;
+            }
+            (ConfigState::Trial, _) => {
+                // Rebooted without a commit
+                self.write_state(eeprom, Some(record), ConfigState::Idle, record.active, None)
+                    .await?;
+            }
+            _ => {}
+        }
+        let slot = record.active.ok_or(Error::NotFound)?;
+        match eeprom
+            .read_signed_block(self.slot_offset(slot), CONFIG_MAGIC, buf)
+            .await?
+        {
+            BlockStatus::Valid { len, .. } => Ok(ActiveConfig { len, trial: false }),
+            _ => Err(Error::CrcMismatch),
+        }
+    }
+
+    /// Makes the configuration on trial the active one.
+    /// Does nothing if it already was, returns `InvalidArgument` if nothing is on trial.
+    pub async fn commit<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let last = self.scan(eeprom).await?;
+        match last {
+            Some(record) if record.state == ConfigState::Trial => {
+                self.write_state(eeprom, last, ConfigState::Committed, record.candidate, None)
+                    .await?;
+                Ok(())
+            }
+            Some(record) if record.state == ConfigState::Committed => Ok(()),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// Drops a staged candidate or the one on trial, the active configuration is loaded
+    /// from the next [`load_active`](Self::load_active) on
+    pub async fn rollback<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let last = self.scan(eeprom).await?;
+        match last {
+            Some(record) if record.candidate.is_some() => {
+                self.write_state(eeprom, last, ConfigState::Idle, record.active, None)
+                    .await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn slot_offset(&self, slot: usize) -> u32 {
+        self.offset + ((1 + slot) * PAGE_SIZE) as u32
+    }
+
+    /// Writes a new state to the place the last one isn't in
+    async fn write_state<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+        last: Option<Record>,
+        state: ConfigState,
+        active: Option<usize>,
+        candidate: Option<usize>,
+    ) -> Result<Record, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let (place, sequence) = match last {
+            Some(last) => (1 - last.place, last.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let slot = |slot: Option<usize>| slot.map_or(NO_SLOT, |slot| slot as u8);
+        let mut payload = [0; STATE_SIZE];
+        payload[..4].copy_from_slice(&sequence.to_le_bytes());
+        payload[4] = state as u8;
+        payload[5] = slot(active);
+        payload[6] = slot(candidate);
+        eeprom
+            .write_signed_block(
+                self.offset + (place * STATE_STRIDE) as u32,
+                STATE_MAGIC,
+                0,
+                &payload,
+            )
+            .await?;
+        Ok(Record {
+            place,
+            sequence,
+            state,
+            active,
+            candidate,
+        })
+    }
+
+    /// Finds the newest intact state, `None` if none was written yet.
+    /// Returns `CrcMismatch` if both places are damaged.
+    async fn scan<I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<Option<Record>, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if self.offset as usize % PAGE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+        if eeprom.page_size() < STATE_STRIDE {
+            return Err(Error::InvalidArgument);
+        }
+        if self.offset as usize + CONFIG_REGION_SIZE > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        let mut newest: Option<Record> = None;
+        let mut corrupt = 0;
+        for place in 0..2 {
+            let mut payload = [0; STATE_SIZE];
+            let status = match eeprom
+                .read_signed_block(
+                    self.offset + (place * STATE_STRIDE) as u32,
+                    STATE_MAGIC,
+                    &mut payload,
+                )
+                .await
+            {
+                Err(Error::OutOfBounds) => BlockStatus::Corrupt,
+                status => status?,
+            };
+            let record = match status {
+                BlockStatus::Blank => continue,
+                BlockStatus::Valid { len, .. } if len == STATE_SIZE => decode(place, &payload),
+                _ => None,
+            };
+            let Some(record) = record else {
+                corrupt += 1;
+                continue;
+            };
+            let is_newer = newest.as_ref().map_or(true, |n| {
+                (record.sequence.wrapping_sub(n.sequence) as i32) > 0
+            });
+            if is_newer {
+                newest = Some(record);
+            }
+        }
+        if corrupt == 2 {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(newest)
+    }
+}
+
+/// Reads a state written by `write_state`, `None` if it doesn't make sense
+fn decode(place: usize, payload: &[u8; STATE_SIZE]) -> Option<Record> {
+    let state = match payload[4] {
+        0 => ConfigState::Idle,
+        1 => ConfigState::Staged,
+        2 => ConfigState::Trial,
+        3 => ConfigState::Committed,
+        _ => return None,
+    };
+    let slot = |slot: u8| match slot {
+        0 | 1 => Some(Some(slot as usize)),
+        NO_SLOT => Some(None),
+        _ => None,
+    };
+    let active = slot(payload[5])?;
+    let candidate = slot(payload[6])?;
+    let has_candidate = matches!(state, ConfigState::Staged | ConfigState::Trial);
+    if candidate.is_some() != has_candidate || candidate.is_some() && candidate == active {
+        return None;
+    }
+    Some(Record {
+        place,
+        sequence: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+        state,
+        active,
+        candidate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimBus, SimError};
+    use crate::Address;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec::Vec;
+
+    const OFFSET: u32 = 0x800;
+    const CONFIG: CommitableConfig = CommitableConfig::new(OFFSET);
+
+    fn driver(memory: &[u8]) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        bus.memory_mut().copy_from_slice(memory);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    fn blank() -> Vec<u8> {
+        SimBus::new(Address(0, 0), 17).memory().to_vec()
+    }
+
+    async fn load(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> Option<(Vec<u8>, bool)> {
+        let mut buf = [0; MAX_CONFIG_SIZE];
+        match CONFIG.load_active(eeprom, &mut buf).await {
+            Ok(active) => Some((buf[..active.len].to_vec(), active.trial)),
+            Err(Error::NotFound) => None,
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+
+    /// What the next two boots load, without a commit in between
+    async fn boots(memory: &[u8]) -> [Option<(Vec<u8>, bool)>; 2] {
+        let mut eeprom = driver(memory);
+        [load(&mut eeprom).await, load(&mut eeprom).await]
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Op {
+        Stage(&'static [u8]),
+        Load,
+        Commit,
+        Rollback,
+    }
+
+    async fn apply(eeprom: &mut At24Cx<SimBus, NoopDelay>, op: Op) -> Result<(), Error<SimError>> {
+        let mut buf = [0; MAX_CONFIG_SIZE];
+        match op {
+            Op::Stage(payload) => CONFIG.stage(eeprom, payload).await,
+            Op::Load => CONFIG.load_active(eeprom, &mut buf).await.map(|_| ()),
+            Op::Commit => CONFIG.commit(eeprom).await,
+            Op::Rollback => CONFIG.rollback(eeprom).await,
+        }
+    }
+
+    #[tokio::test]
+    async fn trial_needs_a_commit() {
+        let mut eeprom = driver(&blank());
+        assert_eq!(load(&mut eeprom).await, None);
+        assert_eq!(CONFIG.state(&mut eeprom).await.unwrap(), ConfigState::Idle);
+        assert!(matches!(
+            CONFIG.commit(&mut eeprom).await,
+            Err(Error::InvalidArgument)
+        ));
+
+        CONFIG.stage(&mut eeprom, b"first").await.unwrap();
+        assert_eq!(
+            CONFIG.state(&mut eeprom).await.unwrap(),
+            ConfigState::Staged
+        );
+        assert_eq!(load(&mut eeprom).await, Some((b"first".to_vec(), true)));
+        assert_eq!(CONFIG.state(&mut eeprom).await.unwrap(), ConfigState::Trial);
+        CONFIG.commit(&mut eeprom).await.unwrap();
+        CONFIG.commit(&mut eeprom).await.unwrap();
+        assert_eq!(
+            CONFIG.state(&mut eeprom).await.unwrap(),
+            ConfigState::Committed
+        );
+        assert_eq!(load(&mut eeprom).await, Some((b"first".to_vec(), false)));
+
+        // Not committed, so the next boot falls back
+        CONFIG.stage(&mut eeprom, b"second").await.unwrap();
+        assert_eq!(load(&mut eeprom).await, Some((b"second".to_vec(), true)));
+        assert_eq!(load(&mut eeprom).await, Some((b"first".to_vec(), false)));
+        assert_eq!(CONFIG.state(&mut eeprom).await.unwrap(), ConfigState::Idle);
+
+        CONFIG.stage(&mut eeprom, b"third").await.unwrap();
+        CONFIG.rollback(&mut eeprom).await.unwrap();
+        assert_eq!(load(&mut eeprom).await, Some((b"first".to_vec(), false)));
+
+        assert!(matches!(
+            CONFIG.stage(&mut eeprom, &[0; MAX_CONFIG_SIZE + 1]).await,
+            Err(Error::OutOfBounds)
+        ));
+        let unaligned = CommitableConfig::new(OFFSET + 1);
+        assert!(matches!(
+            unaligned.state(&mut eeprom).await,
+            Err(Error::NotAligned)
+        ));
+    }
+
+    #[tokio::test]
+    async fn small_pages() {
+        let bus = SimBus::new(Address(0, 0), 17).with_page_size(32);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_page_size(32).unwrap();
+        let payload = [0x42; MAX_CONFIG_SIZE];
+        CONFIG.stage(&mut eeprom, &payload).await.unwrap();
+        assert_eq!(load(&mut eeprom).await, Some((payload.to_vec(), true)));
+        CONFIG.commit(&mut eeprom).await.unwrap();
+        assert_eq!(load(&mut eeprom).await, Some((payload.to_vec(), false)));
+
+        // The state block wouldn't be written in a single write cycle
+        eeprom.set_page_size(16).unwrap();
+        assert!(matches!(
+            CONFIG.state(&mut eeprom).await,
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[tokio::test]
+    async fn power_loss_at_every_transition() {
+        // Visits every state, with the state in either place and with or without an
+        // active configuration
+        let scenario = [
+            Op::Stage(b"first"),
+            Op::Load,
+            Op::Commit,
+            Op::Stage(b"second"),
+            Op::Load,
+            Op::Load,
+            Op::Stage(b"third"),
+            Op::Rollback,
+            Op::Stage(b"fourth"),
+            Op::Stage(b"fifth"),
+            Op::Load,
+            Op::Rollback,
+            Op::Stage(&[0x5A; MAX_CONFIG_SIZE]),
+            Op::Load,
+            Op::Commit,
+        ];
+        let transitions = [Op::Stage(b"next"), Op::Load, Op::Commit, Op::Rollback];
+        let mut memory = blank();
+        for step in scenario {
+            let before = boots(&memory).await;
+            for op in transitions {
+                let mut eeprom = driver(&memory);
+                let done = apply(&mut eeprom, op).await;
+                let after = boots(eeprom.i2c.memory()).await;
+                let mut eeprom = driver(&memory);
+                CONFIG.rollback(&mut eeprom).await.unwrap();
+                let rolled_back = boots(eeprom.i2c.memory()).await;
+
+                for cut in 0.. {
+                    let mut eeprom = driver(&memory);
+                    eeprom.i2c.cut_power_after(cut);
+                    let result = apply(&mut eeprom, op).await;
+                    eeprom.i2c.restore_power();
+                    let loaded = boots(eeprom.i2c.memory()).await;
+                    if result.is_ok() || done.is_err() {
+                        assert_eq!(&loaded, if done.is_ok() { &after } else { &before });
+                        break;
+                    }
+                    // Restaging drops the earlier candidate before overwriting it
+                    let restaged = matches!(op, Op::Stage(_)) && loaded == rolled_back;
+                    assert!(
+                        loaded == before || restaged,
+                        "{op:?} before {step:?} cut after {cut} bytes loads {loaded:?}"
+                    );
+                }
+            }
+            let mut eeprom = driver(&memory);
+            apply(&mut eeprom, step).await.unwrap();
+            memory = eeprom.i2c.memory().to_vec();
+        }
+    }
+}
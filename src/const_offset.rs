@@ -0,0 +1,76 @@
+//! Accessors at offsets fixed at compile time.
+//!
+//! Which part is on the bus isn't part of the driver's type, so an access is checked at
+//! compile time against [`MAX_CAPACITY`], what the largest part the driver addresses holds.
+//! It is checked against the configured part at runtime, like any other access.
+
+use crate::{At24Cx, Error, MAX_CAPACITY};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// `N` bytes at `OFFSET`, `CHECK` fails to compile if they end past [`MAX_CAPACITY`]
+struct Access<const OFFSET: u32, const N: usize>;
+
+impl<const OFFSET: u32, const N: usize> Access<OFFSET, N> {
+    const CHECK: () = assert!(
+        OFFSET as usize + N <= MAX_CAPACITY,
+        "access ends past the largest part the driver addresses"
+    );
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Reads the `N` bytes at `OFFSET`. Doesn't compile if they end past
+    /// [`MAX_CAPACITY`], returns `OutOfBounds` if they end past the part's capacity.
+    pub async fn read_at<const OFFSET: u32, const N: usize>(
+        &mut self,
+    ) -> Result<[u8; N], Error<E>> {
+        let () = Access::<OFFSET, N>::CHECK;
+        let mut buf = [0; N];
+        self.read(OFFSET, &mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Writes `data` at `OFFSET`, checked like [`read_at`](Self::read_at)
+    pub async fn write_at<const OFFSET: u32, const N: usize>(
+        &mut self,
+        data: &[u8; N],
+    ) -> Result<(), Error<E>> {
+        let () = Access::<OFFSET, N>::CHECK;
+        self.write(OFFSET, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBus;
+    use crate::Address;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.write_at::<0xFFFE, 4>(&[1, 2, 3, 4]).await.unwrap();
+        assert_eq!(eeprom.i2c.memory()[0xFFFE..0x10002], [1, 2, 3, 4]);
+        assert_eq!(eeprom.read_at::<0xFFFF, 2>().await.unwrap(), [2, 3]);
+        assert_eq!(eeprom.read_at::<0x1FFFF, 1>().await.unwrap(), [0xFF]);
+        assert_eq!(eeprom.read_at::<0x20000, 0>().await.unwrap(), []);
+
+        // Fits the driver, but not a 64KiB part
+        let bus = SimBus::new(Address(0, 0), 16);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 16, NoopDelay::new());
+        assert!(matches!(
+            eeprom.read_at::<0xFFFF, 2>().await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.write_at::<0x10000, 1>(&[0]).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,296 @@
+//! Wear-leveled monotonic counter in a region of the device.
+//!
+//! The region starts with two epoch slots of 6 bytes, a little endian epoch number and a
+//! CRC-16 over it, followed by a bitmap. Every increment programs one more bit of the
+//! bitmap, so a bitmap of `n` bytes counts `8·n` increments per epoch while each of its
+//! bytes is written 8 times. In even epochs increments clear bits, in odd epochs they set
+//! them again, which means the bitmap never has to be erased: a full bitmap is also an
+//! empty one for the next epoch. The epoch is written alternately to the two slots, once
+//! every `8·n` increments. The value of the counter is `epoch · 8·n + programmed bits`.
+//!
+//! A torn increment either programmed its bit or it didn't. A torn epoch write leaves the
+//! other slot with the previous epoch and a full bitmap, which is the same value. Either
+//! way the counter never goes backwards.
+
+use crate::crc::crc16;
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+const SLOT_SIZE: usize = 6;
+const BITMAP_START: u32 = 2 * SLOT_SIZE as u32;
+const CHUNK_SIZE: usize = 64;
+
+/// Monotonic counter over a region, see the [module docs](self)
+///
+/// The counter caches its value after the first access, so a `Counter` must always be used
+/// with the same device and be the only writer to its region.
+pub struct Counter {
+    region: Range<u32>,
+    state: Option<State>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    epoch: u32,
+    /// Bits programmed in the current epoch
+    bits: u32,
+}
+
+impl Counter {
+    /// Creates a counter over `region`, which needs more than 12 bytes
+    pub fn new(region: Range<u32>) -> Self {
+        Self {
+            region,
+            state: None,
+        }
+    }
+
+    /// Increments the counter can take before the epoch is rewritten
+    pub fn increments_per_epoch(&self) -> u32 {
+        (self
+            .region
+            .end
+            .saturating_sub(self.region.start + BITMAP_START))
+            * 8
+    }
+
+    /// Current value of the counter.
+    ///
+    /// Returns `OutOfBounds` if the region doesn't fit on the device and `InvalidArgument`
+    /// if it's too small.
+    pub async fn read<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<u64, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let state = self.state(eeprom).await?;
+        Ok(self.value(state))
+    }
+
+    /// Increments the counter and returns the new value
+    pub async fn increment<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<u64, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let mut state = self.state(eeprom).await?;
+        if state.bits == self.increments_per_epoch() {
+            state = State {
+                epoch: state.epoch + 1,
+                bits: 0,
+            };
+            let mut slot = [0; SLOT_SIZE];
+            slot[..4].copy_from_slice(&state.epoch.to_le_bytes());
+            let crc = crc16(&slot[..4]);
+            slot[4..].copy_from_slice(&crc.to_le_bytes());
+            let offset = self.region.start + (state.epoch % 2) * SLOT_SIZE as u32;
+            eeprom.write(offset, &slot).await?;
+            self.state = Some(state);
+        }
+
+        let programmed = (state.bits % 8 + 1) as u8;
+        let mask = ((1u16 << programmed) - 1) as u8;
+        let byte = if state.epoch % 2 == 0 { !mask } else { mask };
+        let offset = self.region.start + BITMAP_START + state.bits / 8;
+        eeprom.write(offset, &[byte]).await?;
+        state.bits += 1;
+        self.state = Some(state);
+        Ok(self.value(state))
+    }
+
+    fn value(&self, state: State) -> u64 {
+        state.epoch as u64 * self.increments_per_epoch() as u64 + state.bits as u64
+    }
+
+    async fn state<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<State, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if let Some(state) = self.state {
+            return Ok(state);
+        }
+        if self.region.end as usize > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        if self.increments_per_epoch() == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut slots = [0; 2 * SLOT_SIZE];
+        eeprom.read(self.region.start, &mut slots).await?;
+        let epoch = slots
+            .chunks(SLOT_SIZE)
+            .filter_map(|slot| {
+                let epoch = u32::from_le_bytes([slot[0], slot[1], slot[2], slot[3]]);
+                // A blank slot could pass the CRC check
+                let valid = epoch != u32::MAX
+                    && crc16(&slot[..4]) == u16::from_le_bytes([slot[4], slot[5]]);
+                valid.then_some(epoch)
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut bits = 0;
+        let mut offset = self.region.start + BITMAP_START;
+        let mut chunk = [0; CHUNK_SIZE];
+        while offset < self.region.end {
+            let len = CHUNK_SIZE.min((self.region.end - offset) as usize);
+            eeprom.read(offset, &mut chunk[..len]).await?;
+            bits += chunk[..len]
+                .iter()
+                .map(|b| {
+                    if epoch % 2 == 0 {
+                        b.count_zeros()
+                    } else {
+                        b.count_ones()
+                    }
+                })
+                .sum::<u32>();
+            offset += len as u32;
+        }
+
+        let state = State { epoch, bits };
+        self.state = Some(state);
+        Ok(state)
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Increments the [`Counter`] in the `region_len` bytes at `region_offset` and returns
+    /// the new value. Every call scans the region, keep a `Counter` around to avoid that.
+    pub async fn increment_counter(
+        &mut self,
+        region_offset: u32,
+        region_len: u32,
+    ) -> Result<u64, Error<E>> {
+        let mut counter = Counter::new(region(region_offset, region_len)?);
+        counter.increment(self).await
+    }
+
+    /// Reads the [`Counter`] in the `region_len` bytes at `region_offset`
+    pub async fn read_counter(
+        &mut self,
+        region_offset: u32,
+        region_len: u32,
+    ) -> Result<u64, Error<E>> {
+        let mut counter = Counter::new(region(region_offset, region_len)?);
+        counter.read(self).await
+    }
+}
+
+fn region<E: Debug>(offset: u32, len: u32) -> Result<Range<u32>, Error<E>> {
+    let end = offset.checked_add(len).ok_or(Error::OutOfBounds)?;
+    Ok(offset..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: Range<u32> = 0x100..0x120;
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn spreads_wear_across_epochs() {
+        let mut eeprom = driver(None);
+        let mut counter = Counter::new(REGION);
+        assert_eq!(counter.increments_per_epoch(), 160);
+        assert_eq!(counter.read(&mut eeprom).await.unwrap(), 0);
+        for i in 1..=500 {
+            assert_eq!(counter.increment(&mut eeprom).await.unwrap(), i);
+            if i % 37 == 0 {
+                let mut counter = Counter::new(REGION);
+                assert_eq!(counter.read(&mut eeprom).await.unwrap(), i);
+            }
+        }
+
+        // 500 increments span 4 epochs, the epoch slots were written 3 times in total
+        for offset in REGION.start..REGION.start + BITMAP_START {
+            assert!(eeprom.i2c.write_count(offset as usize) <= 2);
+        }
+        for offset in REGION.start + BITMAP_START..REGION.end {
+            assert!(eeprom.i2c.write_count(offset as usize) <= 4 * 8);
+        }
+        assert_eq!(eeprom.i2c.write_count(REGION.end as usize), 0);
+    }
+
+    #[tokio::test]
+    async fn torn_increments_never_go_backwards() {
+        let mut eeprom = driver(None);
+        let mut counter = Counter::new(REGION);
+        for _ in 0..160 {
+            counter.increment(&mut eeprom).await.unwrap();
+        }
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        // Bumping the epoch is torn at every byte of the epoch slot
+        for cut in 0..SLOT_SIZE {
+            let mut eeprom = driver(Some(&snapshot));
+            eeprom.i2c.cut_power_after(cut);
+            assert!(Counter::new(REGION).increment(&mut eeprom).await.is_err());
+            eeprom.i2c.restore_power();
+
+            let mut counter = Counter::new(REGION);
+            assert_eq!(counter.read(&mut eeprom).await.unwrap(), 160);
+            assert_eq!(counter.increment(&mut eeprom).await.unwrap(), 161);
+        }
+
+        // The bitmap byte is lost, the epoch made it
+        let mut eeprom = driver(Some(&snapshot));
+        eeprom.i2c.cut_power_after(SLOT_SIZE);
+        assert!(Counter::new(REGION).increment(&mut eeprom).await.is_err());
+        eeprom.i2c.restore_power();
+        let mut counter = Counter::new(REGION);
+        assert_eq!(counter.read(&mut eeprom).await.unwrap(), 160);
+        assert_eq!(counter.increment(&mut eeprom).await.unwrap(), 161);
+    }
+
+    #[tokio::test]
+    async fn counts_through_the_driver() {
+        let mut eeprom = driver(None);
+        assert_eq!(eeprom.read_counter(0x100, 0x20).await.unwrap(), 0);
+        for i in 1..=200 {
+            assert_eq!(eeprom.increment_counter(0x100, 0x20).await.unwrap(), i);
+        }
+        assert_eq!(Counter::new(REGION).read(&mut eeprom).await.unwrap(), 200);
+        assert!(matches!(
+            eeprom.increment_counter(u32::MAX, 0x20).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_regions() {
+        let mut eeprom = driver(None);
+        assert!(matches!(
+            Counter::new(0x100..0x10C).read(&mut eeprom).await,
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            Counter::new(0x1FFF0..0x20010).read(&mut eeprom).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+}
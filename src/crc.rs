@@ -0,0 +1,84 @@
+//! Bitwise CRC implementations, small enough to not need lookup tables.
+
+// Not every CRC is used in every feature combination
+#![allow(dead_code)]
+
+/// CRC-8 with polynomial 0x07 (CRC-8/SMBUS)
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// CRC-16 with polynomial 0x1021 and initial value 0xFFFF (CRC-16/CCITT-FALSE)
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |mut crc, byte| {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Reflected polynomial of the IEEE 802.3 CRC-32
+pub const CRC32_IEEE: u32 = 0xEDB8_8320;
+/// Reflected polynomial of the Castagnoli CRC-32C
+pub const CRC32_CASTAGNOLI: u32 = 0x82F6_3B78;
+
+/// CRC-32 (IEEE 802.3, reflected with polynomial 0xEDB88320)
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Feeds more data into a running CRC-32. Start with `!0` and invert the result when done.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    crc32_update_with(CRC32_IEEE, crc, data)
+}
+
+/// [`crc32_update`] with another reflected polynomial
+pub fn crc32_update_with(poly: u32, crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |mut crc, byte| {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ poly
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            !crc32_update(crc32_update(!0, b"1234"), b"56789"),
+            0xCBF4_3926
+        );
+        assert_eq!(
+            !crc32_update_with(CRC32_CASTAGNOLI, !0, b"123456789"),
+            0xE306_9283
+        );
+    }
+}
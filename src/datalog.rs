@@ -0,0 +1,318 @@
+//! Circular log of fixed-size records in a region of the device.
+//!
+//! The region is divided into slots of `record_size + 6` bytes: a little endian sequence
+//! number, the record and a CRC-16 over both. The record with sequence number `s` always
+//! lives in slot `s % slots`, so once the log is full every append replaces the oldest
+//! record. Mounting finds the newest intact record and walks back from it for as long as
+//! the records are intact and in sequence, which drops a record torn by a power loss.
+//!
+//! Records can carry a key that only ever grows, usually a timestamp, which allows a binary
+//! search with [`find_by_key`](DataLog::find_by_key) instead of a scan.
+
+use crate::crc::crc16;
+use crate::{EepromPartition, Error};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Largest record size supported
+pub const MAX_RECORD_SIZE: usize = 64;
+
+const SEQ_SIZE: usize = 4;
+const SLOT_OVERHEAD: usize = SEQ_SIZE + 2;
+
+/// Fixed-record log over a partition, see the [module docs](self)
+pub struct DataLog<'a, I2C, D> {
+    region: EepromPartition<'a, I2C, D>,
+    record_size: usize,
+    slots: u32,
+    count: u32,
+    next_seq: u32,
+    monotonic_key: Option<fn(&[u8]) -> u64>,
+}
+
+impl<'a, I2C, E: Debug, D: DelayNs> DataLog<'a, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Opens the log in `region` with records of `record_size` bytes, recovering the
+    /// records already there. The record size has to stay the same across mounts.
+    ///
+    /// Returns `InvalidArgument` for a record size of 0 or above [`MAX_RECORD_SIZE`] or a
+    /// region without room for two records.
+    pub async fn mount(
+        region: EepromPartition<'a, I2C, D>,
+        record_size: usize,
+    ) -> Result<Self, Error<E>> {
+        if record_size == 0 || record_size > MAX_RECORD_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let slots = (region.capacity() / (record_size + SLOT_OVERHEAD)) as u32;
+        if slots < 2 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut log = Self {
+            region,
+            record_size,
+            slots,
+            count: 0,
+            next_seq: 0,
+            monotonic_key: None,
+        };
+
+        let mut buf = [0; MAX_RECORD_SIZE];
+        let mut newest: Option<u32> = None;
+        for slot in 0..slots {
+            if let Some(seq) = log.read_slot(slot, &mut buf).await? {
+                if seq % slots == slot && newest.map_or(true, |n| seq > n) {
+                    newest = Some(seq);
+                }
+            }
+        }
+        let Some(newest) = newest else {
+            return Ok(log);
+        };
+        log.next_seq = newest + 1;
+        let mut seq = newest;
+        loop {
+            log.count += 1;
+            if seq == 0 || log.count == slots {
+                break;
+            }
+            seq -= 1;
+            if log.read_slot(seq % slots, &mut buf).await? != Some(seq) {
+                break;
+            }
+        }
+        Ok(log)
+    }
+
+    /// Rejects appends whose key, as returned by `key`, is smaller than the newest
+    /// record's. `None` turns the check off, which is the default.
+    pub fn set_monotonic_key(&mut self, key: Option<fn(&[u8]) -> u64>) {
+        self.monotonic_key = key;
+    }
+
+    /// Number of records in the log
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Number of records the log holds before it starts replacing the oldest
+    pub fn slots(&self) -> usize {
+        self.slots as usize
+    }
+
+    /// Appends a record, replacing the oldest one once the log is full.
+    ///
+    /// Returns `InvalidArgument` if the record doesn't have the configured size or its key
+    /// goes backwards (see [`set_monotonic_key`](Self::set_monotonic_key)).
+    pub async fn append(&mut self, record: &[u8]) -> Result<(), Error<E>> {
+        if record.len() != self.record_size {
+            return Err(Error::InvalidArgument);
+        }
+        if let Some(key) = self.monotonic_key {
+            if self.count > 0 {
+                let mut newest = [0; MAX_RECORD_SIZE];
+                let newest = &mut newest[..self.record_size];
+                self.get(self.len() - 1, newest).await?;
+                if key(record) < key(newest) {
+                    return Err(Error::InvalidArgument);
+                }
+            }
+        }
+
+        let seq = self.next_seq;
+        let mut slot = [0; SLOT_OVERHEAD + MAX_RECORD_SIZE];
+        let slot = &mut slot[..SLOT_OVERHEAD + self.record_size];
+        slot[..SEQ_SIZE].copy_from_slice(&seq.to_le_bytes());
+        slot[SEQ_SIZE..SEQ_SIZE + record.len()].copy_from_slice(record);
+        let crc = crc16(&slot[..SEQ_SIZE + record.len()]);
+        slot[SEQ_SIZE + record.len()..].copy_from_slice(&crc.to_le_bytes());
+        let offset = self.slot_offset(seq % self.slots);
+        self.region.write(offset, slot).await?;
+
+        self.next_seq += 1;
+        self.count = (self.count + 1).min(self.slots);
+        Ok(())
+    }
+
+    /// Reads the record at `index`, counting from the oldest, into the start of `buf`.
+    /// Returns `NotFound` past the newest record and `OutOfBounds` if `buf` is too small.
+    pub async fn get(&mut self, index: usize, buf: &mut [u8]) -> Result<(), Error<E>> {
+        if index >= self.len() {
+            return Err(Error::NotFound);
+        }
+        if buf.len() < self.record_size {
+            return Err(Error::OutOfBounds);
+        }
+        let seq = self.next_seq - self.count + index as u32;
+        let offset = self.slot_offset(seq % self.slots) + SEQ_SIZE as u32;
+        self.region.read(offset, &mut buf[..self.record_size]).await
+    }
+
+    /// Index of the first record whose key is at least `key`, `None` if every key is
+    /// smaller. `extractor` returns the key of a record, keys have to increase with the
+    /// index. `buf` is scratch space for the probed records.
+    pub async fn find_by_key(
+        &mut self,
+        key: u64,
+        extractor: impl Fn(&[u8]) -> u64,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, Error<E>> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            self.get(mid, buf).await?;
+            if extractor(&buf[..self.record_size]) < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok((low < self.len()).then_some(low))
+    }
+
+    fn slot_offset(&self, slot: u32) -> u32 {
+        slot * (self.record_size + SLOT_OVERHEAD) as u32
+    }
+
+    /// Sequence number in `slot` if it holds an intact record
+    async fn read_slot(
+        &mut self,
+        slot: u32,
+        buf: &mut [u8; MAX_RECORD_SIZE],
+    ) -> Result<Option<u32>, Error<E>> {
+        let mut bytes = [0; SLOT_OVERHEAD + MAX_RECORD_SIZE];
+        let bytes = &mut bytes[..SLOT_OVERHEAD + self.record_size];
+        self.region.read(self.slot_offset(slot), bytes).await?;
+        let (data, crc) = bytes.split_at(SEQ_SIZE + self.record_size);
+        let seq = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        // A blank slot could pass the CRC check
+        if seq == u32::MAX || crc16(data) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return Ok(None);
+        }
+        buf[..self.record_size].copy_from_slice(&data[SEQ_SIZE..]);
+        Ok(Some(seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: core::ops::Range<u32> = 0x4000..0x4100;
+    const RECORD_SIZE: usize = 20;
+
+    /// Timestamp followed by 12 bytes of sample data
+    fn sample(timestamp: u64) -> [u8; RECORD_SIZE] {
+        let mut record = [timestamp as u8; RECORD_SIZE];
+        record[..8].copy_from_slice(&timestamp.to_le_bytes());
+        record
+    }
+
+    fn timestamp(record: &[u8]) -> u64 {
+        u64::from_le_bytes(record[..8].try_into().unwrap())
+    }
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn mount(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> DataLog<'_, SimBus, NoopDelay> {
+        DataLog::mount(eeprom.partition(REGION).unwrap(), RECORD_SIZE)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn binary_search_within_window() {
+        let mut eeprom = driver(None);
+        let mut log = mount(&mut eeprom).await;
+        // 256 / 26 = 9 slots, the first 6 records get replaced
+        assert_eq!(log.slots(), 9);
+        for t in 0..15 {
+            log.append(&sample(100 + t * 10)).await.unwrap();
+        }
+        assert_eq!(log.len(), 9);
+
+        let mut buf = [0; RECORD_SIZE];
+        let mut find = async |key| log.find_by_key(key, timestamp, &mut buf).await.unwrap();
+        // Oldest surviving record is 160
+        assert_eq!(find(0).await, Some(0));
+        assert_eq!(find(160).await, Some(0));
+        assert_eq!(find(161).await, Some(1));
+        assert_eq!(find(200).await, Some(4));
+        assert_eq!(find(240).await, Some(8));
+        assert_eq!(find(241).await, None);
+
+        let mut log = mount(&mut eeprom).await;
+        assert_eq!(log.len(), 9);
+        log.get(0, &mut buf).await.unwrap();
+        assert_eq!(buf, sample(160));
+        log.get(8, &mut buf).await.unwrap();
+        assert_eq!(buf, sample(240));
+        assert!(matches!(log.get(9, &mut buf).await, Err(Error::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_size_and_going_backwards() {
+        let mut eeprom = driver(None);
+        let mut log = mount(&mut eeprom).await;
+        let mut buf = [0; RECORD_SIZE];
+        assert_eq!(log.find_by_key(5, timestamp, &mut buf).await.unwrap(), None);
+        assert!(matches!(
+            log.append(&[0; RECORD_SIZE - 1]).await,
+            Err(Error::InvalidArgument)
+        ));
+        log.set_monotonic_key(Some(timestamp));
+        log.append(&sample(50)).await.unwrap();
+        log.append(&sample(50)).await.unwrap();
+        assert!(matches!(
+            log.append(&sample(49)).await,
+            Err(Error::InvalidArgument)
+        ));
+        log.set_monotonic_key(None);
+        log.append(&sample(49)).await.unwrap();
+        assert_eq!(log.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn recovers_after_torn_append() {
+        let mut eeprom = driver(None);
+        let mut log = mount(&mut eeprom).await;
+        for t in 0..12 {
+            log.append(&sample(t)).await.unwrap();
+        }
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        for cut in 1..RECORD_SIZE + SLOT_OVERHEAD {
+            let mut eeprom = driver(Some(&snapshot));
+            eeprom.i2c.cut_power_after(cut);
+            assert!(mount(&mut eeprom).await.append(&sample(12)).await.is_err());
+            eeprom.i2c.restore_power();
+
+            // The torn append replaced the oldest record, 3
+            let mut log = mount(&mut eeprom).await;
+            assert_eq!(log.len(), 8, "cut after {cut} bytes");
+            let mut buf = [0; RECORD_SIZE];
+            log.get(0, &mut buf).await.unwrap();
+            assert_eq!(buf, sample(4));
+            log.append(&sample(12)).await.unwrap();
+            let mut log = mount(&mut eeprom).await;
+            assert_eq!(log.len(), 9);
+            log.get(8, &mut buf).await.unwrap();
+            assert_eq!(buf, sample(12));
+        }
+    }
+}
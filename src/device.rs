@@ -0,0 +1,634 @@
+use crate::{Address, At24Cx, DeviceAddress, Error, ADDRESS_BYTES, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+/// Members of the AT24Cx family and their geometry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// 128 bytes
+    At24c01,
+    /// 256 bytes
+    At24c02,
+    /// 512 bytes
+    At24c04,
+    /// 1KiB
+    At24c08,
+    /// 2KiB
+    At24c16,
+    /// 4KiB
+    At24c32,
+    /// 8KiB
+    At24c64,
+    /// 16KiB
+    At24c128,
+    /// 32KiB
+    At24c256,
+    /// 64KiB
+    At24c512,
+    /// 128KiB
+    At24cm01,
+    /// 256KiB
+    At24cm02,
+}
+
+impl DeviceKind {
+    /// Every member of the family, smallest first
+    pub const ALL: [DeviceKind; 12] = [
+        DeviceKind::At24c01,
+        DeviceKind::At24c02,
+        DeviceKind::At24c04,
+        DeviceKind::At24c08,
+        DeviceKind::At24c16,
+        DeviceKind::At24c32,
+        DeviceKind::At24c64,
+        DeviceKind::At24c128,
+        DeviceKind::At24c256,
+        DeviceKind::At24c512,
+        DeviceKind::At24cm01,
+        DeviceKind::At24cm02,
+    ];
+
+    /// Number of bits in a memory offset
+    pub const fn address_bits(self) -> usize {
+        match self {
+            DeviceKind::At24c01 => 7,
+            DeviceKind::At24c02 => 8,
+            DeviceKind::At24c04 => 9,
+            DeviceKind::At24c08 => 10,
+            DeviceKind::At24c16 => 11,
+            DeviceKind::At24c32 => 12,
+            DeviceKind::At24c64 => 13,
+            DeviceKind::At24c128 => 14,
+            DeviceKind::At24c256 => 15,
+            DeviceKind::At24c512 => 16,
+            DeviceKind::At24cm01 => 17,
+            DeviceKind::At24cm02 => 18,
+        }
+    }
+
+    /// Size in bytes
+    pub const fn capacity(self) -> usize {
+        1 << self.address_bits()
+    }
+
+    /// Size in bytes of a write page
+    pub const fn page_size(self) -> usize {
+        match self {
+            DeviceKind::At24c01 | DeviceKind::At24c02 => 8,
+            DeviceKind::At24c04 | DeviceKind::At24c08 | DeviceKind::At24c16 => 16,
+            DeviceKind::At24c32 | DeviceKind::At24c64 => 32,
+            DeviceKind::At24c128 | DeviceKind::At24c256 => 64,
+            DeviceKind::At24c512 => 128,
+            DeviceKind::At24cm01 | DeviceKind::At24cm02 => 256,
+        }
+    }
+
+    /// Longest write cycle, in microseconds, to wait for. 5 ms per the datasheets, but the
+    /// AT24C32 clones on DS3231 RTC modules take up to 10 ms.
+    pub const fn write_cycle_us(self) -> u32 {
+        match self {
+            DeviceKind::At24c32 => 10_000,
+            _ => 5_000,
+        }
+    }
+
+    /// Number of memory address bytes sent after the device address. Parts with up to
+    /// 2KiB take one, the bits above it go into the device address.
+    pub const fn address_bytes(self) -> usize {
+        if self.address_bits() <= 11 {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Number of high offset bits carried in the device address instead of the memory
+    /// address bytes. These replace address pins.
+    pub const fn page_select_bits(self) -> usize {
+        self.address_bits().saturating_sub(8 * self.address_bytes())
+    }
+
+    /// The part with `address_bits` bits in a memory offset, if there is one
+    pub const fn from_address_bits(address_bits: usize) -> Option<Self> {
+        let mut i = 0;
+        while i < Self::ALL.len() {
+            if Self::ALL[i].address_bits() == address_bits {
+                return Some(Self::ALL[i]);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// The part with `address_bits` bits in a memory offset, if there is one and the driver
+    /// addresses and pages it the way it expects
+    pub const fn checked_from_address_bits(address_bits: usize) -> Result<Self, UnsupportedDevice> {
+        let Some(kind) = Self::from_address_bits(address_bits) else {
+            return Err(UnsupportedDevice::AddressBits { bits: address_bits });
+        };
+        match kind.check() {
+            Ok(()) => Ok(kind),
+            Err(unsupported) => Err(unsupported),
+        }
+    }
+
+    /// Checks that the driver addresses and pages this part the way it expects
+    pub const fn check(self) -> Result<(), UnsupportedDevice> {
+        if self.address_bytes() != ADDRESS_BYTES {
+            return Err(UnsupportedDevice::AddressBytes {
+                required: self.address_bytes(),
+            });
+        }
+        if self.page_select_bits() > 1 {
+            return Err(UnsupportedDevice::PageSelectBits {
+                required: self.page_select_bits(),
+            });
+        }
+        if self.page_size() > PAGE_SIZE {
+            return Err(UnsupportedDevice::PageSize {
+                required: self.page_size(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that `address` leaves the part's page select bits clear
+    fn check_base_address(self, address: DeviceAddress) -> Result<(), UnsupportedDevice> {
+        let page_select_bits = self.page_select_bits();
+        if u8::from(address) & ((1 << page_select_bits) - 1) != 0 {
+            return Err(UnsupportedDevice::PageSelectBitsSet { page_select_bits });
+        }
+        Ok(())
+    }
+}
+
+// Every part's geometry has to add up, so a typo in a new variant fails the build
+const _: () = {
+    let mut i = 0;
+    while i < DeviceKind::ALL.len() {
+        let kind = DeviceKind::ALL[i];
+        let capacity = kind.capacity();
+        let page_size = kind.page_size();
+        assert!(capacity.is_power_of_two(), "capacity isn't a power of two");
+        assert!(
+            page_size.is_power_of_two(),
+            "page size isn't a power of two"
+        );
+        assert!(page_size <= capacity, "page is larger than the part");
+        assert!(
+            capacity % page_size == 0,
+            "page size doesn't divide the capacity"
+        );
+        assert!(
+            page_size <= 1 << (8 * kind.address_bytes()),
+            "page doesn't fit in what the address bytes address"
+        );
+        assert!(
+            8 * kind.address_bytes() + kind.page_select_bits() >= kind.address_bits(),
+            "address bytes and page select bits don't reach the whole part"
+        );
+        assert!(
+            kind.page_select_bits() <= 3,
+            "more page select bits than the device address has pins"
+        );
+        if i > 0 {
+            assert!(
+                DeviceKind::ALL[i - 1].capacity() < capacity,
+                "ALL isn't sorted by capacity"
+            );
+        }
+        i += 1;
+    }
+};
+
+/// Why [`At24Cx::with_device`] or [`At24Cx::try_new`] rejected a part. Driving it with the driver's fixed
+/// geometry would corrupt data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsupportedDevice {
+    /// The part takes a different number of memory address bytes than [`ADDRESS_BYTES`]
+    AddressBytes { required: usize },
+    /// The part carries more offset bits in the device address than the one the driver
+    /// sets
+    PageSelectBits { required: usize },
+    /// The part's write pages are larger than [`PAGE_SIZE`] bytes
+    PageSize { required: usize },
+    /// No part in the family has this many bits in a memory offset
+    AddressBits { bits: usize },
+    /// The base address has some of the part's page select bits set, so the upper pages
+    /// would alias another address
+    PageSelectBitsSet { page_select_bits: usize },
+}
+
+impl<I2C, D> At24Cx<I2C, D> {
+    /// Creates a driver for `kind`, rejecting parts whose addressing or page size doesn't
+    /// match what the driver implements, and base addresses with any of the part's page
+    /// select bits set. Writes are split at the part's pages, and ACK polling waits at least
+    /// twice its [write cycle](DeviceKind::write_cycle_us).
+    pub fn with_device(
+        i2c: I2C,
+        address: impl Into<DeviceAddress>,
+        kind: DeviceKind,
+        delay: D,
+    ) -> Result<Self, UnsupportedDevice> {
+        kind.check()?;
+        let address: DeviceAddress = address.into();
+        kind.check_base_address(address)?;
+        let mut eeprom = Self::new(i2c, address, kind.address_bits(), delay);
+        eeprom.page_size = kind.page_size();
+        let polls =
+            (2 * kind.write_cycle_us() as u64 * 1_000).div_ceil(eeprom.poll_delay_ns as u64);
+        eeprom.poll_max_retries = eeprom.poll_max_retries.max(polls as usize);
+        Ok(eeprom)
+    }
+
+    /// Like [`new`](Self::new), but rejects an `address_bits` no supported part has, which
+    /// `new` would address with the wrong memory address bytes, and base addresses with any
+    /// of that part's page select bits set. Unlike [`with_device`](Self::with_device) it
+    /// keeps writing pages of [`PAGE_SIZE`] bytes.
+    pub fn try_new(
+        i2c: I2C,
+        address: impl Into<DeviceAddress>,
+        address_bits: usize,
+        delay: D,
+    ) -> Result<Self, UnsupportedDevice> {
+        let kind = DeviceKind::checked_from_address_bits(address_bits)?;
+        let address: DeviceAddress = address.into();
+        kind.check_base_address(address)?;
+        Ok(Self::new(i2c, address, address_bits, delay))
+    }
+
+    /// Creates a driver for the AT24C32 on DS3231 RTC modules, which have pull-ups on all
+    /// three address pins and answer at `0x57`
+    pub fn ds3231_module(i2c: I2C, delay: D) -> Self {
+        match Self::with_device(i2c, Address::pins(1, 1, 1), DeviceKind::At24c32, delay) {
+            Ok(eeprom) => eeprom,
+            Err(_) => unreachable!("the driver supports the AT24C32"),
+        }
+    }
+
+    /// Creates a driver for an AT24C256, the part on the common 32KiB breakout modules.
+    /// All three address pins are valid and its two address bytes reach the whole part, so
+    /// nothing is ever set in the device address on top of the pins. Writes are split at its
+    /// 64 byte pages.
+    pub fn at24c256(i2c: I2C, address: impl Into<DeviceAddress>, delay: D) -> Self {
+        match Self::with_device(i2c, address, DeviceKind::At24c256, delay) {
+            Ok(eeprom) => eeprom,
+            Err(_) => unreachable!("the driver supports the AT24C256"),
+        }
+    }
+}
+
+/// Proof that the caller lets [`At24Cx::probe_page_size`] overwrite a page of the device.
+pub struct PageOverwriteToken(());
+
+impl PageOverwriteToken {
+    /// I understand that probing overwrites the page it runs on with a test pattern.
+    pub fn i_accept_losing_the_page_contents() -> Self {
+        Self(())
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Finds the write page size of the part on the bus, to check a [`DeviceKind`] against
+    /// real silicon.
+    ///
+    /// Writes [`PAGE_SIZE`] distinct bytes to the page at `offset` in a single write cycle.
+    /// A part with smaller pages rolls over within its page, which ends up holding the last
+    /// bytes that were sent, so reading the page back tells how many bytes the write wrapped
+    /// after. Every power of two up to `PAGE_SIZE` is told apart by the one write.
+    ///
+    /// # Destructive
+    ///
+    /// The page is left holding the test pattern. `offset` has to be page aligned, returns
+    /// `NotAligned` otherwise, and `ReadbackFail` if what is read back doesn't look like any
+    /// page size.
+    pub async fn probe_page_size(
+        &mut self,
+        offset: u32,
+        _token: &PageOverwriteToken,
+    ) -> Result<usize, Error<E>> {
+        if offset as usize % PAGE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+        let pattern: [u8; PAGE_SIZE] = core::array::from_fn(|i| i as u8);
+        self.write_cycle(offset, &pattern).await?;
+        self.flush().await?;
+        let mut readback = [0; PAGE_SIZE];
+        self.read(offset, &mut readback).await?;
+
+        // With pages of `size` bytes the first one holds the last `size` bytes of the pattern
+        let size = match readback[0] {
+            0 => PAGE_SIZE,
+            first => PAGE_SIZE - first as usize,
+        };
+        if !size.is_power_of_two() || readback[..size] != pattern[PAGE_SIZE - size..] {
+            return Err(Error::ReadbackFail);
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{expect_ack_poll, expect_page_write, expect_read};
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::{delay::NoopDelay, i2c::Mock as I2cMock};
+    use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+    #[test]
+    fn derives_addressing_for_every_part() {
+        // (part, capacity, address bytes, page select bits, page size)
+        let expected = [
+            (DeviceKind::At24c01, 128, 1, 0, 8),
+            (DeviceKind::At24c02, 256, 1, 0, 8),
+            (DeviceKind::At24c04, 512, 1, 1, 16),
+            (DeviceKind::At24c08, 1024, 1, 2, 16),
+            (DeviceKind::At24c16, 2048, 1, 3, 16),
+            (DeviceKind::At24c32, 4096, 2, 0, 32),
+            (DeviceKind::At24c64, 8192, 2, 0, 32),
+            (DeviceKind::At24c128, 16384, 2, 0, 64),
+            (DeviceKind::At24c256, 32768, 2, 0, 64),
+            (DeviceKind::At24c512, 65536, 2, 0, 128),
+            (DeviceKind::At24cm01, 131072, 2, 1, 256),
+            (DeviceKind::At24cm02, 262144, 2, 2, 256),
+        ];
+        assert_eq!(expected.len(), DeviceKind::ALL.len());
+        for (kind, capacity, address_bytes, page_select_bits, page_size) in expected {
+            assert_eq!(kind.capacity(), capacity, "{kind:?}");
+            assert_eq!(kind.address_bytes(), address_bytes, "{kind:?}");
+            assert_eq!(kind.page_select_bits(), page_select_bits, "{kind:?}");
+            assert_eq!(kind.page_size(), page_size, "{kind:?}");
+            assert_eq!(
+                DeviceKind::from_address_bits(kind.address_bits()),
+                Some(kind)
+            );
+        }
+        assert_eq!(DeviceKind::from_address_bits(19), None);
+    }
+
+    #[test]
+    fn rejects_parts_the_driver_cant_address() {
+        let expected = [
+            (
+                DeviceKind::At24c01,
+                Err(UnsupportedDevice::AddressBytes { required: 1 }),
+            ),
+            (
+                DeviceKind::At24c02,
+                Err(UnsupportedDevice::AddressBytes { required: 1 }),
+            ),
+            (
+                DeviceKind::At24c04,
+                Err(UnsupportedDevice::AddressBytes { required: 1 }),
+            ),
+            (
+                DeviceKind::At24c08,
+                Err(UnsupportedDevice::AddressBytes { required: 1 }),
+            ),
+            (
+                DeviceKind::At24c16,
+                Err(UnsupportedDevice::AddressBytes { required: 1 }),
+            ),
+            // Smaller pages are split at
+            (DeviceKind::At24c32, Ok(())),
+            (DeviceKind::At24c64, Ok(())),
+            (DeviceKind::At24c128, Ok(())),
+            (DeviceKind::At24c256, Ok(())),
+            (DeviceKind::At24c512, Ok(())),
+            (DeviceKind::At24cm01, Ok(())),
+            (
+                DeviceKind::At24cm02,
+                Err(UnsupportedDevice::PageSelectBits { required: 2 }),
+            ),
+        ];
+        for (kind, result) in expected {
+            assert_eq!(kind.check(), result, "{kind:?}");
+        }
+
+        let bus = SimBus::new(Address(0, 0), 17);
+        let eeprom =
+            At24Cx::with_device(bus, Address(0, 0), DeviceKind::At24cm01, NoopDelay::new())
+                .unwrap();
+        assert_eq!(eeprom.capacity(), 131072);
+        let bus = SimBus::new(Address(0, 0), 8);
+        assert!(matches!(
+            At24Cx::with_device(bus, Address(0, 0), DeviceKind::At24c02, NoopDelay::new()),
+            Err(UnsupportedDevice::AddressBytes { required: 1 })
+        ));
+    }
+
+    #[test]
+    fn rejects_page_select_bits_in_the_base_address() {
+        let with_device = |address, kind| {
+            let bus = SimBus::new(Address(0, 0), 17);
+            At24Cx::with_device(bus, address, kind, NoopDelay::new()).map(|_| ())
+        };
+        // A0 is P0 on the AT24CM01, the upper half would answer at 0x51 as well
+        assert_eq!(
+            with_device(Address::pins(1, 0, 0), DeviceKind::At24cm01),
+            Err(UnsupportedDevice::PageSelectBitsSet {
+                page_select_bits: 1
+            })
+        );
+        assert_eq!(
+            with_device(Address::pins(0, 1, 1), DeviceKind::At24cm01),
+            Ok(())
+        );
+        assert_eq!(
+            with_device(Address::pins(1, 0, 0), DeviceKind::At24c512),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn checks_address_widths() {
+        assert_eq!(
+            DeviceKind::checked_from_address_bits(17),
+            Ok(DeviceKind::At24cm01)
+        );
+        assert_eq!(
+            DeviceKind::checked_from_address_bits(8),
+            Err(UnsupportedDevice::AddressBytes { required: 1 })
+        );
+        assert_eq!(
+            DeviceKind::checked_from_address_bits(19),
+            Err(UnsupportedDevice::AddressBits { bits: 19 })
+        );
+    }
+
+    #[test]
+    fn try_new_checks_the_address_width() {
+        let new = |address: u8, bits| {
+            let bus = SimBus::new(Address(0, 0), 17);
+            At24Cx::try_new(bus, DeviceAddress(address), bits, NoopDelay::new()).map(|_| ())
+        };
+        assert_eq!(
+            new(0x50, 8),
+            Err(UnsupportedDevice::AddressBytes { required: 1 })
+        );
+        assert_eq!(
+            new(0x50, 19),
+            Err(UnsupportedDevice::AddressBits { bits: 19 })
+        );
+        assert_eq!(
+            new(0x51, 17),
+            Err(UnsupportedDevice::PageSelectBitsSet {
+                page_select_bits: 1
+            })
+        );
+        assert_eq!(new(0x51, 16), Ok(()));
+
+        // Keeps the default page size, unlike `with_device`
+        let bus = SimBus::new(Address(0, 0), 12);
+        let eeprom = At24Cx::try_new(bus, Address(0, 0), 12, NoopDelay::new()).unwrap();
+        assert_eq!(eeprom.page_size(), PAGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn ds3231_module() {
+        // The module's EEPROM answers at 0x57 with two address bytes
+        let expectations = [
+            expect_page_write(0x57, 0xFFF, &[1]),
+            expect_ack_poll(0x57, 0),
+            expect_read(0x57, 0xFFF, &[1]),
+        ]
+        .concat();
+        let mut eeprom = At24Cx::ds3231_module(I2cMock::new(&expectations), NoopDelay::new());
+        assert_eq!(eeprom.capacity(), 4096);
+        assert_eq!(eeprom.page_size(), 32);
+        // Twice the 10 ms write cycle of the clones
+        assert_eq!(eeprom.poll_max_retries(), 100);
+        eeprom.write(0xFFF, &[1]).await.unwrap();
+        let mut buf = [0];
+        eeprom.read(0xFFF, &mut buf).await.unwrap();
+        assert!(matches!(
+            eeprom.write(0x1000, &[1]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.read(0xFFF, &mut [0; 2]).await,
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn at24c256() {
+        let data: [u8; 0xD0] = core::array::from_fn(|i| i as u8);
+        let expectations = [
+            expect_page_write(0x55, 0x7F30, &data[..0x10]),
+            expect_ack_poll(0x55, 0),
+            expect_page_write(0x55, 0x7F40, &data[0x10..0x50]),
+            expect_ack_poll(0x55, 0),
+            expect_page_write(0x55, 0x7F80, &data[0x50..0x90]),
+            expect_ack_poll(0x55, 0),
+            // Ends exactly at the end of the part, and still waits for the write cycle
+            expect_page_write(0x55, 0x7FC0, &data[0x90..]),
+            expect_ack_poll(0x55, 2),
+            expect_read(0x55, 0x7FFF, &[0xCF]),
+        ]
+        .concat();
+        let mut eeprom = At24Cx::at24c256(
+            I2cMock::new(&expectations),
+            Address::pins(1, 0, 1),
+            NoopDelay::new(),
+        );
+        assert_eq!(eeprom.capacity(), 32768);
+        assert_eq!(eeprom.page_size(), 64);
+        assert_eq!(eeprom.decode_offset(0x7FFF).unwrap().0, 0x55);
+        // Takes the pins without a conversion like the other constructors
+        let other = At24Cx::at24c256(I2cMock::new(&[]), Address(1, 1), NoopDelay::new());
+        assert_eq!(other.decode_offset(0).unwrap().0, 0x56);
+        other.into_parts().0.done();
+        eeprom.write(0x7F30, &data).await.unwrap();
+        let mut buf = [0];
+        eeprom.read(0x7FFF, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xCF]);
+        assert!(matches!(
+            eeprom.write(0x7FFF, &[1, 2]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.write(0x8000, &[1]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.read(0x8000, &mut buf).await,
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn writes_across_small_pages() {
+        assert_eq!(u8::from(Address::pins(1, 0, 1)), 0x55);
+        let address = Address::pins(1, 1, 1);
+        let bus = SimBus::new(address, 12).with_page_size(32);
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+        let data: [u8; 200] = core::array::from_fn(|i| i as u8);
+        eeprom.write(0x10, &data).await.unwrap();
+        assert_eq!(eeprom.write_transaction_count(0x10, 200), 7);
+        assert_eq!(eeprom.i2c.memory()[0x10..0x10 + 200], data);
+        let mut readback = [0; 200];
+        eeprom.read(0x10, &mut readback).await.unwrap();
+        assert_eq!(readback, data);
+
+        eeprom.fill(0xFF0, 0x10, 7).await.unwrap();
+        assert!(eeprom.i2c.memory()[0xFF0..].iter().all(|&b| b == 7));
+        assert!(matches!(
+            eeprom.page_write(0x20, &[0; 33]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.set_page_size(24),
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[tokio::test]
+    async fn probes_the_page_size() {
+        let token = PageOverwriteToken::i_accept_losing_the_page_contents();
+        for page_size in [8, 16, 32, 64, 128, 256] {
+            let bus = SimBus::new(Address(0, 0), 17).with_page_size(page_size);
+            let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+            let probed = eeprom.probe_page_size(0x10100, &token).await.unwrap();
+            assert_eq!(probed, page_size);
+            // The pages after the first one are untouched
+            assert!(eeprom.i2c.memory()[0x10100 + page_size..0x10200]
+                .iter()
+                .all(|b| *b == 0xFF));
+        }
+
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert!(matches!(
+            eeprom.probe_page_size(0x180, &token).await,
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            eeprom.probe_page_size(0x20000, &token).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_pages_that_dont_keep_the_pattern() {
+        let token = PageOverwriteToken::i_accept_losing_the_page_contents();
+        // A stuck bit in the first byte reads like a page of 255 bytes
+        let bus = SimBus::builder(Address(0, 0), 17)
+            .bit_error(0x200, 0x01)
+            .persistent()
+            .build();
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert!(matches!(
+            eeprom.probe_page_size(0x200, &token).await,
+            Err(Error::ReadbackFail)
+        ));
+    }
+}
@@ -0,0 +1,300 @@
+//! Write endurance characterisation.
+//!
+//! # WARNING: DESTRUCTIVE
+//!
+//! Everything in this module deliberately wears out EEPROM cells until they fail. A page that
+//! was hammered is permanently damaged and must never be used to store data again. Only run
+//! this on sacrificial parts on a test bench, never on a device that ships.
+//!
+//! To make accidental use harder, every entry point requires a [`DestructiveTestToken`].
+
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+/// Proof that the caller knowingly runs a test that destroys EEPROM cells.
+pub struct DestructiveTestToken(());
+
+impl DestructiveTestToken {
+    /// I understand that the endurance test permanently wears out the page it runs on.
+    pub fn i_accept_permanent_eeprom_damage() -> Self {
+        Self(())
+    }
+}
+
+/// Passed to the progress callback after every completed cycle.
+#[derive(Debug, Clone, Copy)]
+pub struct EnduranceStatus {
+    /// Total number of completed cycles, including resumed ones
+    pub cycle: u32,
+    /// Number of bytes in the page that have failed so far
+    pub failed_bytes: usize,
+}
+
+/// Bytes of a report serialized by [`EnduranceReport::to_bytes`]
+pub const REPORT_SIZE: usize = 10 + 4 * PAGE_SIZE;
+
+/// Stored for a byte that hasn't failed yet
+const NO_FAILURE: u32 = u32::MAX;
+
+/// Result of an endurance run. Save it with [`to_bytes`](Self::to_bytes) and restore it with
+/// [`from_bytes`](Self::from_bytes) to resume a run after a reset.
+#[derive(Debug, Clone)]
+pub struct EnduranceReport {
+    /// Page index that was hammered
+    pub page: u32,
+    /// Total number of completed write cycles
+    pub cycles_completed: u32,
+    page_size: usize,
+    first_failure: [Option<u32>; PAGE_SIZE],
+}
+
+impl EnduranceReport {
+    /// An empty report to start a fresh run on `page` of a part with pages of `page_size`
+    /// bytes. `None` unless the page size is a power of two up to [`PAGE_SIZE`].
+    pub fn new(page: u32, page_size: usize) -> Option<Self> {
+        if !page_size.is_power_of_two() || page_size > PAGE_SIZE {
+            return None;
+        }
+        Some(Self {
+            page,
+            cycles_completed: 0,
+            page_size,
+            first_failure: [None; PAGE_SIZE],
+        })
+    }
+
+    /// Size of the hammered page
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// First cycle at which verification failed, per byte of the page
+    pub fn first_failure(&self) -> &[Option<u32>] {
+        &self.first_failure[..self.page_size]
+    }
+
+    pub fn failed_bytes(&self) -> usize {
+        self.first_failure().iter().filter(|f| f.is_some()).count()
+    }
+
+    /// Serializes the report: the page, completed cycles and page size as little endian
+    /// `u32`, `u32` and `u16`, followed by the first failing cycle of each of the
+    /// [`PAGE_SIZE`] bytes as a little endian `u32`, `0xFFFFFFFF` for none.
+    pub fn to_bytes(&self) -> [u8; REPORT_SIZE] {
+        let mut bytes = [0; REPORT_SIZE];
+        bytes[0..4].copy_from_slice(&self.page.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.cycles_completed.to_le_bytes());
+        bytes[8..10].copy_from_slice(&(self.page_size as u16).to_le_bytes());
+        for (out, failure) in bytes[10..].chunks_exact_mut(4).zip(self.first_failure) {
+            out.copy_from_slice(&failure.unwrap_or(NO_FAILURE).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Restores a report saved with [`to_bytes`](Self::to_bytes). `None` if the bytes don't
+    /// hold a report: the page size isn't valid, a byte failed in a cycle that wasn't
+    /// completed or a byte past the page failed.
+    pub fn from_bytes(bytes: &[u8; REPORT_SIZE]) -> Option<Self> {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let page_size = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let mut report = Self::new(u32_at(0), page_size)?;
+        report.cycles_completed = u32_at(4);
+        for (i, failure) in report.first_failure.iter_mut().enumerate() {
+            *failure = match u32_at(10 + 4 * i) {
+                NO_FAILURE => None,
+                cycle if cycle < report.cycles_completed && i < page_size => Some(cycle),
+                _ => return None,
+            };
+        }
+        Some(report)
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Writes alternating patterns to `page`, counted in the driver's
+    /// [pages](Self::set_page_size), for `cycles` cycles, verifying each write.
+    ///
+    /// Stops early once every byte of the page has failed. See the [module docs](self).
+    pub async fn hammer_page(
+        &mut self,
+        token: &DestructiveTestToken,
+        page: u32,
+        cycles: u32,
+        progress: impl FnMut(EnduranceStatus),
+    ) -> Result<EnduranceReport, Error<E>> {
+        let report = EnduranceReport::new(page, self.page_size).ok_or(Error::InvalidArgument)?;
+        self.resume_hammer_page(token, report, cycles, progress)
+            .await
+    }
+
+    /// Continues a run from a previously saved report for another `cycles` cycles. Returns
+    /// `InvalidArgument` if the report is for another page size than the driver's.
+    pub async fn resume_hammer_page(
+        &mut self,
+        _token: &DestructiveTestToken,
+        mut report: EnduranceReport,
+        cycles: u32,
+        mut progress: impl FnMut(EnduranceStatus),
+    ) -> Result<EnduranceReport, Error<E>> {
+        let page_size = self.page_size;
+        if report.page_size != page_size {
+            return Err(Error::InvalidArgument);
+        }
+        let page_count = (self.capacity() / page_size) as u32;
+        if report.page >= page_count {
+            return Err(Error::OutOfBounds);
+        }
+        let address = report.page * page_size as u32;
+        let mut readback = [0; PAGE_SIZE];
+        for _ in 0..cycles {
+            if report.failed_bytes() == page_size {
+                break;
+            }
+            let cycle = report.cycles_completed;
+            // Every bit flips on every cycle
+            let pattern = if cycle & 1 == 0 { 0xAA } else { 0x55 };
+            let data = [pattern; PAGE_SIZE];
+            match self
+                .page_write_verified(address, &data[..page_size], &mut readback)
+                .await
+            {
+                Ok(()) => {}
+                Err(Error::ReadbackFail) => {
+                    let failures = report.first_failure[..page_size].iter_mut();
+                    for (failure, &byte) in failures.zip(&readback[..page_size]) {
+                        if failure.is_none() && byte != pattern {
+                            *failure = Some(cycle);
+                        }
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+            report.cycles_completed += 1;
+            progress(EnduranceStatus {
+                cycle: report.cycles_completed,
+                failed_bytes: report.failed_bytes(),
+            });
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, DeviceKind};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver(bus: SimBus) -> At24Cx<SimBus, NoopDelay> {
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn records_first_failure_per_byte() {
+        let mut bus = SimBus::new(Address(0, 0), 17).with_endurance(10);
+        // Byte 3 of page 2 is already worn
+        bus.set_write_count(2 * PAGE_SIZE + 3, 6);
+        let mut eeprom = driver(bus);
+        let token = DestructiveTestToken::i_accept_permanent_eeprom_damage();
+        let mut statuses = 0;
+        let report = eeprom
+            .hammer_page(&token, 2, 20, |_| statuses += 1)
+            .await
+            .unwrap();
+
+        assert_eq!(report.first_failure()[3], Some(4));
+        assert_eq!(report.first_failure()[0], Some(10));
+        assert_eq!(report.first_failure()[PAGE_SIZE - 1], Some(10));
+        // Stops as soon as the whole page has failed
+        assert_eq!(report.cycles_completed, 11);
+        assert_eq!(statuses, 11);
+        assert_eq!(eeprom.i2c.write_count(PAGE_SIZE), 0);
+    }
+
+    #[tokio::test]
+    async fn resumes_from_saved_report() {
+        let mut eeprom = driver(SimBus::new(Address(0, 0), 17).with_endurance(8));
+        let token = DestructiveTestToken::i_accept_permanent_eeprom_damage();
+        let report = eeprom.hammer_page(&token, 0, 5, |_| {}).await.unwrap();
+        assert_eq!(report.cycles_completed, 5);
+        assert_eq!(report.failed_bytes(), 0);
+        // Across a reset
+        let saved = report.to_bytes();
+        let report = EnduranceReport::from_bytes(&saved).unwrap();
+        assert_eq!((report.page, report.cycles_completed), (0, 5));
+
+        let mut last = None;
+        let report = eeprom
+            .resume_hammer_page(&token, report, 10, |s| last = Some(s))
+            .await
+            .unwrap();
+        assert_eq!(report.first_failure()[0], Some(8));
+        assert_eq!(report.cycles_completed, 9);
+        let last = last.unwrap();
+        assert_eq!((last.cycle, last.failed_bytes), (9, PAGE_SIZE));
+    }
+
+    #[tokio::test]
+    async fn saves_and_restores_reports() {
+        let mut eeprom = driver(SimBus::new(Address(0, 0), 17).with_endurance(3));
+        eeprom.set_page_size(16).unwrap();
+        let token = DestructiveTestToken::i_accept_permanent_eeprom_damage();
+        let report = eeprom.hammer_page(&token, 7, 10, |_| {}).await.unwrap();
+        let saved = report.to_bytes();
+        let restored = EnduranceReport::from_bytes(&saved).unwrap();
+        assert_eq!(restored.page_size(), 16);
+        assert_eq!(restored.first_failure(), report.first_failure());
+        assert_eq!(restored.to_bytes(), saved);
+
+        // Another part's page size
+        eeprom.set_page_size(32).unwrap();
+        let result = eeprom.resume_hammer_page(&token, restored, 1, |_| {}).await;
+        assert!(matches!(result, Err(Error::InvalidArgument)));
+
+        let mut bad_size = saved;
+        bad_size[8] = 24;
+        assert!(EnduranceReport::from_bytes(&bad_size).is_none());
+        let mut future_failure = saved;
+        future_failure[10..14].copy_from_slice(&report.cycles_completed.to_le_bytes());
+        assert!(EnduranceReport::from_bytes(&future_failure).is_none());
+        let mut past_the_page = saved;
+        past_the_page[10 + 4 * 16..10 + 4 * 17].fill(0);
+        assert!(EnduranceReport::from_bytes(&past_the_page).is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_page_outside_device() {
+        let mut eeprom = driver(SimBus::new(Address(0, 0), 17));
+        let token = DestructiveTestToken::i_accept_permanent_eeprom_damage();
+        let result = eeprom.hammer_page(&token, 512, 1, |_| {}).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+    }
+
+    #[tokio::test]
+    async fn hammers_small_pages() {
+        let address = Address::pins(1, 1, 1);
+        let mut bus = SimBus::new(address, 12)
+            .with_page_size(32)
+            .with_endurance(4);
+        bus.set_write_count(3 * 32 + 1, 2);
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+        let token = DestructiveTestToken::i_accept_permanent_eeprom_damage();
+        let report = eeprom.hammer_page(&token, 3, 20, |_| {}).await.unwrap();
+        assert_eq!(report.first_failure()[1], Some(2));
+        assert_eq!(report.first_failure()[31], Some(4));
+        assert_eq!(report.first_failure().len(), 32);
+        assert_eq!((report.failed_bytes(), report.cycles_completed), (32, 5));
+        // Only the page itself was written
+        assert_eq!(eeprom.i2c.write_count(4 * 32), 0);
+        assert_eq!(eeprom.i2c.write_count(3 * 32 - 1), 0);
+        let result = eeprom.hammer_page(&token, 128, 1, |_| {}).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+    }
+}
@@ -0,0 +1,425 @@
+//! Operation sequences checked against plain models, shared by the property tests and the
+//! `cargo fuzz` targets in `fuzz/`.
+//!
+//! [`decode_ops`] turns arbitrary bytes into a sequence of [`Op`]s, leaning towards the
+//! interesting offsets and lengths like the property test strategies do: page boundaries,
+//! the 64 KiB block boundary, the end of the device, empty lengths and values that overflow
+//! `u32` arithmetic. [`run`] applies them to a simulated driver and a `Vec<u8>` model of the
+//! device and panics as soon as the two disagree, about the contents or about whether an
+//! operation fails. The `kv` feature adds the same for the key-value store.
+
+use crate::sim::{SimBus, SimClock, SimDelay};
+use crate::{Address, At24Cx, Error, PAGE_SIZE};
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use std::vec;
+use std::vec::Vec;
+
+/// Address bits of the simulated device, an AT24CM01
+pub const ADDRESS_BITS: usize = 17;
+/// Capacity of the simulated device
+pub const CAPACITY: u32 = 1 << ADDRESS_BITS;
+/// Offsets that are interesting on their own, besides the page boundaries
+pub const BOUNDARIES: [u32; 3] = [0, 0x10000, CAPACITY];
+/// Longest sequence [`decode_ops`] returns
+pub const MAX_OPS: usize = 64;
+
+/// One step of a sequence
+#[derive(Debug, Clone)]
+pub enum Op {
+    Write { offset: u32, data: Vec<u8> },
+    PageWrite { offset: u32, data: Vec<u8> },
+    Fill { offset: u32, len: usize, value: u8 },
+    Read { offset: u32, len: usize },
+    CanWrite { offset: u32, len: usize },
+    AlignUp { offset: u32 },
+}
+
+/// An offset up to 4 bytes before or 3 after `at`, for `delta` in `0..8`
+pub fn near(at: u32, delta: u32) -> u32 {
+    at.wrapping_add(delta % 8).wrapping_sub(4)
+}
+
+/// Data of `len` bytes counting up from `seed`, so shifted writes are told apart
+pub fn pattern(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| seed.wrapping_add(i as u8)).collect()
+}
+
+/// Reads the input of a fuzz target, yielding zeros once it runs out
+struct Input<'a> {
+    bytes: &'a [u8],
+}
+
+impl Input<'_> {
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn u8(&mut self) -> u8 {
+        let Some((&first, rest)) = self.bytes.split_first() else {
+            return 0;
+        };
+        self.bytes = rest;
+        first
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes([self.u8(), self.u8()])
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes([self.u8(), self.u8(), self.u8(), self.u8()])
+    }
+
+    fn offset(&mut self) -> u32 {
+        let delta = self.u8() as u32;
+        match delta >> 3 & 0b111 {
+            0 | 1 => self.u32() % CAPACITY,
+            2 | 3 => near(
+                (self.u16() as u32 % (CAPACITY / PAGE_SIZE as u32)) * PAGE_SIZE as u32,
+                delta,
+            ),
+            4 => near(BOUNDARIES[self.u8() as usize % BOUNDARIES.len()], delta),
+            5 => u32::MAX - delta % 16,
+            _ => self.u32(),
+        }
+    }
+
+    fn len(&mut self) -> usize {
+        match self.u8() {
+            tag @ 0..=127 => tag as usize % 16,
+            tag @ 128..=191 => PAGE_SIZE - 2 + tag as usize % 5,
+            _ => self.u16() as usize % (3 * PAGE_SIZE),
+        }
+    }
+}
+
+/// Decodes up to [`MAX_OPS`] operations from `input`, every input is a valid sequence
+pub fn decode_ops(input: &[u8]) -> Vec<Op> {
+    let mut input = Input { bytes: input };
+    let mut ops = Vec::new();
+    while !input.is_empty() && ops.len() < MAX_OPS {
+        let op = match input.u8() % 6 {
+            0 => Op::Write {
+                offset: input.offset(),
+                data: pattern(input.u8(), input.len()),
+            },
+            1 => Op::PageWrite {
+                offset: input.offset(),
+                data: pattern(input.u8(), input.u16() as usize % (PAGE_SIZE + 2)),
+            },
+            2 => Op::Fill {
+                offset: input.offset(),
+                len: input.len(),
+                value: input.u8(),
+            },
+            3 => Op::Read {
+                offset: input.offset(),
+                len: input.len(),
+            },
+            4 => Op::CanWrite {
+                offset: input.offset(),
+                len: input.u32() as usize,
+            },
+            _ => Op::AlignUp {
+                offset: input.offset(),
+            },
+        };
+        ops.push(op);
+    }
+    ops
+}
+
+/// Whether `len` bytes at `offset` fit on the device, without overflowing
+fn fits(offset: u32, len: usize) -> bool {
+    offset as u64 + len as u64 <= CAPACITY as u64
+}
+
+/// A driver on a blank simulated device
+pub fn driver() -> At24Cx<SimBus, SimDelay> {
+    let bus = SimBus::new(Address(0, 0), ADDRESS_BITS);
+    At24Cx::new(
+        bus,
+        Address(0, 0),
+        ADDRESS_BITS,
+        SimDelay::new(SimClock::new()),
+    )
+}
+
+/// Applies `op` to the driver and the model and checks they agree
+pub async fn step(eeprom: &mut At24Cx<SimBus, SimDelay>, model: &mut [u8], op: &Op) {
+    match op {
+        Op::Write { offset, data } => {
+            let result = eeprom.write(*offset, data).await;
+            if fits(*offset, data.len()) {
+                result.unwrap();
+                model[*offset as usize..][..data.len()].copy_from_slice(data);
+            } else {
+                assert!(matches!(result, Err(Error::OutOfBounds)), "{result:?}");
+            }
+        }
+        Op::PageWrite { offset, data } => {
+            let result = eeprom.page_write(*offset, data).await;
+            if data.is_empty() && *offset <= CAPACITY {
+                result.unwrap();
+            } else if *offset < CAPACITY && data.len() <= PAGE_SIZE {
+                result.unwrap();
+                // Rolls over within the page
+                let page = *offset as usize - *offset as usize % PAGE_SIZE;
+                for (i, byte) in data.iter().enumerate() {
+                    model[page + (*offset as usize + i) % PAGE_SIZE] = *byte;
+                }
+            } else {
+                assert!(matches!(result, Err(Error::OutOfBounds)), "{result:?}");
+            }
+        }
+        Op::Fill { offset, len, value } => {
+            let result = eeprom.fill(*offset, *len, *value).await;
+            if fits(*offset, *len) {
+                result.unwrap();
+                model[*offset as usize..][..*len].fill(*value);
+            } else {
+                assert!(matches!(result, Err(Error::OutOfBounds)), "{result:?}");
+            }
+        }
+        Op::Read { offset, len } => {
+            let mut buf = vec![0; *len];
+            let result = eeprom.read(*offset, &mut buf).await;
+            if fits(*offset, *len) {
+                result.unwrap();
+                assert_eq!(buf, model[*offset as usize..][..*len]);
+            } else {
+                assert!(matches!(result, Err(Error::OutOfBounds)), "{result:?}");
+            }
+        }
+        Op::CanWrite { offset, len } => {
+            assert_eq!(eeprom.can_write(*offset, *len).is_ok(), fits(*offset, *len));
+        }
+        Op::AlignUp { offset } => {
+            let aligned = (*offset as u64).div_ceil(PAGE_SIZE as u64) * PAGE_SIZE as u64;
+            match eeprom.align_up_to_page(*offset) {
+                Ok(result) => assert_eq!(result as u64, aligned),
+                Err(e) => {
+                    assert!(aligned > CAPACITY as u64);
+                    assert!(matches!(e, Error::OutOfBounds));
+                }
+            }
+        }
+    }
+    assert!(eeprom.i2c.memory() == model, "memory differs after {op:?}");
+}
+
+/// Runs `ops` on a blank device, panicking at the first disagreement with the model
+pub async fn run(ops: &[Op]) {
+    let mut eeprom = driver();
+    let mut model = vec![0xFF; CAPACITY as usize];
+    for op in ops {
+        step(&mut eeprom, &mut model, op).await;
+    }
+}
+
+/// Polls `future` to completion. The simulator never waits, so this doesn't need a real
+/// executor.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    // SAFETY: the vtable functions do nothing, so any data pointer is fine
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+#[cfg(feature = "kv")]
+pub use kv::{decode_kv_ops, run_kv, KvOp, KV_REGION};
+
+#[cfg(feature = "kv")]
+mod kv {
+    use super::{driver, pattern, Input, MAX_OPS, PAGE_SIZE};
+    use crate::kv::{KvStore, MAX_KEY_LEN, MAX_VALUE_LEN};
+    use crate::Error;
+    use core::ops::Range;
+    use std::collections::BTreeMap;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Region the key-value store is mounted in, small enough to fill up and compact often
+    pub const KV_REGION: Range<u32> = 0x1000..0x1000 + 12 * PAGE_SIZE as u32;
+
+    /// One step of a key-value sequence
+    #[derive(Debug, Clone)]
+    pub enum KvOp {
+        Set {
+            key: Vec<u8>,
+            value: Vec<u8>,
+        },
+        Remove {
+            key: Vec<u8>,
+        },
+        Get {
+            key: Vec<u8>,
+        },
+        Compact,
+        /// Mounts the store again, as after a reboot
+        Remount,
+    }
+
+    impl Input<'_> {
+        /// Keys from a small alphabet so they repeat, including empty and too long ones
+        fn key(&mut self) -> Vec<u8> {
+            let tag = self.u8();
+            let len = match tag >> 6 {
+                0 => 0,
+                3 => MAX_KEY_LEN + (tag & 1) as usize,
+                _ => 1 + (tag & 0b11) as usize,
+            };
+            vec![b'a' + (tag >> 2 & 0b11); len]
+        }
+
+        fn value_len(&mut self) -> usize {
+            match self.u8() {
+                tag @ 0..=127 => tag as usize % 16,
+                tag @ 128..=191 => MAX_VALUE_LEN - 2 + tag as usize % 4,
+                _ => self.u16() as usize % 300,
+            }
+        }
+    }
+
+    /// Decodes up to [`MAX_OPS`] key-value operations from `input`
+    pub fn decode_kv_ops(input: &[u8]) -> Vec<KvOp> {
+        let mut input = Input { bytes: input };
+        let mut ops = Vec::new();
+        while !input.is_empty() && ops.len() < MAX_OPS {
+            let op = match input.u8() % 8 {
+                0..=2 => KvOp::Set {
+                    key: input.key(),
+                    value: pattern(input.u8(), input.value_len()),
+                },
+                3 => KvOp::Remove { key: input.key() },
+                4 | 5 => KvOp::Get { key: input.key() },
+                6 => KvOp::Compact,
+                _ => KvOp::Remount,
+            };
+            ops.push(op);
+        }
+        ops
+    }
+
+    fn valid_key(key: &[u8]) -> bool {
+        (1..=MAX_KEY_LEN).contains(&key.len())
+    }
+
+    /// Runs `ops` on a blank store, panicking at the first disagreement with a map of the
+    /// values that should be stored
+    pub async fn run_kv(ops: &[KvOp]) {
+        let mut eeprom = driver();
+        let mut model: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut store = KvStore::mount(eeprom.partition(KV_REGION).unwrap())
+            .await
+            .unwrap();
+        for op in ops {
+            match op {
+                KvOp::Set { key, value } => match store.set(key, value).await {
+                    Ok(()) => {
+                        model.insert(key.clone(), value.clone());
+                    }
+                    // A store too full for the record is left as it was
+                    Err(Error::Full) => assert!(valid_key(key) && value.len() <= MAX_VALUE_LEN),
+                    Err(e) => {
+                        assert!(matches!(e, Error::InvalidArgument), "{op:?}: {e:?}");
+                        assert!(!valid_key(key) || value.len() > MAX_VALUE_LEN);
+                    }
+                },
+                KvOp::Remove { key } => match store.remove(key).await {
+                    Ok(()) => {
+                        model.remove(key);
+                    }
+                    Err(e) => {
+                        assert!(matches!(e, Error::InvalidArgument), "{op:?}: {e:?}");
+                        assert!(!valid_key(key));
+                    }
+                },
+                KvOp::Get { key } => {
+                    let mut buf = [0; MAX_VALUE_LEN];
+                    match store.get(key, &mut buf).await {
+                        Ok(len) => assert_eq!(Some(&buf[..len]), model.get(key).map(|v| &v[..])),
+                        Err(Error::NotFound) => {
+                            assert!(valid_key(key) && !model.contains_key(key))
+                        }
+                        Err(e) => {
+                            assert!(matches!(e, Error::InvalidArgument), "{op:?}: {e:?}");
+                            assert!(!valid_key(key));
+                        }
+                    }
+                }
+                KvOp::Compact => store.compact().await.unwrap(),
+                KvOp::Remount => {
+                    store = KvStore::mount(eeprom.partition(KV_REGION).unwrap())
+                        .await
+                        .unwrap();
+                }
+            }
+        }
+        // Everything in the model reads back after a final remount
+        let mut store = KvStore::mount(eeprom.partition(KV_REGION).unwrap())
+            .await
+            .unwrap();
+        let mut buf = [0; MAX_VALUE_LEN];
+        for (key, value) in &model {
+            let len = store.get(key, &mut buf).await.unwrap();
+            assert_eq!(&buf[..len], &value[..], "{key:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Runs every seed input of the fuzz target `target` through `run_input`
+    fn for_seeds(target: &str, mut run_input: impl FnMut(&[u8])) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/seeds")
+            .join(target);
+        let mut seeds = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let input = std::fs::read(entry.unwrap().path()).unwrap();
+            run_input(&input);
+            seeds += 1;
+        }
+        assert!(seeds > 0);
+    }
+
+    #[test]
+    fn driver_seeds() {
+        for_seeds("driver_ops", |input| block_on(run(&decode_ops(input))));
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn kv_seeds() {
+        for_seeds("kv_ops", |input| block_on(run_kv(&decode_kv_ops(input))));
+    }
+
+    #[test]
+    fn decodes_every_input() {
+        assert!(decode_ops(&[]).is_empty());
+        // Runs out in the middle of an operation
+        assert!(matches!(
+            decode_ops(&[0])[..],
+            [Op::Write { offset: 0, ref data }] if data.is_empty()
+        ));
+        assert_eq!(decode_ops(&[0xFF; 1024]).len(), MAX_OPS);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{bus_error, At24Cx, DeviceKind, Error, ReadMethod, ADDRESS_BYTES};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+/// An identification page, read at its own device address starting at memory address 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdPage {
+    /// 7-bit device address of the page, including the address pins. For an ST M24M01-D
+    /// with both pins low that is `0x58`.
+    pub device_address: u8,
+    /// Size in bytes
+    pub len: usize,
+}
+
+impl DeviceKind {
+    /// The part's identification page. None of the AT24Cx parts has one, compatible parts
+    /// that do are configured with [`At24Cx::set_id_page`].
+    pub const fn id_page(self) -> Option<IdPage> {
+        None
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Overrides the identification page the [part](DeviceKind::id_page) has
+    pub fn set_id_page(&mut self, id_page: Option<IdPage>) {
+        self.id_page = id_page;
+    }
+
+    pub fn id_page(&self) -> Option<IdPage> {
+        self.id_page
+    }
+
+    /// Reads the start of the identification page into `buf`.
+    ///
+    /// Returns `DeviceNotFound` if the part has no identification page and `OutOfBounds` if
+    /// `buf` is longer than the page.
+    pub async fn read_id_page(&mut self, buf: &mut [u8]) -> Result<(), Error<E>> {
+        let id_page = self.id_page.ok_or(Error::DeviceNotFound)?;
+        if buf.len() > id_page.len {
+            return Err(Error::OutOfBounds);
+        }
+        self.flush().await?;
+        let address = id_page.device_address;
+        let memaddr = [0; ADDRESS_BYTES];
+        self.guard_delay().await;
+        match self.read_method {
+            ReadMethod::CombinedWriteRead => self.i2c.write_read(address, &memaddr, buf).await,
+            ReadMethod::WriteStopRead => match self.i2c.write(address, &memaddr).await {
+                Ok(()) => {
+                    self.guard_delay().await;
+                    self.i2c.read(address, buf).await
+                }
+                Err(e) => Err(e),
+            },
+        }
+        .map_err(bus_error::<I2C>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Address;
+    use embedded_hal_mock::eh1::{
+        delay::NoopDelay,
+        i2c::{Mock as I2cMock, Transaction},
+    };
+
+    #[tokio::test]
+    async fn reads_a_configured_id_page() {
+        let expectations = [Transaction::write_read(
+            0x5A,
+            std::vec![0x00, 0x00],
+            std::vec![0x20, 0xE0, 0x01],
+        )];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(1, 0), 17, NoopDelay::new());
+        let mut buf = [0; 3];
+        assert!(matches!(
+            eeprom.read_id_page(&mut buf).await,
+            Err(Error::DeviceNotFound)
+        ));
+
+        eeprom.set_id_page(Some(IdPage {
+            device_address: 0x5A,
+            len: 256,
+        }));
+        eeprom.read_id_page(&mut buf).await.unwrap();
+        assert_eq!(buf, [0x20, 0xE0, 0x01]);
+        assert!(matches!(
+            eeprom.read_id_page(&mut [0; 257]).await,
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[test]
+    fn no_family_member_has_an_id_page() {
+        for kind in DeviceKind::ALL {
+            assert_eq!(kind.id_page(), None);
+        }
+    }
+}
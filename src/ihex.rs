@@ -0,0 +1,401 @@
+//! Intel HEX import and export over `embedded-io-async` streams.
+//!
+//! [`write_ihex`](At24Cx::write_ihex) understands data (`00`), end of file (`01`), extended
+//! segment address (`02`) and extended linear address (`04`) records, and skips the start
+//! address records `03` and `05`. Consecutive data records are collected into page writes.
+//! [`read_ihex`](At24Cx::read_ihex) emits data records, an extended linear address record
+//! whenever the upper 16 bits of the address change, and an end of file record. Lines end in
+//! `\n`, and `\r\n` is accepted when reading.
+
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_io_async::{Read, Write};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Most data bytes a record can hold
+pub const MAX_IHEX_RECORD_LEN: usize = 255;
+
+/// Byte count, address, type, data and checksum
+const MAX_RECORD_SIZE: usize = 5 + MAX_IHEX_RECORD_LEN;
+/// A colon followed by a record in hex
+const MAX_LINE_LEN: usize = 1 + 2 * MAX_RECORD_SIZE;
+
+/// Why an Intel HEX import or export stopped. Lines are counted from 1.
+#[derive(Debug)]
+pub enum IhexError<E: Debug, IoE> {
+    /// Accessing the device failed
+    Eeprom(Error<E>),
+    /// The stream failed
+    Io(IoE),
+    /// The line isn't a record, or a record of a type that isn't supported
+    Malformed { line: u32 },
+    /// The checksum of the record on the line doesn't match
+    Checksum { line: u32 },
+    /// The record on the line addresses bytes past the end of the device
+    OutOfBounds { line: u32 },
+    /// The input ended without an end of file record
+    MissingEndOfFile,
+}
+
+impl<E: Debug, IoE> From<Error<E>> for IhexError<E, IoE> {
+    fn from(error: Error<E>) -> Self {
+        IhexError::Eeprom(error)
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Writes the data records read from `src` up to its end of file record, and returns the
+    /// number of data bytes written. A record that fails to parse stops the import, the
+    /// records before it may already be written.
+    pub async fn write_ihex<R: Read>(
+        &mut self,
+        src: &mut R,
+    ) -> Result<u32, IhexError<E, R::Error>> {
+        let mut loader = Loader {
+            base: 0,
+            start: 0,
+            pending: [0; PAGE_SIZE],
+            len: 0,
+            written: 0,
+        };
+        let mut line = [0; MAX_LINE_LEN];
+        let mut len = 0;
+        // Longer lines are malformed, but still have to be skipped to their end
+        let mut overlong = false;
+        let mut number = 1;
+        let mut chunk = [0; 64];
+        loop {
+            let n = src.read(&mut chunk).await.map_err(IhexError::Io)?;
+            for &c in &chunk[..n] {
+                if c != b'\n' {
+                    if len < MAX_LINE_LEN {
+                        line[len] = c;
+                        len += 1;
+                    } else {
+                        overlong = true;
+                    }
+                    continue;
+                }
+                if overlong {
+                    return Err(IhexError::Malformed { line: number });
+                }
+                if loader.line(self, &line[..len], number).await? {
+                    return Ok(loader.written);
+                }
+                len = 0;
+                number += 1;
+            }
+            if n == 0 {
+                break;
+            }
+        }
+        // The end of file record may lack a line ending
+        if len > 0 && !overlong && loader.line(self, &line[..len], number).await? {
+            return Ok(loader.written);
+        }
+        Err(IhexError::MissingEndOfFile)
+    }
+
+    /// Writes `range` to `dst` as Intel HEX with `bytes_per_line` data bytes per record,
+    /// followed by an end of file record.
+    ///
+    /// Returns `InvalidArgument` if `bytes_per_line` is 0 and `OutOfBounds` if the range isn't
+    /// on the device.
+    pub async fn read_ihex<W: Write>(
+        &mut self,
+        range: Range<u32>,
+        bytes_per_line: u8,
+        dst: &mut W,
+    ) -> Result<(), IhexError<E, W::Error>> {
+        if bytes_per_line == 0 {
+            return Err(Error::InvalidArgument.into());
+        }
+        if range.start > range.end || range.end as usize > self.capacity() {
+            return Err(Error::OutOfBounds.into());
+        }
+        let mut upper = 0;
+        let mut data = [0; MAX_IHEX_RECORD_LEN];
+        let mut offset = range.start;
+        while offset < range.end {
+            if offset >> 16 != upper {
+                upper = offset >> 16;
+                write_record(dst, 0, 0x04, &(upper as u16).to_be_bytes()).await?;
+            }
+            // Records can't wrap around the 16 bit address
+            let segment_end = (upper + 1) << 16;
+            let len = (bytes_per_line as u32)
+                .min(range.end - offset)
+                .min(segment_end - offset) as usize;
+            let data = &mut data[..len];
+            self.read(offset, data).await?;
+            write_record(dst, offset as u16, 0x00, data).await?;
+            offset += len as u32;
+        }
+        write_record(dst, 0, 0x01, &[]).await?;
+        dst.flush().await.map_err(IhexError::Io)
+    }
+}
+
+/// State of an import, collecting data for the current page
+struct Loader {
+    /// Added to the address of data records
+    base: u32,
+    /// Offset of the collected data
+    start: u32,
+    pending: [u8; PAGE_SIZE],
+    len: usize,
+    written: u32,
+}
+
+impl Loader {
+    /// Handles one line, returning `true` at the end of file record
+    async fn line<I2C, E: Debug, D: DelayNs, IoE>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        text: &[u8],
+        line: u32,
+    ) -> Result<bool, IhexError<E, IoE>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        if text.is_empty() {
+            return Ok(false);
+        }
+        let mut buf = [0; MAX_RECORD_SIZE];
+        let record = parse(text, &mut buf).ok_or(IhexError::Malformed { line })?;
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(IhexError::Checksum { line });
+        }
+        let address = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let data = &record[4..record.len() - 1];
+        match (record[3], data.len()) {
+            (0x00, _) => {
+                let offset = self.base + address;
+                if eeprom.can_write(offset, data.len()).is_err() {
+                    return Err(IhexError::OutOfBounds { line });
+                }
+                self.push(eeprom, offset, data).await?;
+            }
+            (0x01, 0) => {
+                self.flush(eeprom).await?;
+                return Ok(true);
+            }
+            (0x02, 2) => self.base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            (0x04, 2) => self.base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            (0x03 | 0x05, 4) => {}
+            _ => return Err(IhexError::Malformed { line }),
+        }
+        Ok(false)
+    }
+
+    /// Collects `data` for `offset`, writing out whatever it doesn't continue
+    async fn push<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        mut offset: u32,
+        mut data: &[u8],
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        while !data.is_empty() {
+            if self.len > 0 && offset != self.start + self.len as u32 {
+                self.flush(eeprom).await?;
+            }
+            if self.len == 0 {
+                self.start = offset;
+            }
+            let page_size = eeprom.page_size();
+            let room = page_size - offset as usize % page_size;
+            let take = room.min(data.len());
+            self.pending[self.len..self.len + take].copy_from_slice(&data[..take]);
+            self.len += take;
+            offset += take as u32;
+            data = &data[take..];
+            if take == room {
+                self.flush(eeprom).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if self.len > 0 {
+            eeprom.write(self.start, &self.pending[..self.len]).await?;
+            self.written += self.len as u32;
+            self.len = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the hex digits of a line into `buf`, checking the byte count but not the
+/// checksum
+fn parse<'b>(text: &[u8], buf: &'b mut [u8; MAX_RECORD_SIZE]) -> Option<&'b [u8]> {
+    let digits = text.strip_prefix(b":")?;
+    if digits.len() % 2 != 0 || digits.len() < 10 || digits.len() > 2 * MAX_RECORD_SIZE {
+        return None;
+    }
+    let record = &mut buf[..digits.len() / 2];
+    for (byte, pair) in record.iter_mut().zip(digits.chunks(2)) {
+        *byte = hex_value(pair[0])? << 4 | hex_value(pair[1])?;
+    }
+    (record.len() == 5 + record[0] as usize).then_some(record)
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        _ => None,
+    }
+}
+
+async fn write_record<W: Write, E: Debug>(
+    dst: &mut W,
+    address: u16,
+    kind: u8,
+    data: &[u8],
+) -> Result<(), IhexError<E, W::Error>> {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut line = [0; MAX_LINE_LEN + 1];
+    line[0] = b':';
+    let mut len = 1;
+    let [high, low] = address.to_be_bytes();
+    let header = [data.len() as u8, high, low, kind];
+    let checksum = header
+        .iter()
+        .chain(data)
+        .fold(0u8, |sum, b| sum.wrapping_sub(*b));
+    for byte in header.iter().chain(data).chain([&checksum]) {
+        line[len] = DIGITS[(byte >> 4) as usize];
+        line[len + 1] = DIGITS[(byte & 0xF) as usize];
+        len += 2;
+    }
+    line[len] = b'\n';
+    dst.write_all(&line[..len + 1]).await.map_err(IhexError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    const FIXTURE: &str = "\
+:0C01000068656C6C6F2C20776F726C646B\r
+\r
+:020000040001F9\r
+:04234500010203048A\r
+:0400000500000100F6\r
+:00000001FF\r
+this is ignored
+";
+
+    #[tokio::test]
+    async fn imports_a_fixture() {
+        let mut eeprom = driver();
+        let written = eeprom.write_ihex(&mut FIXTURE.as_bytes()).await.unwrap();
+        assert_eq!(written, 16);
+        assert_eq!(&eeprom.i2c.memory()[0x100..0x10C], b"hello, world");
+        assert_eq!(eeprom.i2c.memory()[0x12345..0x1234A], [1, 2, 3, 4, 0xFF]);
+    }
+
+    #[tokio::test]
+    async fn exports_across_64k() {
+        let mut eeprom = driver();
+        for (i, byte) in eeprom.i2c.memory_mut().iter_mut().enumerate() {
+            *byte = (i % 251) as u8;
+        }
+        let mut out = [0; 128];
+        let mut dst = &mut out[..];
+        eeprom
+            .read_ihex(0xFFF8..0x10008, 8, &mut dst)
+            .await
+            .unwrap();
+        let len = 128 - dst.len();
+        assert_eq!(
+            core::str::from_utf8(&out[..len]).unwrap(),
+            "\
+:08FFF80011121314151617185D
+:020000040001F9
+:08000000191A1B1C1D1E1F2014
+:00000001FF
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn round_trips() {
+        let mut source = driver();
+        for (i, byte) in source.i2c.memory_mut().iter_mut().enumerate() {
+            *byte = (i * 7 % 256) as u8;
+        }
+        let range = 0xFF00..0x10345;
+        let mut image = std::vec![0; 0x4000];
+        let mut dst = &mut image[..];
+        source.read_ihex(range.clone(), 32, &mut dst).await.unwrap();
+        let len = 0x4000 - dst.len();
+
+        let mut target = driver();
+        let written = target.write_ihex(&mut &image[..len]).await.unwrap();
+        assert_eq!(written, range.end - range.start);
+        let range = range.start as usize..range.end as usize;
+        assert_eq!(
+            target.i2c.memory()[range.clone()],
+            source.i2c.memory()[range]
+        );
+        assert_eq!(target.i2c.memory()[0xFEFF], 0xFF);
+        assert_eq!(target.i2c.memory()[0x10345], 0xFF);
+    }
+
+    #[tokio::test]
+    async fn reports_bad_lines() {
+        let mut eeprom = driver();
+        let mut import = async |text: &str| eeprom.write_ihex(&mut text.as_bytes()).await;
+        assert!(matches!(
+            import(":0C0100\n").await,
+            Err(IhexError::Malformed { line: 1 })
+        ));
+        assert!(matches!(
+            import(":020000040001F9\n:0100000G01FE\n").await,
+            Err(IhexError::Malformed { line: 2 })
+        ));
+        assert!(matches!(
+            import(":04234500010203048B\n").await,
+            Err(IhexError::Checksum { line: 1 })
+        ));
+        assert!(matches!(
+            import(":020000040002F8\n\n:0100000001FE\n").await,
+            Err(IhexError::OutOfBounds { line: 3 })
+        ));
+        assert!(matches!(
+            import(":0C01000068656C6C6F2C20776F726C646B\n").await,
+            Err(IhexError::MissingEndOfFile)
+        ));
+        // Nothing after the end of file record is read
+        assert_eq!(import(":00000001FF\n:0C0100").await.unwrap(), 0);
+        assert!(matches!(
+            eeprom.read_ihex(0..16, 0, &mut &mut [0u8; 64][..]).await,
+            Err(IhexError::Eeprom(Error::InvalidArgument))
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+//! Dumping the device to, and restoring it from, an `embedded-io-async` stream.
+//!
+//! Both directions stream the data in chunks that never cross a page, so at most a page is
+//! buffered on the stack. The progress callback gets the number of bytes transferred so far
+//! after every chunk.
+
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_io_async::{Read, Write};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Why a [dump](At24Cx::dump_to) or [restore](At24Cx::restore_from) stopped
+#[derive(Debug)]
+pub enum TransferError<E: Debug, IoE> {
+    /// Accessing the device failed
+    Eeprom(Error<E>),
+    /// The stream failed
+    Io(IoE),
+    /// The source ended after `written` bytes, which were restored
+    Truncated { written: u32 },
+    /// The byte at `offset` didn't read back as restored
+    Mismatch { offset: u32 },
+}
+
+impl<E: Debug, IoE> From<Error<E>> for TransferError<E, IoE> {
+    fn from(error: Error<E>) -> Self {
+        TransferError::Eeprom(error)
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Writes the contents of the whole device to `w`
+    pub async fn dump_to<W: Write>(
+        &mut self,
+        w: &mut W,
+        progress: impl FnMut(u32),
+    ) -> Result<(), TransferError<E, W::Error>> {
+        let range = 0..self.capacity() as u32;
+        self.dump_range_to(range, w, progress).await
+    }
+
+    /// Writes the bytes in `range` to `w`
+    pub async fn dump_range_to<W: Write>(
+        &mut self,
+        range: Range<u32>,
+        w: &mut W,
+        mut progress: impl FnMut(u32),
+    ) -> Result<(), TransferError<E, W::Error>> {
+        if range.start > range.end || range.end as usize > self.capacity() {
+            return Err(Error::OutOfBounds.into());
+        }
+        let mut buf = [0; PAGE_SIZE];
+        let mut offset = range.start;
+        while offset < range.end {
+            let chunk = &mut buf[..chunk_len(offset, range.end, self.page_size)];
+            self.read(offset, chunk).await?;
+            w.write_all(chunk).await.map_err(TransferError::Io)?;
+            offset += chunk.len() as u32;
+            progress(offset - range.start);
+        }
+        w.flush().await.map_err(TransferError::Io)
+    }
+
+    /// Overwrites the whole device with the contents of `r`, see
+    /// [`restore_range_from`](Self::restore_range_from)
+    pub async fn restore_from<R: Read>(
+        &mut self,
+        r: &mut R,
+        verify: bool,
+        progress: impl FnMut(u32),
+    ) -> Result<(), TransferError<E, R::Error>> {
+        let range = 0..self.capacity() as u32;
+        self.restore_range_from(range, r, verify, progress).await
+    }
+
+    /// Overwrites `range` with the contents of `r`. With `verify` every chunk is read back
+    /// after it is written.
+    ///
+    /// Returns `Truncated` if `r` ends before the range is filled, after writing what it got,
+    /// and `Mismatch` with the first byte that didn't read back.
+    pub async fn restore_range_from<R: Read>(
+        &mut self,
+        range: Range<u32>,
+        r: &mut R,
+        verify: bool,
+        mut progress: impl FnMut(u32),
+    ) -> Result<(), TransferError<E, R::Error>> {
+        if range.start > range.end || range.end as usize > self.capacity() {
+            return Err(Error::OutOfBounds.into());
+        }
+        let mut buf = [0; PAGE_SIZE];
+        let mut readback = [0; PAGE_SIZE];
+        let mut offset = range.start;
+        while offset < range.end {
+            let chunk = &mut buf[..chunk_len(offset, range.end, self.page_size)];
+            let mut len = 0;
+            while len < chunk.len() {
+                match r.read(&mut chunk[len..]).await.map_err(TransferError::Io)? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            if len > 0 {
+                self.write(offset, &chunk[..len]).await?;
+                if verify {
+                    let readback = &mut readback[..len];
+                    self.read(offset, readback).await?;
+                    if let Some(i) = readback.iter().zip(&chunk[..len]).position(|(a, b)| a != b) {
+                        return Err(TransferError::Mismatch {
+                            offset: offset + i as u32,
+                        });
+                    }
+                }
+                offset += len as u32;
+                progress(offset - range.start);
+            }
+            if len < chunk.len() {
+                return Err(TransferError::Truncated {
+                    written: offset - range.start,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Length of the chunk at `offset`, up to the end of its page of `page_size` bytes or `end`
+fn chunk_len(offset: u32, end: u32, page_size: usize) -> usize {
+    let page_end = (offset / page_size as u32 + 1) * page_size as u32;
+    (page_end.min(end) - offset) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver(bus: SimBus) -> At24Cx<SimBus, NoopDelay> {
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn round_trips() {
+        let mut source = driver(SimBus::new(Address(0, 0), 17));
+        for (i, byte) in source.i2c.memory_mut().iter_mut().enumerate() {
+            *byte = (i % 251) as u8;
+        }
+        let mut image = std::vec![0; source.capacity()];
+        let mut calls = 0;
+        source
+            .dump_to(&mut &mut image[..], |done| {
+                calls += 1;
+                assert_eq!(done, calls * PAGE_SIZE as u32);
+            })
+            .await
+            .unwrap();
+        assert_eq!(calls, 512);
+        assert_eq!(image, source.i2c.memory());
+
+        let mut target = driver(SimBus::new(Address(0, 0), 17));
+        let mut done = 0;
+        target
+            .restore_from(&mut &image[..], true, |d| done = d)
+            .await
+            .unwrap();
+        assert_eq!(done, 131072);
+        assert_eq!(target.i2c.memory(), source.i2c.memory());
+
+        // A range that starts and ends within pages
+        let mut part = [0; 300];
+        source
+            .dump_range_to(0x1F0..0x31C, &mut &mut part[..], |_| {})
+            .await
+            .unwrap();
+        assert_eq!(part[..], source.i2c.memory()[0x1F0..0x31C]);
+    }
+
+    #[tokio::test]
+    async fn reports_truncated_sources() {
+        let mut eeprom = driver(SimBus::new(Address(0, 0), 17));
+        let image = [0x5A; 300];
+        assert!(matches!(
+            eeprom
+                .restore_range_from(0x80..0x200, &mut &image[..], false, |_| {})
+                .await,
+            Err(TransferError::Truncated { written: 300 })
+        ));
+        assert!(eeprom.i2c.memory()[0x80..0x1AC].iter().all(|&b| b == 0x5A));
+        assert_eq!(eeprom.i2c.memory()[0x1AC], 0xFF);
+    }
+
+    #[tokio::test]
+    async fn reports_the_first_mismatch() {
+        let mut bus = SimBus::new(Address(0, 0), 17).with_endurance(1);
+        // A worn out cell on the second page
+        bus.set_write_count(0x123, 1);
+        let mut eeprom = driver(bus);
+        let image = [0; 0x200];
+        assert!(matches!(
+            eeprom
+                .restore_range_from(0..0x200, &mut &image[..], true, |_| {})
+                .await,
+            Err(TransferError::Mismatch { offset: 0x123 })
+        ));
+        // Without verify the restore goes through
+        eeprom
+            .restore_range_from(0..0x200, &mut &image[..], false, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(eeprom.i2c.memory()[0x123], 0xFF);
+    }
+}
@@ -0,0 +1,607 @@
+//! Key-value store in a region of the device.
+//!
+//! The region is split into two halves of which one is active. The active half starts with
+//! a header (magic `KVS1`, a generation number and a CRC-32) followed by an append-only log
+//! of records:
+//!
+//! | bytes     | content                                    |
+//! |-----------|--------------------------------------------|
+//! | 0..2      | generation of the half it was written in   |
+//! | 2         | key length                                 |
+//! | 3         | kind, value or tombstone                   |
+//! | 4..6      | value length                               |
+//! | 6..8      | CRC-16 of the key, to skip records cheaply |
+//! | 8..8+k    | key                                        |
+//! | ..+v      | value                                      |
+//! | ..+4      | CRC-32 over everything before it           |
+//!
+//! All integers are little endian. The newest record for a key wins, a tombstone removes
+//! it. Mounting scans the log and stops at the first record that doesn't check out, so a
+//! record torn by a power loss is ignored and overwritten by the next one.
+//!
+//! [`compact`](KvStore::compact) copies the live records to the other half and only then
+//! writes that half's header with the next generation, so an interrupted compaction leaves
+//! the old half active. [`set`](KvStore::set) compacts on its own when the active half is
+//! full.
+
+use crate::crc::{crc16, crc32_update};
+use crate::{EepromPartition, Error};
+use core::cmp::min;
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Longest key accepted by the store
+pub const MAX_KEY_LEN: usize = 32;
+/// Longest value accepted by the store
+pub const MAX_VALUE_LEN: usize = 1024;
+
+const MAGIC: [u8; 4] = *b"KVS1";
+const HALF_HEADER_SIZE: u32 = 10;
+const RECORD_HEADER_SIZE: usize = 8;
+const RECORD_OVERHEAD: u32 = RECORD_HEADER_SIZE as u32 + 4;
+const KIND_VALUE: u8 = 1;
+const KIND_TOMBSTONE: u8 = 2;
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct RecordHeader {
+    generation: u16,
+    key_len: u8,
+    kind: u8,
+    value_len: u16,
+    hash: u16,
+}
+
+impl RecordHeader {
+    fn encode(&self) -> [u8; RECORD_HEADER_SIZE] {
+        let mut out = [0; RECORD_HEADER_SIZE];
+        out[0..2].copy_from_slice(&self.generation.to_le_bytes());
+        out[2] = self.key_len;
+        out[3] = self.kind;
+        out[4..6].copy_from_slice(&self.value_len.to_le_bytes());
+        out[6..8].copy_from_slice(&self.hash.to_le_bytes());
+        out
+    }
+
+    fn decode(bytes: &[u8; RECORD_HEADER_SIZE]) -> Self {
+        Self {
+            generation: u16::from_le_bytes([bytes[0], bytes[1]]),
+            key_len: bytes[2],
+            kind: bytes[3],
+            value_len: u16::from_le_bytes([bytes[4], bytes[5]]),
+            hash: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+
+    /// Size of the whole record on the device
+    fn len(&self) -> u32 {
+        RECORD_OVERHEAD + self.key_len as u32 + self.value_len as u32
+    }
+}
+
+/// Key-value store over a partition, see the [module docs](self)
+pub struct KvStore<'a, I2C, D> {
+    region: EepromPartition<'a, I2C, D>,
+    half_len: u32,
+    active: u32,
+    generation: u16,
+    /// End of the log, relative to the start of the active half
+    end: u32,
+}
+
+impl<'a, I2C, E: Debug, D: DelayNs> KvStore<'a, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Opens the store in `region`, initializing it if neither half has a valid header.
+    ///
+    /// Returns `InvalidArgument` if the region is too small to hold the largest record.
+    pub async fn mount(mut region: EepromPartition<'a, I2C, D>) -> Result<Self, Error<E>> {
+        let half_len = region.capacity() as u32 / 2;
+        let largest = HALF_HEADER_SIZE + RECORD_OVERHEAD + (MAX_KEY_LEN + MAX_VALUE_LEN) as u32;
+        if half_len < largest {
+            return Err(Error::InvalidArgument);
+        }
+        let first = read_half_header(&mut region, 0).await?;
+        let second = read_half_header(&mut region, half_len).await?;
+        let (active, generation) = match (first, second) {
+            (Some(a), Some(b)) if (b.wrapping_sub(a) as i16) > 0 => (1, b),
+            (Some(a), _) => (0, a),
+            (None, Some(b)) => (1, b),
+            (None, None) => {
+                write_half_header(&mut region, 0, 0).await?;
+                (0, 0)
+            }
+        };
+
+        let mut store = Self {
+            region,
+            half_len,
+            active,
+            generation,
+            end: HALF_HEADER_SIZE,
+        };
+        while let Some(header) = store.read_valid_record(store.end).await? {
+            store.end += header.len();
+        }
+        Ok(store)
+    }
+
+    /// Bytes left in the active half. [`compact`](Self::compact) may free more.
+    pub fn free_bytes(&self) -> usize {
+        (self.half_len - self.end) as usize
+    }
+
+    /// Reads the value stored for `key` into the start of `buf`, returning its length.
+    ///
+    /// Returns `NotFound` if the key isn't set and `OutOfBounds` if `buf` is too small.
+    pub async fn get(&mut self, key: &[u8], buf: &mut [u8]) -> Result<usize, Error<E>> {
+        check_key(key)?;
+        let (position, header) = match self.find(key).await? {
+            Some((position, header)) if header.kind == KIND_VALUE => (position, header),
+            _ => return Err(Error::NotFound),
+        };
+        let len = header.value_len as usize;
+        if len > buf.len() {
+            return Err(Error::OutOfBounds);
+        }
+        let value_offset = position + RECORD_HEADER_SIZE as u32 + header.key_len as u32;
+        self.read(value_offset, &mut buf[..len]).await?;
+        Ok(len)
+    }
+
+    /// Stores `value` for `key`, replacing any previous value.
+    ///
+    /// Keys are 1 to [`MAX_KEY_LEN`] bytes and values up to [`MAX_VALUE_LEN`] bytes, anything
+    /// else is rejected with `InvalidArgument`. Returns `Full` if the live records don't
+    /// leave room for this one even after compacting.
+    pub async fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        check_key(key)?;
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        self.append(KIND_VALUE, key, value).await
+    }
+
+    /// Removes `key` by appending a tombstone. Removing a key that isn't set does nothing.
+    pub async fn remove(&mut self, key: &[u8]) -> Result<(), Error<E>> {
+        check_key(key)?;
+        match self.find(key).await? {
+            Some((_, header)) if header.kind == KIND_VALUE => {
+                self.append(KIND_TOMBSTONE, key, &[]).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Rewrites the live records into the other half, dropping overwritten values and
+    /// tombstones.
+    pub async fn compact(&mut self) -> Result<(), Error<E>> {
+        let target = (1 - self.active) * self.half_len;
+        let generation = self.generation.wrapping_add(1);
+        let mut out = HALF_HEADER_SIZE;
+        let mut position = HALF_HEADER_SIZE;
+        while position < self.end {
+            let header = self.read_header(position).await?;
+            if header.kind == KIND_VALUE && !self.is_superseded(position, header).await? {
+                self.copy_record(position, header, target + out, generation)
+                    .await?;
+                out += header.len();
+            }
+            position += header.len();
+        }
+        write_half_header(&mut self.region, target, generation).await?;
+        self.active = 1 - self.active;
+        self.generation = generation;
+        self.end = out;
+        Ok(())
+    }
+
+    async fn append(&mut self, kind: u8, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        let mut header = RecordHeader {
+            generation: self.generation,
+            key_len: key.len() as u8,
+            kind,
+            value_len: value.len() as u16,
+            hash: crc16(key),
+        };
+        if header.len() > self.half_len - self.end {
+            self.compact().await?;
+            if header.len() > self.half_len - self.end {
+                return Err(Error::Full);
+            }
+            // Mounting only accepts records of the generation of their half
+            header.generation = self.generation;
+        }
+        let mut head = [0; RECORD_HEADER_SIZE + MAX_KEY_LEN];
+        head[..RECORD_HEADER_SIZE].copy_from_slice(&header.encode());
+        head[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + key.len()].copy_from_slice(key);
+        let head = &head[..RECORD_HEADER_SIZE + key.len()];
+        let crc = !crc32_update(crc32_update(!0, head), value);
+
+        let mut offset = self.end;
+        self.write(offset, head).await?;
+        offset += head.len() as u32;
+        self.write(offset, value).await?;
+        offset += value.len() as u32;
+        self.write(offset, &crc.to_le_bytes()).await?;
+        self.end += header.len();
+        Ok(())
+    }
+
+    /// Position and header of the newest record for `key`
+    async fn find(&mut self, key: &[u8]) -> Result<Option<(u32, RecordHeader)>, Error<E>> {
+        let hash = crc16(key);
+        let mut found = None;
+        let mut position = HALF_HEADER_SIZE;
+        while position < self.end {
+            let header = self.read_header(position).await?;
+            if header.hash == hash && self.key_matches(position, header, key).await? {
+                found = Some((position, header));
+            }
+            position += header.len();
+        }
+        Ok(found)
+    }
+
+    /// Whether a later record exists for the key of the record at `position`
+    async fn is_superseded(
+        &mut self,
+        position: u32,
+        header: RecordHeader,
+    ) -> Result<bool, Error<E>> {
+        let mut key = [0; MAX_KEY_LEN];
+        let key = &mut key[..header.key_len as usize];
+        self.read(position + RECORD_HEADER_SIZE as u32, key).await?;
+        let mut later = position + header.len();
+        while later < self.end {
+            let other = self.read_header(later).await?;
+            if other.hash == header.hash && self.key_matches(later, other, key).await? {
+                return Ok(true);
+            }
+            later += other.len();
+        }
+        Ok(false)
+    }
+
+    async fn key_matches(
+        &mut self,
+        position: u32,
+        header: RecordHeader,
+        key: &[u8],
+    ) -> Result<bool, Error<E>> {
+        if header.key_len as usize != key.len() {
+            return Ok(false);
+        }
+        let mut stored = [0; MAX_KEY_LEN];
+        let stored = &mut stored[..key.len()];
+        self.read(position + RECORD_HEADER_SIZE as u32, stored)
+            .await?;
+        Ok(stored == key)
+    }
+
+    /// Copies a record to an absolute offset in the region, rewriting its generation
+    async fn copy_record(
+        &mut self,
+        position: u32,
+        header: RecordHeader,
+        destination: u32,
+        generation: u16,
+    ) -> Result<(), Error<E>> {
+        let source = self.active * self.half_len + position;
+        let new_header = RecordHeader {
+            generation,
+            ..header
+        }
+        .encode();
+        self.region.write(destination, &new_header).await?;
+        let mut crc = crc32_update(!0, &new_header);
+
+        let body_len = header.len() - RECORD_OVERHEAD;
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut done = 0;
+        while done < body_len {
+            let len = min(CHUNK_SIZE as u32, body_len - done);
+            let chunk = &mut chunk[..len as usize];
+            let from = source + RECORD_HEADER_SIZE as u32 + done;
+            self.region.read(from, chunk).await?;
+            let to = destination + RECORD_HEADER_SIZE as u32 + done;
+            self.region.write(to, chunk).await?;
+            crc = crc32_update(crc, chunk);
+            done += len;
+        }
+        let crc_offset = destination + RECORD_HEADER_SIZE as u32 + body_len;
+        self.region.write(crc_offset, &(!crc).to_le_bytes()).await
+    }
+
+    /// Header of a record known to be valid
+    async fn read_header(&mut self, position: u32) -> Result<RecordHeader, Error<E>> {
+        let mut bytes = [0; RECORD_HEADER_SIZE];
+        self.read(position, &mut bytes).await?;
+        Ok(RecordHeader::decode(&bytes))
+    }
+
+    /// Header of the record at `position` if there is a complete, intact one
+    async fn read_valid_record(&mut self, position: u32) -> Result<Option<RecordHeader>, Error<E>> {
+        if position + RECORD_OVERHEAD > self.half_len {
+            return Ok(None);
+        }
+        let mut bytes = [0; RECORD_HEADER_SIZE];
+        self.read(position, &mut bytes).await?;
+        let header = RecordHeader::decode(&bytes);
+        let plausible = header.generation == self.generation
+            && (header.kind == KIND_VALUE || header.kind == KIND_TOMBSTONE)
+            && (1..=MAX_KEY_LEN).contains(&(header.key_len as usize))
+            && header.value_len as usize <= MAX_VALUE_LEN
+            && header.len() <= self.half_len - position;
+        if !plausible {
+            return Ok(None);
+        }
+
+        let mut crc = crc32_update(!0, &bytes);
+        let body_len = header.len() - RECORD_OVERHEAD;
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut done = 0;
+        while done < body_len {
+            let len = min(CHUNK_SIZE as u32, body_len - done);
+            let chunk = &mut chunk[..len as usize];
+            self.read(position + RECORD_HEADER_SIZE as u32 + done, chunk)
+                .await?;
+            crc = crc32_update(crc, chunk);
+            done += len;
+        }
+        let mut stored = [0; 4];
+        self.read(position + RECORD_HEADER_SIZE as u32 + body_len, &mut stored)
+            .await?;
+        Ok((!crc == u32::from_le_bytes(stored)).then_some(header))
+    }
+
+    /// Reads relative to the start of the active half
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        let start = self.active * self.half_len;
+        self.region.read(start + offset, bytes).await
+    }
+
+    /// Writes relative to the start of the active half
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error<E>> {
+        let start = self.active * self.half_len;
+        self.region.write(start + offset, bytes).await
+    }
+}
+
+fn check_key<E: Debug>(key: &[u8]) -> Result<(), Error<E>> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// The generation in the header of the half at `offset`, if it is valid
+async fn read_half_header<I2C, E, D>(
+    region: &mut EepromPartition<'_, I2C, D>,
+    offset: u32,
+) -> Result<Option<u16>, Error<E>>
+where
+    I2C: I2c<Error = E>,
+    E: Debug,
+    D: DelayNs,
+{
+    let mut header = [0; HALF_HEADER_SIZE as usize];
+    region.read(offset, &mut header).await?;
+    let crc = !crc32_update(!0, &header[..6]);
+    if header[0..4] != MAGIC
+        || crc != u32::from_le_bytes([header[6], header[7], header[8], header[9]])
+    {
+        return Ok(None);
+    }
+    Ok(Some(u16::from_le_bytes([header[4], header[5]])))
+}
+
+async fn write_half_header<I2C, E, D>(
+    region: &mut EepromPartition<'_, I2C, D>,
+    offset: u32,
+    generation: u16,
+) -> Result<(), Error<E>>
+where
+    I2C: I2c<Error = E>,
+    E: Debug,
+    D: DelayNs,
+{
+    let mut header = [0; HALF_HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&generation.to_le_bytes());
+    let crc = !crc32_update(!0, &header[..6]);
+    header[6..10].copy_from_slice(&crc.to_le_bytes());
+    region.write(offset, &header).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: core::ops::Range<u32> = 0x1000..0x1C00;
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn mount(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> KvStore<'_, SimBus, NoopDelay> {
+        KvStore::mount(eeprom.partition(REGION).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn get(eeprom: &mut At24Cx<SimBus, NoopDelay>, key: &[u8]) -> Option<std::vec::Vec<u8>> {
+        let mut store = mount(eeprom).await;
+        let mut buf = [0; MAX_VALUE_LEN];
+        match store.get(key, &mut buf).await {
+            Ok(len) => Some(buf[..len].to_vec()),
+            Err(Error::NotFound) => None,
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn overwrite_and_remount() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        let free = store.free_bytes();
+        store.set(b"volume", &[3]).await.unwrap();
+        store.set(b"name", b"synth").await.unwrap();
+        store.set(b"volume", &[7]).await.unwrap();
+        assert_eq!(store.free_bytes(), free - 19 - 21 - 19);
+        let mut buf = [0; 4];
+        assert!(matches!(
+            store.get(b"name", &mut buf).await,
+            Err(Error::OutOfBounds)
+        ));
+
+        assert_eq!(get(&mut eeprom, b"volume").await, Some(std::vec![7]));
+        assert_eq!(get(&mut eeprom, b"name").await, Some(b"synth".to_vec()));
+        assert_eq!(get(&mut eeprom, b"other").await, None);
+    }
+
+    #[tokio::test]
+    async fn tombstones_survive_remount_and_compaction() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        store.set(b"a", &[1; 100]).await.unwrap();
+        store.set(b"b", &[2; 100]).await.unwrap();
+        store.remove(b"a").await.unwrap();
+        store.remove(b"missing").await.unwrap();
+        assert_eq!(get(&mut eeprom, b"a").await, None);
+
+        let mut store = mount(&mut eeprom).await;
+        let free = store.free_bytes();
+        store.compact().await.unwrap();
+        assert_eq!(store.free_bytes(), free + 113 + 13);
+        assert_eq!(get(&mut eeprom, b"a").await, None);
+        assert_eq!(get(&mut eeprom, b"b").await, Some(std::vec![2; 100]));
+    }
+
+    #[tokio::test]
+    async fn enforces_limits_and_compacts_when_full() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        let long_key = [b'k'; MAX_KEY_LEN + 1];
+        assert!(matches!(
+            store.set(&long_key, &[]).await,
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            store.set(b"k", &[0; MAX_VALUE_LEN + 1]).await,
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            store.set(b"", &[]).await,
+            Err(Error::InvalidArgument)
+        ));
+
+        // Overwriting one key forever keeps compacting
+        for i in 0..20u8 {
+            store.set(b"k", &[i; 500]).await.unwrap();
+        }
+        store.set(b"l", &[0; 900]).await.unwrap();
+        assert!(matches!(
+            store.set(b"m", &[0; MAX_VALUE_LEN]).await,
+            Err(Error::Full)
+        ));
+        assert_eq!(get(&mut eeprom, b"k").await, Some(std::vec![19; 500]));
+    }
+
+    #[tokio::test]
+    async fn set_that_compacts_survives_remount() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        store.set(b"k", &[1; 500]).await.unwrap();
+        store.set(b"k", &[2; 500]).await.unwrap();
+        // Only fits after compacting
+        store.set(b"k", &[3; 500]).await.unwrap();
+        store.set(b"l", &[4; 10]).await.unwrap();
+        assert_eq!(get(&mut eeprom, b"k").await, Some(std::vec![3; 500]));
+        assert_eq!(get(&mut eeprom, b"l").await, Some(std::vec![4; 10]));
+    }
+
+    #[tokio::test]
+    async fn power_loss_during_set() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        store.set(b"key", &[1; 40]).await.unwrap();
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        for cut in 0..12 + 3 + 40 {
+            let mut eeprom = driver(Some(&snapshot));
+            // Mounting an initialized store only reads
+            eeprom.i2c.cut_power_after(cut);
+            let mut store = mount(&mut eeprom).await;
+            assert!(store.set(b"key", &[2; 40]).await.is_err());
+            eeprom.i2c.restore_power();
+
+            let value = get(&mut eeprom, b"key").await.unwrap();
+            assert_eq!(value, std::vec![1; 40], "cut after {cut} bytes");
+            // The torn record is overwritten by the next one
+            mount(&mut eeprom)
+                .await
+                .set(b"key", &[3; 40])
+                .await
+                .unwrap();
+            assert_eq!(get(&mut eeprom, b"key").await, Some(std::vec![3; 40]));
+        }
+    }
+
+    #[tokio::test]
+    async fn power_loss_during_compaction() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        store.set(b"a", &[1; 30]).await.unwrap();
+        store.set(b"b", &[2; 30]).await.unwrap();
+        store.set(b"a", &[3; 30]).await.unwrap();
+        store.remove(b"b").await.unwrap();
+        store.set(b"c", &[4; 30]).await.unwrap();
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        // Two records of 43 bytes and the half header
+        for cut in 0..2 * 43 + 10 {
+            let mut eeprom = driver(Some(&snapshot));
+            // Mounting an initialized store only reads
+            eeprom.i2c.cut_power_after(cut);
+            let mut store = mount(&mut eeprom).await;
+            assert!(store.compact().await.is_err());
+            eeprom.i2c.restore_power();
+
+            assert_eq!(get(&mut eeprom, b"a").await, Some(std::vec![3; 30]));
+            assert_eq!(get(&mut eeprom, b"b").await, None);
+            assert_eq!(get(&mut eeprom, b"c").await, Some(std::vec![4; 30]));
+        }
+    }
+
+    #[tokio::test]
+    async fn bit_error_in_the_newest_record() {
+        // Header, then a 55 byte record for "key" and a value byte of the second one
+        let offset = REGION.start as usize + 10 + 55 + 8 + 3 + 20;
+        let bus = SimBus::builder(Address(0, 0), 17)
+            .bit_error(offset, 0x04)
+            .build();
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let mut store = mount(&mut eeprom).await;
+        store.set(b"key", &[1; 40]).await.unwrap();
+        store.set(b"key", &[2; 40]).await.unwrap();
+        assert_eq!(eeprom.i2c.fired().len(), 1);
+
+        // The corrupted record fails its CRC, the one before it is the newest
+        assert_eq!(get(&mut eeprom, b"key").await, Some(std::vec![1; 40]));
+        mount(&mut eeprom)
+            .await
+            .set(b"key", &[3; 40])
+            .await
+            .unwrap();
+        assert_eq!(get(&mut eeprom, b"key").await, Some(std::vec![3; 40]));
+    }
+}
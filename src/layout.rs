@@ -0,0 +1,238 @@
+//! Declarative memory maps.
+//!
+//! [`eeprom_layout!`](crate::eeprom_layout) turns a table of fields and offsets into a
+//! struct of [`Cell`](crate::cell::Cell)s and [`Region`]s, and checks at compile time that
+//! no two fields overlap, that every field fits within the declared capacity and that
+//! regions are aligned to [`PAGE_SIZE`] so they can be borrowed as partitions. The part isn't
+//! known at compile time, so regions are aligned to the largest page, which is a whole
+//! number of pages on parts with smaller ones.
+//!
+//! ```
+//! # use at24cx::eeprom_layout;
+//! #[repr(C)]
+//! #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+//! struct CalTable {
+//!     gain: [f32; 4],
+//! }
+//!
+//! eeprom_layout! {
+//!     pub struct Layout(capacity = 0x8000) {
+//!         version: u16 @ 0x000,
+//!         serial: [u8; 12] @ 0x002,
+//!         cal: CalTable @ 0x010,
+//!         log: region(0x100, 0x1000),
+//!     }
+//! }
+//!
+//! const LAYOUT: Layout = Layout::new();
+//! assert_eq!(LAYOUT.cal.offset(), 0x010);
+//! assert_eq!(LAYOUT.log.range(), 0x100..0x1000);
+//! ```
+
+use crate::{At24Cx, EepromPartition, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+/// A page aligned range of the device that can be borrowed as a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    start: u32,
+    end: u32,
+}
+
+impl Region {
+    pub const fn new(range: Range<u32>) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+        }
+    }
+
+    pub const fn range(&self) -> Range<u32> {
+        self.start..self.end
+    }
+
+    /// Borrows the region as a partition
+    pub fn partition<'a, I2C, E: Debug, D: DelayNs>(
+        &self,
+        eeprom: &'a mut At24Cx<I2C, D>,
+    ) -> Result<EepromPartition<'a, I2C, D>, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        eeprom.partition(self.range())
+    }
+}
+
+/// Panics, at compile time when used in a constant, if any two of the `(start, end,
+/// is_region)` spans overlap, any of them ends past `capacity` or a region isn't aligned to
+/// [`PAGE_SIZE`]. Used by [`eeprom_layout!`](crate::eeprom_layout).
+#[doc(hidden)]
+pub const fn check_layout(spans: &[(u32, u32, bool)], capacity: u32) {
+    let mut i = 0;
+    while i < spans.len() {
+        let (start, end, is_region) = spans[i];
+        assert!(start <= end, "field ends before it starts");
+        assert!(end <= capacity, "field doesn't fit within the capacity");
+        if is_region {
+            assert!(
+                start % PAGE_SIZE as u32 == 0 && end % PAGE_SIZE as u32 == 0,
+                "region isn't page aligned"
+            );
+        }
+        let mut j = i + 1;
+        while j < spans.len() {
+            let (other_start, other_end, _) = spans[j];
+            assert!(end <= other_start || other_end <= start, "fields overlap");
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Declares a memory map, see the [`layout`](crate::layout) module.
+///
+/// Fields are either `name: Type @ offset`, a [`Cell`](crate::cell::Cell) holding a
+/// [`Pod`](bytemuck::Pod) type, or `name: region(start, end)`, a [`Region`]. The struct gets
+/// a `const fn new()` and a `CAPACITY` constant.
+#[macro_export]
+macro_rules! eeprom_layout {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident(capacity = $capacity:expr) {
+            $($body:tt)*
+        }
+    ) => {
+        $crate::eeprom_layout!(@munch [$(#[$meta])* $vis struct $name ($capacity)] [] $($body)*);
+    };
+
+    // Regions
+    (
+        @munch $header:tt [$($done:tt)*]
+        $(#[$field_meta:meta])* $field:ident : region($start:expr, $end:expr) $(, $($rest:tt)*)?
+    ) => {
+        $crate::eeprom_layout!(
+            @munch $header
+            [$($done)* {region [$(#[$field_meta])*] $field $start, $end}]
+            $($($rest)*)?
+        );
+    };
+    // Cells, the type is collected token by token up to the `@`
+    (
+        @munch $header:tt [$($done:tt)*]
+        $(#[$field_meta:meta])* $field:ident : $($rest:tt)+
+    ) => {
+        $crate::eeprom_layout!(@type $header [$($done)*] [$(#[$field_meta])*] $field [] $($rest)+);
+    };
+    (
+        @type $header:tt [$($done:tt)*] $field_meta:tt $field:ident [$($ty:tt)+]
+        @ $offset:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::eeprom_layout!(
+            @munch $header
+            [$($done)* {cell $field_meta $field [$($ty)+] $offset}]
+            $($($rest)*)?
+        );
+    };
+    (
+        @type $header:tt $done:tt $field_meta:tt $field:ident [$($ty:tt)*]
+        $next:tt $($rest:tt)*
+    ) => {
+        $crate::eeprom_layout!(@type $header $done $field_meta $field [$($ty)* $next] $($rest)*);
+    };
+
+    (
+        @munch [$(#[$meta:meta])* $vis:vis struct $name:ident ($capacity:expr)]
+        [$({$kind:ident [$(#[$field_meta:meta])*] $field:ident $($spec:tt)*})*]
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy)]
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                pub $field: $crate::eeprom_layout!(@field_type $kind $($spec)*),
+            )*
+        }
+
+        impl $name {
+            pub const CAPACITY: u32 = $capacity;
+
+            pub const fn new() -> Self {
+                Self {
+                    $($field: $crate::eeprom_layout!(@field_new $kind $($spec)*),)*
+                }
+            }
+        }
+
+        const _: () = $crate::layout::check_layout(
+            &[$($crate::eeprom_layout!(@span $kind $($spec)*)),*],
+            $capacity,
+        );
+    };
+
+    (@field_type cell [$($ty:tt)+] $offset:expr) => { $crate::cell::Cell<$($ty)+> };
+    (@field_type region $start:expr, $end:expr) => { $crate::layout::Region };
+    (@field_new cell [$($ty:tt)+] $offset:expr) => { $crate::cell::Cell::new($offset) };
+    (@field_new region $start:expr, $end:expr) => { $crate::layout::Region::new($start..$end) };
+    (@span cell [$($ty:tt)+] $offset:expr) => {
+        ($offset, $offset + ::core::mem::size_of::<$($ty)+>() as u32, false)
+    };
+    (@span region $start:expr, $end:expr) => { ($start, $end, true) };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cell::Cell;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_storage_async::nor_flash::ReadNorFlash;
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+    struct CalTable {
+        gain: [f32; 4],
+        offset: [i16; 4],
+    }
+
+    eeprom_layout! {
+        /// Test layout
+        struct Layout(capacity = 0x20000) {
+            version: u16 @ 0x000,
+            /// Serial number
+            serial: [u8; 12] @ 0x002,
+            cal: CalTable @ 0x010,
+            log: region(0x100, 0x1000),
+            flags: u8 @ 0x1000,
+        }
+    }
+
+    const LAYOUT: Layout = Layout::new();
+
+    #[test]
+    fn offsets_match() {
+        assert_eq!(Layout::CAPACITY, 0x20000);
+        assert_eq!(LAYOUT.version.offset(), 0x000);
+        assert_eq!(LAYOUT.serial.offset(), 0x002);
+        assert_eq!(LAYOUT.cal.offset(), 0x010);
+        assert_eq!(LAYOUT.log.range(), 0x100..0x1000);
+        assert_eq!(LAYOUT.flags.offset(), 0x1000);
+        assert_eq!(Cell::<CalTable>::SIZE, 24);
+    }
+
+    #[tokio::test]
+    async fn accessors_use_cells_and_partitions() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        LAYOUT.version.store(&mut eeprom, &3).await.unwrap();
+        LAYOUT
+            .serial
+            .store(&mut eeprom, b"SN0123456789")
+            .await
+            .unwrap();
+        assert_eq!(eeprom.i2c.memory()[..0x0E], *b"\x03\x00SN0123456789");
+        assert_eq!(LAYOUT.version.load(&mut eeprom).await.unwrap(), 3);
+        let partition = LAYOUT.log.partition(&mut eeprom).unwrap();
+        assert_eq!(partition.capacity(), 0xF00);
+    }
+}
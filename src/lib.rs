@@ -1,36 +1,190 @@
 #![cfg_attr(not(test), no_std)]
+// `is_multiple_of` and `Option::is_none_or` are too new for our MSRV
+#![allow(clippy::manual_is_multiple_of, clippy::unnecessary_map_or)]
+
+#[cfg(any(feature = "linux", feature = "sim", feature = "test-support"))]
+extern crate std;
 
 use core::cmp::min;
 use core::fmt::Debug;
 use embedded_hal_async::{
     delay::DelayNs,
-    i2c::{Error as I2cError, ErrorType as I2cErrorType, I2c},
+    i2c::{Error as I2cError, ErrorKind, ErrorType as I2cErrorType, I2c},
 };
 use embedded_storage_async::nor_flash::{
     ErrorType as StorageErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
 };
+use heapless::Vec;
+use timeout::with_timeout;
+
+pub use bad_pages::{BadPage, MAX_BAD_PAGES};
+pub use checked::PageChecksum;
+pub use clone::{CapacityPolicy, CloneError, CloneProgress};
+pub use crc::{CRC32_CASTAGNOLI, CRC32_IEEE};
+pub use device::{DeviceKind, PageOverwriteToken, UnsupportedDevice};
+#[cfg(feature = "id-page")]
+pub use id_page::IdPage;
+#[cfg(feature = "ihex")]
+pub use ihex::{IhexError, MAX_IHEX_RECORD_LEN};
+#[cfg(feature = "io")]
+pub use io::TransferError;
+pub use partition::{validate_layout, EepromPartition, LayoutConflict};
+pub use partition_table::{
+    PartitionEntry, MAX_PARTITIONS, PARTITION_TABLE_END, PARTITION_TABLE_OFFSET,
+};
+pub use pending::{EnqueueError, PendingWrites};
+#[cfg(feature = "postcard")]
+pub use postcard::MAX_POSTCARD_LEN;
+pub use program::{ProgramError, ProgramOptions, ProgramProgress, ProgramReport};
+pub use region::{At24CxRegion, SplitRegions};
+pub use signature::{FormatInfo, FormatWipe};
+pub use staged::{StagedWrite, STAGE_OVERHEAD};
+pub use ten_bit::TenBitBus;
+pub use used_regions::UsedRegions;
+
+#[cfg(feature = "auditlog")]
+pub mod auditlog;
+#[cfg(feature = "autosave")]
+pub mod autosave;
+mod bad_pages;
+mod batch;
+#[cfg(feature = "bitset")]
+pub mod bitset;
+#[cfg(feature = "blocking-facade")]
+pub mod blocking;
+#[cfg(feature = "breadcrumbs")]
+pub mod breadcrumbs;
+#[cfg(feature = "bytemuck")]
+pub mod cell;
+mod checked;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod clone;
+#[cfg(feature = "commitable-config")]
+pub mod commitable_config;
+mod const_offset;
+#[cfg(feature = "counter")]
+pub mod counter;
+mod crc;
+#[cfg(feature = "datalog")]
+pub mod datalog;
+mod device;
+#[cfg(feature = "endurance")]
+pub mod endurance;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
+#[cfg(feature = "id-page")]
+mod id_page;
+#[cfg(feature = "ihex")]
+mod ihex;
+#[cfg(feature = "io")]
+mod io;
+#[cfg(feature = "kv")]
+pub mod kv;
+#[cfg(feature = "bytemuck")]
+pub mod layout;
+#[cfg(feature = "linux")]
+pub mod linux;
+mod migrate;
+#[cfg(test)]
+mod model;
+#[cfg(feature = "odometer")]
+pub mod odometer;
+#[cfg(feature = "panic-store")]
+pub mod panic_store;
+mod partition;
+mod partition_table;
+mod pending;
+#[cfg(feature = "postcard")]
+mod postcard;
+mod program;
+#[cfg(feature = "queue")]
+pub mod queue;
+mod region;
+#[cfg(feature = "ringlog")]
+pub mod ringlog;
+#[cfg(feature = "seed-store")]
+pub mod seed_store;
+#[cfg(feature = "settings")]
+pub mod settings;
+#[cfg(feature = "embassy")]
+pub mod shared;
+pub mod signature;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+mod staged;
+#[cfg(feature = "sync")]
+mod sync;
+mod ten_bit;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+mod timeout;
+#[cfg(feature = "tlv")]
+pub mod tlv;
+#[cfg(any(test, feature = "sim"))]
+pub mod trace;
+mod used_regions;
+mod vectored;
+mod verify;
+mod wrapping;
+#[cfg(feature = "writer-task")]
+pub mod writer;
 
 // TODO: These are only valid for AT24CM01. Implement the others
-/// 256 pages for the AT24CM01
+/// 256 pages for the AT24CM01, the largest write page the driver handles. Parts with
+/// smaller pages are written a [page](At24Cx::set_page_size) at a time all the same.
 pub const PAGE_SIZE: usize = 256;
 /// 2 address bytes for the AT24CM01
 pub const ADDRESS_BYTES: usize = 2;
+/// Capacity of the largest part the driver addresses, with the memory address bytes and
+/// the P0 bit in the device address
+pub const MAX_CAPACITY: usize = 1 << (8 * ADDRESS_BYTES + 1);
 
-// Adds up to 6ms after which the at24x should definitely be ready
+// Adds up to 6ms after which the at24x should definitely be ready. Defaults for
+// `set_poll_max_retries` and `set_poll_delay_ns`
 const POLL_MAX_RETRIES: usize = 60;
-const POLL_DELAY_US: u32 = 200;
+const POLL_DELAY_NS: u32 = 200_000;
+
+/// First offset of the upper half of the memory, the one selected by P0
+const HALF_SEAM: u32 = 1 << 16;
+
+/// Largest length short reads can be padded to, see [`At24Cx::set_min_read_len`]
+pub const MAX_MIN_READ_LEN: usize = 16;
 
 /// Custom error type for the various errors that can be thrown by AT24Cx
 /// Can be converted into a NorFlashError.
+///
+/// Bus errors are classified by their [`ErrorKind`], each variant keeps the error the bus
+/// returned, see [`i2c_error`](Error::i2c_error). Kinds without a variant of their own stay
+/// an `I2cError`. `embedded-hal` has no timeout kind, a HAL that times out on a stretched
+/// clock reports it as another kind. The driver's own
+/// [read timeout](At24Cx::set_read_timeout_us) is reported as `Timeout`.
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Error<E: Debug> {
     I2cError(E),
+    /// A misplaced start or stop condition, or a line the bus couldn't drive
+    Bus(E),
+    /// Another controller won arbitration of the bus
+    ArbitrationLoss(E),
+    /// The device didn't acknowledge its address or data outside of ACK polling
+    NoAcknowledge(E),
+    /// The controller lost data it received before it could be read
+    Overrun(E),
     NotAligned,
     OutOfBounds,
     WriteEnableFail,
     ReadbackFail,
     WriteAckTimeout,
+    CrcMismatch,
+    NotFound,
+    InvalidArgument,
+    Full,
+    BadPage,
+    UnsupportedVersion,
+    Deserialize,
+    Timeout,
+    DeviceNotFound,
 }
 
 impl<E: Debug> NorFlashError for Error<E> {
@@ -43,105 +197,959 @@ impl<E: Debug> NorFlashError for Error<E> {
     }
 }
 
+impl<E: Debug> Error<E> {
+    fn from_kind(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => Error::NotAligned,
+            _ => Error::OutOfBounds,
+        }
+    }
+
+    /// The error the bus returned, for the variants that come from one
+    pub fn i2c_error(&self) -> Option<&E> {
+        match self {
+            Error::I2cError(error)
+            | Error::Bus(error)
+            | Error::ArbitrationLoss(error)
+            | Error::NoAcknowledge(error)
+            | Error::Overrun(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
 impl<E: I2cError> From<E> for Error<E> {
     fn from(error: E) -> Self {
-        Error::I2cError(error)
+        match error.kind() {
+            ErrorKind::Bus => Error::Bus(error),
+            ErrorKind::ArbitrationLoss => Error::ArbitrationLoss(error),
+            ErrorKind::NoAcknowledge(_) => Error::NoAcknowledge(error),
+            ErrorKind::Overrun => Error::Overrun(error),
+            _ => Error::I2cError(error),
+        }
+    }
+}
+
+/// How [`read`](ReadNorFlash::read) sends the memory address before reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadMethod {
+    /// A single `write_read`, with a repeated start between the address and the data
+    #[default]
+    CombinedWriteRead,
+    /// A `write` of the address terminated by a STOP, then a separate `read`. The EEPROM
+    /// keeps the address pointer across the STOP. Use this if the HAL can't do a true
+    /// repeated start.
+    WriteStopRead,
+}
+
+/// Which [`DelayNs`] method waits between acknowledge probes, see
+/// [`At24Cx::set_delay_granularity`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DelayGranularity {
+    /// `delay_ns` with the exact poll delay
+    Nanos,
+    /// `delay_us` with the poll delay rounded up to whole microseconds
+    #[default]
+    Micros,
+    /// `delay_ms` with the poll delay rounded up to whole milliseconds, for timers that
+    /// can't wait any shorter
+    Millis,
+}
+
+impl DelayGranularity {
+    /// `ns` in units of this granularity, rounded up
+    fn units(self, ns: u32) -> u32 {
+        match self {
+            DelayGranularity::Nanos => ns,
+            DelayGranularity::Micros => ns.div_ceil(1_000),
+            DelayGranularity::Millis => ns.div_ceil(1_000_000),
+        }
     }
 }
 
+/// The bus operation used to check whether the device acknowledges, while ACK polling for
+/// the end of a write cycle and in [`is_ready`](At24Cx::is_ready)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AckProbe {
+    /// A one byte write. It only sets the upper byte of the address pointer and never
+    /// starts a write cycle.
+    #[default]
+    Write,
+    /// A one byte read. Reads the byte at the address pointer and advances it.
+    Read,
+    /// A zero-length write, the SMBus quick command. Not every HAL can send one.
+    Quick,
+}
+
 pub struct Address(pub u8, pub u8);
 
+impl Address {
+    /// The device address of a part with all three address pins, from the AT24C32 to the
+    /// AT24C512. `Address` itself leaves A0 out, the AT24CM01 uses that bit to select the
+    /// upper half of the memory.
+    pub const fn pins(a0: u8, a1: u8, a2: u8) -> DeviceAddress {
+        DeviceAddress(0x50 | (a2 & 1) << 2 | (a1 & 1) << 1 | (a0 & 1))
+    }
+
+    /// A device address given directly instead of through the address pins, for parts
+    /// behind an address translator or strapped to a non-standard base. `None` if `addr`
+    /// isn't a 7-bit address or is in the ranges the I2C specification reserves,
+    /// `0x00..=0x07` and `0x78..=0x7F`.
+    ///
+    /// Like `0x50` for the pins, `addr` is the address the part answers at with its
+    /// page-select bits clear, and the driver sets those bits on top of it. A part with
+    /// page-select bits takes the addresses with them set as well, which must also be free:
+    /// an AT24CM01 at `Address::raw(0x1C)` answers at `0x1C` and `0x1D`, so `addr` must
+    /// have those bits clear.
+    pub const fn raw(addr: u8) -> Option<DeviceAddress> {
+        match addr {
+            0x08..=0x77 => Some(DeviceAddress(addr)),
+            _ => None,
+        }
+    }
+}
+
 impl From<Address> for u8 {
     fn from(a: Address) -> Self {
         0x50 | (a.1 << 2) | (a.0 << 1)
     }
 }
 
+/// The 7-bit address a device answers at with its page-select bits clear, from the
+/// [address pins](Address) or [given directly](Address::raw)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceAddress(u8);
+
+impl From<Address> for DeviceAddress {
+    fn from(a: Address) -> Self {
+        Self(a.into())
+    }
+}
+
+impl From<DeviceAddress> for u8 {
+    fn from(a: DeviceAddress) -> Self {
+        a.0
+    }
+}
+
+/// Driver for an AT24Cx EEPROM.
+///
+/// Owns the bus and delay it is given. Since `embedded-hal-async` implements its traits for
+/// `&mut T`, it can also be created over borrowed resources (`At24Cx<&mut I2C, &mut D>`) that
+/// are used elsewhere once the driver is dropped. An owning driver hands its resources back
+/// through [`into_parts`](Self::into_parts).
+///
+/// The same goes the other way: `embedded-storage-async` implements the `NorFlash` traits
+/// for `&mut T`, so `&mut eeprom` can be handed to a library that takes its storage by value
+/// and the driver is usable again once the library is done with it.
 pub struct At24Cx<I2C, D> {
     address_bits: usize,
+    page_size: usize,
+    max_write_len: usize,
     base_address: u8,
     delay: D,
     i2c: I2C,
+    page_checksum: PageChecksum,
+    default_byte: u8,
+    max_unverified_writes: usize,
+    unverified_writes: usize,
+    read_method: ReadMethod,
+    ack_probe: AckProbe,
+    page_aligned_writes: bool,
+    read_timeout_us: Option<u32>,
+    min_read_len: usize,
+    seal_footer: Option<(u32, usize)>,
+    batch_merge_gap: usize,
+    poll_delay_ns: u32,
+    delay_granularity: DelayGranularity,
+    poll_max_retries: usize,
+    write_retries: usize,
+    inter_op_delay_us: u32,
+    high_water_mark: Option<u32>,
+    read_only: bool,
+    upper_half_address: Option<u8>,
+    #[cfg(feature = "id-page")]
+    id_page: Option<IdPage>,
+    bad_pages: Vec<BadPage, MAX_BAD_PAGES>,
 }
 
-impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
-where
-    I2C: I2c<Error = E>,
-{
-    pub fn new(i2c: I2C, address: Address, address_bits: usize, delay: D) -> Self {
+impl<I2C, D> At24Cx<I2C, D> {
+    /// Creates a driver for a part with `address_bits` bits in a memory offset. The driver
+    /// sends [`ADDRESS_BYTES`] memory address bytes and writes pages of [`PAGE_SIZE`] bytes
+    /// unless [told otherwise](Self::set_page_size), [`with_device`](Self::with_device)
+    /// checks that a part is addressed that way and sets its page size.
+    /// [`try_new`](Self::try_new) checks the width without setting the page size.
+    pub fn new(i2c: I2C, address: impl Into<DeviceAddress>, address_bits: usize, delay: D) -> Self {
+        let address: DeviceAddress = address.into();
         Self {
             address_bits,
+            page_size: PAGE_SIZE,
+            max_write_len: PAGE_SIZE,
             base_address: address.into(),
             delay,
             i2c,
+            page_checksum: PageChecksum::None,
+            default_byte: 0xFF,
+            max_unverified_writes: 1,
+            unverified_writes: 0,
+            read_method: ReadMethod::CombinedWriteRead,
+            ack_probe: AckProbe::Write,
+            page_aligned_writes: false,
+            read_timeout_us: None,
+            min_read_len: 0,
+            seal_footer: None,
+            batch_merge_gap: 8,
+            poll_delay_ns: POLL_DELAY_NS,
+            delay_granularity: DelayGranularity::Micros,
+            poll_max_retries: POLL_MAX_RETRIES,
+            write_retries: 0,
+            inter_op_delay_us: 0,
+            high_water_mark: None,
+            read_only: false,
+            upper_half_address: None,
+            #[cfg(feature = "id-page")]
+            id_page: DeviceKind::from_address_bits(address_bits).and_then(DeviceKind::id_page),
+            bad_pages: Vec::new(),
         }
     }
 
-    fn get_device_address(&self, memory_address: u32) -> Result<u8, Error<E>> {
+    /// Releases the bus and delay
+    pub fn into_parts(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+    /// Where a read of `len` bytes at `offset` starts once padded to the minimum read length,
+    /// and how many padding bytes precede the requested ones. `None` if it isn't padded.
+    fn padded_read(&self, offset: u32, len: usize) -> Option<(u32, usize)> {
+        if len >= self.min_read_len {
+            return None;
+        }
+        // Padded after the requested bytes, or before them at the end of the device
+        let start = offset.min((1 << self.address_bits) - self.min_read_len as u32);
+        Some((start, (offset - start) as usize))
+    }
+
+    /// The device byte for an offset, `None` past the end of the device
+    fn device_address(&self, memory_address: u32) -> Option<u8> {
         if memory_address >= (1 << self.address_bits) {
-            return Err(Error::OutOfBounds);
+            return None;
         }
-        let p0 = if memory_address & (1 << 16) == 0 {
-            0
+        if memory_address & HALF_SEAM == 0 {
+            Some(self.base_address)
         } else {
-            1
-        };
-        Ok(self.base_address | p0)
+            Some(self.upper_half_address.unwrap_or(self.base_address | 1))
+        }
+    }
+
+    /// How much of a transfer at `offset` lies below the seam between the halves, if it
+    /// crosses it and the upper half has an [address of its own](At24Cx::set_upper_half_address)
+    fn below_seam(&self, offset: u32, len: usize) -> Option<usize> {
+        let crosses = offset < HALF_SEAM && offset as usize + len > HALF_SEAM as usize;
+        if self.upper_half_address.is_none() || !crosses {
+            return None;
+        }
+        Some((HALF_SEAM - offset) as usize)
     }
 
+    /// Returns `WriteEnableFail` if the driver is [read-only](At24Cx::set_read_only)
+    fn check_writable<E: Debug>(&self) -> Result<(), Error<E>> {
+        if self.read_only {
+            return Err(Error::WriteEnableFail);
+        }
+        Ok(())
+    }
+
+    /// Raises the high water mark to the last byte of a write of `len` bytes at `offset`
+    fn record_written(&mut self, offset: u32, len: usize) {
+        if len > 0 {
+            let last = offset + len as u32 - 1;
+            self.high_water_mark = Some(self.high_water_mark.map_or(last, |mark| mark.max(last)));
+        }
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Sets the value of a blank cell, used by [`clear`](Self::clear) and a real
+    /// [`erase`](NorFlash::erase). Defaults to 0xFF like NOR flash.
+    pub fn set_default_byte(&mut self, value: u8) {
+        self.default_byte = value;
+    }
+
+    pub fn default_byte(&self) -> u8 {
+        self.default_byte
+    }
+
+    /// Sets how many page writes may be issued before the driver ACK polls for their write
+    /// cycle to finish. Defaults to 1, polling after every page. With a higher value a page
+    /// write that finds the device still busy is retried until it is accepted, and the
+    /// outstanding cycle is always waited for before reading and at the end of a
+    /// [`write`](NorFlash::write). Zero is treated as 1.
+    pub fn set_max_unverified_writes(&mut self, pages: usize) {
+        self.max_unverified_writes = pages.max(1);
+    }
+
+    pub fn max_unverified_writes(&self) -> usize {
+        self.max_unverified_writes
+    }
+
+    /// Selects how reads address the device. There is no reliable way to detect whether a
+    /// HAL's `write_read` really uses a repeated start, so this has to be declared.
+    pub fn set_read_method(&mut self, method: ReadMethod) {
+        self.read_method = method;
+    }
+
+    pub fn read_method(&self) -> ReadMethod {
+        self.read_method
+    }
+
+    /// Selects how the device is probed for an acknowledge. Pick the operation whose NACK
+    /// the HAL reliably reports as `NoAcknowledge`.
+    pub fn set_ack_probe(&mut self, probe: AckProbe) {
+        self.ack_probe = probe;
+    }
+
+    pub fn ack_probe(&self) -> AckProbe {
+        self.ack_probe
+    }
+
+    /// Makes every write cycle of a [`write`](NorFlash::write) that spans more than one page
+    /// start on a page boundary. The bytes between the start of the first page and the write
+    /// offset are read first and written back together with the data, so a power loss during
+    /// the write leaves at most one partially written page behind. This costs a read of up to
+    /// a page less one byte per unaligned write. Off by default.
+    pub fn set_page_aligned_writes(&mut self, enabled: bool) {
+        self.page_aligned_writes = enabled;
+    }
+
+    pub fn page_aligned_writes(&self) -> bool {
+        self.page_aligned_writes
+    }
+
+    /// Bounds how long the bus may take for the transfers of a read, so a wedged bus can't
+    /// hang the task. The transfers race the delay and are dropped once it elapses, failing
+    /// the read with `Timeout`. Each chunk of a read split by bad-page remapping, and each
+    /// transaction with [`WriteStopRead`](ReadMethod::WriteStopRead), gets the full timeout.
+    /// Off by default.
+    pub fn set_read_timeout_us(&mut self, timeout_us: Option<u32>) {
+        self.read_timeout_us = timeout_us;
+    }
+
+    pub fn read_timeout_us(&self) -> Option<u32> {
+        self.read_timeout_us
+    }
+
+    /// Works around bus controllers that misbehave on very short transfers: reads of fewer
+    /// than `len` bytes read `len` bytes and discard the extra ones, which come after the
+    /// requested bytes or before them at the end of the device. Up to [`MAX_MIN_READ_LEN`],
+    /// returns `InvalidArgument` above that. 0, the default, turns it off.
+    ///
+    /// Only reads of the memory array are padded. Writes are not: padding bytes would be
+    /// programmed into the cells next to the written ones. Writes of a single page always put
+    /// the two address bytes and at least one data byte on the bus, and
+    /// [`set_page_aligned_writes`](Self::set_page_aligned_writes) lengthens short writes
+    /// safely by writing back the bytes before them. ACK polling, the write of the address
+    /// for [`ReadMethod::WriteStopRead`] and the identification page are not padded either.
+    pub fn set_min_read_len(&mut self, len: usize) -> Result<(), Error<E>> {
+        if len > MAX_MIN_READ_LEN || len > self.capacity() {
+            return Err(Error::InvalidArgument);
+        }
+        self.min_read_len = len;
+        Ok(())
+    }
+
+    pub fn min_read_len(&self) -> usize {
+        self.min_read_len
+    }
+
+    /// Sets where [`seal`](Self::seal) keeps the CRC-32 of the device: in the first four of
+    /// `len` bytes at `offset`, the rest of the footer is reserved. The whole footer is left
+    /// out of the CRC. Defaults to the last four bytes of the device. Returns
+    /// `InvalidArgument` if the footer is shorter than the CRC or doesn't fit on the device.
+    pub fn set_seal_footer(&mut self, offset: u32, len: usize) -> Result<(), Error<E>> {
+        if len < 4 || offset as usize > self.capacity() || len > self.capacity() - offset as usize {
+            return Err(Error::InvalidArgument);
+        }
+        self.seal_footer = Some((offset, len));
+        Ok(())
+    }
+
+    pub fn seal_footer(&self) -> (u32, usize) {
+        self.seal_footer.unwrap_or((self.capacity() as u32 - 4, 4))
+    }
+
+    /// Sets how many unwanted bytes [`read_batch`](Self::read_batch) may read between two
+    /// regions to get both in one transfer. A separate transfer sends the device address
+    /// twice and the memory address, about as long as reading four bytes, and may have to
+    /// wait for a shared bus. Defaults to 8.
+    pub fn set_batch_merge_gap(&mut self, bytes: usize) {
+        self.batch_merge_gap = bytes;
+    }
+
+    pub fn batch_merge_gap(&self) -> usize {
+        self.batch_merge_gap
+    }
+
+    /// Sets the delay between two acknowledge probes while waiting for a write cycle.
+    /// Defaults to 200µs.
+    pub fn set_poll_delay_us(&mut self, delay_us: u32) {
+        self.poll_delay_ns = delay_us.saturating_mul(1_000);
+    }
+
+    /// The poll delay rounded up to whole microseconds
+    pub fn poll_delay_us(&self) -> u32 {
+        self.poll_delay_ns.div_ceil(1_000)
+    }
+
+    /// [`set_poll_delay_us`](Self::set_poll_delay_us) in nanoseconds, to poll fast parts
+    /// more finely. Up to about 4.3s.
+    pub fn set_poll_delay_ns(&mut self, delay_ns: u32) {
+        self.poll_delay_ns = delay_ns;
+    }
+
+    pub fn poll_delay_ns(&self) -> u32 {
+        self.poll_delay_ns
+    }
+
+    /// Selects the [`DelayNs`] method the poll delay is waited with, rounding the delay up
+    /// to its unit. Timers often only count in microseconds or milliseconds, where waiting
+    /// through `delay_ns` costs more than the rounding. Defaults to
+    /// [`Micros`](DelayGranularity::Micros).
+    pub fn set_delay_granularity(&mut self, granularity: DelayGranularity) {
+        self.delay_granularity = granularity;
+    }
+
+    pub fn delay_granularity(&self) -> DelayGranularity {
+        self.delay_granularity
+    }
+
+    /// Sets how many times the device is probed for an acknowledge before a write cycle
+    /// fails with `WriteAckTimeout`. Together with the [poll delay](Self::set_poll_delay_us)
+    /// this bounds how long a write cycle may take, 60 probes or about 12ms by default.
+    pub fn set_poll_max_retries(&mut self, retries: usize) {
+        self.poll_max_retries = retries;
+    }
+
+    pub fn poll_max_retries(&self) -> usize {
+        self.poll_max_retries
+    }
+
+    /// Sets how many times a [`write`](NorFlash::write) rewrites a page whose write cycle
+    /// timed out with `WriteAckTimeout`, before giving up with that error. Each attempt gets
+    /// the full ACK polling. Defaults to 0, aborting on the first timeout.
+    pub fn set_write_retries(&mut self, retries: usize) {
+        self.write_retries = retries;
+    }
+
+    pub fn write_retries(&self) -> usize {
+        self.write_retries
+    }
+
+    /// Waits `delay_us` before every transaction on the bus, for marginal buses that miss
+    /// the start condition of a transaction right after the previous one. It applies to the
+    /// transactions of reads, page writes and every try of ACK polling and retries, which
+    /// costs throughput: a read takes one more delay, or two with
+    /// [`WriteStopRead`](ReadMethod::WriteStopRead), and ACK polling one per try on top of
+    /// the poll delay. Defaults to 0, which doesn't wait at all.
+    pub fn set_inter_op_delay_us(&mut self, delay_us: u32) {
+        self.inter_op_delay_us = delay_us;
+    }
+
+    pub fn inter_op_delay_us(&self) -> u32 {
+        self.inter_op_delay_us
+    }
+
+    /// The highest offset a write went to since the driver was created or
+    /// [reset](Self::reset_high_water_mark), `None` if nothing was written. Only kept in
+    /// memory, it tells a provisioning flow how far it got in this session.
+    pub fn high_water_mark(&self) -> Option<u32> {
+        self.high_water_mark
+    }
+
+    pub fn reset_high_water_mark(&mut self) {
+        self.high_water_mark = None;
+    }
+
+    /// Makes every method that writes to the device return `WriteEnableFail` without
+    /// touching the bus, for diagnostic builds that must never modify the contents. Reads
+    /// work as usual. Off by default.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Sets the size of the part's write pages, which writes are split at. A single write
+    /// cycle can't cross a page, the part wraps around to the start of the page instead and
+    /// overwrites what is there. Returns `InvalidArgument` unless `size` is a power of two
+    /// up to [`PAGE_SIZE`], the default. [`with_device`](Self::with_device) sets it for the
+    /// part.
+    pub fn set_page_size(&mut self, size: usize) -> Result<(), Error<E>> {
+        if !size.is_power_of_two() || size > PAGE_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        self.page_size = size;
+        Ok(())
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Caps the bytes of a single write cycle below the page size, to spread a large write
+    /// over more, shorter write cycles for time-sliced writing. A [`page_write`](Self::page_write)
+    /// of more data is split into sub-writes within the same page, each ACK polled before the
+    /// next, so writes take more cycles and wear the page more. Returns `InvalidArgument`
+    /// unless `len` is between 1 and [`PAGE_SIZE`], the default.
+    pub fn set_max_write_len(&mut self, len: usize) -> Result<(), Error<E>> {
+        if len == 0 || len > PAGE_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        self.max_write_len = len;
+        Ok(())
+    }
+
+    pub fn max_write_len(&self) -> usize {
+        self.max_write_len
+    }
+
+    /// Gives the upper half of the memory its own device address, for a part wired to answer
+    /// at two unrelated addresses instead of the base address with P0 set. Offsets are still
+    /// flat: the driver picks the address for each transfer and splits reads at the seam
+    /// between the halves, and ACK polling waits for both in case they are two chips.
+    /// Returns `InvalidArgument` unless the part has 17 address bits, where each half is
+    /// addressed by the two memory address bytes alone. `None`, the default, goes back to
+    /// setting P0.
+    pub fn set_upper_half_address(
+        &mut self,
+        address: Option<DeviceAddress>,
+    ) -> Result<(), Error<E>> {
+        if address.is_some() && self.address_bits != 17 {
+            return Err(Error::InvalidArgument);
+        }
+        self.upper_half_address = address.map(u8::from);
+        Ok(())
+    }
+
+    pub fn upper_half_address(&self) -> Option<DeviceAddress> {
+        self.upper_half_address.map(DeviceAddress)
+    }
+
+    fn get_device_address(&self, memory_address: u32) -> Result<u8, Error<E>> {
+        self.device_address(memory_address)
+            .ok_or(Error::OutOfBounds)
+    }
+
+    /// Returns the device byte and the memory address bytes that would be sent on the bus
+    /// for the given offset. Does not touch the bus.
+    pub fn decode_offset(&self, offset: u32) -> Result<(u8, Vec<u8, 4>), Error<E>> {
+        let device_address = self.get_device_address(offset)?;
+        let mut memory_address = Vec::new();
+        // Capacity of 4 always fits ADDRESS_BYTES
+        let _ = memory_address.extend_from_slice(&memory_address_bytes(offset));
+        Ok((device_address, memory_address))
+    }
+
+    /// Writes `data` at `address` in a single write cycle. The data has to fit in the rest
+    /// of the page, the device wraps around to the start of the page otherwise. Returns
+    /// `OutOfBounds` for more than a [page](Self::set_page_size) of data. Data longer than
+    /// the [write length cap](Self::set_max_write_len) takes several write cycles.
+    ///
+    /// Empty data is a no-op that doesn't touch the bus, but like an empty
+    /// [`write`](NorFlash::write) it returns `OutOfBounds` if `address` is past the end of
+    /// the device. An empty write right at the end is fine.
     pub async fn page_write(&mut self, address: u32, data: &[u8]) -> Result<(), Error<E>> {
+        self.check_writable()?;
+        if data.len() > self.page_size {
+            return Err(Error::OutOfBounds);
+        }
+        if data.len() <= self.max_write_len {
+            return self.write_cycle(address, data).await;
+        }
+        let page_size = self.page_size as u32;
+        let page_start = address - address % page_size;
+        let mut lead = address % page_size;
+        for chunk in data.chunks(self.max_write_len) {
+            // Wraps within the page like a single write cycle would
+            self.write_cycle(page_start + lead, chunk).await?;
+            self.flush().await?;
+            lead = (lead + chunk.len() as u32) % page_size;
+        }
+        Ok(())
+    }
+
+    /// A single write cycle of up to [`PAGE_SIZE`] bytes, whatever the page size is set to
+    pub(crate) async fn write_cycle(&mut self, address: u32, data: &[u8]) -> Result<(), Error<E>> {
+        self.check_writable()?;
         if data.len() > PAGE_SIZE {
             return Err(Error::OutOfBounds);
         }
+        if data.is_empty() && address as usize > self.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        let offset = address;
+        let address = self.remap_write(address).ok_or(Error::BadPage)?;
 
         let mut payload: [u8; ADDRESS_BYTES + PAGE_SIZE] = [0; ADDRESS_BYTES + PAGE_SIZE];
-        payload[0] = (address >> 8) as u8;
-        payload[1] = address as u8;
+        payload[..ADDRESS_BYTES].copy_from_slice(&memory_address_bytes(address));
         payload[ADDRESS_BYTES..ADDRESS_BYTES + data.len()].copy_from_slice(data);
 
         let dev_addr = self.get_device_address(address)?;
+        let payload = &payload[..ADDRESS_BYTES + data.len()];
+        if self.unverified_writes == 0 {
+            self.guard_delay().await;
+            self.i2c
+                .write(dev_addr, payload)
+                .await
+                .map_err(bus_error::<I2C>)?;
+        } else {
+            // The previous write cycle may still be running, the device NACKs until it is done
+            self.write_when_ready(dev_addr, payload).await?;
+        }
+        self.record_written(offset, data.len());
+
+        self.unverified_writes += 1;
+        if self.unverified_writes >= self.max_unverified_writes {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// A page write that is repeated up to [`write_retries`](Self::set_write_retries) times
+    /// while it times out
+    async fn page_write_retrying(&mut self, address: u32, data: &[u8]) -> Result<(), Error<E>> {
+        let mut retries = self.write_retries;
+        loop {
+            match self.page_write(address, data).await {
+                Err(Error::WriteAckTimeout) if retries > 0 => retries -= 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Waits for a write cycle that was started but not yet ACK polled, see
+    /// [`set_max_unverified_writes`](Self::set_max_unverified_writes).
+    pub async fn flush(&mut self) -> Result<(), Error<E>> {
+        if self.unverified_writes == 0 {
+            return Ok(());
+        }
+        // Any P0 selects the same chip, the whole device is busy during a write cycle
+        self.poll_ack(self.base_address).await?;
+        if let Some(upper_half_address) = self.upper_half_address {
+            // A half with its own address may be a chip of its own
+            self.poll_ack(upper_half_address).await?;
+        }
+        self.unverified_writes = 0;
+        Ok(())
+    }
+
+    /// Low-level write of `payload` to the 7-bit I2C address `dev_addr`, exactly as given.
+    ///
+    /// No memory address is prepended and nothing is checked against the device's
+    /// capacity or page size, so this is only meant for parts without a memory address, for
+    /// continuing a write after the address was sent, or for custom protocols. Pending
+    /// write cycles are [flushed](Self::flush) first. With `poll`, this ACK polls
+    /// `dev_addr` afterwards until the write cycle the payload started is done.
+    pub async fn write_raw(
+        &mut self,
+        dev_addr: u8,
+        payload: &[u8],
+        poll: bool,
+    ) -> Result<(), Error<E>> {
+        self.check_writable()?;
+        self.flush().await?;
+        self.guard_delay().await;
         self.i2c
-            .write(dev_addr, &payload[..ADDRESS_BYTES + data.len()])
+            .write(dev_addr, payload)
             .await
-            .map_err(Error::I2cError)?;
+            .map_err(bus_error::<I2C>)?;
+        if poll {
+            self.poll_ack(dev_addr).await?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the device to finish its internal write cycle (ACK polling).
+    async fn poll_ack(&mut self, dev_addr: u8) -> Result<(), Error<E>> {
+        for _ in 0..self.poll_max_retries {
+            self.guard_delay().await;
+            if let Ok(true) = probe(&mut self.i2c, dev_addr, self.ack_probe).await {
+                return Ok(());
+            }
+            self.poll_delay().await;
+        }
+        Err(Error::WriteAckTimeout)
+    }
+
+    /// Waits the poll delay with the configured granularity
+    async fn poll_delay(&mut self) {
+        let units = self.delay_granularity.units(self.poll_delay_ns);
+        match self.delay_granularity {
+            DelayGranularity::Nanos => self.delay.delay_ns(units).await,
+            DelayGranularity::Micros => self.delay.delay_us(units).await,
+            DelayGranularity::Millis => self.delay.delay_ms(units).await,
+        }
+    }
+
+    /// Waits the [inter-operation delay](Self::set_inter_op_delay_us) before a transaction
+    async fn guard_delay(&mut self) {
+        if self.inter_op_delay_us > 0 {
+            self.delay.delay_us(self.inter_op_delay_us).await;
+        }
+    }
 
-        // ACK polling
-        const DUMMY: [u8; 1] = [0];
-        for _ in 0..POLL_MAX_RETRIES {
-            if self.i2c.write(dev_addr, &DUMMY).await.is_ok() {
+    /// Retries a write for as long as ACK polling would, while the device doesn't acknowledge.
+    async fn write_when_ready(&mut self, dev_addr: u8, bytes: &[u8]) -> Result<(), Error<E>> {
+        for _ in 0..self.poll_max_retries {
+            self.guard_delay().await;
+            if try_write(&mut self.i2c, dev_addr, bytes)
+                .await
+                .map_err(bus_error::<I2C>)?
+            {
                 return Ok(());
             }
-            self.delay.delay_us(POLL_DELAY_US).await;
+            self.poll_delay().await;
         }
         Err(Error::WriteAckTimeout)
     }
+
+    /// A single read transaction, without bad-page remapping.
+    ///
+    /// The device doesn't stop at the end of its memory: a sequential read that runs past the
+    /// last byte silently wraps around to offset 0. This doesn't check bounds, callers have
+    /// to keep `offset + bytes.len()` within the capacity.
+    async fn read_unmapped(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        let Some((start, skip)) = self.padded_read(offset, bytes.len()) else {
+            return self.read_transfer(offset, bytes).await;
+        };
+        let mut padded = [0; MAX_MIN_READ_LEN];
+        let padded = &mut padded[..self.min_read_len];
+        self.read_transfer(start, padded).await?;
+        bytes.copy_from_slice(&padded[skip..skip + bytes.len()]);
+        Ok(())
+    }
+
+    /// The transfers of a read, exactly as long as `bytes`
+    async fn read_transfer(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        if let Some(lower_len) = self.below_seam(offset, bytes.len()) {
+            let (lower, upper) = bytes.split_at_mut(lower_len);
+            self.read_transaction(offset, lower).await?;
+            return self.read_transaction(HALF_SEAM, upper).await;
+        }
+        self.read_transaction(offset, bytes).await
+    }
+
+    /// The transfers of a read within one half
+    async fn read_transaction(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        let device_address = self.get_device_address(offset)?;
+        let memaddr = memory_address_bytes(offset);
+        self.guard_delay().await;
+        let timeout_us = self.read_timeout_us;
+        match self.read_method {
+            ReadMethod::CombinedWriteRead => {
+                let transfer = self.i2c.write_read(device_address, &memaddr, bytes);
+                with_timeout(&mut self.delay, timeout_us, transfer).await
+            }
+            ReadMethod::WriteStopRead => {
+                let transfer = self.i2c.write(device_address, &memaddr);
+                match with_timeout(&mut self.delay, timeout_us, transfer).await {
+                    Some(Ok(())) => {
+                        self.guard_delay().await;
+                        let transfer = self.i2c.read(device_address, bytes);
+                        with_timeout(&mut self.delay, timeout_us, transfer).await
+                    }
+                    result => result,
+                }
+            }
+        }
+        .ok_or(Error::Timeout)?
+        .map_err(bus_error::<I2C>)
+    }
+
+    /// Single-shot version of the ACK polling done after every page write.
+    /// Returns `false` while the device is busy with a write cycle (it doesn't acknowledge).
+    pub async fn is_ready(&mut self) -> Result<bool, Error<E>> {
+        let dev_addr = self.get_device_address(0)?;
+        self.guard_delay().await;
+        probe(&mut self.i2c, dev_addr, self.ack_probe)
+            .await
+            .map_err(bus_error::<I2C>)
+    }
+
+    /// Writes a page like [`page_write`](Self::page_write) and reads it back into `readback`.
+    /// Returns `ReadbackFail` if the data doesn't match, leaving what was read in `readback`.
+    pub async fn page_write_verified(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        readback: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        if readback.len() < data.len() {
+            return Err(Error::OutOfBounds);
+        }
+        self.page_write(address, data).await?;
+        let readback = &mut readback[..data.len()];
+        self.read(address, readback).await?;
+        if readback != data {
+            return Err(Error::ReadbackFail);
+        }
+        Ok(())
+    }
+
+    /// Checks that `total_len` bytes at `offset` fit on the device, without touching the bus.
+    /// Methods that write several pieces call it first, so nothing is written if they don't.
+    pub fn can_write(&self, offset: u32, total_len: usize) -> Result<(), Error<E>> {
+        self.check_writable()?;
+        check_write(self, offset, total_len).map_err(Error::from_kind)
+    }
+
+    /// Reads `count` entries of `entry_size` bytes into `buf`. The first is at `base` and every
+    /// next one `stride` bytes after the previous, or before it for a negative `stride`.
+    ///
+    /// Returns `OutOfBounds` without reading anything if `buf` is too short or an entry lies
+    /// outside the device.
+    pub async fn read_strided(
+        &mut self,
+        base: u32,
+        stride: i32,
+        count: usize,
+        entry_size: usize,
+        buf: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        let total = count.checked_mul(entry_size).ok_or(Error::OutOfBounds)?;
+        if buf.len() < total {
+            return Err(Error::OutOfBounds);
+        }
+        if total == 0 {
+            return Ok(());
+        }
+        let last = (stride as i64)
+            .checked_mul(count as i64 - 1)
+            .and_then(|span| span.checked_add(base as i64))
+            .ok_or(Error::OutOfBounds)?;
+        let (lowest, highest) = (last.min(base as i64), last.max(base as i64));
+        if lowest < 0 || highest + entry_size as i64 > self.capacity() as i64 {
+            return Err(Error::OutOfBounds);
+        }
+        for (i, entry) in buf[..total].chunks_exact_mut(entry_size).enumerate() {
+            let offset = base as i64 + stride as i64 * i as i64;
+            self.read(offset as u32, entry).await?;
+        }
+        Ok(())
+    }
+
+    /// Offset of the first byte of the page `offset` is on
+    pub fn page_start(&self, offset: u32) -> u32 {
+        offset - offset % self.page_size as u32
+    }
+
+    /// Number of the page `offset` is on, counting from 0
+    pub fn page_index(&self, offset: u32) -> u32 {
+        offset / self.page_size as u32
+    }
+
+    /// Number of page writes a [`write`](NorFlash::write) of `len` bytes at `offset` makes,
+    /// one for every page the range touches. Each waits for its own write cycle. Doesn't
+    /// check bounds and doesn't count retries.
+    pub fn write_transaction_count(&self, offset: u32, len: usize) -> usize {
+        if len == 0 {
+            return 0;
+        }
+        (offset as usize % self.page_size + len).div_ceil(self.page_size)
+    }
+
+    /// The smallest page-aligned offset at or after `offset`, which may be the capacity.
+    /// Returns `OutOfBounds` past that.
+    pub fn align_up_to_page(&self, offset: u32) -> Result<u32, Error<E>> {
+        let page_size = self.page_size as u64;
+        let aligned = (offset as u64).div_ceil(page_size) * page_size;
+        if aligned > self.capacity() as u64 {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(aligned as u32)
+    }
+
+    /// Sets `len` bytes starting at `offset` to `value`, one page at a time.
+    pub async fn fill(&mut self, mut offset: u32, len: usize, value: u8) -> Result<(), Error<E>> {
+        self.can_write(offset, len)?;
+        let buf = [value; PAGE_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk_size = min(remaining, self.page_size - offset as usize % self.page_size);
+            self.page_write(offset, &buf[..chunk_size]).await?;
+            offset += chunk_size as u32;
+            remaining -= chunk_size;
+        }
+        self.flush().await
+    }
+
+    /// Writes `data` at `offset` one page at a time, waiting for every write cycle before the
+    /// next. `progress` is called with the number of bytes written after every page.
+    pub async fn write_image(
+        &mut self,
+        mut offset: u32,
+        data: &[u8],
+        mut progress: impl FnMut(u32),
+    ) -> Result<(), Error<E>> {
+        self.can_write(offset, data.len())?;
+        let mut written = 0;
+        while written < data.len() {
+            let chunk_size = min(
+                data.len() - written,
+                self.page_size - offset as usize % self.page_size,
+            );
+            self.page_write_retrying(offset, &data[written..written + chunk_size])
+                .await?;
+            self.flush().await?;
+            offset += chunk_size as u32;
+            written += chunk_size;
+            progress(written as u32);
+        }
+        Ok(())
+    }
+
+    /// Sets `len` bytes starting at `offset` to the [default byte](Self::set_default_byte).
+    pub async fn clear(&mut self, offset: u32, len: usize) -> Result<(), Error<E>> {
+        self.fill(offset, len, self.default_byte).await
+    }
 }
 
-impl<I2C, E: Debug, D: DelayNs> StorageErrorType for At24Cx<I2C, D>
-where
-    I2C: I2cErrorType<Error = E>,
-{
-    type Error = Error<E>;
+impl<I2C: I2cErrorType, D> StorageErrorType for At24Cx<I2C, D> {
+    type Error = Error<I2C::Error>;
 }
 
-impl<I2C, E: Debug, D: DelayNs> ReadNorFlash for At24Cx<I2C, D>
-where
-    I2C: I2c<Error = E>,
-{
+impl<I2C: I2c, D: DelayNs> ReadNorFlash for At24Cx<I2C, D> {
     const READ_SIZE: usize = 1;
 
-    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
-        match check_read(self, offset, bytes.len()) {
-            Err(NorFlashErrorKind::NotAligned) => return Err(Error::NotAligned),
-            Err(_) => return Err(Error::OutOfBounds),
-            Ok(_) => {}
+    async fn read(&mut self, mut offset: u32, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
+        // Reads past the end would wrap around on the device instead of failing
+        check_read(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        self.flush().await?;
+        // Nothing to transfer, and at the capacity there's no device address to read from
+        if bytes.is_empty() {
+            return Ok(());
         }
-        let device_address = self.get_device_address(offset)?;
-        let memaddr = [(offset >> 8) as u8, offset as u8];
-        self.i2c
-            .write_read(device_address, &memaddr[..2], bytes)
-            .await
-            .map_err(Error::I2cError)
+        if self.bad_pages.is_empty() {
+            return self.read_unmapped(offset, bytes).await;
+        }
+        while !bytes.is_empty() {
+            let chunk_size = min(
+                bytes.len(),
+                self.page_size - offset as usize % self.page_size,
+            );
+            let (chunk, rest) = bytes.split_at_mut(chunk_size);
+            self.read_unmapped(self.remap_read(offset), chunk).await?;
+            offset += chunk_size as u32;
+            bytes = rest;
+        }
+        Ok(())
     }
 
     fn capacity(&self) -> usize {
@@ -149,37 +1157,89 @@ where
     }
 }
 
-impl<I2C, E: Debug, D: DelayNs> NorFlash for At24Cx<I2C, D>
-where
-    I2C: I2c<Error = E>,
-{
+impl<I2C: I2c, D: DelayNs> NorFlash for At24Cx<I2C, D> {
     const WRITE_SIZE: usize = 1;
 
     const ERASE_SIZE: usize = PAGE_SIZE;
 
-    async fn erase(&mut self, _from: u32, _to: u32) -> Result<(), Self::Error> {
-        // No explicit erase needed
-        Ok(())
+    #[cfg(not(feature = "real-erase"))]
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        // No explicit erase needed, only the range is checked
+        check_erase(self, from, to).map_err(Error::from_kind)
+    }
+
+    /// Programs the range with the [default byte](At24Cx::set_default_byte).
+    #[cfg(feature = "real-erase")]
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        check_erase(self, from, to).map_err(Error::from_kind)?;
+        self.clear(from, (to - from) as usize).await
     }
 
     async fn write(&mut self, mut offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
-        match check_write(self, offset, bytes.len()) {
-            Err(NorFlashErrorKind::NotAligned) => return Err(Error::NotAligned),
-            Err(_) => return Err(Error::OutOfBounds),
-            Ok(_) => {}
+        self.check_writable()?;
+        check_write(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        let page_size = self.page_size;
+        let lead = offset as usize % page_size;
+        if self.page_aligned_writes && lead != 0 && bytes.len() > page_size - lead {
+            // Rewrite the start of the first page so the cycle starts on its boundary
+            let mut page = [0; PAGE_SIZE];
+            let page = &mut page[..page_size];
+            let page_start = offset - lead as u32;
+            self.read(page_start, &mut page[..lead]).await?;
+            page[lead..].copy_from_slice(&bytes[..page_size - lead]);
+            self.page_write_retrying(page_start, page).await?;
+            offset += (page_size - lead) as u32;
+            bytes = &bytes[page_size - lead..];
         }
         while !bytes.is_empty() {
-            let this_page_offset = offset as usize % PAGE_SIZE;
-            let this_page_remaining = PAGE_SIZE - this_page_offset;
+            let this_page_offset = offset as usize % page_size;
+            let this_page_remaining = page_size - this_page_offset;
             let chunk_size = min(bytes.len(), this_page_remaining);
-            self.page_write(offset, &bytes[..chunk_size]).await?;
+            self.page_write_retrying(offset, &bytes[..chunk_size])
+                .await?;
             offset += chunk_size as u32;
             bytes = &bytes[chunk_size..];
         }
-        Ok(())
+        self.flush().await
     }
 }
 
+/// Converts a bus error into an [`Error`] classified by its kind
+pub(crate) fn bus_error<I: I2cErrorType>(error: I::Error) -> Error<I::Error> {
+    Error::from(error)
+}
+
+/// Probes whether the device acknowledges its address, see [`AckProbe`].
+/// A NACK means the device is busy (or absent) and is reported as `Ok(false)`.
+async fn probe<I: I2c>(i2c: &mut I, dev_addr: u8, method: AckProbe) -> Result<bool, I::Error> {
+    let result = match method {
+        AckProbe::Write => i2c.write(dev_addr, &[0]).await,
+        AckProbe::Read => i2c.read(dev_addr, &mut [0]).await,
+        AckProbe::Quick => i2c.write(dev_addr, &[]).await,
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes `bytes`, returning `false` instead of an error if the device doesn't acknowledge.
+async fn try_write<I: I2c>(i2c: &mut I, dev_addr: u8, bytes: &[u8]) -> Result<bool, I::Error> {
+    match i2c.write(dev_addr, bytes).await {
+        Ok(()) => Ok(true),
+        Err(e) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The memory address bytes (MSB first) for an offset. Bit 16 is carried in the device address.
+fn memory_address_bytes(offset: u32) -> [u8; ADDRESS_BYTES] {
+    [(offset >> 8) as u8, offset as u8]
+}
+
 // Copied from https://github.com/rust-embedded-community/embedded-storage/blob/master/src/nor_flash.rs
 // TODO: It's not in the async version yet
 fn check_slice<T: ReadNorFlash>(
@@ -220,7 +1280,1069 @@ fn check_write<T: NorFlash>(
     check_slice(flash, T::WRITE_SIZE, offset, length)
 }
 
+// Copied from https://github.com/rust-embedded-community/embedded-storage/blob/master/src/nor_flash.rs
+// TODO: It's not in the async version yet
+/// Return whether an erase operation is aligned and within bounds.
+fn check_erase<T: NorFlash>(flash: &T, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+    if from > to {
+        return Err(NorFlashErrorKind::OutOfBounds);
+    }
+    check_slice(flash, T::ERASE_SIZE, from, (to - from) as usize)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sim::SimBus;
+    use crate::test_support::*;
+    use embedded_hal_mock::eh1::{
+        delay::NoopDelay,
+        i2c::{Mock as I2cMock, Transaction},
+    };
+
+    /// A bus that can't fail, like many test doubles
+    struct InfallibleBus;
+
+    impl I2cErrorType for InfallibleBus {
+        type Error = core::convert::Infallible;
+    }
+
+    impl I2c for InfallibleBus {
+        async fn transaction(
+            &mut self,
+            _address: u8,
+            operations: &mut [embedded_hal_async::i2c::Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            for operation in operations {
+                if let embedded_hal_async::i2c::Operation::Read(buf) = operation {
+                    buf.fill(0xFF);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// A bus whose transfers never complete
+    struct HangingBus;
+
+    impl I2cErrorType for HangingBus {
+        type Error = core::convert::Infallible;
+    }
+
+    impl I2c for HangingBus {
+        async fn transaction(
+            &mut self,
+            _address: u8,
+            _operations: &mut [embedded_hal_async::i2c::Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+    }
+
+    fn driver(
+        address: impl Into<DeviceAddress>,
+        address_bits: usize,
+    ) -> At24Cx<I2cMock, NoopDelay> {
+        At24Cx::new(I2cMock::new(&[]), address, address_bits, NoopDelay::new())
+    }
+
+    #[test]
+    fn decode_offset_cm01() {
+        let mut eeprom = driver(Address(0, 0), 17);
+        assert_eq!(
+            eeprom.decode_offset(0x0000).unwrap(),
+            (0x50, Vec::from_slice(&[0x00, 0x00]).unwrap())
+        );
+        assert_eq!(
+            eeprom.decode_offset(0xFFFF).unwrap(),
+            (0x50, Vec::from_slice(&[0xFF, 0xFF]).unwrap())
+        );
+        assert_eq!(
+            eeprom.decode_offset(0x10000).unwrap(),
+            (0x51, Vec::from_slice(&[0x00, 0x00]).unwrap())
+        );
+        assert_eq!(
+            eeprom.decode_offset(0x1ABCD).unwrap(),
+            (0x51, Vec::from_slice(&[0xAB, 0xCD]).unwrap())
+        );
+        assert!(matches!(
+            eeprom.decode_offset(0x20000),
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[test]
+    fn decode_offset_address_pins() {
+        let mut eeprom = driver(Address(1, 1), 17);
+        assert_eq!(eeprom.decode_offset(0x0123).unwrap().0, 0x56);
+        assert_eq!(eeprom.decode_offset(0x10123).unwrap().0, 0x57);
+        eeprom.i2c.done();
+    }
+
+    #[test]
+    fn decode_offset_raw_address() {
+        assert_eq!(Address::raw(0x1C).map(u8::from), Some(0x1C));
+        assert_eq!(Address::raw(0x54), Some(Address(0, 1).into()));
+        for reserved in [0x00, 0x07, 0x78, 0x7F, 0x80, 0xFF] {
+            assert_eq!(Address::raw(reserved), None, "{reserved:#x}");
+        }
+        // P0 is set on top of the raw base
+        let mut eeprom = driver(Address::raw(0x1C).unwrap(), 17);
+        assert_eq!(eeprom.decode_offset(0x0123).unwrap().0, 0x1C);
+        assert_eq!(eeprom.decode_offset(0x10123).unwrap().0, 0x1D);
+        eeprom.i2c.done();
+    }
+
+    #[test]
+    fn decode_offset_single_block_parts() {
+        // 64KiB (AT24C512) and 32KiB (AT24C256) parts never fold bits into the device byte
+        let mut eeprom = driver(Address(0, 1), 16);
+        assert_eq!(
+            eeprom.decode_offset(0xFFFF).unwrap(),
+            (0x54, Vec::from_slice(&[0xFF, 0xFF]).unwrap())
+        );
+        assert!(matches!(
+            eeprom.decode_offset(0x10000),
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+
+        let mut eeprom = driver(Address(0, 0), 15);
+        assert_eq!(
+            eeprom.decode_offset(0x7FFF).unwrap(),
+            (0x50, Vec::from_slice(&[0x7F, 0xFF]).unwrap())
+        );
+        assert!(matches!(
+            eeprom.decode_offset(0x8000),
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn page_write_verified_detects_stuck_cells() {
+        let mut bus = SimBus::new(Address(0, 0), 17).with_endurance(1);
+        bus.set_write_count(0x105, 1);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let mut readback = [0; 8];
+
+        eeprom
+            .page_write_verified(0x10, &[1, 2, 3, 4, 5, 6, 7, 8], &mut readback)
+            .await
+            .unwrap();
+        assert_eq!(&eeprom.i2c.memory()[0x10..0x18], &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let result = eeprom
+            .page_write_verified(0x100, &[0; 8], &mut readback)
+            .await;
+        assert!(matches!(result, Err(Error::ReadbackFail)));
+        assert_eq!(readback, [0, 0, 0, 0, 0, 0xFF, 0, 0]);
+        assert_eq!(eeprom.i2c.write_count(0x105), 2);
+    }
+
+    #[tokio::test]
+    async fn page_write_verified_detects_bit_errors() {
+        let bus = SimBus::builder(Address(0, 0), 17)
+            .bit_error(0x42, 0x10)
+            .build();
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let mut readback = [0; 4];
+        let result = eeprom
+            .page_write_verified(0x40, &[1, 2, 3, 4], &mut readback)
+            .await;
+        assert!(matches!(result, Err(Error::ReadbackFail)));
+        assert_eq!(readback, [1, 2, 0x13, 4]);
+        // The flip happened once, rewriting fixes it
+        eeprom
+            .page_write_verified(0x40, &[1, 2, 3, 4], &mut readback)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_retries_outlast_a_vanished_device() {
+        // Gone for the 4 polls after the first page write
+        let bus = SimBus::builder(Address(0, 0), 17).absent(1, 4).build();
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_poll_max_retries(4);
+        assert!(matches!(
+            eeprom.write(0, &[1]).await,
+            Err(Error::WriteAckTimeout)
+        ));
+
+        let bus = SimBus::builder(Address(0, 0), 17).absent(1, 4).build();
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_poll_max_retries(4);
+        eeprom.set_write_retries(1);
+        eeprom.write(0, &[1]).await.unwrap();
+        assert_eq!(eeprom.i2c.write_count(0), 2);
+        assert_eq!(eeprom.i2c.fired().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn infallible_bus_is_nor_flash() {
+        async fn round_trip<F: NorFlash>(flash: &mut F) -> Result<(), F::Error> {
+            flash.write(0, &[1, 2, 3]).await?;
+            flash.read(0, &mut [0; 3]).await
+        }
+        let mut eeprom = At24Cx::new(InfallibleBus, Address(0, 0), 17, NoopDelay::new());
+        let result: Result<(), Error<core::convert::Infallible>> = round_trip(&mut eeprom).await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn plain_bus_is_nor_flash() {
+        #[derive(Debug)]
+        struct BusError;
+
+        impl I2cError for BusError {
+            fn kind(&self) -> ErrorKind {
+                ErrorKind::Other
+            }
+        }
+
+        struct PlainBus;
+
+        impl I2cErrorType for PlainBus {
+            type Error = BusError;
+        }
+
+        impl I2c for PlainBus {
+            async fn transaction(
+                &mut self,
+                _address: u8,
+                _operations: &mut [embedded_hal_async::i2c::Operation<'_>],
+            ) -> Result<(), Self::Error> {
+                Err(BusError)
+            }
+        }
+
+        fn assert_nor_flash<F: NorFlash<Error = Error<BusError>>>(_: &F) {}
+        let eeprom = At24Cx::new(PlainBus, Address(0, 0), 17, NoopDelay::new());
+        assert_nor_flash(&eeprom);
+        let bus_error: Error<BusError> = BusError.into();
+        assert!(matches!(bus_error, Error::I2cError(BusError)));
+    }
+
+    #[tokio::test]
+    async fn is_ready_probes_once() {
+        let nack = ErrorKind::NoAcknowledge(embedded_hal_async::i2c::NoAcknowledgeSource::Address);
+        let expectations = [
+            Transaction::write(0x52, std::vec![0]).with_error(nack),
+            Transaction::write(0x52, std::vec![0]),
+            Transaction::write(0x52, std::vec![0]).with_error(ErrorKind::Bus),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(1, 0), 17, NoopDelay::new());
+        assert!(!eeprom.is_ready().await.unwrap());
+        assert!(eeprom.is_ready().await.unwrap());
+        assert!(matches!(
+            eeprom.is_ready().await,
+            Err(Error::Bus(ErrorKind::Bus))
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn caps_the_write_length() {
+        let data: [u8; 40] = core::array::from_fn(|i| i as u8);
+        let expectations = [
+            expect_page_write(0x50, 0x10, &data[..16]),
+            expect_ack_poll(0x50, 1),
+            expect_page_write(0x50, 0x20, &data[16..32]),
+            expect_ack_poll(0x50, 0),
+            expect_page_write(0x50, 0x30, &data[32..]),
+            expect_ack_poll(0x50, 0),
+            // Continues at the start of the page like the device would
+            expect_page_write(0x50, 0x1F8, &data[..16]),
+            expect_ack_poll(0x50, 0),
+            expect_page_write(0x50, 0x108, &data[16..20]),
+            expect_ack_poll(0x50, 0),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        assert!(matches!(
+            eeprom.set_max_write_len(0),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            eeprom.set_max_write_len(PAGE_SIZE + 1),
+            Err(Error::InvalidArgument)
+        ));
+        eeprom.set_max_write_len(16).unwrap();
+        assert_eq!(eeprom.max_write_len(), 16);
+        eeprom.write(0x10, &data).await.unwrap();
+        eeprom.page_write(0x1F8, &data[..20]).await.unwrap();
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn upper_half_at_its_own_address() {
+        let upper = Address::raw(0x5C).unwrap();
+        let expectations = [
+            // ACK polling waits for both halves, they may be two chips
+            Transaction::write(0x50, std::vec![0xFF, 0xFF, 1]),
+            Transaction::write(0x50, std::vec![0]),
+            Transaction::write(0x5C, std::vec![0]),
+            Transaction::write(0x5C, std::vec![0x00, 0x00, 2]),
+            Transaction::write(0x50, std::vec![0]),
+            Transaction::write(0x5C, std::vec![0]),
+            // Split at the seam
+            Transaction::write_read(0x50, std::vec![0xFF, 0xFE], std::vec![0, 1]),
+            Transaction::write_read(0x5C, std::vec![0x00, 0x00], std::vec![2]),
+            Transaction::write_read(0x5C, std::vec![0xFF, 0xFF], std::vec![3]),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_upper_half_address(Some(upper)).unwrap();
+        assert_eq!(eeprom.upper_half_address(), Some(upper));
+        assert_eq!(eeprom.decode_offset(0xFFFF).unwrap().0, 0x50);
+        assert_eq!(eeprom.decode_offset(0x10000).unwrap().0, 0x5C);
+        eeprom.write(0xFFFF, &[1, 2]).await.unwrap();
+        let mut buf = [0; 3];
+        eeprom.read(0xFFFE, &mut buf).await.unwrap();
+        assert_eq!(buf, [0, 1, 2]);
+        eeprom.read(0x1FFFF, &mut buf[..1]).await.unwrap();
+        assert_eq!(buf[0], 3);
+        eeprom.i2c.done();
+
+        let mut eeprom = At24Cx::new(I2cMock::new(&[]), Address(0, 0), 16, NoopDelay::new());
+        assert!(matches!(
+            eeprom.set_upper_half_address(Some(upper)),
+            Err(Error::InvalidArgument)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn classifies_bus_errors() {
+        use embedded_hal_async::i2c::NoAcknowledgeSource;
+        fn classify(error: &Error<ErrorKind>) -> &'static str {
+            match error {
+                Error::Bus(_) => "bus",
+                Error::ArbitrationLoss(_) => "arbitration loss",
+                Error::NoAcknowledge(_) => "no acknowledge",
+                Error::Overrun(_) => "overrun",
+                // A HAL timeout has no kind of its own
+                Error::I2cError(_) => "other",
+                _ => "not a bus error",
+            }
+        }
+        let kinds = [
+            (ErrorKind::Bus, "bus"),
+            (ErrorKind::ArbitrationLoss, "arbitration loss"),
+            (
+                ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+                "no acknowledge",
+            ),
+            (
+                ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data),
+                "no acknowledge",
+            ),
+            (
+                ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+                "no acknowledge",
+            ),
+            (ErrorKind::Overrun, "overrun"),
+            (ErrorKind::Other, "other"),
+        ];
+        let expectations: std::vec::Vec<_> = kinds
+            .iter()
+            .map(|&(kind, _)| {
+                Transaction::write_read(0x50, std::vec![0, 0], std::vec![0]).with_error(kind)
+            })
+            .collect();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        for (kind, class) in kinds {
+            let error = eeprom.read(0, &mut [0]).await.unwrap_err();
+            assert_eq!(classify(&error), class, "{kind:?}");
+            // The raw error is still there for logging
+            assert_eq!(error.i2c_error(), Some(&kind));
+        }
+        assert_eq!(Error::<ErrorKind>::Timeout.i2c_error(), None);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn ack_probe_methods() {
+        let nack = ErrorKind::NoAcknowledge(embedded_hal_async::i2c::NoAcknowledgeSource::Address);
+        let expectations = [
+            Transaction::read(0x50, std::vec![0xFF]).with_error(nack),
+            Transaction::read(0x50, std::vec![0xFF]),
+            Transaction::write(0x50, std::vec![]).with_error(nack),
+            Transaction::write(0x50, std::vec![]),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.ack_probe(), AckProbe::Write);
+        eeprom.set_ack_probe(AckProbe::Read);
+        assert!(!eeprom.is_ready().await.unwrap());
+        assert!(eeprom.is_ready().await.unwrap());
+        eeprom.set_ack_probe(AckProbe::Quick);
+        assert!(!eeprom.is_ready().await.unwrap());
+        assert!(eeprom.is_ready().await.unwrap());
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn polls_after_max_unverified_writes() {
+        let nack = ErrorKind::NoAcknowledge(embedded_hal_async::i2c::NoAcknowledgeSource::Address);
+        let expectations = [
+            expect_page_write(0x50, 0x000, &[1]),
+            // Still busy with the first page
+            std::vec![Transaction::write(0x50, std::vec![0x01, 0x00, 2]).with_error(nack)],
+            expect_page_write(0x50, 0x100, &[2]),
+            expect_page_write(0x50, 0x200, &[3]),
+            expect_ack_poll(0x50, 0),
+            expect_page_write(0x50, 0x300, &[4]),
+            // Reading waits for the last page
+            expect_ack_poll(0x50, 1),
+            expect_read(0x50, 0, &[1]),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_max_unverified_writes(3);
+        for page in 0..4u8 {
+            eeprom
+                .page_write(page as u32 * PAGE_SIZE as u32, &[page + 1])
+                .await
+                .unwrap();
+        }
+        let mut buf = [0];
+        eeprom.read(0, &mut buf).await.unwrap();
+        assert_eq!(buf, [1]);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn writes_raw() {
+        let expectations = [
+            expect_page_write(0x50, 0x100, &[1]),
+            // Flushes the page write first
+            expect_ack_poll(0x50, 0),
+            std::vec![Transaction::write(0x50, std::vec![2, 3])],
+            expect_ack_poll(0x50, 2),
+            std::vec![Transaction::write(0x51, std::vec![4])],
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_max_unverified_writes(2);
+        eeprom.page_write(0x100, &[1]).await.unwrap();
+        eeprom.write_raw(0x50, &[2, 3], true).await.unwrap();
+        eeprom.write_raw(0x51, &[4], false).await.unwrap();
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn poll_retries() {
+        let expectations = [
+            expect_page_write(0x50, 0, &[1]),
+            expect_ack_timeout(0x50, 2),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.poll_delay_us(), 200);
+        assert_eq!(eeprom.poll_max_retries(), 60);
+        eeprom.set_poll_delay_us(500);
+        eeprom.set_poll_max_retries(2);
+        assert_eq!(eeprom.poll_delay_us(), 500);
+        assert!(matches!(
+            eeprom.page_write(0, &[1]).await,
+            Err(Error::WriteAckTimeout)
+        ));
+        eeprom.i2c.done();
+    }
+
+    /// Records how every delay was asked for
+    #[derive(Default)]
+    struct RecordingDelay(std::vec::Vec<(&'static str, u32)>);
+
+    impl DelayNs for RecordingDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.0.push(("ns", ns));
+        }
+
+        async fn delay_us(&mut self, us: u32) {
+            self.0.push(("us", us));
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            self.0.push(("ms", ms));
+        }
+    }
+
+    #[tokio::test]
+    async fn poll_delay_granularity() {
+        let i2c = I2cMock::new(&[]);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.poll_delay_ns(), 200_000);
+        assert_eq!(eeprom.delay_granularity(), DelayGranularity::Micros);
+        eeprom.set_poll_delay_ns(1_001);
+        assert_eq!(eeprom.poll_delay_us(), 2);
+        eeprom.i2c.done();
+
+        for (granularity, expected) in [
+            (DelayGranularity::Nanos, ("ns", 1_500)),
+            (DelayGranularity::Micros, ("us", 2)),
+            (DelayGranularity::Millis, ("ms", 1)),
+        ] {
+            let expectations =
+                [expect_page_write(0x50, 0, &[1]), expect_ack_poll(0x50, 2)].concat();
+            let i2c = I2cMock::new(&expectations);
+            let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, RecordingDelay::default());
+            eeprom.set_poll_delay_ns(1_500);
+            eeprom.set_delay_granularity(granularity);
+            eeprom.page_write(0, &[1]).await.unwrap();
+            let (mut i2c, delay) = eeprom.into_parts();
+            i2c.done();
+            assert_eq!(delay.0, [expected; 2], "{granularity:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let i2c = I2cMock::new(&expect_read(0x50, 0x10, &[1, 2]));
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        assert!(!eeprom.read_only());
+        eeprom.set_read_only(true);
+        assert!(matches!(
+            eeprom.write(0x10, &[1]).await,
+            Err(Error::WriteEnableFail)
+        ));
+        assert!(matches!(
+            eeprom.erase(0, PAGE_SIZE as u32).await,
+            Err(Error::WriteEnableFail)
+        ));
+        assert!(matches!(
+            eeprom.fill(0, 4, 0).await,
+            Err(Error::WriteEnableFail)
+        ));
+        assert!(matches!(
+            eeprom.page_write(0x10, &[]).await,
+            Err(Error::WriteEnableFail)
+        ));
+        assert!(matches!(
+            eeprom.write_raw(0x50, &[0], true).await,
+            Err(Error::WriteEnableFail)
+        ));
+        let mut buf = [0; 2];
+        eeprom.read(0x10, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2]);
+        eeprom.i2c.done();
+    }
+
+    /// Stands for a library that takes its storage by value
+    async fn round_trip<F: NorFlash>(mut flash: F, offset: u32) -> Result<[u8; 4], F::Error> {
+        flash.write(offset, &[1, 2, 3, 4]).await?;
+        let mut buf = [0; 4];
+        flash.read(offset, &mut buf).await?;
+        Ok(buf)
+    }
+
+    #[tokio::test]
+    async fn lends_a_mutable_reference() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(round_trip(&mut eeprom, 0x10).await.unwrap(), [1, 2, 3, 4]);
+        assert!(matches!(
+            round_trip(&mut eeprom, 0x1FFFE).await,
+            Err(Error::OutOfBounds)
+        ));
+        // Still ours
+        eeprom.write(0x12, &[9]).await.unwrap();
+        let mut buf = [0; 4];
+        eeprom.read(0x10, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 9, 4]);
+    }
+
+    #[tokio::test]
+    async fn empty_page_write() {
+        let i2c = I2cMock::new(&[]);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.page_write(0x15, &[]).await.unwrap();
+        eeprom.page_write(0x1FFFF, &[]).await.unwrap();
+        eeprom.page_write(0x20000, &[]).await.unwrap();
+        assert!(matches!(
+            eeprom.page_write(0x20001, &[]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(eeprom.high_water_mark(), None);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn tracks_high_water_mark() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.high_water_mark(), None);
+        eeprom.write(0x1F0, &[1; 0x20]).await.unwrap();
+        assert_eq!(eeprom.high_water_mark(), Some(0x20F));
+        // Lower writes and empty ones leave it alone
+        eeprom.write(0x10, &[2; 4]).await.unwrap();
+        eeprom.write(0x400, &[]).await.unwrap();
+        assert_eq!(eeprom.high_water_mark(), Some(0x20F));
+        eeprom.page_write(0x300, &[3; 2]).await.unwrap();
+        assert_eq!(eeprom.high_water_mark(), Some(0x301));
+
+        eeprom.reset_high_water_mark();
+        assert_eq!(eeprom.high_water_mark(), None);
+        eeprom.write(0x10, &[4]).await.unwrap();
+        assert_eq!(eeprom.high_water_mark(), Some(0x10));
+    }
+
+    #[tokio::test]
+    async fn inter_op_delay() {
+        let expectations = [
+            expect_page_write(0x50, 0, &[1]),
+            expect_ack_poll(0x50, 1),
+            expect_read_with(ReadMethod::WriteStopRead, 0x50, 0, &[1]),
+        ]
+        .concat();
+        for guard in [0, 150] {
+            let i2c = I2cMock::new(&expectations);
+            let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, RecordingDelay::default());
+            eeprom.set_read_method(ReadMethod::WriteStopRead);
+            eeprom.set_inter_op_delay_us(guard);
+            assert_eq!(eeprom.inter_op_delay_us(), guard);
+            eeprom.page_write(0, &[1]).await.unwrap();
+            eeprom.read(0, &mut [0]).await.unwrap();
+            let (mut i2c, delay) = eeprom.into_parts();
+            i2c.done();
+            let poll = ("us", 200);
+            if guard == 0 {
+                assert_eq!(delay.0, [poll]);
+            } else {
+                let guard = ("us", guard);
+                // Write, two polls and the two halves of the read
+                assert_eq!(delay.0, [guard, guard, poll, guard, guard, guard]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn write_retries() {
+        let expectations = [
+            expect_page_write(0x50, 0, &[1]),
+            expect_ack_timeout(0x50, 1),
+            // Rewritten once
+            expect_write(0x50, 0, &[1]),
+            expect_page_write(0x50, 0x10, &[2]),
+            expect_ack_timeout(0x50, 1),
+            expect_page_write(0x50, 0x10, &[2]),
+            expect_ack_timeout(0x50, 1),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_poll_max_retries(1);
+        eeprom.set_write_retries(1);
+        eeprom.write(0, &[1]).await.unwrap();
+        assert!(matches!(
+            eeprom.write(0x10, &[2]).await,
+            Err(Error::WriteAckTimeout)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn read_methods() {
+        let expectations = [
+            expect_read(0x50, 0x10010, &[1, 2]),
+            std::vec![
+                Transaction::write(0x51, std::vec![0x00, 0x10]),
+                Transaction::read(0x51, std::vec![3, 4]),
+            ],
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        let mut buf = [0; 2];
+        eeprom.read(0x10010, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2]);
+        eeprom.set_read_method(ReadMethod::WriteStopRead);
+        eeprom.read(0x10010, &mut buf).await.unwrap();
+        assert_eq!(buf, [3, 4]);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn read_methods_on_every_read_path() {
+        for method in [ReadMethod::CombinedWriteRead, ReadMethod::WriteStopRead] {
+            let read = |offset, data: &[u8]| expect_read_with(method, 0x50, offset, data);
+            let expectations = [
+                // Sequential reads run on across the 64K block in one transfer
+                read(0xFFFE, &[1, 2, 3, 4]),
+                // Padded to the minimum read length
+                read(0x10, &[5, 6, 7, 8]),
+                // Split at a remapped page
+                read(0x1FE, &[9, 10]),
+                read(0x500, &[11, 12]),
+                // Page by page
+                read(0x3F0, &[0; 0x10]),
+                read(0x400, &[0; 0x10]),
+                // Read-back of a verified write
+                expect_page_write(0x50, 0x600, &[13]),
+                expect_ack_poll(0x50, 0),
+                read(0x600, &[13]),
+            ]
+            .concat();
+            let i2c = I2cMock::new(&expectations);
+            let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+            eeprom.set_read_method(method);
+
+            let mut buf = [0; 4];
+            eeprom.read(0xFFFE, &mut buf).await.unwrap();
+            assert_eq!(buf, [1, 2, 3, 4]);
+
+            eeprom.set_min_read_len(4).unwrap();
+            eeprom.read(0x10, &mut buf[..1]).await.unwrap();
+            assert_eq!(buf[0], 5);
+            eeprom.set_min_read_len(0).unwrap();
+
+            eeprom
+                .set_bad_pages(&[BadPage {
+                    page: 2,
+                    remap: Some(5),
+                }])
+                .unwrap();
+            eeprom.read(0x1FE, &mut buf).await.unwrap();
+            assert_eq!(buf, [9, 10, 11, 12]);
+            eeprom.set_bad_pages(&[]).unwrap();
+
+            let mut bits = [0];
+            eeprom
+                .verify_pages(0x3F0, &[0; 0x20], &mut bits)
+                .await
+                .unwrap();
+            assert_eq!(bits, [0]);
+
+            eeprom
+                .page_write_verified(0x600, &[13], &mut buf)
+                .await
+                .unwrap();
+            eeprom.i2c.done();
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_up_to_the_last_byte() {
+        let expectations = expect_read(0x50, 0xFFF6, &[0x42; 10]);
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 16, NoopDelay::new());
+        let mut buf = [0; 11];
+        eeprom.read(0xFFF6, &mut buf[..10]).await.unwrap();
+        assert_eq!(buf[..10], [0x42; 10]);
+        // One byte more would wrap around to offset 0, so it's rejected before the bus
+        assert!(matches!(
+            eeprom.read(0xFFF6, &mut buf).await,
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn pads_short_reads() {
+        let expected = [0x11, 0x22, 0x33, 0x44];
+        let expectations = [
+            expect_read(0x50, 0x10, &expected),
+            // At the end of the device the padding comes first
+            expect_read(0x51, 0x1FFFC, &expected),
+            expect_read(0x50, 0x20, &[0; 5]),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        assert!(matches!(
+            eeprom.set_min_read_len(MAX_MIN_READ_LEN + 1),
+            Err(Error::InvalidArgument)
+        ));
+        eeprom.set_min_read_len(4).unwrap();
+        assert_eq!(eeprom.min_read_len(), 4);
+        let mut buf = [0; 2];
+        eeprom.read(0x10, &mut buf).await.unwrap();
+        assert_eq!(buf, [0x11, 0x22]);
+        eeprom.read(0x1FFFE, &mut buf).await.unwrap();
+        assert_eq!(buf, [0x33, 0x44]);
+        // Long enough reads are left alone
+        eeprom.read(0x20, &mut [0; 5]).await.unwrap();
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn read_timeout() {
+        let mut eeprom = At24Cx::new(HangingBus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_read_timeout_us(Some(1000));
+        let mut buf = [0; 4];
+        assert!(matches!(
+            eeprom.read(0x10, &mut buf).await,
+            Err(Error::Timeout)
+        ));
+        eeprom.set_read_method(ReadMethod::WriteStopRead);
+        assert!(matches!(
+            eeprom.read(0x10, &mut buf).await,
+            Err(Error::Timeout)
+        ));
+
+        // A bus that answers wins the race
+        let expectations = [
+            Transaction::write(0x50, std::vec![0x00, 0x10]),
+            Transaction::read(0x50, std::vec![1, 2, 3, 4]),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_read_method(ReadMethod::WriteStopRead);
+        eeprom.set_read_timeout_us(Some(1000));
+        eeprom.read(0x10, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn page_aligned_writes() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        bus.memory_mut()[0x100..0x200].fill(0x11);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_page_aligned_writes(true);
+        eeprom.write(0x1F0, &[0x22; 0x20]).await.unwrap();
+        // The leading page was rewritten from its start, the old bytes are kept
+        assert!(eeprom.i2c.memory()[0x100..0x1F0].iter().all(|b| *b == 0x11));
+        assert!(eeprom.i2c.memory()[0x1F0..0x210].iter().all(|b| *b == 0x22));
+        assert_eq!(eeprom.i2c.write_count(0x100), 1);
+        assert_eq!(eeprom.i2c.write_count(0x210), 0);
+
+        // Writes within a single page aren't padded
+        eeprom.write(0x2F0, &[0x33; 0x10]).await.unwrap();
+        assert_eq!(eeprom.i2c.write_count(0x200), 1);
+        assert_eq!(eeprom.i2c.write_count(0x2EF), 0);
+    }
+
+    #[tokio::test]
+    async fn write_image() {
+        let data: std::vec::Vec<u8> = (0..0x220).map(|i| i as u8).collect();
+        let expectations = [
+            expect_write(0x50, 0xF0, &data[..0x10]),
+            expect_page_write(0x50, 0x100, &data[0x10..0x110]),
+            expect_ack_poll(0x50, 2),
+            expect_write(0x50, 0x200, &data[0x110..]),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        // Polling happens after every page, not only at the end
+        eeprom.set_max_unverified_writes(4);
+        let mut calls = std::vec::Vec::new();
+        eeprom
+            .write_image(0xF0, &data, |written| calls.push(written))
+            .await
+            .unwrap();
+        assert_eq!(calls, [0x10, 0x110, 0x210, 0x220]);
+        assert!(matches!(
+            eeprom.write_image(0x1FFF0, &data, |_| {}).await,
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn fill_and_clear() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.fill(0xF0, 0x220, 0x11).await.unwrap();
+        assert!(eeprom.i2c.memory()[0xF0..0x310].iter().all(|b| *b == 0x11));
+        assert_eq!(eeprom.i2c.memory()[0x310], 0xFF);
+
+        eeprom.clear(0x100, 0x10).await.unwrap();
+        assert!(eeprom.i2c.memory()[0x100..0x110].iter().all(|b| *b == 0xFF));
+        eeprom.set_default_byte(0x00);
+        eeprom.clear(0x110, 0x10).await.unwrap();
+        assert!(eeprom.i2c.memory()[0x110..0x120].iter().all(|b| *b == 0x00));
+        assert_eq!(eeprom.i2c.memory()[0x120], 0x11);
+
+        let result = eeprom.fill(0x1FFF0, 0x20, 0).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+    }
+
+    #[test]
+    fn can_write() {
+        let i2c = I2cMock::new(&[]);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.can_write(0x1FFF0, 0x10).unwrap();
+        eeprom.can_write(0x20000, 0).unwrap();
+        assert!(matches!(
+            eeprom.can_write(0x1FFF0, 0x11),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.can_write(0x20001, 0),
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn counts_page_writes() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        for (offset, len, count) in [
+            (0x100, 0, 0),
+            (0x100, 1, 1),
+            (0x100, 0x100, 1),
+            (0x101, 0x100, 2),
+            (0x1FF, 2, 2),
+            (0xFFF0, 0x220, 4),
+        ] {
+            assert_eq!(eeprom.write_transaction_count(offset, len), count);
+            // A page write and a poll that finds the device ready for each
+            let before = eeprom.i2c.transaction_count();
+            eeprom.write(offset, &std::vec![0; len]).await.unwrap();
+            assert_eq!(eeprom.i2c.transaction_count() - before, 2 * count);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_strided() {
+        let mut eeprom = At24Cx::new(
+            SimBus::new(Address(0, 0), 17),
+            Address(0, 0),
+            17,
+            NoopDelay::new(),
+        );
+        for (i, byte) in eeprom.i2c.memory_mut().iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut buf = [0; 8];
+        // Newest first
+        eeprom
+            .read_strided(0x130, -0x10, 4, 2, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, [0x30, 0x31, 0x20, 0x21, 0x10, 0x11, 0x00, 0x01]);
+        eeprom
+            .read_strided(0x1FFE0, 0x10, 2, 4, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, [0xE0, 0xE1, 0xE2, 0xE3, 0xF0, 0xF1, 0xF2, 0xF3]);
+
+        let transactions = eeprom.i2c.transaction_count();
+        for (base, stride, count, entry_size) in [
+            (0x1FFF0, 0x10, 2, 4),
+            (0x1FFE0, 0x10, 3, 2),
+            (0x20, -0x10, 4, 2),
+            (0, 1, 9, 1),
+        ] {
+            assert!(matches!(
+                eeprom
+                    .read_strided(base, stride, count, entry_size, &mut buf)
+                    .await,
+                Err(Error::OutOfBounds)
+            ));
+        }
+        assert_eq!(eeprom.i2c.transaction_count(), transactions);
+    }
+
+    #[test]
+    fn page_alignment() {
+        let i2c = I2cMock::new(&[]);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.page_start(0x1FF), 0x100);
+        assert_eq!(eeprom.page_index(0x1FF), 1);
+        assert_eq!(eeprom.align_up_to_page(0).unwrap(), 0);
+        assert_eq!(eeprom.align_up_to_page(0x100).unwrap(), 0x100);
+        assert_eq!(eeprom.align_up_to_page(0x101).unwrap(), 0x200);
+        assert_eq!(eeprom.align_up_to_page(0x1FF01).unwrap(), 0x20000);
+        assert!(matches!(
+            eeprom.align_up_to_page(0x20001),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.align_up_to_page(u32::MAX),
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[cfg(not(feature = "real-erase"))]
+    #[tokio::test]
+    async fn erase_is_a_no_op() {
+        let i2c = I2cMock::new(&[]);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.erase(0, 0x1000).await.unwrap();
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn erase_checks_its_range() {
+        use NorFlashErrorKind::{NotAligned, OutOfBounds};
+        let page = PAGE_SIZE as u32;
+        let capacity = 0x20000;
+        let cases = [
+            (0, page, Ok(())),
+            (page, 4 * page, Ok(())),
+            (capacity - page, capacity, Ok(())),
+            // An empty range is a legal no-op, even at the very end
+            (0, 0, Ok(())),
+            (page, page, Ok(())),
+            (capacity, capacity, Ok(())),
+            (2 * page, page, Err(OutOfBounds)),
+            (0, capacity + page, Err(OutOfBounds)),
+            (capacity + page, capacity + page, Err(OutOfBounds)),
+            (1, page, Err(NotAligned)),
+            (0, page + 1, Err(NotAligned)),
+            (0x80, 0x180, Err(NotAligned)),
+        ];
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        for (from, to, expected) in cases {
+            let result = eeprom.erase(from, to).await.map_err(|e| e.kind());
+            assert_eq!(result, expected, "{from:#x}..{to:#x}");
+        }
+    }
+
+    #[cfg(feature = "real-erase")]
+    #[tokio::test]
+    async fn erase_programs_default_byte() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        bus.memory_mut().fill(0x42);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_default_byte(0x00);
+        eeprom.erase(0x100, 0x300).await.unwrap();
+        assert!(eeprom.i2c.memory()[0x100..0x300].iter().all(|b| *b == 0x00));
+        assert_eq!(eeprom.i2c.memory()[0xFF], 0x42);
+        assert_eq!(eeprom.i2c.memory()[0x300], 0x42);
+    }
+
+    async fn write_and_read_back<F: NorFlash>(flash: &mut F) -> [u8; 4] {
+        flash.write(0xFE, &[1, 2, 3, 4]).await.unwrap();
+        let mut buf = [0; 4];
+        flash.read(0xFE, &mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn borrowed_bus_and_delay() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        let mut delay = NoopDelay::new();
+        {
+            let mut eeprom = At24Cx::new(&mut bus, Address(0, 0), 17, &mut delay);
+            assert_eq!(write_and_read_back(&mut eeprom).await, [1, 2, 3, 4]);
+            assert!(eeprom.is_ready().await.unwrap());
+        }
+        // The bus is ours again
+        assert_eq!(&bus.memory()[0xFE..0x102], &[1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn into_parts_returns_resources() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.fill(0x10, 4, 0xAB).await.unwrap();
+        let (bus, _delay) = eeprom.into_parts();
+        assert_eq!(&bus.memory()[0x10..0x14], &[0xAB; 4]);
+    }
 }
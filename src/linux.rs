@@ -0,0 +1,182 @@
+//! Running the driver on Linux through `/dev/i2c-*`, for provisioning tools on a desktop or
+//! a Raspberry Pi.
+//!
+//! [`LinuxI2c`] puts every transaction on the bus as one `I2C_RDWR` transfer and
+//! [`StdDelay`] sleeps the thread. Both block, so they work with any executor, or with
+//! none through the blocking traits of the `sync` feature. Errors are classified from the
+//! errno the kernel returns, so a device that doesn't acknowledge reads as a NACK and ACK
+//! polling works.
+//!
+//! ```no_run
+//! # use at24cx::{Address, At24Cx};
+//! # use embedded_storage_async::nor_flash::ReadNorFlash;
+//! # async fn provision() {
+//! let mut eeprom = At24Cx::new_linux("/dev/i2c-1", Address(0, 0), 17).unwrap();
+//! let mut serial = [0; 16];
+//! eeprom.read(0, &mut serial).await.unwrap();
+//! # }
+//! ```
+
+use crate::{At24Cx, DeviceAddress};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use i2cdev::core::{I2CMessage, I2CTransfer};
+use i2cdev::linux::{LinuxI2CBus, LinuxI2CError, LinuxI2CMessage};
+use std::path::Path;
+use std::time::Duration;
+use std::vec::Vec;
+
+// Linux errno values the I2C adapters use, see Documentation/i2c/fault-codes.rst
+const EIO: i32 = 5;
+const ENXIO: i32 = 6;
+const EAGAIN: i32 = 11;
+const EPROTO: i32 = 71;
+const EBADMSG: i32 = 74;
+const EOVERFLOW: i32 = 75;
+const EREMOTEIO: i32 = 121;
+
+/// Error of a [`LinuxI2c`] transaction
+#[derive(Debug)]
+pub struct LinuxError(pub LinuxI2CError);
+
+impl LinuxError {
+    fn errno(&self) -> Option<i32> {
+        match &self.0 {
+            LinuxI2CError::Nix(errno) => Some(*errno as i32),
+            LinuxI2CError::Io(e) => e.raw_os_error(),
+        }
+    }
+}
+
+/// Classifies an errno of an I2C transfer
+fn errno_kind(errno: i32) -> ErrorKind {
+    match errno {
+        // The address wasn't acknowledged, which is how a busy EEPROM answers
+        ENXIO => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+        // Not acknowledged in the address or the data phase, depending on the adapter
+        EREMOTEIO => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+        EAGAIN => ErrorKind::ArbitrationLoss,
+        EOVERFLOW => ErrorKind::Overrun,
+        EIO | EPROTO | EBADMSG => ErrorKind::Bus,
+        _ => ErrorKind::Other,
+    }
+}
+
+impl Error for LinuxError {
+    fn kind(&self) -> ErrorKind {
+        self.errno().map_or(ErrorKind::Other, errno_kind)
+    }
+}
+
+/// A Linux I2C bus, see the [module](self)
+pub struct LinuxI2c {
+    bus: LinuxI2CBus,
+}
+
+impl LinuxI2c {
+    /// Opens the bus at `path`, like `/dev/i2c-1`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LinuxError> {
+        let bus = LinuxI2CBus::new(path).map_err(LinuxError)?;
+        Ok(Self { bus })
+    }
+
+    fn run(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), LinuxError> {
+        let mut messages: Vec<LinuxI2CMessage<'_>> = operations
+            .iter_mut()
+            .map(|operation| match operation {
+                Operation::Read(buffer) => LinuxI2CMessage::read(buffer),
+                Operation::Write(bytes) => LinuxI2CMessage::write(bytes),
+            })
+            .map(|message| message.with_address(address as u16))
+            .collect();
+        self.bus.transfer(&mut messages).map_err(LinuxError)?;
+        Ok(())
+    }
+}
+
+impl ErrorType for LinuxI2c {
+    type Error = LinuxError;
+}
+
+impl I2c for LinuxI2c {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run(address, operations)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl embedded_hal::i2c::I2c for LinuxI2c {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run(address, operations)
+    }
+}
+
+/// A delay that sleeps the thread
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdDelay;
+
+impl DelayNs for StdDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(ns.into()));
+    }
+}
+
+#[cfg(feature = "sync")]
+impl embedded_hal::delay::DelayNs for StdDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(ns.into()));
+    }
+}
+
+impl At24Cx<LinuxI2c, StdDelay> {
+    /// Like [`new`](At24Cx::new), for a device on the Linux I2C bus at `path`
+    pub fn new_linux(
+        path: impl AsRef<Path>,
+        address: impl Into<DeviceAddress>,
+        address_bits: usize,
+    ) -> Result<Self, LinuxError> {
+        let i2c = LinuxI2c::open(path)?;
+        Ok(At24Cx::new(i2c, address, address_bits, StdDelay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn classifies_errnos() {
+        let kind =
+            |errno| LinuxError(LinuxI2CError::Io(io::Error::from_raw_os_error(errno))).kind();
+        assert_eq!(
+            kind(ENXIO),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        );
+        assert_eq!(
+            kind(EREMOTEIO),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+        );
+        assert_eq!(kind(EAGAIN), ErrorKind::ArbitrationLoss);
+        assert_eq!(kind(EOVERFLOW), ErrorKind::Overrun);
+        assert_eq!(kind(EIO), ErrorKind::Bus);
+        // ETIMEDOUT
+        assert_eq!(kind(110), ErrorKind::Other);
+        let other = LinuxError(LinuxI2CError::Io(io::Error::other("no errno")));
+        assert_eq!(other.kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn missing_bus() {
+        let error = LinuxI2c::open("/dev/i2c-does-not-exist").err().unwrap();
+        assert!(matches!(error.0, LinuxI2CError::Io(e) if e.kind() == io::ErrorKind::NotFound));
+    }
+}
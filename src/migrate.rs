@@ -0,0 +1,114 @@
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Rewrites the whole device in place, passing every [page](Self::set_page_size) with
+    /// its offset through `transform` in a buffer of at most [`PAGE_SIZE`] bytes. That buffer
+    /// and a copy to compare it with are all the memory this takes.
+    ///
+    /// Pages are read, transformed and written back one at a time from offset 0. Pages that
+    /// `transform` left as they were aren't written, so they don't wear. The migration isn't
+    /// atomic: after a power loss or an error the pages before the one that failed hold the
+    /// new layout, the rest the old one and that page can be torn between both. Keep the
+    /// migration idempotent so it can simply be run again, or have the last page tell the
+    /// layouts apart, since it is written last if it changes.
+    pub async fn migrate(
+        &mut self,
+        mut transform: impl FnMut(u32, &mut [u8]),
+    ) -> Result<(), Error<E>> {
+        let page_size = self.page_size;
+        let mut buf = [0; PAGE_SIZE];
+        let mut original = [0; PAGE_SIZE];
+        let (page, original) = (&mut buf[..page_size], &mut original[..page_size]);
+        for offset in (0..self.capacity() as u32).step_by(page_size) {
+            self.read(offset, page).await?;
+            original.copy_from_slice(page);
+            transform(offset, page);
+            if page != original {
+                self.write(offset, page).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBus;
+    use crate::{Address, DeviceKind};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec::Vec;
+
+    #[tokio::test]
+    async fn transforms_every_page() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        for (i, byte) in bus.memory_mut().iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let mut offsets = Vec::new();
+        eeprom
+            .migrate(|offset, page| {
+                offsets.push(offset);
+                assert_eq!(page[1], 1);
+                page[0] = (offset >> 8) as u8;
+            })
+            .await
+            .unwrap();
+        let expected: Vec<u32> = (0..0x20000).step_by(PAGE_SIZE).collect();
+        assert_eq!(offsets, expected);
+
+        let memory = eeprom.i2c.memory();
+        assert_eq!(memory[0x1FF00], 0xFF);
+        assert_eq!(memory[0x1FF01..0x1FF03], [1, 2]);
+        assert_eq!(memory[0x300], 3);
+        assert_eq!(eeprom.i2c.write_count(0x300), 1);
+        // Page 0 already started with 0
+        assert_eq!(eeprom.i2c.write_count(0), 0);
+    }
+
+    #[tokio::test]
+    async fn steps_by_the_page_size_and_skips_unchanged_pages() {
+        let address = Address::pins(1, 1, 1);
+        let bus = SimBus::new(address, 12).with_page_size(32);
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+        let mut offsets = Vec::new();
+        eeprom
+            .migrate(|offset, page| {
+                offsets.push(offset);
+                assert_eq!(page.len(), 32);
+                if offset % 0x100 == 0 {
+                    page[31] = 0;
+                }
+            })
+            .await
+            .unwrap();
+        let expected: Vec<u32> = (0..0x1000).step_by(32).collect();
+        assert_eq!(offsets, expected);
+        let written = (0..0x1000).filter(|&i| eeprom.i2c.write_count(i) > 0);
+        assert!(written.eq((0..0x1000).step_by(0x100).flat_map(|page| page..page + 32)));
+        assert_eq!(eeprom.i2c.memory()[0x11F], 0);
+        assert_eq!(eeprom.i2c.memory()[0x13F], 0xFF);
+    }
+
+    #[tokio::test]
+    async fn stops_at_a_power_loss() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        // In the middle of the third page
+        bus.cut_power_after(2 * PAGE_SIZE + 10);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert!(eeprom.migrate(|_, page| page.fill(0)).await.is_err());
+        eeprom.i2c.restore_power();
+
+        let memory = eeprom.i2c.memory();
+        assert!(memory[..2 * PAGE_SIZE + 10].iter().all(|&b| b == 0));
+        assert!(memory[2 * PAGE_SIZE + 10..].iter().all(|&b| b == 0xFF));
+    }
+}
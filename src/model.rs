@@ -0,0 +1,57 @@
+//! Property tests running random operation sequences against the simulator and a plain
+//! `Vec<u8>` model of the device.
+//!
+//! Offsets and lengths favour the interesting cases: page boundaries, the 64 KiB block
+//! boundary, the end of the device and values that overflow `u32` arithmetic. The
+//! operations and the model are shared with the fuzz targets, see [`crate::fuzzing`].
+
+use crate::fuzzing::{block_on, near, run, Op, BOUNDARIES, CAPACITY};
+use crate::PAGE_SIZE;
+use proptest::prelude::*;
+use std::vec::Vec;
+
+/// Offsets anywhere on the device, next to a boundary or far past the end
+pub fn offset() -> impl Strategy<Value = u32> {
+    let near = |at: u32| (0..8u32).prop_map(move |d| near(at, d));
+    prop_oneof![
+        0..CAPACITY,
+        (0..CAPACITY / PAGE_SIZE as u32).prop_flat_map(move |p| near(p * PAGE_SIZE as u32)),
+        proptest::sample::select(&BOUNDARIES[..]).prop_flat_map(near),
+        (0..16u32).prop_map(|d| u32::MAX - d),
+    ]
+}
+
+/// Lengths from empty to a few pages, with extra weight around a page
+pub fn len() -> impl Strategy<Value = usize> {
+    prop_oneof![0..16usize, PAGE_SIZE - 2..PAGE_SIZE + 3, 0..3 * PAGE_SIZE,]
+}
+
+fn data() -> impl Strategy<Value = Vec<u8>> {
+    len().prop_flat_map(|len| proptest::collection::vec(any::<u8>(), len))
+}
+
+pub fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (offset(), data()).prop_map(|(offset, data)| Op::Write { offset, data }),
+        (
+            offset(),
+            proptest::collection::vec(any::<u8>(), 0..PAGE_SIZE + 2)
+        )
+            .prop_map(|(offset, data)| Op::PageWrite { offset, data }),
+        (offset(), len(), any::<u8>()).prop_map(|(offset, len, value)| Op::Fill {
+            offset,
+            len,
+            value
+        }),
+        (offset(), len()).prop_map(|(offset, len)| Op::Read { offset, len }),
+        (offset(), any::<usize>()).prop_map(|(offset, len)| Op::CanWrite { offset, len }),
+        offset().prop_map(|offset| Op::AlignUp { offset }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn driver_matches_the_model(ops in proptest::collection::vec(op(), 1..24)) {
+        block_on(run(&ops));
+    }
+}
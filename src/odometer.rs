@@ -0,0 +1,293 @@
+//! Accumulating 64-bit odometer in a region of the device, for operating hours and the like.
+//!
+//! Time is added with [`tick`](Odometer::tick) in RAM and only written to the device once
+//! the uncommitted amount reaches a threshold, or on an explicit
+//! [`commit`](Odometer::commit). The region is divided into 16 byte slots used in rotation,
+//! each holding a little endian sequence number, the value and a CRC-32 over both. Loading
+//! picks the intact slot with the highest sequence number, so a torn commit falls back to
+//! the previous one.
+
+use crate::crc::crc32;
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+const SLOT_SIZE: usize = 16;
+
+/// Value found on the device by [`Odometer::load`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OdometerReading {
+    pub value: u64,
+    /// How far `value` can be behind the time actually ticked before the reset.
+    ///
+    /// Up to `threshold - 1` can be pending in RAM when power is lost, and a commit torn
+    /// by the power loss drops what it was committing, less than `threshold` plus the tick
+    /// that triggered it. As long as no single tick exceeds the threshold this makes
+    /// `2 · threshold - 1`.
+    pub max_undercount: u64,
+}
+
+/// Batched odometer over a region, see the [module docs](self)
+///
+/// The odometer caches its state after the first access, so it must always be used with the
+/// same device and be the only writer to its region.
+pub struct Odometer {
+    region: Range<u32>,
+    threshold: u64,
+    state: Option<State>,
+    pending: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    /// Sequence number and slot of the last commit, `None` if there wasn't any
+    last: Option<(u32, u32)>,
+    committed: u64,
+}
+
+impl Odometer {
+    /// Creates an odometer over `region`, which needs room for at least two slots of 16
+    /// bytes. Ticks are committed once `threshold` of them are pending, a threshold of 0 is
+    /// treated as 1.
+    pub fn new(region: Range<u32>, threshold: u64) -> Self {
+        Self {
+            region,
+            threshold: threshold.max(1),
+            state: None,
+            pending: 0,
+        }
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    /// Number of slots the commits rotate through
+    pub fn slots(&self) -> u32 {
+        self.region.end.saturating_sub(self.region.start) / SLOT_SIZE as u32
+    }
+
+    /// Reads the last committed value from the device.
+    ///
+    /// Returns `OutOfBounds` if the region doesn't fit on the device and `InvalidArgument`
+    /// if it's too small.
+    pub async fn load<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<OdometerReading, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let state = self.state(eeprom).await?;
+        Ok(OdometerReading {
+            value: state.committed,
+            max_undercount: 2 * self.threshold - 1,
+        })
+    }
+
+    /// Adds `elapsed` and commits if the uncommitted amount reached the threshold.
+    /// Returns whether it committed.
+    pub async fn tick<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        elapsed: u64,
+    ) -> Result<bool, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        self.pending = self.pending.saturating_add(elapsed);
+        if self.pending < self.threshold {
+            return Ok(false);
+        }
+        self.commit(eeprom).await?;
+        Ok(true)
+    }
+
+    /// Writes the current value to the next slot, if anything is pending
+    pub async fn commit<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let mut state = self.state(eeprom).await?;
+        if self.pending == 0 {
+            return Ok(());
+        }
+        let (seq, slot) = match state.last {
+            Some((seq, slot)) => (seq + 1, (slot + 1) % self.slots()),
+            None => (0, 0),
+        };
+        let value = state.committed.saturating_add(self.pending);
+        let mut bytes = [0; SLOT_SIZE];
+        bytes[..4].copy_from_slice(&seq.to_le_bytes());
+        bytes[4..12].copy_from_slice(&value.to_le_bytes());
+        let crc = crc32(&bytes[..12]);
+        bytes[12..].copy_from_slice(&crc.to_le_bytes());
+        let offset = self.region.start + slot * SLOT_SIZE as u32;
+        eeprom.write(offset, &bytes).await?;
+
+        state.last = Some((seq, slot));
+        state.committed = value;
+        self.state = Some(state);
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Current value including what's still pending, or `None` before the first access to
+    /// the device
+    pub fn value(&self) -> Option<u64> {
+        self.state
+            .map(|state| state.committed.saturating_add(self.pending))
+    }
+
+    async fn state<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<State, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if let Some(state) = self.state {
+            return Ok(state);
+        }
+        if self.region.end as usize > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        if self.slots() < 2 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut state = State {
+            last: None,
+            committed: 0,
+        };
+        for slot in 0..self.slots() {
+            let mut bytes = [0; SLOT_SIZE];
+            let offset = self.region.start + slot * SLOT_SIZE as u32;
+            eeprom.read(offset, &mut bytes).await?;
+            let seq = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            let crc = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+            // A blank slot could pass the CRC check
+            if seq == u32::MAX || crc32(&bytes[..12]) != crc {
+                continue;
+            }
+            if state.last.map_or(true, |(last, _)| seq > last) {
+                state.last = Some((seq, slot));
+                state.committed = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+            }
+        }
+        self.state = Some(state);
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: Range<u32> = 0x200..0x240;
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn rotates_through_slots() {
+        let mut eeprom = driver(None);
+        let mut odometer = Odometer::new(REGION, 100);
+        assert_eq!(odometer.slots(), 4);
+        assert_eq!(odometer.load(&mut eeprom).await.unwrap().value, 0);
+        let mut commits = 0;
+        for _ in 0..20 {
+            commits += odometer.tick(&mut eeprom, 50).await.unwrap() as u32;
+        }
+        assert_eq!(commits, 10);
+        assert_eq!(odometer.value(), Some(1000));
+
+        // 10 commits over 4 slots
+        for (slot, writes) in [(0, 3), (1, 3), (2, 2), (3, 2)] {
+            let offset = REGION.start as usize + slot * SLOT_SIZE;
+            assert_eq!(eeprom.i2c.write_count(offset), writes);
+        }
+        let mut odometer = Odometer::new(REGION, 100);
+        assert_eq!(odometer.load(&mut eeprom).await.unwrap().value, 1000);
+        odometer.tick(&mut eeprom, 30).await.unwrap();
+        odometer.commit(&mut eeprom).await.unwrap();
+        assert_eq!(
+            eeprom
+                .i2c
+                .write_count(REGION.start as usize + 2 * SLOT_SIZE),
+            3
+        );
+        let mut odometer = Odometer::new(REGION, 100);
+        assert_eq!(odometer.load(&mut eeprom).await.unwrap().value, 1030);
+    }
+
+    #[tokio::test]
+    async fn torn_commit_falls_back() {
+        let mut eeprom = driver(None);
+        let mut odometer = Odometer::new(REGION, 100);
+        for _ in 0..6 {
+            odometer.tick(&mut eeprom, 100).await.unwrap();
+        }
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        for cut in 0..SLOT_SIZE {
+            let mut eeprom = driver(Some(&snapshot));
+            eeprom.i2c.cut_power_after(cut);
+            let mut odometer = Odometer::new(REGION, 100);
+            assert!(odometer.tick(&mut eeprom, 100).await.is_err());
+            eeprom.i2c.restore_power();
+
+            let mut odometer = Odometer::new(REGION, 100);
+            assert_eq!(odometer.load(&mut eeprom).await.unwrap().value, 600);
+            odometer.tick(&mut eeprom, 100).await.unwrap();
+            let mut odometer = Odometer::new(REGION, 100);
+            assert_eq!(odometer.load(&mut eeprom).await.unwrap().value, 700);
+        }
+    }
+
+    #[tokio::test]
+    async fn undercount_stays_within_bound() {
+        let threshold = 60;
+        let snapshot = driver(None).i2c.memory().to_vec();
+        // Every tick size up to the threshold, losing power before, during and after a commit
+        for tick in 1..=threshold {
+            for cut in [0, SLOT_SIZE / 2, SLOT_SIZE] {
+                let mut eeprom = driver(Some(&snapshot));
+                let mut odometer = Odometer::new(REGION, threshold);
+                let mut ticked = 0;
+                while eeprom.i2c.write_count(REGION.start as usize + SLOT_SIZE) == 0 {
+                    odometer.tick(&mut eeprom, tick).await.unwrap();
+                    ticked += tick;
+                }
+                eeprom.i2c.cut_power_after(cut);
+                loop {
+                    ticked += tick;
+                    if odometer.tick(&mut eeprom, tick).await.is_err() {
+                        break;
+                    }
+                }
+                eeprom.i2c.restore_power();
+
+                let reading = Odometer::new(REGION, threshold)
+                    .load(&mut eeprom)
+                    .await
+                    .unwrap();
+                assert_eq!(reading.max_undercount, 2 * threshold - 1);
+                assert!(reading.value <= ticked);
+                assert!(ticked - reading.value <= reading.max_undercount);
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Panic messages saved to the device for post-mortem analysis.
+//!
+//! A panic handler can't await, so [`PanicStore::write_panic_blocking`] goes through the
+//! blocking [`NorFlash`](BlockingNorFlash) implementation of a driver over a blocking bus,
+//! see the `sync` feature. On the next boot the async driver
+//! [takes](PanicStore::take_last_panic) the message back. The region holds a single record:
+//!
+//! | bytes     | content                                     |
+//! |-----------|---------------------------------------------|
+//! | 0..4      | sequence number, counting panics            |
+//! | 4         | status, 0xFF until taken, 0x00 after        |
+//! | 5..7      | message length                              |
+//! | 7..7+n    | message, UTF-8 truncated to fit             |
+//! | ..+4      | CRC-32 over everything before it but status |
+//!
+//! All integers are little endian.
+//!
+//! ```ignore
+//! #[panic_handler]
+//! fn panic(info: &core::panic::PanicInfo) -> ! {
+//!     let mut eeprom = At24Cx::new(steal_blocking_i2c(), Address(0, 0), 17, Delay);
+//!     let _ = PANIC_STORE.write_panic_blocking(&mut eeprom, info);
+//!     reset()
+//! }
+//! ```
+
+use crate::crc::crc32_update;
+use crate::{At24Cx, Error};
+use core::fmt::{Debug, Display, Write};
+use core::ops::Range;
+use core::panic::PanicInfo;
+use embedded_storage::nor_flash::{
+    NorFlash as BlockingNorFlash, ReadNorFlash as BlockingReadNorFlash,
+};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Longest message that is kept, longer ones are truncated
+pub const MAX_PANIC_MESSAGE_LEN: usize = 256;
+
+const HEADER_SIZE: usize = 7;
+const OVERHEAD: usize = HEADER_SIZE + 4;
+const STATUS_UNREAD: u8 = 0xFF;
+const STATUS_TAKEN: u8 = 0x00;
+
+/// Panic message record in a region, see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct PanicStore {
+    region: Range<u32>,
+}
+
+impl PanicStore {
+    /// Creates a store over `region`. Messages are truncated to the region's length minus
+    /// 11 bytes of framing, or [`MAX_PANIC_MESSAGE_LEN`] if that is shorter.
+    pub const fn new(region: Range<u32>) -> Self {
+        Self { region }
+    }
+
+    /// Longest message the region can hold
+    pub fn capacity(&self) -> usize {
+        let len = self.region.end.saturating_sub(self.region.start) as usize;
+        len.saturating_sub(OVERHEAD).min(MAX_PANIC_MESSAGE_LEN)
+    }
+
+    /// Saves the panic message with blocking bus operations, for use in a
+    /// `#[panic_handler]`. The message is truncated to the [capacity](Self::capacity).
+    pub fn write_panic_blocking<I2C, D>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        info: &PanicInfo,
+    ) -> Result<(), Error<I2C::Error>>
+    where
+        I2C: embedded_hal::i2c::I2c,
+        D: embedded_hal::delay::DelayNs,
+    {
+        self.write_message_blocking(eeprom, info)
+    }
+
+    /// Saves any message like [`write_panic_blocking`](Self::write_panic_blocking), e.g.
+    /// from a hard fault handler.
+    ///
+    /// Returns `InvalidArgument` if the region can't hold a single byte of message and
+    /// `OutOfBounds` if it doesn't fit on the device.
+    pub fn write_message_blocking<I2C, D>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        message: impl Display,
+    ) -> Result<(), Error<I2C::Error>>
+    where
+        I2C: embedded_hal::i2c::I2c,
+        D: embedded_hal::delay::DelayNs,
+    {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut sequence = [0; 4];
+        BlockingReadNorFlash::read(eeprom, self.region.start, &mut sequence)?;
+        let sequence = match u32::from_le_bytes(sequence) {
+            u32::MAX => 0,
+            last => last.wrapping_add(1),
+        };
+
+        let mut record = [0; OVERHEAD + MAX_PANIC_MESSAGE_LEN];
+        let mut writer = Truncating {
+            buf: &mut record[HEADER_SIZE..HEADER_SIZE + capacity],
+            len: 0,
+        };
+        // Truncation isn't an error
+        let _ = write!(writer, "{message}");
+        let len = writer.len;
+        record[0..4].copy_from_slice(&sequence.to_le_bytes());
+        record[4] = STATUS_UNREAD;
+        record[5..7].copy_from_slice(&(len as u16).to_le_bytes());
+        let (header, message) = record.split_at(HEADER_SIZE);
+        let crc = crc(header, &message[..len]);
+        record[HEADER_SIZE + len..OVERHEAD + len].copy_from_slice(&crc.to_le_bytes());
+        BlockingNorFlash::write(eeprom, self.region.start, &record[..OVERHEAD + len])
+    }
+
+    /// Reads the message saved since the last call into `buf` and marks it as taken.
+    ///
+    /// Returns `None` if there is no message, it was already taken or the record doesn't
+    /// check out, and `OutOfBounds` if `buf` is shorter than the message.
+    pub async fn take_last_panic<'b, I2C, E: Debug, D>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        buf: &'b mut [u8],
+    ) -> Result<Option<&'b str>, Error<E>>
+    where
+        I2C: embedded_hal_async::i2c::I2c<Error = E>,
+        D: embedded_hal_async::delay::DelayNs,
+    {
+        let mut header = [0; HEADER_SIZE];
+        ReadNorFlash::read(eeprom, self.region.start, &mut header).await?;
+        let len = u16::from_le_bytes([header[5], header[6]]) as usize;
+        if header[4] != STATUS_UNREAD || len > self.capacity() {
+            return Ok(None);
+        }
+        if len > buf.len() {
+            return Err(Error::OutOfBounds);
+        }
+        let message = &mut buf[..len];
+        let mut stored = [0; 4];
+        let offset = self.region.start + HEADER_SIZE as u32;
+        ReadNorFlash::read(eeprom, offset, message).await?;
+        ReadNorFlash::read(eeprom, offset + len as u32, &mut stored).await?;
+        if crc(&header, message) != u32::from_le_bytes(stored) {
+            return Ok(None);
+        }
+        NorFlash::write(eeprom, self.region.start + 4, &[STATUS_TAKEN]).await?;
+        Ok(core::str::from_utf8(message).ok())
+    }
+}
+
+/// CRC-32 over a record without its status byte
+fn crc(header: &[u8], message: &[u8]) -> u32 {
+    let crc = crc32_update(crc32_update(!0, &header[..4]), &header[5..HEADER_SIZE]);
+    !crc32_update(crc, message)
+}
+
+/// Formats into a buffer, dropping whatever doesn't fit on a character boundary
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let room = self.buf.len() - self.len;
+        let mut end = s.len().min(room);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        if end < s.len() {
+            return Err(core::fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: Range<u32> = 0x1000..0x1020;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn take_clears() {
+        let mut eeprom = driver();
+        let mut store = PanicStore::new(REGION);
+        let mut buf = [0; 64];
+        assert_eq!(
+            store.take_last_panic(&mut eeprom, &mut buf).await.unwrap(),
+            None
+        );
+
+        let line = 42;
+        store
+            .write_message_blocking(&mut eeprom, format_args!("oops at line {line}"))
+            .unwrap();
+        assert_eq!(
+            store.take_last_panic(&mut eeprom, &mut buf).await.unwrap(),
+            Some("oops at line 42")
+        );
+        assert_eq!(
+            store.take_last_panic(&mut eeprom, &mut buf).await.unwrap(),
+            None
+        );
+
+        store.write_message_blocking(&mut eeprom, "again").unwrap();
+        assert_eq!(eeprom.i2c.memory()[0x1000..0x1004], [1, 0, 0, 0]);
+        let mut small = [0; 4];
+        assert!(matches!(
+            store.take_last_panic(&mut eeprom, &mut small).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(
+            store.take_last_panic(&mut eeprom, &mut buf).await.unwrap(),
+            Some("again")
+        );
+    }
+
+    #[tokio::test]
+    async fn truncates_on_char_boundaries() {
+        let mut eeprom = driver();
+        let mut store = PanicStore::new(REGION);
+        assert_eq!(store.capacity(), 21);
+        // 20 ASCII bytes, then a two byte character that doesn't fit
+        store
+            .write_message_blocking(&mut eeprom, "01234567890123456789é and more")
+            .unwrap();
+        let mut buf = [0; 64];
+        assert_eq!(
+            store.take_last_panic(&mut eeprom, &mut buf).await.unwrap(),
+            Some("01234567890123456789")
+        );
+        // Nothing is written past the region
+        assert_eq!(eeprom.i2c.write_count(REGION.end as usize), 0);
+
+        let mut tiny = PanicStore::new(0x2000..0x200B);
+        assert!(matches!(
+            tiny.write_message_blocking(&mut eeprom, "x"),
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_garbage() {
+        let mut eeprom = driver();
+        let mut store = PanicStore::new(REGION);
+        let mut buf = [0; 64];
+        eeprom.i2c.memory_mut()[0x1000..0x1020].fill(0x00);
+        eeprom.i2c.memory_mut()[0x1004] = 0xFF;
+        eeprom.i2c.memory_mut()[0x1005] = 3;
+        assert_eq!(
+            store.take_last_panic(&mut eeprom, &mut buf).await.unwrap(),
+            None
+        );
+
+        store.write_message_blocking(&mut eeprom, "boom").unwrap();
+        eeprom.i2c.memory_mut()[0x1008] ^= 0x20;
+        assert_eq!(
+            store.take_last_panic(&mut eeprom, &mut buf).await.unwrap(),
+            None
+        );
+        // A rejected record isn't marked as taken
+        assert_eq!(eeprom.i2c.memory()[0x1004], 0xFF);
+    }
+}
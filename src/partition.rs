@@ -0,0 +1,327 @@
+use crate::{check_read, check_write, At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{
+    delay::DelayNs,
+    i2c::{ErrorType as I2cErrorType, I2c},
+};
+use embedded_storage_async::nor_flash::{
+    ErrorType as StorageErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// A view of a sub-range of the EEPROM. Offsets are relative to the start of the partition
+/// and any access outside of it is rejected with `OutOfBounds`. Like the driver, a
+/// partition can be lent to a library that takes `NorFlash` storage by value as `&mut`.
+pub struct EepromPartition<'a, I2C, D> {
+    eeprom: &'a mut At24Cx<I2C, D>,
+    start: u32,
+    len: u32,
+}
+
+fn check_range(range: &Range<u32>, len: u32) -> Result<(), NorFlashErrorKind> {
+    if range.start > range.end || range.end > len {
+        return Err(NorFlashErrorKind::OutOfBounds);
+    }
+    if range.start as usize % PAGE_SIZE != 0 || range.end as usize % PAGE_SIZE != 0 {
+        return Err(NorFlashErrorKind::NotAligned);
+    }
+    Ok(())
+}
+
+/// Why [`validate_layout`] rejected a layout, naming the regions by their index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutConflict {
+    /// The region ends past the capacity
+    OutOfBounds { region: usize },
+    /// The two regions share at least one byte, `first` comes before `second` in the list
+    Overlap { first: usize, second: usize },
+}
+
+/// Checks that the `(offset, size)` regions of a storage layout fit within `capacity` and
+/// don't overlap. Empty regions never overlap anything. Being a `const fn` it can check a
+/// static layout at compile time:
+///
+/// ```
+/// # use at24cx::validate_layout;
+/// const REGIONS: [(u32, usize); 2] = [(0x000, 0x100), (0x100, 0x1000)];
+/// const _: () = assert!(validate_layout(&REGIONS, 0x8000).is_ok());
+/// ```
+pub const fn validate_layout(
+    regions: &[(u32, usize)],
+    capacity: usize,
+) -> Result<(), LayoutConflict> {
+    let mut i = 0;
+    while i < regions.len() {
+        let (start, size) = regions[i];
+        if start as u64 + size as u64 > capacity as u64 {
+            return Err(LayoutConflict::OutOfBounds { region: i });
+        }
+        let mut j = 0;
+        while j < i {
+            let (other, other_size) = regions[j];
+            let overlaps = size > 0
+                && other_size > 0
+                && (start as u64) < other as u64 + other_size as u64
+                && (other as u64) < start as u64 + size as u64;
+            if overlaps {
+                return Err(LayoutConflict::Overlap {
+                    first: j,
+                    second: i,
+                });
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+impl<I2C, D> At24Cx<I2C, D> {
+    /// Checks a storage layout against the capacity of the device, see [`validate_layout`]
+    pub fn validate_layout(&self, regions: &[(u32, usize)]) -> Result<(), LayoutConflict> {
+        validate_layout(regions, 1 << self.address_bits)
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Borrows the given range of the EEPROM as a partition.
+    /// Both ends of the range need to be aligned to the page size.
+    pub fn partition(
+        &mut self,
+        range: Range<u32>,
+    ) -> Result<EepromPartition<'_, I2C, D>, Error<E>> {
+        check_range(&range, self.capacity() as u32).map_err(Error::from_kind)?;
+        Ok(EepromPartition {
+            eeprom: self,
+            start: range.start,
+            len: range.end - range.start,
+        })
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> EepromPartition<'_, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Borrows a range of this partition as a nested partition.
+    /// The range is relative to the start of this partition.
+    pub fn partition(
+        &mut self,
+        range: Range<u32>,
+    ) -> Result<EepromPartition<'_, I2C, D>, Error<E>> {
+        check_range(&range, self.len).map_err(Error::from_kind)?;
+        Ok(EepromPartition {
+            eeprom: self.eeprom,
+            start: self.start + range.start,
+            len: range.end - range.start,
+        })
+    }
+
+    /// Absolute offset of the start of the partition on the device
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// Reads treating the partition as a ring, continuing at its start past the end
+    #[cfg(any(feature = "ringlog", feature = "queue"))]
+    pub(crate) async fn read_wrapping(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        let offset = offset % self.len;
+        let first = core::cmp::min(bytes.len(), (self.len - offset) as usize);
+        let (start, rest) = bytes.split_at_mut(first);
+        self.read(offset, start).await?;
+        if !rest.is_empty() {
+            self.read(0, rest).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes treating the partition as a ring, continuing at its start past the end
+    #[cfg(any(feature = "ringlog", feature = "queue"))]
+    pub(crate) async fn write_wrapping(
+        &mut self,
+        offset: u32,
+        bytes: &[u8],
+    ) -> Result<(), Error<E>> {
+        let offset = offset % self.len;
+        let first = core::cmp::min(bytes.len(), (self.len - offset) as usize);
+        let (start, rest) = bytes.split_at(first);
+        self.write(offset, start).await?;
+        if !rest.is_empty() {
+            self.write(0, rest).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<I2C: I2cErrorType, D> StorageErrorType for EepromPartition<'_, I2C, D> {
+    type Error = Error<I2C::Error>;
+}
+
+impl<I2C: I2c, D: DelayNs> ReadNorFlash for EepromPartition<'_, I2C, D> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        self.eeprom.read(self.start + offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.len as usize
+    }
+}
+
+impl<I2C: I2c, D: DelayNs> NorFlash for EepromPartition<'_, I2C, D> {
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_range(&(from..to), self.len).map_err(Error::from_kind)?;
+        self.eeprom.erase(self.start + from, self.start + to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        self.eeprom.write(self.start + offset, bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        At24Cx::new(
+            SimBus::new(Address(0, 0), 17),
+            Address(0, 0),
+            17,
+            NoopDelay::new(),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn rejects_invalid_ranges() {
+        let mut eeprom = driver();
+        assert!(matches!(
+            eeprom.partition(0x100..0x180),
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            eeprom.partition(0x80..0x100),
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            eeprom.partition(0x200..0x100),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.partition(0x1FF00..0x20100),
+            Err(Error::OutOfBounds)
+        ));
+        let mut partition = eeprom.partition(0x1000..0x2000).unwrap();
+        assert_eq!(partition.capacity(), 0x1000);
+        assert!(matches!(
+            partition.partition(0x800..0x1100),
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn validates_layouts() {
+        let eeprom = driver();
+        let cases: [(&[(u32, usize)], _); 7] = [
+            (&[(0, 0x100), (0x100, 0x1000), (0x1FF00, 0x100)], Ok(())),
+            (&[(0x40, 0), (0, 0x100)], Ok(())),
+            (&[], Ok(())),
+            (
+                &[(0, 0x100), (0x1FF00, 0x101)],
+                Err(LayoutConflict::OutOfBounds { region: 1 }),
+            ),
+            (
+                &[(u32::MAX, 2)],
+                Err(LayoutConflict::OutOfBounds { region: 0 }),
+            ),
+            (
+                &[(0, 0x100), (0x200, 0x100), (0x2FF, 2)],
+                Err(LayoutConflict::Overlap {
+                    first: 1,
+                    second: 2,
+                }),
+            ),
+            (
+                &[(0x100, 0x10), (0, 0x1000)],
+                Err(LayoutConflict::Overlap {
+                    first: 0,
+                    second: 1,
+                }),
+            ),
+        ];
+        for (regions, expected) in cases {
+            assert_eq!(eeprom.validate_layout(regions), expected, "{regions:x?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn translates_across_64k_boundary() {
+        let mut eeprom = driver();
+        let mut partition = eeprom.partition(0xF000..0x11000).unwrap();
+        let data: [u8; 0x200] = core::array::from_fn(|i| i as u8);
+        partition.write(0xF00, &data).await.unwrap();
+        let mut readback = [0; 0x200];
+        partition.read(0xF00, &mut readback).await.unwrap();
+        assert_eq!(readback, data);
+        assert_eq!(&eeprom.i2c.memory()[0xFF00..0x10100], &data);
+    }
+
+    async fn fill_with<F: NorFlash>(mut flash: F, value: u8) -> Result<(), F::Error> {
+        let capacity = flash.capacity();
+        flash.write(0, &std::vec![value; capacity]).await
+    }
+
+    #[tokio::test]
+    async fn lends_a_mutable_reference() {
+        let mut eeprom = driver();
+        let mut partition = eeprom.partition(0x100..0x200).unwrap();
+        fill_with(&mut partition, 7).await.unwrap();
+        partition.write(0, &[1]).await.unwrap();
+        let memory = eeprom.i2c.memory();
+        assert_eq!(memory[0xFF..0x102], [0xFF, 1, 7]);
+        assert_eq!(memory[0x1FF..0x201], [7, 0xFF]);
+    }
+
+    #[tokio::test]
+    async fn rejects_access_outside_partition() {
+        let mut eeprom = driver();
+        let mut partition = eeprom.partition(0..0x1000).unwrap();
+        let result = partition.write(0xFF8, &[0; 16]).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+        let result = partition.read(0x1000, &mut [0; 1]).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+        assert!(eeprom.i2c.memory()[0x1000..0x1008]
+            .iter()
+            .all(|b| *b == 0xFF));
+    }
+
+    #[tokio::test]
+    async fn nested_partition_at_top_of_array() {
+        let mut eeprom = driver();
+        let mut top = eeprom.partition(0x1E000..0x20000).unwrap();
+        let mut last = top.partition(0x1000..0x2000).unwrap();
+        assert_eq!(last.start(), 0x1F000);
+        last.write(0xFF0, &[0xA5; 16]).await.unwrap();
+        let mut readback = [0; 16];
+        last.read(0xFF0, &mut readback).await.unwrap();
+        assert_eq!(readback, [0xA5; 16]);
+        assert_eq!(&eeprom.i2c.memory()[0x1FFF0..], &[0xA5; 16]);
+    }
+}
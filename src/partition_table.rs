@@ -0,0 +1,310 @@
+//! Partition table stored on the device itself.
+//!
+//! The table lives in the two pages after the format signature, one full copy per page.
+//! Each copy is a [signed block](crate::signature) with magic `PTBL` whose payload is a
+//! little endian sequence number followed by the entries, 12 bytes each (id, offset, length,
+//! flags). Updates always overwrite the older copy with a single page write, so a torn
+//! update leaves the previous table intact.
+
+use crate::signature::{BlockStatus, SIGNATURE_OFFSET};
+use crate::{At24Cx, EepromPartition, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+use heapless::Vec;
+
+/// Maximum number of entries in the partition table
+pub const MAX_PARTITIONS: usize = 16;
+/// Offset of the partition table, right after the format signature. It occupies two pages.
+pub const PARTITION_TABLE_OFFSET: u32 = SIGNATURE_OFFSET + PAGE_SIZE as u32;
+/// First offset after the partition table
+pub const PARTITION_TABLE_END: u32 = PARTITION_TABLE_OFFSET + 2 * PAGE_SIZE as u32;
+
+const MAGIC: [u8; 4] = *b"PTBL";
+const VERSION: u16 = 1;
+const ENTRY_SIZE: usize = 12;
+const MAX_PAYLOAD: usize = 2 + MAX_PARTITIONS * ENTRY_SIZE;
+
+/// A region of the device as recorded in the partition table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionEntry {
+    pub id: u16,
+    /// Start of the partition, page aligned
+    pub offset: u32,
+    /// Length of the partition, page aligned
+    pub length: u32,
+    /// Free for application use
+    pub flags: u16,
+}
+
+impl PartitionEntry {
+    fn encode(&self, out: &mut [u8]) {
+        out[0..2].copy_from_slice(&self.id.to_le_bytes());
+        out[2..6].copy_from_slice(&self.offset.to_le_bytes());
+        out[6..10].copy_from_slice(&self.length.to_le_bytes());
+        out[10..12].copy_from_slice(&self.flags.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Self {
+            id: u16::from_le_bytes([bytes[0], bytes[1]]),
+            offset: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            length: u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]),
+            flags: u16::from_le_bytes([bytes[10], bytes[11]]),
+        }
+    }
+}
+
+/// One of the two copies of the table
+struct TableCopy {
+    slot: usize,
+    sequence: u16,
+    entries: Vec<PartitionEntry, MAX_PARTITIONS>,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Slot {
+    Blank,
+    Corrupt,
+    Valid(TableCopy),
+}
+
+fn decode_slot(slot: usize, status: BlockStatus, payload: &[u8]) -> Slot {
+    let len = match status {
+        BlockStatus::Blank => return Slot::Blank,
+        BlockStatus::Corrupt => return Slot::Corrupt,
+        BlockStatus::Valid { version, len } if version == VERSION => len,
+        BlockStatus::Valid { .. } => return Slot::Corrupt,
+    };
+    if len < 2 || (len - 2) % ENTRY_SIZE != 0 {
+        return Slot::Corrupt;
+    }
+    let entries = payload[2..len]
+        .chunks_exact(ENTRY_SIZE)
+        .map(PartitionEntry::decode)
+        .collect();
+    Slot::Valid(TableCopy {
+        slot,
+        sequence: u16::from_le_bytes([payload[0], payload[1]]),
+        entries,
+    })
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Stores a new partition table on the device, replacing the older of the two copies.
+    ///
+    /// Entries need to be page aligned, fit within the device, not overlap the table itself
+    /// and have unique ids.
+    pub async fn write_partition_table(
+        &mut self,
+        entries: &[PartitionEntry],
+    ) -> Result<(), Error<E>> {
+        if entries.len() > MAX_PARTITIONS {
+            return Err(Error::OutOfBounds);
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            self.check_partition_entry(entry)?;
+            if entry.offset < PARTITION_TABLE_END {
+                return Err(Error::OutOfBounds);
+            }
+            if entries[..i].iter().any(|e| e.id == entry.id) {
+                return Err(Error::InvalidArgument);
+            }
+        }
+
+        let (current, _) = self.read_partition_slots().await?;
+        let (slot, sequence) = match current {
+            Some(copy) => (1 - copy.slot, copy.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        let mut payload = [0; MAX_PAYLOAD];
+        payload[0..2].copy_from_slice(&sequence.to_le_bytes());
+        for (entry, out) in entries
+            .iter()
+            .zip(payload[2..].chunks_exact_mut(ENTRY_SIZE))
+        {
+            entry.encode(out);
+        }
+        let len = 2 + entries.len() * ENTRY_SIZE;
+        let address = PARTITION_TABLE_OFFSET + (slot * PAGE_SIZE) as u32;
+        self.write_signed_block(address, MAGIC, VERSION, &payload[..len])
+            .await
+    }
+
+    /// Reads the newest valid copy of the partition table.
+    ///
+    /// Returns `NotFound` if no table was ever written and `CrcMismatch` if there is a table
+    /// but no copy of it is intact.
+    pub async fn read_partition_table(
+        &mut self,
+    ) -> Result<Vec<PartitionEntry, MAX_PARTITIONS>, Error<E>> {
+        match self.read_partition_slots().await? {
+            (Some(copy), _) => Ok(copy.entries),
+            (None, true) => Err(Error::CrcMismatch),
+            (None, false) => Err(Error::NotFound),
+        }
+    }
+
+    /// Opens the partition with the given id from the on-device partition table.
+    pub async fn open_partition(
+        &mut self,
+        id: u16,
+    ) -> Result<EepromPartition<'_, I2C, D>, Error<E>> {
+        let entries = self.read_partition_table().await?;
+        let entry = entries.iter().find(|e| e.id == id).ok_or(Error::NotFound)?;
+        self.check_partition_entry(entry)?;
+        self.partition(entry.offset..entry.offset + entry.length)
+    }
+
+    fn check_partition_entry(&self, entry: &PartitionEntry) -> Result<(), Error<E>> {
+        let end = entry.offset as u64 + entry.length as u64;
+        if end > self.capacity() as u64 {
+            return Err(Error::OutOfBounds);
+        }
+        if entry.offset as usize % PAGE_SIZE != 0 || entry.length as usize % PAGE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+        Ok(())
+    }
+
+    /// Returns the newest valid copy of the table and whether any copy was corrupt.
+    async fn read_partition_slots(&mut self) -> Result<(Option<TableCopy>, bool), Error<E>> {
+        let mut newest: Option<TableCopy> = None;
+        let mut corrupt = false;
+        let mut payload = [0; MAX_PAYLOAD];
+        for slot in 0..2 {
+            let address = PARTITION_TABLE_OFFSET + (slot * PAGE_SIZE) as u32;
+            let status = match self.read_signed_block(address, MAGIC, &mut payload).await {
+                Err(Error::OutOfBounds) => BlockStatus::Corrupt,
+                status => status?,
+            };
+            match decode_slot(slot, status, &payload) {
+                Slot::Blank => {}
+                Slot::Corrupt => corrupt = true,
+                Slot::Valid(copy) => {
+                    let is_newer = newest.as_ref().map_or(true, |n| {
+                        (copy.sequence.wrapping_sub(n.sequence) as i16) > 0
+                    });
+                    if is_newer {
+                        newest = Some(copy);
+                    }
+                }
+            }
+        }
+        Ok((newest, corrupt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_storage_async::nor_flash::NorFlash;
+
+    const ENTRIES: [PartitionEntry; 2] = [
+        PartitionEntry {
+            id: 1,
+            offset: 0x1000,
+            length: 0x1000,
+            flags: 0,
+        },
+        PartitionEntry {
+            id: 7,
+            offset: 0x10000,
+            length: 0x10000,
+            flags: 0xA5A5,
+        },
+    ];
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let mut eeprom = driver();
+        assert!(matches!(
+            eeprom.read_partition_table().await,
+            Err(Error::NotFound)
+        ));
+        eeprom.write_partition_table(&ENTRIES).await.unwrap();
+        assert_eq!(eeprom.read_partition_table().await.unwrap(), ENTRIES);
+        eeprom.write_partition_table(&ENTRIES[1..]).await.unwrap();
+        assert_eq!(eeprom.read_partition_table().await.unwrap(), ENTRIES[1..]);
+    }
+
+    #[tokio::test]
+    async fn torn_update_keeps_previous_table() {
+        let mut eeprom = driver();
+        eeprom.write_partition_table(&ENTRIES).await.unwrap();
+        eeprom.write_partition_table(&ENTRIES[..1]).await.unwrap();
+        // The second update went to slot 1, tear it
+        let slot1 = PARTITION_TABLE_OFFSET as usize + PAGE_SIZE;
+        eeprom.i2c.memory_mut()[slot1 + 12] ^= 0xFF;
+        assert_eq!(eeprom.read_partition_table().await.unwrap(), ENTRIES);
+    }
+
+    #[tokio::test]
+    async fn rejects_corrupted_table() {
+        let mut eeprom = driver();
+        eeprom.write_partition_table(&ENTRIES).await.unwrap();
+        eeprom.i2c.memory_mut()[PARTITION_TABLE_OFFSET as usize + 11] ^= 0x01;
+        assert!(matches!(
+            eeprom.read_partition_table().await,
+            Err(Error::CrcMismatch)
+        ));
+        assert!(matches!(
+            eeprom.open_partition(1).await,
+            Err(Error::CrcMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_entries() {
+        let mut eeprom = driver();
+        let mut entry = ENTRIES[1];
+        entry.length = 0x10100;
+        assert!(matches!(
+            eeprom.write_partition_table(&[entry]).await,
+            Err(Error::OutOfBounds)
+        ));
+        entry.length = 0x80;
+        assert!(matches!(
+            eeprom.write_partition_table(&[entry]).await,
+            Err(Error::NotAligned)
+        ));
+        entry.offset = 0;
+        entry.length = 0x100;
+        assert!(matches!(
+            eeprom.write_partition_table(&[entry]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom
+                .write_partition_table(&[ENTRIES[0], ENTRIES[0]])
+                .await,
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[tokio::test]
+    async fn open_by_id() {
+        let mut eeprom = driver();
+        eeprom.write_partition_table(&ENTRIES).await.unwrap();
+        let mut partition = eeprom.open_partition(7).await.unwrap();
+        assert_eq!(partition.start(), 0x10000);
+        assert_eq!(partition.capacity(), 0x10000);
+        partition.write(0x20, &[1, 2, 3]).await.unwrap();
+        assert_eq!(&eeprom.i2c.memory()[0x10020..0x10023], &[1, 2, 3]);
+        assert!(matches!(
+            eeprom.open_partition(2).await,
+            Err(Error::NotFound)
+        ));
+    }
+}
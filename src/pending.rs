@@ -0,0 +1,105 @@
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::NorFlash;
+use heapless::{Deque, Vec};
+
+/// Why a write couldn't be queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnqueueError {
+    /// The queue already holds `N` requests
+    Full,
+    /// The data is longer than `MAX` bytes
+    TooLarge,
+}
+
+/// Bounded queue of up to `N` writes of up to `MAX` bytes each, drained one write at a time
+/// by [`process_one`](Self::process_one).
+///
+/// This lets a caller queue writes without waiting for the EEPROM while a low-priority task
+/// performs them later. Unlike the `writer-task` queue it needs no executor support, the
+/// caller shares it between tasks, e.g. behind a mutex.
+pub struct PendingWrites<const N: usize, const MAX: usize> {
+    requests: Deque<(u32, Vec<u8, MAX>), N>,
+}
+
+impl<const N: usize, const MAX: usize> PendingWrites<N, MAX> {
+    pub const fn new() -> Self {
+        Self {
+            requests: Deque::new(),
+        }
+    }
+
+    /// Queues a write of `data` at `offset`
+    pub fn enqueue(&mut self, offset: u32, data: &[u8]) -> Result<(), EnqueueError> {
+        if self.requests.is_full() {
+            return Err(EnqueueError::Full);
+        }
+        let data = Vec::from_slice(data).map_err(|_| EnqueueError::TooLarge)?;
+        // Can't fail, checked above
+        let _ = self.requests.push_back((offset, data));
+        Ok(())
+    }
+
+    /// Number of queued writes
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Performs the oldest queued write, returning `false` if there was none.
+    /// A write that fails is dropped from the queue and its error returned.
+    pub async fn process_one<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<bool, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let Some((offset, data)) = self.requests.pop_front() else {
+            return Ok(false);
+        };
+        eeprom.write(offset, &data).await?;
+        Ok(true)
+    }
+}
+
+impl<const N: usize, const MAX: usize> Default for PendingWrites<N, MAX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    #[tokio::test]
+    async fn drains_in_order() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let mut queue = PendingWrites::<2, 4>::new();
+        assert_eq!(queue.enqueue(0x10, &[0; 5]), Err(EnqueueError::TooLarge));
+        queue.enqueue(0x10, &[1, 2, 3, 4]).unwrap();
+        queue.enqueue(0x12, &[5]).unwrap();
+        assert_eq!(queue.enqueue(0x20, &[6]), Err(EnqueueError::Full));
+        assert_eq!(queue.len(), 2);
+
+        assert!(queue.process_one(&mut eeprom).await.unwrap());
+        assert_eq!(eeprom.i2c.memory()[0x10..0x14], [1, 2, 3, 4]);
+        queue.enqueue(0x1FFFF, &[7, 8]).unwrap();
+        assert!(queue.process_one(&mut eeprom).await.unwrap());
+        assert_eq!(eeprom.i2c.memory()[0x10..0x14], [1, 2, 5, 4]);
+        assert!(matches!(
+            queue.process_one(&mut eeprom).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(queue.is_empty());
+        assert!(!queue.process_one(&mut eeprom).await.unwrap());
+    }
+}
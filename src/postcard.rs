@@ -0,0 +1,262 @@
+//! Serde types encoded with [postcard](::postcard).
+//!
+//! A postcard blob is a little endian `u16` length followed by that many bytes of postcard
+//! encoding. [`store`](At24Cx::store) appends a little endian CRC-32 over the length and the
+//! encoding, so [`load`](At24Cx::load) can tell a blank region from a corrupt one. Values
+//! are encoded and decoded through a buffer of [`MAX_POSTCARD_LEN`] bytes on the stack, so
+//! nothing is allocated.
+
+use crate::crc::crc32_update;
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Longest postcard encoding, without the length prefix, that can be read or written
+pub const MAX_POSTCARD_LEN: usize = 512;
+
+const PREFIX_SIZE: usize = 2;
+const CRC_SIZE: usize = 4;
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Writes `value` as a postcard blob at `offset` and returns the number of bytes written,
+    /// including the length prefix.
+    ///
+    /// Returns `OutOfBounds` if the encoding is longer than [`MAX_POSTCARD_LEN`] or doesn't
+    /// fit on the device.
+    pub async fn write_postcard<T: Serialize>(
+        &mut self,
+        offset: u32,
+        value: &T,
+    ) -> Result<usize, Error<E>> {
+        let mut buf = [0; PREFIX_SIZE + MAX_POSTCARD_LEN];
+        let len = ::postcard::to_slice(value, &mut buf[PREFIX_SIZE..])
+            .map_err(|_| Error::OutOfBounds)?
+            .len();
+        buf[..PREFIX_SIZE].copy_from_slice(&(len as u16).to_le_bytes());
+        let blob = &buf[..PREFIX_SIZE + len];
+        self.write(offset, blob).await?;
+        Ok(blob.len())
+    }
+
+    /// Reads a postcard blob written by [`write_postcard`](Self::write_postcard), reading at
+    /// most `max_len` bytes including the length prefix.
+    ///
+    /// Returns `Deserialize` if the stored length exceeds `max_len` or the bytes don't decode
+    /// as a `T`, which includes a blank device.
+    pub async fn read_postcard<T: DeserializeOwned>(
+        &mut self,
+        offset: u32,
+        max_len: usize,
+    ) -> Result<T, Error<E>> {
+        let mut prefix = [0; PREFIX_SIZE];
+        self.read(offset, &mut prefix).await?;
+        let len = u16::from_le_bytes(prefix) as usize;
+        if PREFIX_SIZE + len > max_len || len > MAX_POSTCARD_LEN {
+            return Err(Error::Deserialize);
+        }
+        let mut buf = [0; MAX_POSTCARD_LEN];
+        let buf = &mut buf[..len];
+        self.read(offset + PREFIX_SIZE as u32, buf).await?;
+        ::postcard::from_bytes(buf).map_err(|_| Error::Deserialize)
+    }
+
+    /// Writes `value` as a postcard blob followed by a CRC-32, taking at most `max_len` bytes
+    /// at `offset`, and returns the number of bytes written.
+    ///
+    /// Returns `OutOfBounds`, without writing anything, if the framed encoding is longer than
+    /// `max_len`, [`MAX_POSTCARD_LEN`] or the rest of the device.
+    pub async fn store<T: Serialize>(
+        &mut self,
+        offset: u32,
+        max_len: u32,
+        value: &T,
+    ) -> Result<usize, Error<E>> {
+        let mut buf = [0; PREFIX_SIZE + MAX_POSTCARD_LEN + CRC_SIZE];
+        let len =
+            ::postcard::to_slice(value, &mut buf[PREFIX_SIZE..PREFIX_SIZE + MAX_POSTCARD_LEN])
+                .map_err(|_| Error::OutOfBounds)?
+                .len();
+        let total = PREFIX_SIZE + len + CRC_SIZE;
+        if total > max_len as usize {
+            return Err(Error::OutOfBounds);
+        }
+        buf[..PREFIX_SIZE].copy_from_slice(&(len as u16).to_le_bytes());
+        let crc = !crc32_update(!0, &buf[..PREFIX_SIZE + len]);
+        buf[PREFIX_SIZE + len..total].copy_from_slice(&crc.to_le_bytes());
+        self.write(offset, &buf[..total]).await?;
+        Ok(total)
+    }
+
+    /// Reads a value written by [`store`](Self::store) with the same `max_len`.
+    ///
+    /// Returns `None` if the length prefix is blank, `CrcMismatch` if the blob is corrupt and
+    /// `Deserialize` if it is intact but doesn't decode as a `T`.
+    pub async fn load<T: DeserializeOwned>(
+        &mut self,
+        offset: u32,
+        max_len: u32,
+    ) -> Result<Option<T>, Error<E>> {
+        let mut prefix = [0; PREFIX_SIZE];
+        self.read(offset, &mut prefix).await?;
+        if prefix == [0xFF; PREFIX_SIZE] {
+            return Ok(None);
+        }
+        let len = u16::from_le_bytes(prefix) as usize;
+        if PREFIX_SIZE + len + CRC_SIZE > max_len as usize || len > MAX_POSTCARD_LEN {
+            return Err(Error::CrcMismatch);
+        }
+        let mut buf = [0; MAX_POSTCARD_LEN + CRC_SIZE];
+        let buf = &mut buf[..len + CRC_SIZE];
+        self.read(offset + PREFIX_SIZE as u32, buf).await?;
+        let (encoding, stored) = buf.split_at(len);
+        let crc = !crc32_update(crc32_update(!0, &prefix), encoding);
+        if crc.to_le_bytes() != stored {
+            return Err(Error::CrcMismatch);
+        }
+        ::postcard::from_bytes(encoding)
+            .map(Some)
+            .map_err(|_| Error::Deserialize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        id: u32,
+        channels: [u8; 4],
+        gain: Option<f32>,
+    }
+
+    #[tokio::test]
+    async fn round_trips() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let config = Config {
+            id: 300,
+            channels: [1, 2, 3, 4],
+            gain: Some(0.5),
+        };
+        let len = eeprom.write_postcard(0xF8, &config).await.unwrap();
+        // Prefix, varint id, channels, option tag and float
+        assert_eq!(len, 2 + 2 + 4 + 1 + 4);
+        assert_eq!(eeprom.i2c.memory()[0xF8..0xFA], [11, 0]);
+        let read: Config = eeprom.read_postcard(0xF8, len).await.unwrap();
+        assert_eq!(read, config);
+
+        assert!(matches!(
+            eeprom.read_postcard::<Config>(0xF8, len - 1).await,
+            Err(Error::Deserialize)
+        ));
+        assert!(matches!(
+            eeprom.read_postcard::<Config>(0x400, 64).await,
+            Err(Error::Deserialize)
+        ));
+        assert!(matches!(
+            eeprom
+                .write_postcard(0, &[[0u8; 32]; MAX_POSTCARD_LEN / 32 + 1])
+                .await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Patch {
+        name: [u8; 8],
+        voices: [Voice; 2],
+        sequence: Option<Sequence>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Voice {
+        detune: i16,
+        config: Config,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sequence {
+        steps: [[u8; 32]; 10],
+    }
+
+    fn patch() -> Patch {
+        let voice = |detune| Voice {
+            detune,
+            config: Config {
+                id: 7,
+                channels: [4, 3, 2, 1],
+                gain: None,
+            },
+        };
+        let mut steps = [[0; 32]; 10];
+        for (i, step) in steps.iter_mut().flatten().enumerate() {
+            *step = i as u8;
+        }
+        Patch {
+            name: *b"bassline",
+            voices: [voice(-20), voice(20)],
+            sequence: Some(Sequence { steps }),
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_nested_structs_across_pages() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.load::<Patch>(0x1C0, 0x200).await.unwrap(), None);
+        let len = eeprom.store(0x1C0, 0x200, &patch()).await.unwrap();
+        // Spans three pages
+        assert!(0x1C0 + len > 0x300);
+        assert_eq!(
+            eeprom.load::<Patch>(0x1C0, 0x200).await.unwrap(),
+            Some(patch())
+        );
+
+        // Too large for the reserved space, nothing is written
+        assert!(matches!(
+            eeprom.store(0x400, len as u32 - 1, &patch()).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(eeprom.i2c.write_count(0x400), 0);
+    }
+
+    #[tokio::test]
+    async fn tells_blank_from_corrupt() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let config = Config {
+            id: 1,
+            channels: [0; 4],
+            gain: Some(2.0),
+        };
+        eeprom.store(0x10, 32, &config).await.unwrap();
+        assert_eq!(eeprom.load(0x10, 32).await.unwrap(), Some(config));
+
+        eeprom.i2c.memory_mut()[0x14] ^= 0x01;
+        assert!(matches!(
+            eeprom.load::<Config>(0x10, 32).await,
+            Err(Error::CrcMismatch)
+        ));
+        // A length that can't be right for the region
+        eeprom.i2c.memory_mut()[0x10] = 40;
+        assert!(matches!(
+            eeprom.load::<Config>(0x10, 32).await,
+            Err(Error::CrcMismatch)
+        ));
+        // Intact, but not a Patch
+        eeprom.store(0x10, 32, &[0xFFu8; 2]).await.unwrap();
+        assert!(matches!(
+            eeprom.load::<Patch>(0x10, 32).await,
+            Err(Error::Deserialize)
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+use crate::crc::crc32_update;
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+/// How [`program_image`](At24Cx::program_image) goes about writing an image
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramOptions {
+    /// Reads every page first and leaves it alone if it already holds the image
+    pub skip_equal: bool,
+    /// Reads every written page back
+    pub verify: bool,
+    /// Times a page that didn't read back is rewritten before giving up
+    pub max_retries: usize,
+    /// Compares the CRC-32 of the programmed range against the image's at the end
+    pub final_crc: bool,
+    /// Returns a tick count, used to time the whole run
+    pub clock: Option<fn() -> u64>,
+}
+
+impl Default for ProgramOptions {
+    /// Skip equal pages, verify with 2 retries and check the CRC, without a clock
+    fn default() -> Self {
+        Self {
+            skip_equal: true,
+            verify: true,
+            max_retries: 2,
+            final_crc: true,
+            clock: None,
+        }
+    }
+}
+
+/// Bytes of the image handled so far, reported after every page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramProgress {
+    pub done: u32,
+    pub total: u32,
+}
+
+/// What a successful [`program_image`](At24Cx::program_image) did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramReport {
+    pub pages_written: u32,
+    /// Pages that already held the image
+    pub pages_skipped: u32,
+    /// Pages that had to be rewritten at least once
+    pub pages_retried: u32,
+    /// CRC-32 of the image
+    pub crc: u32,
+    /// Ticks of the [clock](ProgramOptions::clock) the run took
+    pub ticks: Option<u64>,
+}
+
+/// Why a [`program_image`](At24Cx::program_image) failed
+#[derive(Debug)]
+pub enum ProgramError<E: Debug> {
+    /// Accessing the page at `offset` failed
+    Eeprom { offset: u32, error: Error<E> },
+    /// The byte at `offset` still didn't read back after all retries
+    Verify { offset: u32 },
+    /// The programmed range doesn't have the image's CRC-32
+    Crc { expected: u32, actual: u32 },
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Programs `image` at `offset` page by page, as configured by `opts`.
+    pub async fn program_image(
+        &mut self,
+        offset: u32,
+        image: &[u8],
+        opts: ProgramOptions,
+        mut progress: impl FnMut(ProgramProgress),
+    ) -> Result<ProgramReport, ProgramError<E>> {
+        let start = opts.clock.map(|clock| clock());
+        self.can_write(offset, image.len())
+            .map_err(|error| ProgramError::Eeprom { offset, error })?;
+        let mut report = ProgramReport {
+            pages_written: 0,
+            pages_skipped: 0,
+            pages_retried: 0,
+            crc: !crc32_update(!0, image),
+            ticks: None,
+        };
+
+        let mut buf = [0; PAGE_SIZE];
+        let mut address = offset;
+        let mut remaining = image;
+        while !remaining.is_empty() {
+            let len = remaining
+                .len()
+                .min(self.page_size() - address as usize % self.page_size());
+            let (data, rest) = remaining.split_at(len);
+            let page = &mut buf[..len];
+            let eeprom = |error| ProgramError::Eeprom {
+                offset: address,
+                error,
+            };
+
+            let mut skip = false;
+            if opts.skip_equal {
+                self.read(address, page).await.map_err(eeprom)?;
+                skip = page == data;
+            }
+            if skip {
+                report.pages_skipped += 1;
+            } else {
+                let mut attempts = 0;
+                loop {
+                    self.page_write(address, data).await.map_err(eeprom)?;
+                    if !opts.verify {
+                        break;
+                    }
+                    self.read(address, page).await.map_err(eeprom)?;
+                    let Some(i) = page.iter().zip(data).position(|(a, b)| a != b) else {
+                        break;
+                    };
+                    if attempts == opts.max_retries {
+                        return Err(ProgramError::Verify {
+                            offset: address + i as u32,
+                        });
+                    }
+                    attempts += 1;
+                }
+                report.pages_written += 1;
+                if attempts > 0 {
+                    report.pages_retried += 1;
+                }
+            }
+            address += len as u32;
+            remaining = rest;
+            progress(ProgramProgress {
+                done: address - offset,
+                total: image.len() as u32,
+            });
+        }
+        self.flush()
+            .await
+            .map_err(|error| ProgramError::Eeprom { offset, error })?;
+
+        if opts.final_crc {
+            let mut crc = !0;
+            let mut address = offset;
+            let end = offset + image.len() as u32;
+            while address < end {
+                let len = (end - address).min(PAGE_SIZE as u32) as usize;
+                let chunk = &mut buf[..len];
+                self.read(address, chunk)
+                    .await
+                    .map_err(|error| ProgramError::Eeprom {
+                        offset: address,
+                        error,
+                    })?;
+                crc = crc32_update(crc, chunk);
+                address += len as u32;
+            }
+            if !crc != report.crc {
+                return Err(ProgramError::Crc {
+                    expected: report.crc,
+                    actual: !crc,
+                });
+            }
+        }
+        report.ticks = opts.clock.zip(start).map(|(clock, start)| clock() - start);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_async::i2c::{ErrorType, Operation};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const OFFSET: u32 = 0x380;
+
+    fn image() -> std::vec::Vec<u8> {
+        (0..0x500).map(|i| (i * 3 % 256) as u8).collect()
+    }
+
+    /// Acknowledges the next page write to `target` without programming it
+    struct FlakyBus {
+        bus: SimBus,
+        target: Option<[u8; 2]>,
+    }
+
+    impl ErrorType for FlakyBus {
+        type Error = <SimBus as ErrorType>::Error;
+    }
+
+    impl I2c for FlakyBus {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if let [Operation::Write(bytes)] = operations {
+                if bytes.len() > 2 && self.target == Some([bytes[0], bytes[1]]) {
+                    self.target = None;
+                    return Ok(());
+                }
+            }
+            self.bus.transaction(address, operations).await
+        }
+    }
+
+    fn driver(target: Option<u32>) -> At24Cx<FlakyBus, NoopDelay> {
+        let bus = FlakyBus {
+            bus: SimBus::new(Address(0, 0), 17),
+            target: target.map(|t| [(t >> 8) as u8, t as u8]),
+        };
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn flashes_a_blank_device() {
+        let mut eeprom = driver(None);
+        let image = image();
+        let mut last = None;
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+        let opts = ProgramOptions {
+            // Every call takes 5 ticks
+            clock: Some(|| TICKS.fetch_add(5, Ordering::Relaxed)),
+            ..Default::default()
+        };
+        let report = eeprom
+            .program_image(OFFSET, &image, opts, |p| last = Some(p))
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ProgramReport {
+                // 0x380..0x880 touches 6 pages
+                pages_written: 6,
+                pages_skipped: 0,
+                pages_retried: 0,
+                crc: !crc32_update(!0, &image),
+                ticks: Some(5),
+            }
+        );
+        assert_eq!(
+            last,
+            Some(ProgramProgress {
+                done: 0x500,
+                total: 0x500
+            })
+        );
+        assert_eq!(eeprom.i2c.bus.memory()[0x380..0x880], image[..]);
+    }
+
+    #[tokio::test]
+    async fn skips_equal_pages_on_reflash() {
+        let mut eeprom = driver(None);
+        let image = image();
+        eeprom
+            .program_image(OFFSET, &image, ProgramOptions::default(), |_| {})
+            .await
+            .unwrap();
+        eeprom.i2c.bus.memory_mut()[0x5AB] ^= 0x80;
+        let report = eeprom
+            .program_image(OFFSET, &image, ProgramOptions::default(), |_| {})
+            .await
+            .unwrap();
+        assert_eq!((report.pages_written, report.pages_skipped), (1, 5));
+        assert_eq!(eeprom.i2c.bus.write_count(0x5AB), 2);
+        assert_eq!(eeprom.i2c.bus.write_count(0x6AB), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_a_page_that_did_not_stick() {
+        let mut eeprom = driver(Some(0x500));
+        let image = image();
+        let report = eeprom
+            .program_image(OFFSET, &image, ProgramOptions::default(), |_| {})
+            .await
+            .unwrap();
+        assert_eq!((report.pages_written, report.pages_retried), (6, 1));
+        assert_eq!(eeprom.i2c.bus.memory()[0x380..0x880], image[..]);
+
+        let mut eeprom = driver(Some(0x500));
+        let opts = ProgramOptions {
+            max_retries: 0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            eeprom.program_image(OFFSET, &image, opts, |_| {}).await,
+            Err(ProgramError::Verify { offset: 0x500 })
+        ));
+        // Nothing was written after the failing page
+        assert_eq!(eeprom.i2c.bus.write_count(0x600), 0);
+    }
+
+    #[tokio::test]
+    async fn retries_a_page_with_a_bit_error() {
+        let bus = FlakyBus {
+            bus: SimBus::builder(Address(0, 0), 17)
+                .bit_error(0x7FF, 0x01)
+                .build(),
+            target: None,
+        };
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let image = image();
+        let report = eeprom
+            .program_image(OFFSET, &image, ProgramOptions::default(), |_| {})
+            .await
+            .unwrap();
+        assert_eq!((report.pages_written, report.pages_retried), (6, 1));
+        assert_eq!(eeprom.i2c.bus.write_count(0x7FF), 2);
+        assert_eq!(eeprom.i2c.bus.fired().len(), 1);
+    }
+}
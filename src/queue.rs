@@ -0,0 +1,425 @@
+//! Persistent FIFO queue in a region of the device.
+//!
+//! Items are stored back to back in the region, wrapping around its end, each starting on
+//! a 16 byte boundary:
+//!
+//! | bytes    | content                                        |
+//! |----------|------------------------------------------------|
+//! | 0        | state, 0xFF while pending and 0x00 once popped |
+//! | 1..3     | item length                                    |
+//! | 3..7     | sequence number                                |
+//! | 7..7+len | item                                           |
+//! | ..+4     | CRC-32 over everything before it but the state |
+//!
+//! All integers are little endian. Popping an item overwrites just its state byte, so it
+//! either happens completely or not at all. Space taken by popped items is reused by later
+//! pushes. Mounting finds the item with the highest sequence number, walks the unbroken
+//! chain of items leading up to it and continues after the last popped one. An item torn
+//! by a power loss during [`push`](PersistentQueue::push) is ignored.
+//!
+//! Delivery is at least once: if power is lost after the caller acted on a popped item
+//! but before its state byte was written, the same item is popped again after reboot.
+
+use crate::crc::crc32_update;
+use crate::{EepromPartition, Error};
+use core::cmp::min;
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Items start at multiples of this
+pub const ITEM_ALIGN: usize = 16;
+/// Bytes of framing added to every item
+pub const ITEM_OVERHEAD: usize = HEADER_SIZE + 4;
+
+const HEADER_SIZE: usize = 7;
+const STATE_PENDING: u8 = 0xFF;
+const STATE_POPPED: u8 = 0x00;
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct ItemHeader {
+    state: u8,
+    len: u16,
+    seq: u32,
+}
+
+impl ItemHeader {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut out = [0; HEADER_SIZE];
+        out[0] = self.state;
+        out[1..3].copy_from_slice(&self.len.to_le_bytes());
+        out[3..7].copy_from_slice(&self.seq.to_le_bytes());
+        out
+    }
+
+    fn decode(bytes: &[u8; HEADER_SIZE]) -> Self {
+        Self {
+            state: bytes[0],
+            len: u16::from_le_bytes([bytes[1], bytes[2]]),
+            seq: u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]),
+        }
+    }
+
+    /// Space taken on the device, including padding
+    fn size(&self) -> u32 {
+        item_size(self.len as usize)
+    }
+}
+
+fn item_size(len: usize) -> u32 {
+    (ITEM_OVERHEAD + len).div_ceil(ITEM_ALIGN) as u32 * ITEM_ALIGN as u32
+}
+
+/// FIFO queue over a partition, see the [module docs](self)
+pub struct PersistentQueue<'a, I2C, D> {
+    region: EepromPartition<'a, I2C, D>,
+    capacity: u32,
+    /// Where the next item goes
+    head: u32,
+    /// Start of the oldest pending item
+    tail: u32,
+    /// Bytes taken by the pending items
+    used: u32,
+    count: usize,
+    next_seq: u32,
+}
+
+impl<'a, I2C, E: Debug, D: DelayNs> PersistentQueue<'a, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Opens the queue in `region`, recovering its pending items.
+    /// Returns `InvalidArgument` for an empty region.
+    pub async fn mount(region: EepromPartition<'a, I2C, D>) -> Result<Self, Error<E>> {
+        let capacity = region.capacity() as u32;
+        if capacity == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut queue = Self {
+            region,
+            capacity,
+            head: 0,
+            tail: 0,
+            used: 0,
+            count: 0,
+            next_seq: 0,
+        };
+
+        let mut newest: Option<(u32, ItemHeader)> = None;
+        for position in (0..capacity).step_by(ITEM_ALIGN) {
+            if let Some(header) = queue.read_valid(position).await? {
+                if newest.map_or(true, |(_, n)| (header.seq.wrapping_sub(n.seq) as i32) > 0) {
+                    newest = Some((position, header));
+                }
+            }
+        }
+        let Some((newest_position, newest)) = newest else {
+            return Ok(queue);
+        };
+        queue.head = (newest_position + newest.size()) % capacity;
+        queue.tail = queue.head;
+        queue.next_seq = newest.seq.wrapping_add(1);
+
+        // The chain of items leading up to the newest starts at the first item after the
+        // head that reaches it. Pending items follow the popped ones in that chain.
+        for slot in (0..capacity).step_by(ITEM_ALIGN) {
+            let start = (queue.head + slot) % capacity;
+            if queue.is_chain(start, newest_position).await? {
+                queue.recover_pending(start, newest_position).await?;
+                break;
+            }
+        }
+        Ok(queue)
+    }
+
+    /// Number of pending items
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Bytes left for new items, including their framing and padding
+    pub fn free_bytes(&self) -> usize {
+        (self.capacity - self.used) as usize
+    }
+
+    /// Adds an item at the end of the queue.
+    ///
+    /// Returns `Full` if there isn't enough room until more items are popped, or ever for
+    /// an item larger than the region.
+    pub async fn push(&mut self, item: &[u8]) -> Result<(), Error<E>> {
+        let size = item_size(item.len());
+        if item.len() > u16::MAX as usize || size > self.capacity - self.used {
+            return Err(Error::Full);
+        }
+        let header = ItemHeader {
+            state: STATE_PENDING,
+            len: item.len() as u16,
+            seq: self.next_seq,
+        }
+        .encode();
+        let crc = !crc32_update(crc32_update(!0, &header[1..]), item);
+        let head = self.head;
+        self.region.write_wrapping(head, &header).await?;
+        self.region
+            .write_wrapping(head + HEADER_SIZE as u32, item)
+            .await?;
+        self.region
+            .write_wrapping(head + (HEADER_SIZE + item.len()) as u32, &crc.to_le_bytes())
+            .await?;
+
+        if self.count == 0 {
+            self.tail = head;
+        }
+        self.head = (head + size) % self.capacity;
+        self.used += size;
+        self.count += 1;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Reads the oldest pending item into `buf` without removing it.
+    /// Returns `OutOfBounds` if `buf` is too small.
+    pub async fn peek<'b>(&mut self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error<E>> {
+        if self.count == 0 {
+            return Ok(None);
+        }
+        let header = self.read_header(self.tail).await?;
+        let len = header.len as usize;
+        if len > buf.len() {
+            return Err(Error::OutOfBounds);
+        }
+        self.region
+            .read_wrapping(self.tail + HEADER_SIZE as u32, &mut buf[..len])
+            .await?;
+        Ok(Some(&buf[..len]))
+    }
+
+    /// Removes the oldest pending item, reading it into `buf`.
+    /// Returns `OutOfBounds` without removing anything if `buf` is too small.
+    pub async fn pop<'b>(&mut self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error<E>> {
+        let len = match self.peek(buf).await? {
+            Some(item) => item.len(),
+            None => return Ok(None),
+        };
+        self.region.write(self.tail, &[STATE_POPPED]).await?;
+        let size = item_size(len);
+        self.tail = (self.tail + size) % self.capacity;
+        self.used -= size;
+        self.count -= 1;
+        Ok(Some(&buf[..len]))
+    }
+
+    /// Whether the items from `start` form an unbroken chain up to the newest one
+    async fn is_chain(&mut self, start: u32, newest: u32) -> Result<bool, Error<E>> {
+        let Some(mut header) = self.read_valid(start).await? else {
+            return Ok(false);
+        };
+        let mut position = start;
+        let mut walked = header.size();
+        while position != newest {
+            position = (position + header.size()) % self.capacity;
+            let Some(next) = self.read_valid(position).await? else {
+                return Ok(false);
+            };
+            walked += next.size();
+            if next.seq != header.seq.wrapping_add(1) || walked > self.capacity {
+                return Ok(false);
+            }
+            header = next;
+        }
+        Ok(true)
+    }
+
+    /// Sets tail, count and used from a chain found by [`is_chain`](Self::is_chain)
+    async fn recover_pending(&mut self, start: u32, newest: u32) -> Result<(), Error<E>> {
+        let mut position = start;
+        loop {
+            let header = self.read_header(position).await?;
+            if header.state == STATE_PENDING {
+                if self.count == 0 {
+                    self.tail = position;
+                }
+                self.count += 1;
+                self.used += header.size();
+            }
+            if position == newest {
+                return Ok(());
+            }
+            position = (position + header.size()) % self.capacity;
+        }
+    }
+
+    /// Header of an item known to be valid
+    async fn read_header(&mut self, position: u32) -> Result<ItemHeader, Error<E>> {
+        let mut bytes = [0; HEADER_SIZE];
+        self.region.read_wrapping(position, &mut bytes).await?;
+        Ok(ItemHeader::decode(&bytes))
+    }
+
+    /// Header of the item at `position` if there is a complete, intact one
+    async fn read_valid(&mut self, position: u32) -> Result<Option<ItemHeader>, Error<E>> {
+        let mut bytes = [0; HEADER_SIZE];
+        self.region.read_wrapping(position, &mut bytes).await?;
+        let header = ItemHeader::decode(&bytes);
+        if (header.state != STATE_PENDING && header.state != STATE_POPPED)
+            || header.size() > self.capacity
+        {
+            return Ok(None);
+        }
+
+        let mut crc = crc32_update(!0, &bytes[1..]);
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut done = 0;
+        while done < header.len as usize {
+            let len = min(CHUNK_SIZE, header.len as usize - done);
+            let chunk = &mut chunk[..len];
+            self.region
+                .read_wrapping(position + (HEADER_SIZE + done) as u32, chunk)
+                .await?;
+            crc = crc32_update(crc, chunk);
+            done += len;
+        }
+        let mut stored = [0; 4];
+        let crc_offset = position + (HEADER_SIZE + header.len as usize) as u32;
+        self.region.read_wrapping(crc_offset, &mut stored).await?;
+        Ok((!crc == u32::from_le_bytes(stored)).then_some(header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec::Vec;
+
+    const REGION: core::ops::Range<u32> = 0x3000..0x3100;
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn mount(
+        eeprom: &mut At24Cx<SimBus, NoopDelay>,
+    ) -> PersistentQueue<'_, SimBus, NoopDelay> {
+        PersistentQueue::mount(eeprom.partition(REGION).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn item(n: u32) -> Vec<u8> {
+        std::vec![n as u8; 3 + (n as usize * 11) % 40]
+    }
+
+    async fn pop(queue: &mut PersistentQueue<'_, SimBus, NoopDelay>) -> Option<Vec<u8>> {
+        let mut buf = [0; 256];
+        queue.pop(&mut buf).await.unwrap().map(|item| item.to_vec())
+    }
+
+    #[tokio::test]
+    async fn pops_in_push_order_across_reboots() {
+        let mut eeprom = driver(None);
+        let mut queue = mount(&mut eeprom).await;
+        for n in 0..3 {
+            queue.push(&item(n)).await.unwrap();
+        }
+        assert_eq!(pop(&mut queue).await, Some(item(0)));
+
+        let mut queue = mount(&mut eeprom).await;
+        assert_eq!(queue.len(), 2);
+        let mut buf = [0; 64];
+        assert_eq!(queue.peek(&mut buf).await.unwrap(), Some(&item(1)[..]));
+        assert_eq!(pop(&mut queue).await, Some(item(1)));
+        assert_eq!(pop(&mut queue).await, Some(item(2)));
+        assert_eq!(pop(&mut queue).await, None);
+
+        let mut queue = mount(&mut eeprom).await;
+        assert!(queue.is_empty());
+        assert_eq!(queue.free_bytes(), 256);
+        queue.push(&item(3)).await.unwrap();
+        let mut queue = mount(&mut eeprom).await;
+        assert_eq!(pop(&mut queue).await, Some(item(3)));
+    }
+
+    #[tokio::test]
+    async fn wraps_around_reusing_popped_space() {
+        let mut eeprom = driver(None);
+        let mut next_push = 0;
+        let mut next_pop = 0;
+        for round in 0..40 {
+            let mut queue = mount(&mut eeprom).await;
+            assert_eq!(queue.len(), (next_push - next_pop) as usize);
+            while queue.push(&item(next_push)).await.is_ok() {
+                next_push += 1;
+            }
+            let mut queue = mount(&mut eeprom).await;
+            for _ in 0..1 + round % 3 {
+                assert_eq!(pop(&mut queue).await, Some(item(next_pop)));
+                next_pop += 1;
+            }
+        }
+        assert!(next_push > 40);
+    }
+
+    #[tokio::test]
+    async fn full_and_empty() {
+        let mut eeprom = driver(None);
+        let mut queue = mount(&mut eeprom).await;
+        assert_eq!(pop(&mut queue).await, None);
+        assert!(matches!(queue.push(&[0; 246]).await, Err(Error::Full)));
+        queue.push(&[1; 100]).await.unwrap();
+        queue.push(&[2; 100]).await.unwrap();
+        assert_eq!(queue.free_bytes(), 256 - 2 * 112);
+        assert!(matches!(queue.push(&[3; 30]).await, Err(Error::Full)));
+        let mut small = [0; 10];
+        assert!(matches!(
+            queue.pop(&mut small).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(pop(&mut queue).await, Some(std::vec![1; 100]));
+        queue.push(&[3; 30]).await.unwrap();
+        // The whole region can be used by one item
+        assert_eq!(pop(&mut queue).await, Some(std::vec![2; 100]));
+        assert_eq!(pop(&mut queue).await, Some(std::vec![3; 30]));
+        queue.push(&[4; 245]).await.unwrap();
+        assert_eq!(queue.free_bytes(), 0);
+
+        let mut queue = mount(&mut eeprom).await;
+        assert_eq!(queue.len(), 1);
+        assert_eq!(pop(&mut queue).await, Some(std::vec![4; 245]));
+    }
+
+    #[tokio::test]
+    async fn power_loss_during_push() {
+        let mut eeprom = driver(None);
+        let mut queue = mount(&mut eeprom).await;
+        for n in 0..4 {
+            queue.push(&item(n)).await.unwrap();
+        }
+        assert_eq!(pop(&mut queue).await, Some(item(0)));
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        for cut in 0..ITEM_OVERHEAD + 20 {
+            let mut eeprom = driver(Some(&snapshot));
+            eeprom.i2c.cut_power_after(cut);
+            assert!(mount(&mut eeprom).await.push(&[9; 20]).await.is_err());
+            eeprom.i2c.restore_power();
+
+            let mut queue = mount(&mut eeprom).await;
+            assert_eq!(queue.len(), 3, "cut after {cut} bytes");
+            for n in 1..4 {
+                assert_eq!(pop(&mut queue).await, Some(item(n)));
+            }
+            assert_eq!(pop(&mut queue).await, None);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{check_erase, check_read, check_write, At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{
+    delay::DelayNs,
+    i2c::{ErrorType as I2cErrorType, I2c},
+};
+use embedded_storage_async::nor_flash::{ErrorType as StorageErrorType, NorFlash, ReadNorFlash};
+
+/// A sub-range of the EEPROM with a driver of its own, made by [`At24Cx::split_at`].
+/// Offsets are relative to the start of the region and any access outside of it is
+/// rejected with `OutOfBounds`. Unlike an [`EepromPartition`](crate::EepromPartition) it
+/// owns its bus handle, so it can be moved into a task of its own.
+pub struct At24CxRegion<I2C, D> {
+    eeprom: At24Cx<I2C, D>,
+    start: u32,
+    len: u32,
+}
+
+/// The lower and upper region of a [split](At24Cx::split_at)
+pub type SplitRegions<I2C, D> = (At24CxRegion<I2C, D>, At24CxRegion<I2C, D>);
+
+impl<I2C, D> At24Cx<I2C, D> {
+    /// A driver with the same configuration and state on another bus handle and delay
+    fn with_bus<I2C2, D2>(&self, i2c: I2C2, delay: D2) -> At24Cx<I2C2, D2> {
+        At24Cx {
+            address_bits: self.address_bits,
+            page_size: self.page_size,
+            max_write_len: self.max_write_len,
+            base_address: self.base_address,
+            delay,
+            i2c,
+            page_checksum: self.page_checksum,
+            default_byte: self.default_byte,
+            max_unverified_writes: self.max_unverified_writes,
+            // A write cycle started by this driver is waited for by both
+            unverified_writes: self.unverified_writes,
+            read_method: self.read_method,
+            ack_probe: self.ack_probe,
+            page_aligned_writes: self.page_aligned_writes,
+            read_timeout_us: self.read_timeout_us,
+            min_read_len: self.min_read_len,
+            seal_footer: self.seal_footer,
+            batch_merge_gap: self.batch_merge_gap,
+            poll_delay_ns: self.poll_delay_ns,
+            delay_granularity: self.delay_granularity,
+            poll_max_retries: self.poll_max_retries,
+            write_retries: self.write_retries,
+            inter_op_delay_us: self.inter_op_delay_us,
+            high_water_mark: self.high_water_mark,
+            read_only: self.read_only,
+            upper_half_address: self.upper_half_address,
+            #[cfg(feature = "id-page")]
+            id_page: self.id_page,
+            bad_pages: self.bad_pages.clone(),
+        }
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Splits the EEPROM at `offset` into two regions that are used independently, for
+    /// example from two Embassy tasks. The upper region drives the device through `i2c` and
+    /// `delay`, a second handle to the same bus like an `embassy-embedded-hal` shared
+    /// `I2cDevice`, and takes the driver's configuration with it. `offset` has to be aligned
+    /// to [`PAGE_SIZE`] and within the capacity, so neither region's page writes reach the
+    /// other.
+    pub fn split_at(
+        self,
+        offset: u32,
+        i2c: I2C,
+        delay: D,
+    ) -> Result<SplitRegions<I2C, D>, Error<E>> {
+        let capacity = self.capacity() as u32;
+        if offset > capacity {
+            return Err(Error::OutOfBounds);
+        }
+        if offset as usize % PAGE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+        let upper = At24CxRegion {
+            eeprom: self.with_bus(i2c, delay),
+            start: offset,
+            len: capacity - offset,
+        };
+        let lower = At24CxRegion {
+            eeprom: self,
+            start: 0,
+            len: offset,
+        };
+        Ok((lower, upper))
+    }
+}
+
+impl<I2C, D> At24CxRegion<I2C, D> {
+    /// Absolute offset of the start of the region on the device
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+}
+
+impl<I2C: I2cErrorType, D> StorageErrorType for At24CxRegion<I2C, D> {
+    type Error = Error<I2C::Error>;
+}
+
+impl<I2C: I2c, D: DelayNs> ReadNorFlash for At24CxRegion<I2C, D> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        self.eeprom.read(self.start + offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.len as usize
+    }
+}
+
+impl<I2C: I2c, D: DelayNs> NorFlash for At24CxRegion<I2C, D> {
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to).map_err(Error::from_kind)?;
+        self.eeprom.erase(self.start + from, self.start + to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        self.eeprom.write(self.start + offset, bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimBus, SimDelay, SimError};
+    use crate::Address;
+    use embedded_hal_async::i2c::{ErrorType, Operation};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// One handle to a simulated bus shared by several drivers
+    struct SharedSim(Rc<RefCell<SimBus>>);
+
+    impl ErrorType for SharedSim {
+        type Error = SimError;
+    }
+
+    impl I2c for SharedSim {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            self.0.borrow_mut().run(address, operations)
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_offsets() {
+        let bus = Rc::new(RefCell::new(SimBus::new(Address(0, 0), 17)));
+        let delay = SimDelay::new(bus.borrow().clock());
+        let eeprom = At24Cx::new(SharedSim(bus.clone()), Address(0, 0), 17, delay.clone());
+        assert!(matches!(
+            eeprom.split_at(0x180, SharedSim(bus.clone()), delay.clone()),
+            Err(Error::NotAligned)
+        ));
+        let eeprom = At24Cx::new(SharedSim(bus.clone()), Address(0, 0), 17, delay.clone());
+        assert!(matches!(
+            eeprom.split_at(0x20100, SharedSim(bus), delay),
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[tokio::test]
+    async fn regions_are_isolated() {
+        let bus = Rc::new(RefCell::new(
+            SimBus::new(Address(0, 0), 17).with_write_cycle(5000),
+        ));
+        let delay = SimDelay::new(bus.borrow().clock());
+        let eeprom = At24Cx::new(SharedSim(bus.clone()), Address(0, 0), 17, delay.clone());
+        let (mut lower, mut upper) = eeprom
+            .split_at(0xFF00, SharedSim(bus.clone()), delay)
+            .unwrap();
+        assert_eq!((lower.start(), lower.capacity()), (0, 0xFF00));
+        assert_eq!((upper.start(), upper.capacity()), (0xFF00, 0x10100));
+
+        let low = async {
+            for i in 0..4u32 {
+                lower
+                    .write(0xFE00 + i * 0x40, &[i as u8; 0x40])
+                    .await
+                    .unwrap();
+                tokio::task::yield_now().await;
+            }
+            assert!(matches!(
+                lower.write(0xFEFF, &[0; 2]).await,
+                Err(Error::OutOfBounds)
+            ));
+            let mut readback = [0; 0x100];
+            lower.read(0xFE00, &mut readback).await.unwrap();
+            readback
+        };
+        let high = async {
+            // Crosses the 64KiB boundary at relative 0x100
+            for i in 0..4u32 {
+                upper
+                    .write(0xC0 + i * 0x40, &[0x10 + i as u8; 0x40])
+                    .await
+                    .unwrap();
+                tokio::task::yield_now().await;
+            }
+            assert!(matches!(
+                upper.read(0x10100, &mut [0]).await,
+                Err(Error::OutOfBounds)
+            ));
+            let mut readback = [0; 0x100];
+            upper.read(0xC0, &mut readback).await.unwrap();
+            readback
+        };
+        let (low, high) = tokio::join!(low, high);
+
+        let expected_low: std::vec::Vec<u8> = (0..0x100).map(|i| (i / 0x40) as u8).collect();
+        let expected_high: std::vec::Vec<u8> =
+            (0..0x100).map(|i| 0x10 + (i / 0x40) as u8).collect();
+        assert_eq!(low[..], expected_low[..]);
+        assert_eq!(high[..], expected_high[..]);
+        let bus = bus.borrow();
+        let memory = bus.memory();
+        assert_eq!(memory[0xFE00..0xFF00], expected_low[..]);
+        assert!(memory[0xFF00..0xFFC0].iter().all(|&b| b == 0xFF));
+        assert_eq!(memory[0xFFC0..0x100C0], expected_high[..]);
+    }
+}
@@ -0,0 +1,482 @@
+//! Circular event log in a region of the device.
+//!
+//! Entries start on 16 byte boundaries and are laid out back to back, wrapping around the
+//! end of the region. Once the region is full the oldest entries are dropped to make room.
+//! Each entry is
+//!
+//! | bytes    | content                                             |
+//! |----------|-----------------------------------------------------|
+//! | 0..2     | payload length                                      |
+//! | 2..4     | size of the previous entry on the device, 0 if none |
+//! | 4..8     | sequence number                                     |
+//! | 8..8+len | payload                                             |
+//! | ..+4     | CRC-32 over everything before it                    |
+//!
+//! All integers are little endian. Mounting finds the intact entry with the highest
+//! sequence number and walks forward from it to the oldest entry that still chains up to
+//! it, so an entry torn by a power loss is ignored. An entry, including its framing, can
+//! be as large as the region, in which case it replaces every other entry. Larger entries
+//! are rejected with `OutOfBounds`.
+
+use crate::crc::crc32_update;
+use crate::{EepromPartition, Error};
+use core::cmp::min;
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+/// Entries start at multiples of this
+pub const ENTRY_ALIGN: usize = 16;
+/// Bytes of framing added to every payload
+pub const ENTRY_OVERHEAD: usize = HEADER_SIZE + 4;
+
+const HEADER_SIZE: usize = 8;
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct EntryHeader {
+    len: u16,
+    prev_size: u16,
+    seq: u32,
+}
+
+impl EntryHeader {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut out = [0; HEADER_SIZE];
+        out[0..2].copy_from_slice(&self.len.to_le_bytes());
+        out[2..4].copy_from_slice(&self.prev_size.to_le_bytes());
+        out[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        out
+    }
+
+    fn decode(bytes: &[u8; HEADER_SIZE]) -> Self {
+        Self {
+            len: u16::from_le_bytes([bytes[0], bytes[1]]),
+            prev_size: u16::from_le_bytes([bytes[2], bytes[3]]),
+            seq: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+
+    /// Space taken on the device, including padding
+    fn size(&self) -> u32 {
+        entry_size(self.len as usize)
+    }
+}
+
+fn entry_size(len: usize) -> u32 {
+    (ENTRY_OVERHEAD + len).div_ceil(ENTRY_ALIGN) as u32 * ENTRY_ALIGN as u32
+}
+
+/// An entry read from the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogEntry {
+    pub seq: u32,
+    /// Length of the payload placed at the start of the buffer
+    pub len: usize,
+}
+
+/// Position of [`read_oldest`](RingLog::read_oldest) or [`read_newest`](RingLog::read_newest)
+/// in the log. Start with `LogCursor::default()` and keep passing the same cursor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogCursor {
+    started: bool,
+    position: u32,
+    seq: u32,
+    remaining: usize,
+}
+
+/// Circular log over a partition, see the [module docs](self)
+pub struct RingLog<'a, I2C, D> {
+    region: EepromPartition<'a, I2C, D>,
+    capacity: u32,
+    /// Where the next entry goes
+    head: u32,
+    /// Start of the oldest entry
+    tail: u32,
+    /// Bytes taken by the entries from tail to head
+    used: u32,
+    count: usize,
+    next_seq: u32,
+    /// Size of the newest entry
+    last_size: u32,
+}
+
+impl<'a, I2C, E: Debug, D: DelayNs> RingLog<'a, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Opens the log in `region`, recovering head and tail from the entries on the device.
+    /// Returns `InvalidArgument` for an empty region.
+    pub async fn mount(region: EepromPartition<'a, I2C, D>) -> Result<Self, Error<E>> {
+        let capacity = region.capacity() as u32;
+        if capacity == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut log = Self {
+            region,
+            capacity,
+            head: 0,
+            tail: 0,
+            used: 0,
+            count: 0,
+            next_seq: 0,
+            last_size: 0,
+        };
+
+        let mut newest: Option<(u32, EntryHeader)> = None;
+        for position in (0..capacity).step_by(ENTRY_ALIGN) {
+            if let Some(header) = log.read_valid(position).await? {
+                if newest.map_or(true, |(_, n)| (header.seq.wrapping_sub(n.seq) as i32) > 0) {
+                    newest = Some((position, header));
+                }
+            }
+        }
+        let Some((newest_position, newest)) = newest else {
+            return Ok(log);
+        };
+        log.head = (newest_position + newest.size()) % capacity;
+        log.next_seq = newest.seq.wrapping_add(1);
+        log.last_size = newest.size();
+
+        // The oldest entry is the first one after the head that chains up to the newest
+        for slot in (0..capacity).step_by(ENTRY_ALIGN) {
+            let start = (log.head + slot) % capacity;
+            if let Some(count) = log.chain_length(start, newest_position).await? {
+                log.tail = start;
+                log.count = count;
+                log.used = if slot == 0 { capacity } else { capacity - slot };
+                break;
+            }
+        }
+        Ok(log)
+    }
+
+    /// Number of entries in the log
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Largest payload [`append`](Self::append) accepts
+    pub fn max_payload(&self) -> usize {
+        // Entry sizes need to fit the u16 back link
+        min(self.capacity as usize, u16::MAX as usize) / ENTRY_ALIGN * ENTRY_ALIGN - ENTRY_OVERHEAD
+    }
+
+    /// Appends an entry, dropping the oldest entries if there is no room for it.
+    ///
+    /// Returns `OutOfBounds` if the payload is larger than [`max_payload`](Self::max_payload).
+    pub async fn append(&mut self, payload: &[u8]) -> Result<(), Error<E>> {
+        if payload.len() > self.max_payload() {
+            return Err(Error::OutOfBounds);
+        }
+        let size = entry_size(payload.len());
+        while self.count > 0 && self.capacity - self.used < size {
+            let oldest = self.read_header(self.tail).await?;
+            self.tail = (self.tail + oldest.size()) % self.capacity;
+            self.used -= oldest.size();
+            self.count -= 1;
+        }
+
+        let header = EntryHeader {
+            len: payload.len() as u16,
+            prev_size: if self.count == 0 {
+                0
+            } else {
+                self.last_size as u16
+            },
+            seq: self.next_seq,
+        }
+        .encode();
+        let crc = !crc32_update(crc32_update(!0, &header), payload);
+        let head = self.head;
+        self.region.write_wrapping(head, &header).await?;
+        self.region
+            .write_wrapping(head + HEADER_SIZE as u32, payload)
+            .await?;
+        self.region
+            .write_wrapping(
+                head + (HEADER_SIZE + payload.len()) as u32,
+                &crc.to_le_bytes(),
+            )
+            .await?;
+
+        if self.count == 0 {
+            self.tail = head;
+        }
+        self.head = (head + size) % self.capacity;
+        self.used += size;
+        self.count += 1;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.last_size = size;
+        Ok(())
+    }
+
+    /// Reads the next entry going from oldest to newest into the start of `buf`.
+    /// Returns `None` once every entry was read.
+    ///
+    /// Returns `OutOfBounds` if `buf` is too small, the cursor stays on the entry, and
+    /// `NotFound` if the entry was overwritten since the cursor was started.
+    pub async fn read_oldest(
+        &mut self,
+        cursor: &mut LogCursor,
+        buf: &mut [u8],
+    ) -> Result<Option<LogEntry>, Error<E>> {
+        if !cursor.started {
+            *cursor = LogCursor {
+                started: true,
+                position: self.tail,
+                seq: self.next_seq.wrapping_sub(self.count as u32),
+                remaining: self.count,
+            };
+        }
+        let Some((entry, header)) = self.read_at_cursor(cursor, buf).await? else {
+            return Ok(None);
+        };
+        cursor.position = (cursor.position + header.size()) % self.capacity;
+        cursor.seq = cursor.seq.wrapping_add(1);
+        cursor.remaining -= 1;
+        Ok(Some(entry))
+    }
+
+    /// Reads the next entry going from newest to oldest into the start of `buf`.
+    /// Behaves like [`read_oldest`](Self::read_oldest) otherwise.
+    pub async fn read_newest(
+        &mut self,
+        cursor: &mut LogCursor,
+        buf: &mut [u8],
+    ) -> Result<Option<LogEntry>, Error<E>> {
+        if !cursor.started {
+            *cursor = LogCursor {
+                started: true,
+                position: (self.head + self.capacity - self.last_size) % self.capacity,
+                seq: self.next_seq.wrapping_sub(1),
+                remaining: self.count,
+            };
+        }
+        let Some((entry, header)) = self.read_at_cursor(cursor, buf).await? else {
+            return Ok(None);
+        };
+        cursor.position =
+            (cursor.position + self.capacity - header.prev_size as u32) % self.capacity;
+        cursor.seq = cursor.seq.wrapping_sub(1);
+        cursor.remaining -= 1;
+        Ok(Some(entry))
+    }
+
+    async fn read_at_cursor(
+        &mut self,
+        cursor: &LogCursor,
+        buf: &mut [u8],
+    ) -> Result<Option<(LogEntry, EntryHeader)>, Error<E>> {
+        if cursor.remaining == 0 {
+            return Ok(None);
+        }
+        let header = self.read_header(cursor.position).await?;
+        let oldest_seq = self.next_seq.wrapping_sub(self.count as u32);
+        if header.seq != cursor.seq || (cursor.seq.wrapping_sub(oldest_seq) as i32) < 0 {
+            return Err(Error::NotFound);
+        }
+        let len = header.len as usize;
+        if len > buf.len() {
+            return Err(Error::OutOfBounds);
+        }
+        self.region
+            .read_wrapping(cursor.position + HEADER_SIZE as u32, &mut buf[..len])
+            .await?;
+        Ok(Some((
+            LogEntry {
+                seq: header.seq,
+                len,
+            },
+            header,
+        )))
+    }
+
+    /// Number of entries from `start` up to the newest entry, if they form an unbroken chain
+    async fn chain_length(&mut self, start: u32, newest: u32) -> Result<Option<usize>, Error<E>> {
+        let Some(mut header) = self.read_valid(start).await? else {
+            return Ok(None);
+        };
+        let mut position = start;
+        let mut count = 1;
+        let mut walked = header.size();
+        while position != newest {
+            position = (position + header.size()) % self.capacity;
+            let Some(next) = self.read_valid(position).await? else {
+                return Ok(None);
+            };
+            walked += next.size();
+            if next.seq != header.seq.wrapping_add(1) || walked > self.capacity {
+                return Ok(None);
+            }
+            header = next;
+            count += 1;
+        }
+        Ok(Some(count))
+    }
+
+    /// Header of an entry known to be valid
+    async fn read_header(&mut self, position: u32) -> Result<EntryHeader, Error<E>> {
+        let mut bytes = [0; HEADER_SIZE];
+        self.region.read_wrapping(position, &mut bytes).await?;
+        Ok(EntryHeader::decode(&bytes))
+    }
+
+    /// Header of the entry at `position` if there is a complete, intact one
+    async fn read_valid(&mut self, position: u32) -> Result<Option<EntryHeader>, Error<E>> {
+        let mut bytes = [0; HEADER_SIZE];
+        self.region.read_wrapping(position, &mut bytes).await?;
+        let header = EntryHeader::decode(&bytes);
+        if header.len as usize > self.max_payload() {
+            return Ok(None);
+        }
+
+        let mut crc = crc32_update(!0, &bytes);
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut done = 0;
+        while done < header.len as usize {
+            let len = min(CHUNK_SIZE, header.len as usize - done);
+            let chunk = &mut chunk[..len];
+            self.region
+                .read_wrapping(position + (HEADER_SIZE + done) as u32, chunk)
+                .await?;
+            crc = crc32_update(crc, chunk);
+            done += len;
+        }
+        let mut stored = [0; 4];
+        let crc_offset = position + (HEADER_SIZE + header.len as usize) as u32;
+        self.region.read_wrapping(crc_offset, &mut stored).await?;
+        Ok((!crc == u32::from_le_bytes(stored)).then_some(header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec::Vec;
+
+    const REGION: core::ops::Range<u32> = 0x2000..0x2100;
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn mount(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> RingLog<'_, SimBus, NoopDelay> {
+        RingLog::mount(eeprom.partition(REGION).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn payload(seq: u32) -> Vec<u8> {
+        std::vec![seq as u8; 5 + (seq as usize * 7) % 30]
+    }
+
+    /// All entries, oldest first, checked against the newest first order
+    async fn entries(log: &mut RingLog<'_, SimBus, NoopDelay>) -> Vec<(u32, Vec<u8>)> {
+        let mut buf = [0; 256];
+        let mut oldest = Vec::new();
+        let mut cursor = LogCursor::default();
+        while let Some(entry) = log.read_oldest(&mut cursor, &mut buf).await.unwrap() {
+            oldest.push((entry.seq, buf[..entry.len].to_vec()));
+        }
+        let mut newest = Vec::new();
+        let mut cursor = LogCursor::default();
+        while let Some(entry) = log.read_newest(&mut cursor, &mut buf).await.unwrap() {
+            newest.push((entry.seq, buf[..entry.len].to_vec()));
+        }
+        newest.reverse();
+        assert_eq!(oldest, newest);
+        oldest
+    }
+
+    #[tokio::test]
+    async fn append_and_iterate() {
+        let mut eeprom = driver(None);
+        let mut log = mount(&mut eeprom).await;
+        assert!(log.is_empty());
+        for seq in 0..4 {
+            log.append(&payload(seq)).await.unwrap();
+        }
+        let expected: Vec<_> = (0..4).map(|seq| (seq, payload(seq))).collect();
+        assert_eq!(entries(&mut log).await, expected);
+
+        let mut log = mount(&mut eeprom).await;
+        assert_eq!(log.len(), 4);
+        assert_eq!(entries(&mut log).await, expected);
+    }
+
+    #[tokio::test]
+    async fn wraps_around_and_drops_oldest() {
+        let mut eeprom = driver(None);
+        for seq in 0..50 {
+            let mut log = mount(&mut eeprom).await;
+            log.append(&payload(seq)).await.unwrap();
+
+            let mut log = mount(&mut eeprom).await;
+            let found = entries(&mut log).await;
+            assert_eq!(found.last().unwrap().0, seq);
+            let first = found[0].0;
+            let expected: Vec<_> = (first..=seq).map(|seq| (seq, payload(seq))).collect();
+            assert_eq!(found, expected);
+            let used: u32 = (first..=seq).map(|s| entry_size(payload(s).len())).sum();
+            let before = first.checked_sub(1).map(|s| entry_size(payload(s).len()));
+            // Only as many entries were dropped as needed
+            assert!(before.map_or(true, |b| used + b > 256));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_entries() {
+        let mut eeprom = driver(None);
+        let mut log = mount(&mut eeprom).await;
+        log.append(&[1; 10]).await.unwrap();
+        assert!(matches!(
+            log.append(&[0; 256 - ENTRY_OVERHEAD + 1]).await,
+            Err(Error::OutOfBounds)
+        ));
+        log.append(&[2; 256 - ENTRY_OVERHEAD]).await.unwrap();
+        let mut log = mount(&mut eeprom).await;
+        assert_eq!(entries(&mut log).await, [(1, std::vec![2; 244])]);
+    }
+
+    #[tokio::test]
+    async fn power_loss_mid_append() {
+        let mut eeprom = driver(None);
+        for seq in 0..12 {
+            mount(&mut eeprom)
+                .await
+                .append(&payload(seq))
+                .await
+                .unwrap();
+        }
+        let snapshot = eeprom.i2c.memory().to_vec();
+        let before = entries(&mut mount(&mut eeprom).await).await;
+        let new = std::vec![0xEE; 40];
+
+        for cut in 0..ENTRY_OVERHEAD + new.len() {
+            let mut eeprom = driver(Some(&snapshot));
+            eeprom.i2c.cut_power_after(cut);
+            assert!(mount(&mut eeprom).await.append(&new).await.is_err());
+            eeprom.i2c.restore_power();
+
+            let mut log = mount(&mut eeprom).await;
+            let found = entries(&mut log).await;
+            // Whatever survived is an unbroken run ending with the last complete entry
+            assert_eq!(found.last(), before.last(), "cut after {cut} bytes");
+            assert!(before.ends_with(&found));
+            log.append(&new).await.unwrap();
+            let found = entries(&mut mount(&mut eeprom).await).await;
+            assert_eq!(found.last().unwrap(), &(12, new.clone()));
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Persistent seed for a PRNG on devices without a hardware RNG.
+//!
+//! The region is split into slots of 40 bytes, a little endian sequence number, the 32 byte
+//! seed and a little endian CRC-32 over both. Every update writes the next slot after the
+//! newest one, so the writes rotate over the whole region.
+//!
+//! [`SeedStore::load_and_evolve`] derives the seed it returns and the seed it stores from
+//! the stored one with the ChaCha20 block function, keyed by the stored seed. The new seed
+//! is written before anything is returned: if power is lost before the write completes,
+//! the caller never saw an output of the old seed, so no two boots observe the same seed.
+//! Since ChaCha20 can't be inverted, a returned seed doesn't reveal the stored one.
+//!
+//! A blank region starts from an all-zero seed, which is the same on every device. Fold
+//! in something device specific, like a unique ID or noise from an ADC, with
+//! [`SeedStore::mix_in`] on the first boot and whenever entropy is at hand.
+
+use crate::crc::crc32_update;
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Size of a seed slot
+pub const SEED_SLOT_SIZE: usize = 4 + 32 + 4;
+
+/// Seed slots in a region, see the [module docs](self)
+///
+/// The newest seed is cached after the first access, so a `SeedStore` must always be used
+/// with the same device and be the only writer to its region.
+pub struct SeedStore {
+    region: Range<u32>,
+    /// Sequence number and seed of the newest slot, `None` for a blank region
+    state: Option<Option<(u32, [u8; 32])>>,
+}
+
+impl SeedStore {
+    /// Creates a store over `region`, which needs room for at least two slots of
+    /// [`SEED_SLOT_SIZE`] bytes
+    pub const fn new(region: Range<u32>) -> Self {
+        Self {
+            region,
+            state: None,
+        }
+    }
+
+    /// Number of slots the writes rotate over
+    pub fn slots(&self) -> u32 {
+        self.region.end.saturating_sub(self.region.start) / SEED_SLOT_SIZE as u32
+    }
+
+    /// Returns a fresh seed, after storing the seed the next call derives its own from.
+    ///
+    /// Returns `InvalidArgument` if the region holds less than two slots and `OutOfBounds`
+    /// if it doesn't fit on the device.
+    pub async fn load_and_evolve<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<[u8; 32], Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let seed = self.seed(eeprom).await?;
+        let block = chacha20_block(&seed);
+        let (next, output) = block.split_at(32);
+        self.store(eeprom, next.try_into().unwrap()).await?;
+        Ok(output.try_into().unwrap())
+    }
+
+    /// Folds `entropy` into the stored seed. Any amount of entropy can be mixed in, the
+    /// seed only ever gets harder to guess.
+    pub async fn mix_in<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        entropy: &[u8],
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let mut seed = self.seed(eeprom).await?;
+        // The length goes in too, so trailing zeros still change the seed
+        let mut length = [0; 32];
+        length[..8].copy_from_slice(&(entropy.len() as u64).to_le_bytes());
+        for chunk in entropy.chunks(32).chain([&length[..]]) {
+            for (s, e) in seed.iter_mut().zip(chunk) {
+                *s ^= e;
+            }
+            let block = chacha20_block(&seed);
+            seed.copy_from_slice(&block[..32]);
+        }
+        self.store(eeprom, seed).await
+    }
+
+    /// Writes `seed` to the slot after the newest one
+    async fn store<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        seed: [u8; 32],
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let sequence = match self.state {
+            Some(Some((sequence, _))) => sequence.wrapping_add(1),
+            _ => 0,
+        };
+        let mut slot = [0; SEED_SLOT_SIZE];
+        slot[..4].copy_from_slice(&sequence.to_le_bytes());
+        slot[4..36].copy_from_slice(&seed);
+        let crc = !crc32_update(!0, &slot[..36]);
+        slot[36..].copy_from_slice(&crc.to_le_bytes());
+        let offset = self.region.start + (sequence % self.slots()) * SEED_SLOT_SIZE as u32;
+        eeprom.write(offset, &slot).await?;
+        self.state = Some(Some((sequence, seed)));
+        Ok(())
+    }
+
+    /// The newest stored seed
+    async fn seed<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<[u8; 32], Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if let Some(state) = self.state {
+            return Ok(state.map_or([0; 32], |(_, seed)| seed));
+        }
+        if self.slots() < 2 {
+            return Err(Error::InvalidArgument);
+        }
+        if self.region.end as usize > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+
+        let mut newest: Option<(u32, [u8; 32])> = None;
+        for i in 0..self.slots() {
+            let mut slot = [0; SEED_SLOT_SIZE];
+            eeprom
+                .read(self.region.start + i * SEED_SLOT_SIZE as u32, &mut slot)
+                .await?;
+            let crc = u32::from_le_bytes(slot[36..].try_into().unwrap());
+            if !crc32_update(!0, &slot[..36]) != crc {
+                continue;
+            }
+            let sequence = u32::from_le_bytes(slot[..4].try_into().unwrap());
+            // Sequence numbers wrap, the newest is the one ahead of the others
+            if newest.map_or(true, |(n, _)| (sequence.wrapping_sub(n) as i32) > 0) {
+                newest = Some((sequence, slot[4..36].try_into().unwrap()));
+            }
+        }
+        self.state = Some(newest);
+        Ok(newest.map_or([0; 32], |(_, seed)| seed))
+    }
+}
+
+/// The ChaCha20 block function with `key`, an all-zero nonce and block counter 0
+fn chacha20_block(key: &[u8; 32]) -> [u8; 64] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    for (word, bytes) in state[4..12].iter_mut().zip(key.chunks(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+
+    let mut working = state;
+    for _ in 0..10 {
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    let mut block = [0; 64];
+    for ((bytes, w), s) in block.chunks_mut(4).zip(working).zip(state) {
+        bytes.copy_from_slice(&w.wrapping_add(s).to_le_bytes());
+    }
+    block
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const REGION: Range<u32> = 0x400..0x400 + 4 * SEED_SLOT_SIZE as u32;
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[test]
+    fn chacha20_test_vector() {
+        // RFC 8439 A.1, test vector #1
+        let block = chacha20_block(&[0; 32]);
+        assert_eq!(
+            block[..16],
+            [
+                0x76, 0xb8, 0xe0, 0xad, 0xa0, 0xf1, 0x3d, 0x90, 0x40, 0x5d, 0x6a, 0xe5, 0x53, 0x86,
+                0xbd, 0x28
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn evolves_before_returning() {
+        let mut eeprom = driver(None);
+        let mut seen = std::vec::Vec::new();
+        for _ in 0..3 {
+            seen.push(
+                SeedStore::new(REGION)
+                    .load_and_evolve(&mut eeprom)
+                    .await
+                    .unwrap(),
+            );
+        }
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        // Power is lost at every byte of the write back, nothing is returned
+        for cut in 0..SEED_SLOT_SIZE {
+            let mut eeprom = driver(Some(&snapshot));
+            eeprom.i2c.cut_power_after(cut);
+            assert!(SeedStore::new(REGION)
+                .load_and_evolve(&mut eeprom)
+                .await
+                .is_err());
+            eeprom.i2c.restore_power();
+            let seed = SeedStore::new(REGION)
+                .load_and_evolve(&mut eeprom)
+                .await
+                .unwrap();
+            assert!(!seen.contains(&seed));
+        }
+
+        // The returned seed isn't the stored one
+        let mut store = SeedStore::new(REGION);
+        let seed = store.load_and_evolve(&mut eeprom).await.unwrap();
+        assert!(!seen.contains(&seed));
+        assert!(
+            !eeprom.i2c.memory()[REGION.start as usize..REGION.end as usize]
+                .windows(32)
+                .any(|stored| stored == seed)
+        );
+
+        // Entropy changes every seed that follows
+        let mut other = driver(Some(&snapshot));
+        let mut store = SeedStore::new(REGION);
+        store.mix_in(&mut other, &[0; 16]).await.unwrap();
+        assert_ne!(store.load_and_evolve(&mut other).await.unwrap(), seed);
+    }
+
+    #[tokio::test]
+    async fn rotates_slots() {
+        let mut eeprom = driver(None);
+        for boot in 0..40 {
+            let mut store = SeedStore::new(REGION);
+            store.load_and_evolve(&mut eeprom).await.unwrap();
+            if boot % 10 == 0 {
+                store.mix_in(&mut eeprom, b"adc noise").await.unwrap();
+            }
+        }
+        // 44 writes over 4 slots
+        for slot in 0..4 {
+            let offset = REGION.start as usize + slot * SEED_SLOT_SIZE;
+            assert_eq!(eeprom.i2c.write_count(offset), 11);
+        }
+        assert_eq!(eeprom.i2c.write_count(REGION.end as usize), 0);
+
+        assert!(matches!(
+            SeedStore::new(0x400..0x400 + SEED_SLOT_SIZE as u32)
+                .load_and_evolve(&mut eeprom)
+                .await,
+            Err(Error::InvalidArgument)
+        ));
+    }
+}
@@ -0,0 +1,414 @@
+//! Versioned settings with migrations between schema versions.
+//!
+//! A [`Settings`] keeps two copies of a [`Pod`] value in the two pages starting at its
+//! offset, like the partition table. Each copy is a
+//! [signed block](crate::signature) with magic `STNG` whose version is the schema version
+//! and whose payload is a little endian sequence number followed by the bytes of the value.
+//! Saves always overwrite the older copy, so a torn save leaves the previous settings intact.
+//! The copy is a single page write on parts with [`PAGE_SIZE`] pages.
+//!
+//! When the stored schema version is older than the current one, [`load`](Settings::load)
+//! passes the stored bytes through the [`Migration`]s, one version at a time, before
+//! reading them as the current type. The migrated settings are only written back by the
+//! next [`save`](Settings::save).
+
+use crate::signature::{BlockStatus, SIGNED_BLOCK_OVERHEAD};
+use crate::{At24Cx, Error, PAGE_SIZE};
+use bytemuck::Pod;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+/// Largest value, in bytes, a [`Settings`] can hold in any schema version
+pub const MAX_SETTINGS_SIZE: usize = PAGE_SIZE - SIGNED_BLOCK_OVERHEAD - 4;
+
+const MAGIC: [u8; 4] = *b"STNG";
+
+/// Converts settings from schema version `from` to version `from + 1`.
+///
+/// `migrate` gets the stored bytes and a buffer of [`MAX_SETTINGS_SIZE`] bytes for the
+/// converted ones, and returns how many bytes it wrote.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub from: u16,
+    pub migrate: fn(old: &[u8], new: &mut [u8]) -> usize,
+}
+
+/// Settings of type `T` at schema version `version`, see the [module docs](self)
+///
+/// The settings cache which copy is the newest after the first access, so they must always
+/// be used with the same device and be the only writer to their pages.
+pub struct Settings<T> {
+    offset: u32,
+    version: u16,
+    /// Slot and sequence number of the newest copy once known, `Some(None)` if there isn't any
+    last: Option<Option<(usize, u32)>>,
+    _type: PhantomData<T>,
+}
+
+/// Where the newest intact copy is, as found by [`Settings::scan`]
+struct Newest {
+    slot: usize,
+    sequence: u32,
+    version: u16,
+}
+
+impl<T: Pod> Settings<T> {
+    const FITS: () = assert!(
+        size_of::<T>() <= MAX_SETTINGS_SIZE,
+        "settings don't fit in a page"
+    );
+
+    /// Creates settings stored in the two pages starting at `offset`, which has to be page
+    /// aligned. Fails to compile if `T` is larger than [`MAX_SETTINGS_SIZE`].
+    pub const fn new(offset: u32, version: u16) -> Self {
+        let () = Self::FITS;
+        Self {
+            offset,
+            version,
+            last: None,
+            _type: PhantomData,
+        }
+    }
+
+    pub const fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Current schema version
+    pub const fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Reads the newest intact copy of the settings, migrating it to the current schema
+    /// version if it is older.
+    ///
+    /// Returns `NotFound` if the settings were never saved, `CrcMismatch` if no copy is
+    /// intact and `UnsupportedVersion` if the newest copy has a newer schema version.
+    /// Returns `InvalidArgument` if `migrations` has no migration from one of the versions
+    /// in between, or if the migrated bytes don't have the size of `T`.
+    pub async fn load<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        migrations: &[Migration],
+    ) -> Result<T, Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let (newest, corrupt) = self.scan(eeprom).await?;
+        let Some(newest) = newest else {
+            return Err(if corrupt {
+                Error::CrcMismatch
+            } else {
+                Error::NotFound
+            });
+        };
+        if newest.version > self.version {
+            return Err(Error::UnsupportedVersion);
+        }
+
+        let mut buffers = [[0; 4 + MAX_SETTINGS_SIZE]; 2];
+        let len = match eeprom
+            .read_signed_block(self.slot_offset(newest.slot), MAGIC, &mut buffers[0])
+            .await?
+        {
+            BlockStatus::Valid { len, .. } => len - 4,
+            // Changed since the scan
+            _ => return Err(Error::CrcMismatch),
+        };
+        let mut current = 0;
+        let mut len = len;
+        for version in newest.version..self.version {
+            let migration = migrations
+                .iter()
+                .find(|m| m.from == version)
+                .ok_or(Error::InvalidArgument)?;
+            let [first, second] = &mut buffers;
+            let (old, new) = if current == 0 {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            len = (migration.migrate)(&old[4..4 + len], &mut new[4..]);
+            if len > MAX_SETTINGS_SIZE {
+                return Err(Error::InvalidArgument);
+            }
+            current = 1 - current;
+        }
+        if len != size_of::<T>() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(bytemuck::pod_read_unaligned(&buffers[current][4..4 + len]))
+    }
+
+    /// Writes `value` with the current schema version, replacing the older copy
+    pub async fn save<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+        value: &T,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        let last = match self.last {
+            Some(last) => last,
+            None => self.scan(eeprom).await?.0.map(|n| (n.slot, n.sequence)),
+        };
+        let (slot, sequence) = match last {
+            Some((slot, sequence)) => (1 - slot, sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let mut payload = [0; 4 + MAX_SETTINGS_SIZE];
+        payload[..4].copy_from_slice(&sequence.to_le_bytes());
+        let bytes = bytemuck::bytes_of(value);
+        payload[4..4 + bytes.len()].copy_from_slice(bytes);
+        // Whatever is in the slot is stale now
+        self.last = None;
+        eeprom
+            .write_signed_block(
+                self.slot_offset(slot),
+                MAGIC,
+                self.version,
+                &payload[..4 + bytes.len()],
+            )
+            .await?;
+        self.last = Some(Some((slot, sequence)));
+        Ok(())
+    }
+
+    fn slot_offset(&self, slot: usize) -> u32 {
+        self.offset + (slot * PAGE_SIZE) as u32
+    }
+
+    /// Finds the newest intact copy and whether any copy was corrupt
+    async fn scan<I2C, E: Debug, D: DelayNs>(
+        &mut self,
+        eeprom: &mut At24Cx<I2C, D>,
+    ) -> Result<(Option<Newest>, bool), Error<E>>
+    where
+        I2C: I2c<Error = E>,
+    {
+        if self.offset as usize % PAGE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+        if self.offset as usize + 2 * PAGE_SIZE > eeprom.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        let mut newest: Option<Newest> = None;
+        let mut corrupt = false;
+        let mut payload = [0; 4 + MAX_SETTINGS_SIZE];
+        for slot in 0..2 {
+            let status = match eeprom
+                .read_signed_block(self.slot_offset(slot), MAGIC, &mut payload)
+                .await
+            {
+                Err(Error::OutOfBounds) => BlockStatus::Corrupt,
+                status => status?,
+            };
+            match status {
+                BlockStatus::Blank => {}
+                BlockStatus::Valid { version, len } if len >= 4 => {
+                    let sequence =
+                        u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                    let is_newer = newest
+                        .as_ref()
+                        .map_or(true, |n| (sequence.wrapping_sub(n.sequence) as i32) > 0);
+                    if is_newer {
+                        newest = Some(Newest {
+                            slot,
+                            sequence,
+                            version,
+                        });
+                    }
+                }
+                _ => corrupt = true,
+            }
+        }
+        self.last = Some(newest.as_ref().map(|n| (n.slot, n.sequence)));
+        Ok((newest, corrupt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use bytemuck::Zeroable;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    const OFFSET: u32 = 0x400;
+
+    /// Version 0, a gain in percent
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+    struct V0 {
+        gain_percent: u16,
+    }
+
+    /// Version 1 adds an offset
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+    struct V1 {
+        gain_percent: u16,
+        offset: i16,
+    }
+
+    /// Version 2 stores the gain as a float
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+    struct V2 {
+        gain: f32,
+        offset: i16,
+        _reserved: u16,
+    }
+
+    fn v0_to_v1(old: &[u8], new: &mut [u8]) -> usize {
+        let old: V0 = bytemuck::pod_read_unaligned(old);
+        let v1 = V1 {
+            gain_percent: old.gain_percent,
+            offset: 0,
+        };
+        new[..4].copy_from_slice(bytemuck::bytes_of(&v1));
+        4
+    }
+
+    fn v1_to_v2(old: &[u8], new: &mut [u8]) -> usize {
+        let old: V1 = bytemuck::pod_read_unaligned(old);
+        let v2 = V2 {
+            gain: old.gain_percent as f32 / 100.0,
+            offset: old.offset,
+            _reserved: 0,
+        };
+        new[..8].copy_from_slice(bytemuck::bytes_of(&v2));
+        8
+    }
+
+    const MIGRATIONS: [Migration; 2] = [
+        Migration {
+            from: 1,
+            migrate: v1_to_v2,
+        },
+        Migration {
+            from: 0,
+            migrate: v0_to_v1,
+        },
+    ];
+
+    const SETTINGS: V2 = V2 {
+        gain: 1.5,
+        offset: -3,
+        _reserved: 0,
+    };
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn chains_migrations() {
+        let mut eeprom = driver(None);
+        let mut settings = Settings::<V2>::new(OFFSET, 2);
+        assert!(matches!(
+            settings.load(&mut eeprom, &MIGRATIONS).await,
+            Err(Error::NotFound)
+        ));
+
+        // Saved by an old firmware
+        let mut old = Settings::<V0>::new(OFFSET, 0);
+        old.save(&mut eeprom, &V0 { gain_percent: 250 })
+            .await
+            .unwrap();
+        let loaded = settings.load(&mut eeprom, &MIGRATIONS).await.unwrap();
+        assert_eq!(
+            loaded,
+            V2 {
+                gain: 2.5,
+                offset: 0,
+                _reserved: 0
+            }
+        );
+        assert!(matches!(
+            settings.load(&mut eeprom, &MIGRATIONS[..1]).await,
+            Err(Error::InvalidArgument)
+        ));
+
+        settings.save(&mut eeprom, &SETTINGS).await.unwrap();
+        let mut settings = Settings::<V2>::new(OFFSET, 2);
+        assert_eq!(settings.load(&mut eeprom, &[]).await.unwrap(), SETTINGS);
+
+        // Old firmware can't read the new settings
+        let mut old = Settings::<V1>::new(OFFSET, 1);
+        assert!(matches!(
+            old.load(&mut eeprom, &MIGRATIONS).await,
+            Err(Error::UnsupportedVersion)
+        ));
+    }
+
+    #[tokio::test]
+    async fn tells_corrupt_from_blank() {
+        let mut eeprom = driver(None);
+        let mut settings = Settings::<V2>::new(OFFSET, 2);
+        settings.save(&mut eeprom, &SETTINGS).await.unwrap();
+        eeprom.i2c.memory_mut()[OFFSET as usize + 14] ^= 0x01;
+        assert!(matches!(
+            settings.load(&mut eeprom, &[]).await,
+            Err(Error::CrcMismatch)
+        ));
+        // With no intact copy left, saving starts over in the first slot
+        settings.save(&mut eeprom, &SETTINGS).await.unwrap();
+        assert_eq!(settings.load(&mut eeprom, &[]).await.unwrap(), SETTINGS);
+        assert_eq!(eeprom.i2c.write_count(OFFSET as usize + 14), 2);
+
+        let mut settings = Settings::<V2>::new(OFFSET + 1, 2);
+        assert!(matches!(
+            settings.load(&mut eeprom, &[]).await,
+            Err(Error::NotAligned)
+        ));
+    }
+
+    #[tokio::test]
+    async fn torn_save_stays_loadable() {
+        let mut eeprom = driver(None);
+        let mut settings = Settings::<V1>::new(OFFSET, 1);
+        let first = V1 {
+            gain_percent: 100,
+            offset: 1,
+        };
+        settings.save(&mut eeprom, &first).await.unwrap();
+        let mut snapshots = std::vec![eeprom.i2c.memory().to_vec()];
+        settings
+            .save(&mut eeprom, &V1 { offset: 2, ..first })
+            .await
+            .unwrap();
+        snapshots.push(eeprom.i2c.memory().to_vec());
+
+        // Tear saves into either slot, with the previous copy in the other one
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            let previous = V1 {
+                offset: i as i16 + 1,
+                ..first
+            };
+            let next = V1 {
+                offset: 10,
+                ..first
+            };
+            for cut in 0..=SIGNED_BLOCK_OVERHEAD + 8 {
+                let mut eeprom = driver(Some(snapshot));
+                eeprom.i2c.cut_power_after(cut);
+                let mut settings = Settings::<V1>::new(OFFSET, 1);
+                let saved = settings.save(&mut eeprom, &next).await.is_ok();
+                eeprom.i2c.restore_power();
+
+                let mut settings = Settings::<V1>::new(OFFSET, 1);
+                let loaded = settings.load(&mut eeprom, &[]).await.unwrap();
+                assert_eq!(loaded, if saved { next } else { previous });
+            }
+        }
+    }
+}
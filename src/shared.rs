@@ -0,0 +1,205 @@
+//! Sharing one driver between several Embassy tasks.
+//!
+//! [`SharedAt24Cx`] puts the driver behind an `embassy_sync` mutex and hands out cheap
+//! [`SharedHandle`]s. Every handle operation locks the mutex for exactly one driver
+//! operation (one `read`, one `write`, ...), not for a sequence of calls. Two writes from
+//! the same task can therefore be interleaved with operations of other tasks. Tasks that
+//! need their data to stay disjoint should each use a bounds-restricted handle from
+//! [`partition_handle`](SharedHandle::partition_handle).
+
+use crate::{check_erase, check_read, check_write, At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use core::ops::Range;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_hal_async::{
+    delay::DelayNs,
+    i2c::{ErrorType as I2cErrorType, I2c},
+};
+use embedded_storage_async::nor_flash::{ErrorType as StorageErrorType, NorFlash, ReadNorFlash};
+
+/// A driver behind a mutex, usually placed in a `static`
+pub struct SharedAt24Cx<M: RawMutex, I2C, D> {
+    eeprom: Mutex<M, At24Cx<I2C, D>>,
+    capacity: u32,
+}
+
+impl<M: RawMutex, I2C, E: Debug, D: DelayNs> SharedAt24Cx<M, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    pub fn new(eeprom: At24Cx<I2C, D>) -> Self {
+        let capacity = eeprom.capacity() as u32;
+        Self {
+            eeprom: Mutex::new(eeprom),
+            capacity,
+        }
+    }
+
+    /// A handle covering the whole device
+    pub fn handle(&self) -> SharedHandle<'_, M, I2C, D> {
+        SharedHandle {
+            shared: self,
+            start: 0,
+            len: self.capacity,
+        }
+    }
+
+    /// Locks the driver for a sequence of operations that must not be interleaved.
+    pub async fn lock(&self) -> embassy_sync::mutex::MutexGuard<'_, M, At24Cx<I2C, D>> {
+        self.eeprom.lock().await
+    }
+}
+
+/// A `Clone`-able reference to a [`SharedAt24Cx`], optionally restricted to a range.
+/// Offsets are relative to the start of that range.
+pub struct SharedHandle<'a, M: RawMutex, I2C, D> {
+    shared: &'a SharedAt24Cx<M, I2C, D>,
+    start: u32,
+    len: u32,
+}
+
+impl<M: RawMutex, I2C, D> Clone for SharedHandle<'_, M, I2C, D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M: RawMutex, I2C, D> Copy for SharedHandle<'_, M, I2C, D> {}
+
+impl<M: RawMutex, I2C, E: Debug, D: DelayNs> SharedHandle<'_, M, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// A handle restricted to a page-aligned range of this handle
+    pub fn partition_handle(&self, range: Range<u32>) -> Result<Self, Error<E>> {
+        if range.start > range.end || range.end > self.len {
+            return Err(Error::OutOfBounds);
+        }
+        if range.start as usize % PAGE_SIZE != 0 || range.end as usize % PAGE_SIZE != 0 {
+            return Err(Error::NotAligned);
+        }
+        Ok(Self {
+            shared: self.shared,
+            start: self.start + range.start,
+            len: range.end - range.start,
+        })
+    }
+
+    /// Absolute offset of the start of the handle's range on the device
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Reads while holding the lock for this one operation
+    pub async fn read(&self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        check_read(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        let mut eeprom = self.shared.eeprom.lock().await;
+        eeprom.read(self.start + offset, bytes).await
+    }
+
+    /// Writes while holding the lock for this one operation
+    pub async fn write(&self, offset: u32, bytes: &[u8]) -> Result<(), Error<E>> {
+        check_write(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        let mut eeprom = self.shared.eeprom.lock().await;
+        eeprom.write(self.start + offset, bytes).await
+    }
+}
+
+impl<M: RawMutex, I2C: I2cErrorType, D> StorageErrorType for SharedHandle<'_, M, I2C, D> {
+    type Error = Error<I2C::Error>;
+}
+
+impl<M: RawMutex, I2C: I2c, D: DelayNs> ReadNorFlash for SharedHandle<'_, M, I2C, D> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        SharedHandle::read(self, offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.len as usize
+    }
+}
+
+impl<M: RawMutex, I2C: I2c, D: DelayNs> NorFlash for SharedHandle<'_, M, I2C, D> {
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to).map_err(Error::from_kind)?;
+        let mut eeprom = self.shared.eeprom.lock().await;
+        eeprom.erase(self.start + from, self.start + to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        SharedHandle::write(self, offset, bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    type Shared = SharedAt24Cx<CriticalSectionRawMutex, SimBus, NoopDelay>;
+
+    fn shared() -> Shared {
+        let bus = SimBus::new(Address(0, 0), 17);
+        SharedAt24Cx::new(At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new()))
+    }
+
+    async fn writer(handle: SharedHandle<'_, CriticalSectionRawMutex, SimBus, NoopDelay>, tag: u8) {
+        for i in 0..16u32 {
+            handle
+                .write(i * 64, &[tag.wrapping_add(i as u8); 64])
+                .await
+                .unwrap();
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn interleaved_tasks_stay_in_their_partitions() {
+        let shared = shared();
+        let settings = shared.handle().partition_handle(0xFC00..0x10000).unwrap();
+        // Crosses the 64KiB boundary
+        let log = shared.handle().partition_handle(0x10000..0x10400).unwrap();
+        tokio::join!(writer(settings, 0x10), writer(log, 0x80));
+
+        for i in 0..16u32 {
+            let mut buf = [0; 64];
+            settings.read(i * 64, &mut buf).await.unwrap();
+            assert_eq!(buf, [0x10 + i as u8; 64]);
+            log.read(i * 64, &mut buf).await.unwrap();
+            assert_eq!(buf, [0x80 + i as u8; 64]);
+        }
+        let eeprom = shared.lock().await;
+        assert_eq!(eeprom.i2c.memory()[0xFBFF], 0xFF);
+        assert_eq!(eeprom.i2c.memory()[0x10400], 0xFF);
+    }
+
+    #[tokio::test]
+    async fn handles_reject_access_outside_their_range() {
+        let shared = shared();
+        let mut handle = shared.handle().partition_handle(0x100..0x200).unwrap();
+        assert_eq!(ReadNorFlash::capacity(&handle), 0x100);
+        assert!(matches!(
+            handle.write(0xF0, &[0; 0x20]).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            handle.partition_handle(0..0x80),
+            Err(Error::NotAligned)
+        ));
+        NorFlash::write(&mut handle, 0x10, &[7]).await.unwrap();
+        assert_eq!(shared.lock().await.i2c.memory()[0x110], 7);
+    }
+}
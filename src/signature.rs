@@ -0,0 +1,288 @@
+//! Format signature and the signed block framing it is built on.
+//!
+//! A signed block is a small header, a payload and a CRC-32:
+//!
+//! | bytes     | content                                   |
+//! |-----------|-------------------------------------------|
+//! | 0..4      | magic, identifies what the block contains |
+//! | 4..6      | version                                   |
+//! | 6..8      | payload length                            |
+//! | 8..8+len  | payload                                   |
+//! | ..+4      | CRC-32 over everything before it          |
+//!
+//! All integers are little endian. A missing magic reads as blank, a bad CRC as corrupt, so
+//! applications can tell an uninitialized device from a damaged one.
+
+use crate::crc::crc32_update;
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Offset of the format signature. The whole first page is reserved for it.
+pub const SIGNATURE_OFFSET: u32 = 0;
+/// Header and CRC bytes added to every signed block
+pub const SIGNED_BLOCK_OVERHEAD: usize = HEADER_SIZE + 4;
+
+const SIGNATURE_MAGIC: [u8; 4] = *b"AT24";
+const HEADER_SIZE: usize = 8;
+
+/// What [`format`](At24Cx::format) does with the rest of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatWipe {
+    /// Only write the signature
+    Keep,
+    /// Fill everything after the signature page with the given value
+    Fill(u8),
+}
+
+/// Result of [`is_formatted`](At24Cx::is_formatted)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatInfo {
+    /// There is no signature, the device was never formatted
+    NotFormatted,
+    /// A valid signature with the layout version passed to `format`
+    Formatted { layout_version: u16 },
+    /// There is a signature but its CRC doesn't match
+    Corrupt,
+}
+
+/// Result of [`read_signed_block`](At24Cx::read_signed_block)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The magic is missing
+    Blank,
+    /// The magic is there but the block doesn't check out
+    Corrupt,
+    /// The payload of `len` bytes was read into the buffer
+    Valid { version: u16, len: usize },
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Writes the format signature with the given layout version, optionally filling the
+    /// rest of the device first. The old signature is invalidated before wiping so an
+    /// interrupted format never looks formatted.
+    pub async fn format(&mut self, layout_version: u16, wipe: FormatWipe) -> Result<(), Error<E>> {
+        if let FormatWipe::Fill(value) = wipe {
+            self.clear(SIGNATURE_OFFSET, SIGNATURE_MAGIC.len()).await?;
+            let start = SIGNATURE_OFFSET + PAGE_SIZE as u32;
+            let len = self.capacity() - start as usize;
+            self.fill(start, len, value).await?;
+        }
+        self.write_signed_block(SIGNATURE_OFFSET, SIGNATURE_MAGIC, layout_version, &[])
+            .await
+    }
+
+    /// Checks the format signature written by [`format`](Self::format).
+    pub async fn is_formatted(&mut self) -> Result<FormatInfo, Error<E>> {
+        match self
+            .read_signed_block(SIGNATURE_OFFSET, SIGNATURE_MAGIC, &mut [])
+            .await?
+        {
+            BlockStatus::Blank => Ok(FormatInfo::NotFormatted),
+            BlockStatus::Corrupt => Ok(FormatInfo::Corrupt),
+            BlockStatus::Valid { version, .. } => Ok(FormatInfo::Formatted {
+                layout_version: version,
+            }),
+        }
+    }
+
+    /// Writes `payload` framed as a signed block (see the [module docs](self)).
+    /// A block that fits in the rest of the [page](Self::set_page_size) it starts in is
+    /// programmed in a single write cycle, unless it is longer than the
+    /// [write length cap](Self::set_max_write_len).
+    pub async fn write_signed_block(
+        &mut self,
+        offset: u32,
+        magic: [u8; 4],
+        version: u16,
+        payload: &[u8],
+    ) -> Result<(), Error<E>> {
+        let len = u16::try_from(payload.len()).map_err(|_| Error::OutOfBounds)?;
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(&magic);
+        header[4..6].copy_from_slice(&version.to_le_bytes());
+        header[6..8].copy_from_slice(&len.to_le_bytes());
+        let crc = !crc32_update(crc32_update(!0, &header), payload);
+
+        let total = SIGNED_BLOCK_OVERHEAD + payload.len();
+        if offset as usize % self.page_size + total <= self.page_size {
+            let mut buf = [0; PAGE_SIZE];
+            buf[..HEADER_SIZE].copy_from_slice(&header);
+            buf[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+            buf[total - 4..total].copy_from_slice(&crc.to_le_bytes());
+            return self.write(offset, &buf[..total]).await;
+        }
+        // Validate up front so nothing is written if the block doesn't fit
+        self.can_write(offset, total)?;
+        self.write(offset, &header).await?;
+        self.write(offset + HEADER_SIZE as u32, payload).await?;
+        self.write(offset + (total - 4) as u32, &crc.to_le_bytes())
+            .await
+    }
+
+    /// Reads a signed block written by [`write_signed_block`](Self::write_signed_block),
+    /// placing its payload at the start of `buf`.
+    ///
+    /// Returns `OutOfBounds` if the stored payload is larger than `buf`.
+    pub async fn read_signed_block(
+        &mut self,
+        offset: u32,
+        magic: [u8; 4],
+        buf: &mut [u8],
+    ) -> Result<BlockStatus, Error<E>> {
+        let mut header = [0; HEADER_SIZE];
+        self.read(offset, &mut header).await?;
+        if header[0..4] != magic {
+            return Ok(BlockStatus::Blank);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        let len = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let total = SIGNED_BLOCK_OVERHEAD + len;
+        if offset as usize + total > self.capacity() {
+            return Ok(BlockStatus::Corrupt);
+        }
+        if len > buf.len() {
+            return Err(Error::OutOfBounds);
+        }
+        let payload = &mut buf[..len];
+        self.read(offset + HEADER_SIZE as u32, payload).await?;
+        let mut stored = [0; 4];
+        self.read(offset + (total - 4) as u32, &mut stored).await?;
+        let crc = !crc32_update(crc32_update(!0, &header), payload);
+        if crc != u32::from_le_bytes(stored) {
+            return Ok(BlockStatus::Corrupt);
+        }
+        Ok(BlockStatus::Valid { version, len })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, DeviceKind};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn format_round_trip() {
+        let mut eeprom = driver();
+        assert_eq!(
+            eeprom.is_formatted().await.unwrap(),
+            FormatInfo::NotFormatted
+        );
+        eeprom.format(3, FormatWipe::Keep).await.unwrap();
+        assert_eq!(
+            eeprom.is_formatted().await.unwrap(),
+            FormatInfo::Formatted { layout_version: 3 }
+        );
+        eeprom.format(4, FormatWipe::Keep).await.unwrap();
+        assert_eq!(
+            eeprom.is_formatted().await.unwrap(),
+            FormatInfo::Formatted { layout_version: 4 }
+        );
+    }
+
+    #[tokio::test]
+    async fn format_wipes_rest_of_device() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        bus.memory_mut().fill(0x42);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        eeprom.format(1, FormatWipe::Fill(0x00)).await.unwrap();
+        assert!(eeprom.i2c.memory()[PAGE_SIZE..].iter().all(|b| *b == 0));
+        assert_eq!(
+            eeprom.is_formatted().await.unwrap(),
+            FormatInfo::Formatted { layout_version: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn corrupt_signature_is_not_blank() {
+        let mut eeprom = driver();
+        eeprom.format(1, FormatWipe::Keep).await.unwrap();
+        eeprom.i2c.memory_mut()[4] ^= 0x80;
+        assert_eq!(eeprom.is_formatted().await.unwrap(), FormatInfo::Corrupt);
+        eeprom.i2c.memory_mut()[0] = 0xFF;
+        assert_eq!(
+            eeprom.is_formatted().await.unwrap(),
+            FormatInfo::NotFormatted
+        );
+    }
+
+    #[tokio::test]
+    async fn signed_block_spanning_pages() {
+        let mut eeprom = driver();
+        let payload: [u8; 600] = core::array::from_fn(|i| (i * 7) as u8);
+        eeprom
+            .write_signed_block(0x1F0, *b"TEST", 9, &payload)
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let status = eeprom
+            .read_signed_block(0x1F0, *b"TEST", &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(
+            status,
+            BlockStatus::Valid {
+                version: 9,
+                len: 600
+            }
+        );
+        assert_eq!(buf[..600], payload);
+
+        let status = eeprom
+            .read_signed_block(0x1F0, *b"XXXX", &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(status, BlockStatus::Blank);
+        let result = eeprom
+            .read_signed_block(0x1F0, *b"TEST", &mut buf[..10])
+            .await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+    }
+
+    #[tokio::test]
+    async fn signed_block_in_one_small_page() {
+        let address = Address::pins(1, 1, 1);
+        let bus = SimBus::new(address, 12).with_page_size(32);
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+        let payload = [0x42; 32 - SIGNED_BLOCK_OVERHEAD];
+        let transactions = eeprom.i2c.transaction_count();
+        eeprom
+            .write_signed_block(0x20, *b"TEST", 1, &payload)
+            .await
+            .unwrap();
+        // The write and one ACK poll
+        assert_eq!(eeprom.i2c.transaction_count() - transactions, 2);
+
+        // Doesn't fit in the rest of its page
+        eeprom
+            .write_signed_block(0x50, *b"TEST", 1, &payload)
+            .await
+            .unwrap();
+        let mut buf = [0; 32];
+        for offset in [0x20, 0x50] {
+            let status = eeprom
+                .read_signed_block(offset, *b"TEST", &mut buf)
+                .await
+                .unwrap();
+            assert_eq!(
+                status,
+                BlockStatus::Valid {
+                    version: 1,
+                    len: payload.len()
+                }
+            );
+            assert_eq!(buf[..payload.len()], payload);
+        }
+    }
+}
@@ -0,0 +1,859 @@
+//! An in-memory AT24Cx simulator for testing storage code on the host, without hardware.
+//!
+//! [`SimBus`] answers on the bus like the device would: it decodes the device address and
+//! the P0 bit, wraps writes within their page, NACKs addresses of absent devices and, if
+//! configured, NACKs for a while after every write cycle. Time is counted in ticks of a
+//! [`SimClock`], one per microsecond, which only advances when the paired [`SimDelay`]
+//! waits. Nothing depends on real time, so tests are deterministic.
+//!
+//! Recovery code is tested by installing [`Fault`]s with a [`SimBusBuilder`]. A fault fires
+//! once, at the transaction or write it names, or, if installed as persistent, at that one
+//! and every later one. [`fired`](SimBus::fired) tells which faults fired afterwards.
+//!
+//! ```
+//! # use at24cx::{sim::{SimBus, SimDelay}, Address, At24Cx};
+//! # use embedded_storage_async::nor_flash::NorFlash;
+//! # async fn test() {
+//! let bus = SimBus::new(Address(0, 0), 17).with_write_cycle(5000);
+//! let delay = SimDelay::new(bus.clock());
+//! let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, delay);
+//! eeprom.write(0x100, b"hello").await.unwrap();
+//! # }
+//! ```
+
+use crate::{DeviceAddress, ADDRESS_BYTES, PAGE_SIZE};
+use core::cell::Cell;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::vec;
+use std::vec::Vec;
+use std::{fs, io};
+
+/// Error of a [`SimBus`] transaction
+#[derive(Debug)]
+pub struct SimError(pub(crate) ErrorKind);
+
+impl Error for SimError {
+    fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
+/// Time of a simulation in ticks, shared between a [`SimBus`] and its [`SimDelay`]s
+#[derive(Debug, Clone, Default)]
+pub struct SimClock(Rc<Cell<u64>>);
+
+impl SimClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ticks elapsed so far
+    pub fn now(&self) -> u64 {
+        self.0.get()
+    }
+
+    /// Moves time forward
+    pub fn advance(&self, ticks: u64) {
+        self.0.set(self.0.get() + ticks);
+    }
+}
+
+/// A delay that advances a [`SimClock`] by one tick per microsecond instead of waiting
+#[derive(Debug, Clone)]
+pub struct SimDelay {
+    clock: SimClock,
+}
+
+impl SimDelay {
+    pub fn new(clock: SimClock) -> Self {
+        Self { clock }
+    }
+}
+
+impl DelayNs for SimDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.clock.advance(ns.div_ceil(1000) as u64);
+    }
+}
+
+#[cfg(feature = "sync")]
+impl embedded_hal::delay::DelayNs for SimDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.clock.advance(ns.div_ceil(1000) as u64);
+    }
+}
+
+/// Something going wrong on a [`SimBus`]. Transactions and data writes are counted from 0
+/// since the bus was created, a data write being a write transaction that programs bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The device doesn't acknowledge the `transaction`th transaction
+    Nack { transaction: usize },
+    /// The device doesn't acknowledge the data of the `write`th data write, which programs
+    /// nothing
+    FailWrite { write: usize },
+    /// The bits in `mask` of the byte at `offset` flip after a data write programmed it
+    BitError { offset: usize, mask: u8 },
+    /// The write cycle of the `write`th data write never completes, the device stays busy
+    /// until power is restored
+    StuckBusy { write: usize },
+    /// Power is lost after `bytes` bytes of the `write`th data write were programmed,
+    /// leaving the page partially written until power is restored
+    PowerLoss { write: usize, bytes: usize },
+    /// The device doesn't answer for `count` transactions, starting with the
+    /// `transaction`th. Persistent, it never comes back.
+    Absent { transaction: usize, count: usize },
+}
+
+/// A fault that fired, during the `transaction`th transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FiredFault {
+    pub fault: Fault,
+    pub transaction: usize,
+}
+
+struct InstalledFault {
+    fault: Fault,
+    persistent: bool,
+    spent: bool,
+}
+
+impl InstalledFault {
+    /// Whether the fault fires at transaction `transaction`, during data write `write` if
+    /// there is one
+    fn fires(&self, transaction: usize, write: Option<usize>) -> bool {
+        let at = |n: usize, i: usize| if self.persistent { i >= n } else { i == n };
+        match self.fault {
+            Fault::Nack { transaction: n } => write.is_none() && at(n, transaction),
+            Fault::FailWrite { write: n }
+            | Fault::StuckBusy { write: n }
+            | Fault::PowerLoss { write: n, .. } => write.is_some_and(|w| at(n, w)),
+            Fault::BitError { .. } => !self.spent && write.is_some(),
+            Fault::Absent {
+                transaction: n,
+                count,
+            } => {
+                write.is_none() && transaction >= n && (self.persistent || transaction - n < count)
+            }
+        }
+    }
+}
+
+/// Sets up a [`SimBus`] with faults, see [`SimBus::builder`]
+pub struct SimBusBuilder {
+    bus: SimBus,
+}
+
+impl SimBusBuilder {
+    /// See [`SimBus::with_endurance`]
+    pub fn endurance(mut self, cycles: u32) -> Self {
+        self.bus.endurance = Some(cycles);
+        self
+    }
+
+    /// See [`SimBus::with_write_cycle`]
+    pub fn write_cycle(mut self, ticks: u64) -> Self {
+        self.bus.write_cycle = ticks;
+        self
+    }
+
+    /// See [`SimBus::with_page_size`]
+    pub fn page_size(mut self, bytes: usize) -> Self {
+        self.bus.page_size = bytes;
+        self
+    }
+
+    /// Installs a fault that fires once
+    pub fn fault(mut self, fault: Fault) -> Self {
+        self.bus.inject(fault, false);
+        self
+    }
+
+    /// Makes the fault installed last persistent
+    pub fn persistent(mut self) -> Self {
+        if let Some(installed) = self.bus.faults.last_mut() {
+            installed.persistent = true;
+        }
+        self
+    }
+
+    /// [`Fault::Nack`]
+    pub fn nack(self, transaction: usize) -> Self {
+        self.fault(Fault::Nack { transaction })
+    }
+
+    /// [`Fault::FailWrite`]
+    pub fn fail_write(self, write: usize) -> Self {
+        self.fault(Fault::FailWrite { write })
+    }
+
+    /// [`Fault::BitError`]
+    pub fn bit_error(self, offset: usize, mask: u8) -> Self {
+        self.fault(Fault::BitError { offset, mask })
+    }
+
+    /// [`Fault::StuckBusy`]
+    pub fn stuck_busy(self, write: usize) -> Self {
+        self.fault(Fault::StuckBusy { write })
+    }
+
+    /// [`Fault::PowerLoss`]
+    pub fn power_loss(self, write: usize, bytes: usize) -> Self {
+        self.fault(Fault::PowerLoss { write, bytes })
+    }
+
+    /// [`Fault::Absent`]
+    pub fn absent(self, transaction: usize, count: usize) -> Self {
+        self.fault(Fault::Absent { transaction, count })
+    }
+
+    pub fn build(self) -> SimBus {
+        self.bus
+    }
+}
+
+/// An image that didn't have the size of the device, see [`SimBus::load_image`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageWarning {
+    /// The image had only `len` bytes, the rest of the device is zeroed
+    Short { len: usize },
+    /// The image had `len` bytes, those past the end of the device were dropped
+    Long { len: usize },
+}
+
+/// Emulates the memory array of an AT24Cx on the bus.
+///
+/// Every cell counts how often it was programmed. Once a cell exceeds the configured
+/// endurance it is stuck and keeps its last value. A power cut can be scheduled to happen
+/// after a number of programmed bytes, after which every transaction fails until power is
+/// restored. More faults are installed with [`builder`](Self::builder) or
+/// [`inject`](Self::inject).
+pub struct SimBus {
+    base_address: u8,
+    memory: Vec<u8>,
+    writes: Vec<u32>,
+    endurance: Option<u32>,
+    pointer: usize,
+    power_budget: Option<usize>,
+    powered_off: bool,
+    clock: SimClock,
+    write_cycle: u64,
+    page_size: usize,
+    busy_until: u64,
+    faults: Vec<InstalledFault>,
+    fired: Vec<FiredFault>,
+    transactions: usize,
+    data_writes: usize,
+    file: Option<PathBuf>,
+}
+
+impl SimBus {
+    /// A blank device of `address_bits` bits at `address`
+    pub fn new(address: impl Into<DeviceAddress>, address_bits: usize) -> Self {
+        let address: DeviceAddress = address.into();
+        let capacity = 1 << address_bits;
+        Self {
+            base_address: address.into(),
+            memory: vec![0xFF; capacity],
+            writes: vec![0; capacity],
+            endurance: None,
+            pointer: 0,
+            power_budget: None,
+            powered_off: false,
+            clock: SimClock::new(),
+            write_cycle: 0,
+            page_size: PAGE_SIZE,
+            busy_until: 0,
+            faults: Vec::new(),
+            fired: Vec::new(),
+            transactions: 0,
+            data_writes: 0,
+            file: None,
+        }
+    }
+
+    /// A device whose memory is kept in the raw image file at `path`, for tests spanning
+    /// several processes. The file is created blank if it doesn't exist and written back by
+    /// [`sync`](Self::sync) and when the bus is dropped. A file of the wrong size is loaded
+    /// like [`load_image`](Self::load_image) does and resized on the next sync.
+    pub fn open_file(
+        path: impl AsRef<Path>,
+        address: impl Into<DeviceAddress>,
+        address_bits: usize,
+    ) -> io::Result<(Self, Option<ImageWarning>)> {
+        let path = path.as_ref();
+        let mut bus = Self::new(address, address_bits);
+        let warning = match fs::read(path) {
+            Ok(image) => bus.load_image(&image),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::write(path, &bus.memory)?;
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        bus.file = Some(path.to_path_buf());
+        Ok((bus, warning))
+    }
+
+    /// Writes the memory to the file the bus was [opened](Self::open_file) from, if any
+    pub fn sync(&self) -> io::Result<()> {
+        match &self.file {
+            Some(path) => fs::write(path, &self.memory),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the memory with `image`. A short image is zero-extended to the size of the
+    /// device, a long one is cut off at the end of the device, either is reported.
+    pub fn load_image(&mut self, image: &[u8]) -> Option<ImageWarning> {
+        let len = image.len().min(self.memory.len());
+        self.memory[..len].copy_from_slice(&image[..len]);
+        self.memory[len..].fill(0);
+        match image.len() {
+            len if len < self.memory.len() => Some(ImageWarning::Short { len }),
+            len if len > self.memory.len() => Some(ImageWarning::Long { len }),
+            _ => None,
+        }
+    }
+
+    /// A copy of the memory
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.memory.clone()
+    }
+
+    /// Starts setting up a device like [`new`](Self::new) with faults
+    pub fn builder(address: impl Into<DeviceAddress>, address_bits: usize) -> SimBusBuilder {
+        SimBusBuilder {
+            bus: Self::new(address, address_bits),
+        }
+    }
+
+    /// Installs a fault on a running bus, counting from the transactions and writes so far
+    pub fn inject(&mut self, fault: Fault, persistent: bool) {
+        self.faults.push(InstalledFault {
+            fault,
+            persistent,
+            spent: false,
+        });
+    }
+
+    /// The faults that fired so far, in order
+    pub fn fired(&self) -> &[FiredFault] {
+        &self.fired
+    }
+
+    /// Transactions run so far, including those that failed
+    pub fn transaction_count(&self) -> usize {
+        self.transactions
+    }
+
+    /// After every write the device NACKs for `ticks` ticks of its [clock](Self::clock),
+    /// like the internal write cycle of a real part. Defaults to 0, always ready.
+    pub fn with_write_cycle(mut self, ticks: u64) -> Self {
+        self.write_cycle = ticks;
+        self
+    }
+
+    /// Writes roll over within pages of `bytes` bytes instead of [`PAGE_SIZE`], like a
+    /// smaller part of the family does. The driver still writes up to `PAGE_SIZE` bytes at
+    /// once.
+    pub fn with_page_size(mut self, bytes: usize) -> Self {
+        self.page_size = bytes;
+        self
+    }
+
+    /// The clock the write cycle is timed with, to hand to a [`SimDelay`]
+    pub fn clock(&self) -> SimClock {
+        self.clock.clone()
+    }
+
+    /// Cells accept `cycles` writes, after which they keep their value.
+    pub fn with_endurance(mut self, cycles: u32) -> Self {
+        self.endurance = Some(cycles);
+        self
+    }
+
+    /// The memory array
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The memory array, to preload or corrupt it behind the driver's back
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// How often the cell at `offset` was programmed
+    pub fn write_count(&self, offset: usize) -> u32 {
+        self.writes[offset]
+    }
+
+    /// Pretends a cell has already been programmed `count` times.
+    pub fn set_write_count(&mut self, offset: usize, count: u32) {
+        self.writes[offset] = count;
+    }
+
+    /// Loses power once `bytes` more bytes have been programmed, in the middle of a write.
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.power_budget = Some(bytes);
+    }
+
+    /// Powers the device back up after a [power cut](Self::cut_power_after)
+    pub fn restore_power(&mut self) {
+        self.power_budget = None;
+        self.powered_off = false;
+        self.busy_until = 0;
+    }
+
+    /// Fires the faults of the given kind due now, returning the first
+    fn fire(&mut self, write: Option<usize>, kind: impl Fn(&Fault) -> bool) -> Option<Fault> {
+        let transaction = self.transactions - 1;
+        let mut first = None;
+        for installed in &mut self.faults {
+            if kind(&installed.fault) && installed.fires(transaction, write) {
+                installed.spent = !installed.persistent;
+                self.fired.push(FiredFault {
+                    fault: installed.fault,
+                    transaction,
+                });
+                first = first.or(Some(installed.fault));
+            }
+        }
+        first
+    }
+
+    fn block(&self, address: u8) -> Option<usize> {
+        let blocks = self.memory.len().div_ceil(1 << 16) as u8;
+        let block = address.wrapping_sub(self.base_address);
+        (block < blocks).then_some(block as usize)
+    }
+
+    fn program(&mut self, start: usize, data: &[u8]) {
+        let page = start - start % self.page_size;
+        for (i, byte) in data.iter().enumerate() {
+            if let Some(budget) = &mut self.power_budget {
+                if *budget == 0 {
+                    self.powered_off = true;
+                    return;
+                }
+                *budget -= 1;
+            }
+            // Writes roll over within the page
+            let offset = page + (start + i) % self.page_size;
+            self.writes[offset] += 1;
+            if self.endurance.map_or(true, |e| self.writes[offset] <= e) {
+                self.memory[offset] = *byte;
+            }
+        }
+    }
+}
+
+impl Drop for SimBus {
+    fn drop(&mut self) {
+        // Nowhere to report a failure, call `sync` first to see it
+        let _ = self.sync();
+    }
+}
+
+impl ErrorType for SimBus {
+    type Error = SimError;
+}
+
+impl I2c for SimBus {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run(address, operations)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl embedded_hal::i2c::I2c for SimBus {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run(address, operations)
+    }
+}
+
+impl SimBus {
+    /// Programs `data` at the pointer, applying the faults due for this data write
+    fn data_write(&mut self, data: &[u8]) -> Result<(), SimError> {
+        let write = Some(self.data_writes);
+        self.data_writes += 1;
+        if self
+            .fire(write, |f| matches!(f, Fault::FailWrite { .. }))
+            .is_some()
+        {
+            return Err(SimError(ErrorKind::NoAcknowledge(
+                NoAcknowledgeSource::Data,
+            )));
+        }
+        let power_loss = self.fire(write, |f| matches!(f, Fault::PowerLoss { .. }));
+        if let Some(Fault::PowerLoss { bytes, .. }) = power_loss {
+            self.power_budget = Some(bytes);
+        }
+        self.busy_until = self.clock.now() + self.write_cycle;
+        if self
+            .fire(write, |f| matches!(f, Fault::StuckBusy { .. }))
+            .is_some()
+        {
+            self.busy_until = u64::MAX;
+        }
+        let start = self.pointer;
+        self.program(start, data);
+        if power_loss.is_some() {
+            // Also when the write was shorter than the bytes it was cut after
+            self.power_budget = None;
+            self.powered_off = true;
+        }
+        if self.powered_off {
+            return Err(SimError(ErrorKind::Other));
+        }
+
+        // Bit errors on the bytes that were just programmed
+        let page_size = self.page_size;
+        let page = start - start % page_size;
+        let programmed = |offset: usize| {
+            offset >= page
+                && offset < page + page_size
+                && (offset + page_size - start) % page_size < data.len()
+        };
+        let transaction = self.transactions - 1;
+        for installed in &mut self.faults {
+            if let Fault::BitError { offset, mask } = installed.fault {
+                if installed.fires(transaction, write) && programmed(offset) {
+                    installed.spent = !installed.persistent;
+                    self.memory[offset] ^= mask;
+                    self.fired.push(FiredFault {
+                        fault: installed.fault,
+                        transaction,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn run(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), SimError> {
+        self.transactions += 1;
+        if self.powered_off {
+            return Err(SimError(ErrorKind::Other));
+        }
+        let nack = SimError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        let Some(block) = self.block(address) else {
+            return Err(nack);
+        };
+        if self
+            .fire(None, |f| {
+                matches!(f, Fault::Nack { .. } | Fault::Absent { .. })
+            })
+            .is_some()
+        {
+            return Err(nack);
+        }
+        // Still busy with the last write cycle
+        if self.clock.now() < self.busy_until {
+            return Err(nack);
+        }
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    if bytes.len() < ADDRESS_BYTES {
+                        continue;
+                    }
+                    let offset = (block << 16)
+                        | (bytes[..ADDRESS_BYTES]
+                            .iter()
+                            .fold(0, |acc, b| (acc << 8) | *b as usize));
+                    self.pointer = offset % self.memory.len();
+                    if bytes.len() > ADDRESS_BYTES {
+                        self.data_write(&bytes[ADDRESS_BYTES..])?;
+                    }
+                }
+                Operation::Read(buffer) => {
+                    for byte in buffer.iter_mut() {
+                        *byte = self.memory[self.pointer];
+                        self.pointer = (self.pointer + 1) % self.memory.len();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Address, At24Cx, Error as EepromError};
+    use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+    fn driver(write_cycle: u64) -> At24Cx<SimBus, SimDelay> {
+        let bus = SimBus::new(Address(0, 0), 17).with_write_cycle(write_cycle);
+        let delay = SimDelay::new(bus.clock());
+        At24Cx::new(bus, Address(0, 0), 17, delay)
+    }
+
+    #[tokio::test]
+    async fn nacks_during_the_write_cycle() {
+        let mut eeprom = driver(5000);
+        let clock = eeprom.i2c.clock();
+        I2c::write(&mut eeprom.i2c, 0x50, &[0x00, 0x40, 1, 2, 3])
+            .await
+            .unwrap();
+        assert!(!eeprom.is_ready().await.unwrap());
+        clock.advance(4999);
+        assert!(!eeprom.is_ready().await.unwrap());
+        clock.advance(1);
+        assert!(eeprom.is_ready().await.unwrap());
+
+        // A write over three pages polls through two write cycles
+        let start = clock.now();
+        eeprom.write(0x1F0, &[0xA5; 0x120]).await.unwrap();
+        assert!(clock.now() - start >= 10000);
+        assert!(eeprom.i2c.memory()[0x1F0..0x310].iter().all(|&b| b == 0xA5));
+    }
+
+    #[tokio::test]
+    async fn nacks_when_absent() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(1, 1), 17, SimDelay::new(SimClock::new()));
+        assert!(matches!(
+            eeprom.read(0, &mut [0; 4]).await,
+            Err(EepromError::NoAcknowledge(SimError(
+                ErrorKind::NoAcknowledge(_)
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn writes_across_page_boundaries() {
+        let mut eeprom = driver(5000);
+        let data: Vec<u8> = (0..40).collect();
+        eeprom.write(0xF0, &data).await.unwrap();
+        assert_eq!(eeprom.i2c.memory()[0xF0..0x118], data[..]);
+        assert_eq!(eeprom.i2c.write_count(0xEF), 0);
+        assert_eq!(eeprom.i2c.write_count(0x118), 0);
+
+        // A raw page write rolls over to the start of its page
+        eeprom.page_write(0x2FE, &[7, 8, 9, 10]).await.unwrap();
+        assert_eq!(eeprom.i2c.memory()[0x2FE..0x300], [7, 8]);
+        assert_eq!(eeprom.i2c.memory()[0x200..0x202], [9, 10]);
+    }
+
+    #[tokio::test]
+    async fn writes_the_end_of_the_array() {
+        let mut eeprom = driver(5000);
+        eeprom.write(0x1FFFC, &[1, 2, 3, 4]).await.unwrap();
+        assert_eq!(eeprom.i2c.memory()[0x1FFFC..], [1, 2, 3, 4]);
+        assert!(matches!(
+            eeprom.write(0x1FFFD, &[1, 2, 3, 4]).await,
+            Err(EepromError::OutOfBounds)
+        ));
+        assert_eq!(eeprom.i2c.memory()[0], 0xFF);
+    }
+
+    #[tokio::test]
+    async fn reads_across_the_64k_boundary() {
+        let mut eeprom = driver(0);
+        for (i, byte) in eeprom.i2c.memory_mut().iter_mut().enumerate() {
+            *byte = (i % 253) as u8;
+        }
+        let mut buf = [0; 32];
+        eeprom.read(0xFFF0, &mut buf).await.unwrap();
+        assert_eq!(buf[..], eeprom.i2c.memory()[0xFFF0..0x10010]);
+    }
+
+    fn faulty(builder: SimBusBuilder) -> At24Cx<SimBus, SimDelay> {
+        let bus = builder.build();
+        let delay = SimDelay::new(bus.clock());
+        At24Cx::new(bus, Address(0, 0), 17, delay)
+    }
+
+    #[tokio::test]
+    async fn nacks_the_nth_transaction() {
+        let mut eeprom = faulty(SimBus::builder(Address(0, 0), 17).nack(1));
+        let mut buf = [0; 4];
+        eeprom.read(0, &mut buf).await.unwrap();
+        assert!(matches!(
+            eeprom.read(0, &mut buf).await,
+            Err(EepromError::NoAcknowledge(_))
+        ));
+        eeprom.read(0, &mut buf).await.unwrap();
+        assert_eq!(
+            eeprom.i2c.fired(),
+            [FiredFault {
+                fault: Fault::Nack { transaction: 1 },
+                transaction: 1
+            }]
+        );
+
+        let mut eeprom = faulty(SimBus::builder(Address(0, 0), 17).nack(1).persistent());
+        eeprom.read(0, &mut buf).await.unwrap();
+        for _ in 0..3 {
+            assert!(eeprom.read(0, &mut buf).await.is_err());
+        }
+        assert_eq!(eeprom.i2c.fired().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn fails_and_corrupts_writes() {
+        let mut eeprom = faulty(
+            SimBus::builder(Address(0, 0), 17)
+                .fail_write(0)
+                .bit_error(0x105, 0x81),
+        );
+        assert!(matches!(
+            eeprom.write(0x100, &[0; 8]).await,
+            Err(EepromError::NoAcknowledge(SimError(
+                ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data)
+            )))
+        ));
+        assert_eq!(eeprom.i2c.write_count(0x100), 0);
+
+        // Only the write that programs the byte flips it, and only once
+        eeprom.write(0x180, &[0; 8]).await.unwrap();
+        eeprom.write(0x100, &[0; 8]).await.unwrap();
+        assert_eq!(
+            eeprom.i2c.memory()[0x100..0x108],
+            [0, 0, 0, 0, 0, 0x81, 0, 0]
+        );
+        eeprom.write(0x100, &[0; 8]).await.unwrap();
+        assert_eq!(eeprom.i2c.memory()[0x105], 0);
+        let fired: Vec<Fault> = eeprom.i2c.fired().iter().map(|f| f.fault).collect();
+        assert_eq!(
+            fired,
+            [
+                Fault::FailWrite { write: 0 },
+                Fault::BitError {
+                    offset: 0x105,
+                    mask: 0x81
+                }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn never_finishes_a_stuck_write_cycle() {
+        let mut eeprom = faulty(SimBus::builder(Address(0, 0), 17).stuck_busy(1));
+        eeprom.write(0, &[1]).await.unwrap();
+        assert!(matches!(
+            eeprom.write(1, &[2]).await,
+            Err(EepromError::WriteAckTimeout)
+        ));
+        assert!(!eeprom.is_ready().await.unwrap());
+        eeprom.i2c.restore_power();
+        assert!(eeprom.is_ready().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn loses_power_in_the_middle_of_a_page() {
+        let mut eeprom = faulty(SimBus::builder(Address(0, 0), 17).power_loss(1, 3));
+        eeprom.write(0, &[1; 8]).await.unwrap();
+        assert!(eeprom.write(0x10, &[2; 8]).await.is_err());
+        assert!(eeprom.read(0x10, &mut [0; 8]).await.is_err());
+        eeprom.i2c.restore_power();
+        assert_eq!(
+            eeprom.i2c.memory()[0x10..0x18],
+            [2, 2, 2, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+    }
+
+    #[tokio::test]
+    async fn disappears_and_comes_back() {
+        let mut eeprom = faulty(SimBus::builder(Address(0, 0), 17).absent(1, 2));
+        let mut buf = [0; 1];
+        eeprom.read(0, &mut buf).await.unwrap();
+        assert!(eeprom.read(0, &mut buf).await.is_err());
+        assert!(eeprom.read(0, &mut buf).await.is_err());
+        eeprom.read(0, &mut buf).await.unwrap();
+        assert_eq!(eeprom.i2c.fired().len(), 2);
+
+        // Installed on a running bus, persistently
+        let count = eeprom.i2c.transaction_count();
+        eeprom.i2c.inject(
+            Fault::Absent {
+                transaction: count,
+                count: 0,
+            },
+            true,
+        );
+        for _ in 0..3 {
+            assert!(eeprom.read(0, &mut buf).await.is_err());
+        }
+    }
+
+    /// A path in the temp directory that's removed again when dropped
+    struct TempImage(PathBuf);
+
+    impl TempImage {
+        fn new(name: &str) -> Self {
+            let file = std::format!("at24cx-{}-{name}.bin", std::process::id());
+            Self(std::env::temp_dir().join(file))
+        }
+    }
+
+    impl Drop for TempImage {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn survives_a_reboot_in_a_file() {
+        let image = TempImage::new("reboot");
+        // First boot provisions the device
+        {
+            let (bus, warning) = SimBus::open_file(&image.0, Address(0, 0), 17).unwrap();
+            assert_eq!(warning, None);
+            let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, SimDelay::new(SimClock::new()));
+            eeprom.write(0xFFFE, b"provisioned").await.unwrap();
+        }
+        let raw = fs::read(&image.0).unwrap();
+        assert_eq!(raw.len(), 0x20000);
+        assert_eq!(&raw[0xFFFE..0x10009], b"provisioned");
+
+        // The second boot sees the first one's writes
+        let (bus, warning) = SimBus::open_file(&image.0, Address(0, 0), 17).unwrap();
+        assert_eq!(warning, None);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, SimDelay::new(SimClock::new()));
+        let mut buf = [0; 11];
+        eeprom.read(0xFFFE, &mut buf).await.unwrap();
+        assert_eq!(&buf, b"provisioned");
+        eeprom.write(0, &[1, 2, 3]).await.unwrap();
+        eeprom.i2c.sync().unwrap();
+        assert_eq!(fs::read(&image.0).unwrap()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn loads_images_of_the_wrong_size() {
+        let image = TempImage::new("short");
+        fs::write(&image.0, [0xA5; 100]).unwrap();
+        let (bus, warning) = SimBus::open_file(&image.0, Address(0, 0), 16).unwrap();
+        assert_eq!(warning, Some(ImageWarning::Short { len: 100 }));
+        assert_eq!(bus.memory()[99..101], [0xA5, 0]);
+        assert!(bus.memory()[100..].iter().all(|&b| b == 0));
+        drop(bus);
+        assert_eq!(fs::read(&image.0).unwrap().len(), 0x10000);
+
+        let mut bus = SimBus::new(Address(0, 0), 16);
+        assert_eq!(
+            bus.load_image(&[0x5A; 0x10001]),
+            Some(ImageWarning::Long { len: 0x10001 })
+        );
+        assert_eq!(bus.snapshot(), [0x5A; 0x10000]);
+        assert_eq!(bus.load_image(&[0; 0x10000]), None);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+
+/// Bytes of bookkeeping stored in the staging buffer for every staged write
+pub const STAGE_OVERHEAD: usize = 6;
+
+/// Writes collected in RAM and written to the device in one pass by
+/// [`commit`](Self::commit).
+///
+/// The staging buffer holds `N` bytes. Every call to [`stage`](Self::stage) takes the
+/// staged data plus [`STAGE_OVERHEAD`] bytes of it, or only the data when it directly
+/// continues the previous call. Writes are committed in the order they were staged, so a
+/// later write to the same bytes wins. Nothing guards against a power loss in the middle
+/// of a commit, that's up to the layers built on top of this.
+pub struct StagedWrite<'a, I2C, D, const N: usize> {
+    eeprom: &'a mut At24Cx<I2C, D>,
+    buffer: Vec<u8, N>,
+    /// Start of the last staged write within `buffer`
+    last: Option<usize>,
+}
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Starts collecting writes in a staging buffer of `N` bytes
+    pub fn staged_write<const N: usize>(&mut self) -> StagedWrite<'_, I2C, D, N> {
+        StagedWrite {
+            eeprom: self,
+            buffer: Vec::new(),
+            last: None,
+        }
+    }
+}
+
+impl<I2C, E: Debug, D: DelayNs, const N: usize> StagedWrite<'_, I2C, D, N>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Adds `data` at `offset` to the staged writes.
+    /// Returns `OutOfBounds` for writes past the end of the device and `Full` if the
+    /// staging buffer has no room left, in which case nothing is staged.
+    pub fn stage(&mut self, offset: u32, data: &[u8]) -> Result<(), Error<E>> {
+        if offset as usize + data.len() > self.eeprom.capacity() || data.len() > u16::MAX as usize {
+            return Err(Error::OutOfBounds);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        if let Some(last) = self.last {
+            let (start, len) = header(&self.buffer[last..]);
+            if start + len as u32 == offset && len as usize + data.len() <= u16::MAX as usize {
+                self.buffer
+                    .extend_from_slice(data)
+                    .map_err(|_| Error::Full)?;
+                let len = len + data.len() as u16;
+                self.buffer[last + 4..last + STAGE_OVERHEAD].copy_from_slice(&len.to_le_bytes());
+                return Ok(());
+            }
+        }
+        if self.buffer.len() + STAGE_OVERHEAD + data.len() > N {
+            return Err(Error::Full);
+        }
+        self.last = Some(self.buffer.len());
+        // Can't fail after the check above
+        let _ = self.buffer.extend_from_slice(&offset.to_le_bytes());
+        let _ = self
+            .buffer
+            .extend_from_slice(&(data.len() as u16).to_le_bytes());
+        let _ = self.buffer.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Number of bytes in use in the staging buffer
+    pub fn staged_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Writes all staged data to the device and empties the staging buffer.
+    /// On an error the staged writes are kept, so the commit can be retried.
+    pub async fn commit(&mut self) -> Result<(), Error<E>> {
+        let mut rest = &self.buffer[..];
+        while !rest.is_empty() {
+            let (offset, len) = header(rest);
+            let end = STAGE_OVERHEAD + len as usize;
+            self.eeprom
+                .write(offset, &rest[STAGE_OVERHEAD..end])
+                .await?;
+            rest = &rest[end..];
+        }
+        self.rollback();
+        Ok(())
+    }
+
+    /// Discards all staged writes
+    pub fn rollback(&mut self) {
+        self.buffer.clear();
+        self.last = None;
+    }
+}
+
+/// Offset and length of the staged write at the start of `bytes`
+fn header(bytes: &[u8]) -> (u32, u16) {
+    (
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let bus = SimBus::new(Address(0, 0), 17);
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn commit_writes_in_order() {
+        let mut eeprom = driver();
+        let mut staged = eeprom.staged_write::<64>();
+        staged.stage(0xF8, &[1; 8]).unwrap();
+        // Continues the previous write, so it takes no extra overhead
+        staged.stage(0x100, &[2; 8]).unwrap();
+        assert_eq!(staged.staged_len(), STAGE_OVERHEAD + 16);
+        staged.stage(0x104, &[3; 2]).unwrap();
+        assert!(matches!(
+            staged.stage(0x1FFFF, &[0; 2]),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(staged.stage(0x200, &[0; 32]), Err(Error::Full)));
+        staged.commit().await.unwrap();
+        assert_eq!(staged.staged_len(), 0);
+
+        let memory = eeprom.i2c.memory();
+        assert_eq!(memory[0xF8..0x100], [1; 8]);
+        assert_eq!(memory[0x100..0x108], [2, 2, 2, 2, 3, 3, 2, 2]);
+        assert_eq!(memory[0x200], 0xFF);
+    }
+
+    #[tokio::test]
+    async fn rollback_discards() {
+        let mut eeprom = driver();
+        let mut staged = eeprom.staged_write::<32>();
+        staged.stage(0x10, &[1; 4]).unwrap();
+        staged.rollback();
+        staged.stage(0x20, &[2; 4]).unwrap();
+        staged.commit().await.unwrap();
+        assert_eq!(eeprom.i2c.memory()[0x10], 0xFF);
+        assert_eq!(eeprom.i2c.memory()[0x20..0x24], [2; 4]);
+    }
+}
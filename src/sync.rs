@@ -0,0 +1,339 @@
+//! Blocking `embedded-storage` traits, for bootloaders and other code without an executor.
+//!
+//! With a blocking `embedded-hal` bus and delay the driver implements the synchronous
+//! [`ReadNorFlash`] and [`NorFlash`] traits. Addressing, page chunking, bad-page remapping,
+//! the [`ReadMethod`], the [`AckProbe`], [read padding](At24Cx::set_min_read_len) and
+//! [page aligned writes](At24Cx::set_page_aligned_writes) work like they do for the async
+//! traits. Every page write is ACK polled before the next one, the
+//! [unverified write limit](At24Cx::set_max_unverified_writes) only applies to async writes.
+
+use crate::{
+    memory_address_bytes, AckProbe, At24Cx, DelayGranularity, Error, ReadMethod, ADDRESS_BYTES,
+    HALF_SEAM, MAX_MIN_READ_LEN, PAGE_SIZE,
+};
+use core::cmp::min;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{Error as _, ErrorKind, I2c};
+use embedded_storage::nor_flash::{check_erase, check_read, check_write, NorFlash, ReadNorFlash};
+
+impl<I2C: I2c, D: DelayNs> At24Cx<I2C, D> {
+    /// A single blocking read transaction, without bad-page remapping or bounds checks
+    fn read_unmapped_blocking(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        let Some((start, skip)) = self.padded_read(offset, bytes.len()) else {
+            return self.read_transfer_blocking(offset, bytes);
+        };
+        let mut padded = [0; MAX_MIN_READ_LEN];
+        let padded = &mut padded[..self.min_read_len];
+        self.read_transfer_blocking(start, padded)?;
+        bytes.copy_from_slice(&padded[skip..skip + bytes.len()]);
+        Ok(())
+    }
+
+    fn read_transfer_blocking(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        if let Some(lower_len) = self.below_seam(offset, bytes.len()) {
+            let (lower, upper) = bytes.split_at_mut(lower_len);
+            self.read_transaction_blocking(offset, lower)?;
+            return self.read_transaction_blocking(HALF_SEAM, upper);
+        }
+        self.read_transaction_blocking(offset, bytes)
+    }
+
+    fn read_transaction_blocking(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        let device_address = self.device_address(offset).ok_or(Error::OutOfBounds)?;
+        let memaddr = memory_address_bytes(offset);
+        self.guard_delay_blocking();
+        match self.read_method {
+            ReadMethod::CombinedWriteRead => self
+                .i2c
+                .write_read(device_address, &memaddr, bytes)
+                .map_err(Error::from),
+            ReadMethod::WriteStopRead => {
+                self.i2c
+                    .write(device_address, &memaddr)
+                    .map_err(Error::from)?;
+                self.guard_delay_blocking();
+                self.i2c.read(device_address, bytes).map_err(Error::from)
+            }
+        }
+    }
+
+    /// Writes within a single page, in write cycles of at most the
+    /// [write length cap](At24Cx::set_max_write_len)
+    fn page_write_blocking(&mut self, address: u32, data: &[u8]) -> Result<(), Error<I2C::Error>> {
+        let page_size = self.page_size as u32;
+        let page_start = address - address % page_size;
+        let mut lead = address % page_size;
+        for chunk in data.chunks(self.max_write_len) {
+            self.write_cycle_blocking(page_start + lead, chunk)?;
+            lead = (lead + chunk.len() as u32) % page_size;
+        }
+        Ok(())
+    }
+
+    /// Writes within a single page and waits for the write cycle to finish
+    fn write_cycle_blocking(&mut self, address: u32, data: &[u8]) -> Result<(), Error<I2C::Error>> {
+        self.check_writable()?;
+        let offset = address;
+        let address = self.remap_write(address).ok_or(Error::BadPage)?;
+        let dev_addr = self.device_address(address).ok_or(Error::OutOfBounds)?;
+        let mut payload = [0; ADDRESS_BYTES + PAGE_SIZE];
+        payload[..ADDRESS_BYTES].copy_from_slice(&memory_address_bytes(address));
+        payload[ADDRESS_BYTES..ADDRESS_BYTES + data.len()].copy_from_slice(data);
+        self.guard_delay_blocking();
+        self.i2c
+            .write(dev_addr, &payload[..ADDRESS_BYTES + data.len()])
+            .map_err(Error::from)?;
+        self.record_written(offset, data.len());
+
+        for _ in 0..self.poll_max_retries {
+            self.guard_delay_blocking();
+            let result = match self.ack_probe {
+                AckProbe::Write => self.i2c.write(dev_addr, &[0]),
+                AckProbe::Read => self.i2c.read(dev_addr, &mut [0]),
+                AckProbe::Quick => self.i2c.write(dev_addr, &[]),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => {}
+                Err(e) => return Err(Error::from(e)),
+            }
+            self.poll_delay_blocking();
+        }
+        Err(Error::WriteAckTimeout)
+    }
+
+    /// Waits the poll delay with the configured granularity
+    fn poll_delay_blocking(&mut self) {
+        let units = self.delay_granularity.units(self.poll_delay_ns);
+        match self.delay_granularity {
+            DelayGranularity::Nanos => self.delay.delay_ns(units),
+            DelayGranularity::Micros => self.delay.delay_us(units),
+            DelayGranularity::Millis => self.delay.delay_ms(units),
+        }
+    }
+
+    /// Waits the [inter-operation delay](Self::set_inter_op_delay_us) before a transaction
+    fn guard_delay_blocking(&mut self) {
+        if self.inter_op_delay_us > 0 {
+            self.delay.delay_us(self.inter_op_delay_us);
+        }
+    }
+}
+
+impl<I2C: I2c, D: DelayNs> At24Cx<I2C, D> {
+    /// A page write that is repeated up to [`write_retries`](Self::set_write_retries) times
+    /// while it times out
+    fn page_write_retrying_blocking(
+        &mut self,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        let mut retries = self.write_retries;
+        loop {
+            match self.page_write_blocking(address, data) {
+                Err(Error::WriteAckTimeout) if retries > 0 => retries -= 1,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<I2C: I2c, D: DelayNs> ReadNorFlash for At24Cx<I2C, D> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, mut offset: u32, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
+        // Reads past the end would wrap around on the device instead of failing
+        check_read(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        if self.bad_pages.is_empty() {
+            return self.read_unmapped_blocking(offset, bytes);
+        }
+        while !bytes.is_empty() {
+            let chunk_size = min(
+                bytes.len(),
+                self.page_size - offset as usize % self.page_size,
+            );
+            let (chunk, rest) = bytes.split_at_mut(chunk_size);
+            self.read_unmapped_blocking(self.remap_read(offset), chunk)?;
+            offset += chunk_size as u32;
+            bytes = rest;
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        1 << self.address_bits
+    }
+}
+
+impl<I2C: I2c, D: DelayNs> NorFlash for At24Cx<I2C, D> {
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    #[cfg(not(feature = "real-erase"))]
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        // No explicit erase needed, only the range is checked
+        check_erase(self, from, to).map_err(Error::from_kind)
+    }
+
+    /// Programs the range with the [default byte](At24Cx::set_default_byte).
+    #[cfg(feature = "real-erase")]
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        check_erase(self, from, to).map_err(Error::from_kind)?;
+        let blank = [self.default_byte; PAGE_SIZE];
+        let mut offset = from;
+        while offset < to {
+            let chunk_size = min(
+                (to - offset) as usize,
+                self.page_size - offset as usize % self.page_size,
+            );
+            self.page_write_blocking(offset, &blank[..chunk_size])?;
+            offset += chunk_size as u32;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, mut offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        check_write(self, offset, bytes.len()).map_err(Error::from_kind)?;
+        let page_size = self.page_size;
+        let lead = offset as usize % page_size;
+        if self.page_aligned_writes && lead != 0 && bytes.len() > page_size - lead {
+            let mut page = [0; PAGE_SIZE];
+            let page = &mut page[..page_size];
+            let page_start = offset - lead as u32;
+            ReadNorFlash::read(self, page_start, &mut page[..lead])?;
+            page[lead..].copy_from_slice(&bytes[..page_size - lead]);
+            self.page_write_retrying_blocking(page_start, page)?;
+            offset += (page_size - lead) as u32;
+            bytes = &bytes[page_size - lead..];
+        }
+        while !bytes.is_empty() {
+            let chunk_size = min(bytes.len(), page_size - offset as usize % page_size);
+            self.page_write_retrying_blocking(offset, &bytes[..chunk_size])?;
+            offset += chunk_size as u32;
+            bytes = &bytes[chunk_size..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{expect_ack_poll, expect_page_write, expect_read_with};
+    use crate::{sim::SimBus, Address, BadPage, DeviceKind};
+    use embedded_hal_mock::eh1::{
+        delay::NoopDelay,
+        i2c::{Mock as I2cMock, Transaction},
+    };
+
+    #[test]
+    fn writes_pages_and_checks_bounds() {
+        let nack = ErrorKind::NoAcknowledge(embedded_hal::i2c::NoAcknowledgeSource::Address);
+        let expectations = [
+            Transaction::write(0x50, std::vec![0xFF, 0xFE, 1, 2]),
+            Transaction::write(0x50, std::vec![0]).with_error(nack),
+            Transaction::write(0x50, std::vec![0]),
+            Transaction::write(0x51, std::vec![0x00, 0x00, 3]),
+            Transaction::write(0x51, std::vec![0]),
+            Transaction::write_read(0x50, std::vec![0xFF, 0xFE], std::vec![1, 2, 3]),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        // Crosses into the second 64KiB block
+        eeprom.write(0xFFFE, &[1, 2, 3]).unwrap();
+        let mut buf = [0; 3];
+        eeprom.read(0xFFFE, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+
+        assert!(matches!(
+            eeprom.read(0x1FFFF, &mut buf),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            eeprom.write(0x20000, &[0]),
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[test]
+    fn caps_the_write_length() {
+        let expectations = [
+            expect_page_write(0x50, 0x1FE, &[1, 2]),
+            expect_ack_poll(0x50, 0),
+            expect_page_write(0x50, 0x100, &[3]),
+            expect_ack_poll(0x50, 0),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_max_write_len(2).unwrap();
+        // Sub-writes wrap within the page like a single write cycle
+        eeprom.page_write_blocking(0x1FE, &[1, 2, 3]).unwrap();
+        eeprom.i2c.done();
+    }
+
+    #[test]
+    fn reads_with_a_stop_between_address_and_data() {
+        let expectations = [
+            expect_read_with(ReadMethod::WriteStopRead, 0x50, 0xFFFE, &[1, 2, 3]),
+            // Padded to the minimum read length
+            expect_read_with(ReadMethod::WriteStopRead, 0x50, 0x10, &[4, 5, 6, 7]),
+        ]
+        .concat();
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_read_method(ReadMethod::WriteStopRead);
+        let mut buf = [0; 3];
+        eeprom.read(0xFFFE, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+        eeprom.set_min_read_len(4).unwrap();
+        eeprom.read(0x10, &mut buf[..2]).unwrap();
+        assert_eq!(buf[..2], [4, 5]);
+        eeprom.i2c.done();
+    }
+
+    #[test]
+    fn remaps_small_pages() {
+        let address = Address::pins(1, 1, 1);
+        let bus = SimBus::new(address, 12).with_page_size(32);
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+        eeprom
+            .set_bad_pages(&[BadPage {
+                page: 2,
+                remap: Some(127),
+            }])
+            .unwrap();
+        let data: [u8; 0x40] = core::array::from_fn(|i| i as u8);
+        eeprom.write(0x30, &data).unwrap();
+        let memory = eeprom.i2c.memory();
+        assert_eq!(memory[0x30..0x40], data[..0x10]);
+        assert!(memory[0x40..0x60].iter().all(|b| *b == 0xFF));
+        assert_eq!(memory[0x60..0x70], data[0x30..]);
+        assert_eq!(memory[0xFE0..0x1000], data[0x10..0x30]);
+
+        let mut buf = [0; 0x40];
+        eeprom.read(0x30, &mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{At24Cx, DeviceKind, Error};
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation, SevenBitAddress, TenBitAddress};
+
+/// Puts the driver on a bus that addresses the device with 10 bits.
+///
+/// The driver computes the device byte as usual, including the P0 bit, and this adds the
+/// upper bits of the 10-bit address to it. Created by
+/// [`new_ten_bit`](At24Cx::new_ten_bit).
+#[derive(Debug)]
+pub struct TenBitBus<I2C> {
+    i2c: I2C,
+    high: u16,
+}
+
+impl<I2C> TenBitBus<I2C> {
+    /// The full 10-bit address for a device byte
+    fn address(&self, address: SevenBitAddress) -> TenBitAddress {
+        self.high | address as TenBitAddress
+    }
+
+    pub fn inner(&self) -> &I2C {
+        &self.i2c
+    }
+
+    pub fn inner_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+}
+
+impl<I2C: ErrorType> ErrorType for TenBitBus<I2C> {
+    type Error = I2C::Error;
+}
+
+impl<I2C: I2c<TenBitAddress>> I2c for TenBitBus<I2C> {
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let address = self.address(address);
+        self.i2c.transaction(address, operations).await
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<I2C: embedded_hal::i2c::I2c<TenBitAddress>> embedded_hal::i2c::I2c for TenBitBus<I2C> {
+    fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let address = self.address(address);
+        self.i2c.transaction(address, operations)
+    }
+}
+
+impl<I2C: ErrorType, D> At24Cx<TenBitBus<I2C>, D> {
+    /// Like [`new`](At24Cx::new), for a device at the 10-bit `address`.
+    ///
+    /// Returns `InvalidArgument` if `address` doesn't fit 10 bits, if the device needs the
+    /// P0 bit and it is set in `address`, or if no supported part has `address_bits` bits.
+    pub fn new_ten_bit(
+        i2c: I2C,
+        address: TenBitAddress,
+        address_bits: usize,
+        delay: D,
+    ) -> Result<Self, Error<I2C::Error>> {
+        if address > 0x3FF || (address_bits > 16 && address & 1 != 0) {
+            return Err(Error::InvalidArgument);
+        }
+        if DeviceKind::checked_from_address_bits(address_bits).is_err() {
+            return Err(Error::InvalidArgument);
+        }
+        let bus = TenBitBus {
+            i2c,
+            high: address & 0x300,
+        };
+        let mut eeprom = At24Cx::new(bus, crate::Address(0, 0), address_bits, delay);
+        eeprom.base_address = address as u8;
+        Ok(eeprom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+    /// A simulated device behind a bus with 10-bit addresses, at 0x250
+    struct TenBitSim {
+        sim: SimBus,
+        addresses: std::vec::Vec<TenBitAddress>,
+    }
+
+    impl ErrorType for TenBitSim {
+        type Error = <SimBus as ErrorType>::Error;
+    }
+
+    impl I2c<TenBitAddress> for TenBitSim {
+        async fn transaction(
+            &mut self,
+            address: TenBitAddress,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            self.addresses.push(address);
+            // Anything outside 0x2xx lands on an address the device doesn't answer
+            let address = if address & 0x300 == 0x200 {
+                address as u8
+            } else {
+                0
+            };
+            self.sim.transaction(address, operations).await
+        }
+    }
+
+    #[tokio::test]
+    async fn folds_p0_into_a_ten_bit_address() {
+        let bus = TenBitSim {
+            sim: SimBus::new(Address(0, 0), 17),
+            addresses: std::vec::Vec::new(),
+        };
+        let mut eeprom = At24Cx::new_ten_bit(bus, 0x250, 17, NoopDelay::new()).unwrap();
+        let data = [0xC3; 8];
+        eeprom.write(0xFFFC, &data).await.unwrap();
+        let mut buf = [0; 8];
+        eeprom.read(0xFFFC, &mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(eeprom.i2c.inner().sim.memory()[0xFFFC..0x10004], data);
+        let addresses = &eeprom.i2c.inner().addresses;
+        assert!(addresses.contains(&0x250) && addresses.contains(&0x251));
+        assert!(addresses.iter().all(|a| *a == 0x250 || *a == 0x251));
+    }
+
+    #[test]
+    fn rejects_unusable_addresses() {
+        let new = |address, address_bits| {
+            let bus = TenBitSim {
+                sim: SimBus::new(Address(0, 0), 17),
+                addresses: std::vec::Vec::new(),
+            };
+            At24Cx::new_ten_bit(bus, address, address_bits, NoopDelay::new()).is_ok()
+        };
+        assert!(!new(0x400, 16));
+        // P0 is taken by the device address
+        assert!(!new(0x251, 17));
+        assert!(new(0x251, 16));
+        // No such part
+        assert!(!new(0x250, 8));
+    }
+}
@@ -0,0 +1,146 @@
+//! `embedded-hal-mock` expectations in the shape the driver puts transactions on the bus.
+//!
+//! Every function returns the transactions for one step, tests concatenate them into the
+//! expectations of a mock. They assume the driver's defaults: `AckProbe::Write`,
+//! `ReadMethod::CombinedWriteRead` and one unverified write, so every page write is followed
+//! by ACK polling. `address` is the device address without the P0 bit,
+//! `0x50` for [`Address(0, 0)`](crate::Address).
+//!
+//! Testing a wrapper that stores a `u16` at a fixed offset:
+//!
+//! ```
+//! # use at24cx::{test_support::*, Address, At24Cx};
+//! # use embedded_hal_mock::eh1::{delay::NoopDelay, i2c::Mock as I2cMock};
+//! # use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+//! # use embedded_storage_async::nor_flash::NorFlash;
+//! struct Config<I2C, D>(At24Cx<I2C, D>);
+//!
+//! impl<I2C: I2c, D: DelayNs> Config<I2C, D> {
+//!     async fn set_brightness(&mut self, value: u16) {
+//!         self.0.write(0x1FF, &value.to_be_bytes()).await.unwrap();
+//!     }
+//! }
+//!
+//! # async fn test() {
+//! // The write crosses a page, the device is busy for two polls after the first one
+//! let expectations = [
+//!     expect_page_write(0x50, 0x1FF, &[0x12]),
+//!     expect_ack_poll(0x50, 2),
+//!     expect_page_write(0x50, 0x200, &[0x34]),
+//!     expect_ack_poll(0x50, 0),
+//! ]
+//! .concat();
+//! let i2c = I2cMock::new(&expectations);
+//! let mut config = Config(At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new()));
+//! config.set_brightness(0x1234).await;
+//! let (mut i2c, _) = config.0.into_parts();
+//! i2c.done();
+//! # }
+//! ```
+
+use crate::{memory_address_bytes, ReadMethod, PAGE_SIZE};
+use embedded_hal_async::i2c::{ErrorKind, NoAcknowledgeSource};
+use embedded_hal_mock::eh1::i2c::Transaction;
+use std::vec;
+use std::vec::Vec;
+
+const NACK: ErrorKind = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+
+/// The device byte for `offset`, with P0 from bit 16
+fn device_address(address: u8, offset: u32) -> u8 {
+    address | ((offset >> 16) & 1) as u8
+}
+
+/// A single page write of `data` at `offset`, without the polling after it
+pub fn expect_page_write(address: u8, offset: u32, data: &[u8]) -> Vec<Transaction> {
+    let mut bytes = memory_address_bytes(offset).to_vec();
+    bytes.extend_from_slice(data);
+    vec![Transaction::write(device_address(address, offset), bytes)]
+}
+
+/// ACK polling that finds the device busy `nacks_before_ready` times
+pub fn expect_ack_poll(address: u8, nacks_before_ready: usize) -> Vec<Transaction> {
+    let mut transactions = expect_ack_timeout(address, nacks_before_ready);
+    transactions.push(Transaction::write(address, vec![0]));
+    transactions
+}
+
+/// ACK polling that gives up after `polls` attempts, see
+/// [`set_poll_max_retries`](crate::At24Cx::set_poll_max_retries)
+pub fn expect_ack_timeout(address: u8, polls: usize) -> Vec<Transaction> {
+    (0..polls)
+        .map(|_| Transaction::write(address, vec![0]).with_error(NACK))
+        .collect()
+}
+
+/// A `NorFlash::write` of `data` at `offset`: a page write for every page it touches,
+/// each followed by polling that finds the device ready
+pub fn expect_write(address: u8, mut offset: u32, mut data: &[u8]) -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    while !data.is_empty() {
+        let len = data.len().min(PAGE_SIZE - offset as usize % PAGE_SIZE);
+        let (page, rest) = data.split_at(len);
+        transactions.extend(expect_page_write(address, offset, page));
+        transactions.extend(expect_ack_poll(address, 0));
+        offset += len as u32;
+        data = rest;
+    }
+    transactions
+}
+
+/// A read of `data` at `offset`
+pub fn expect_read(address: u8, offset: u32, data: &[u8]) -> Vec<Transaction> {
+    expect_read_with(ReadMethod::CombinedWriteRead, address, offset, data)
+}
+
+/// A read of `data` at `offset` by a driver set to another
+/// [read method](crate::At24Cx::set_read_method)
+pub fn expect_read_with(
+    method: ReadMethod,
+    address: u8,
+    offset: u32,
+    data: &[u8],
+) -> Vec<Transaction> {
+    let address = device_address(address, offset);
+    let memory_address = memory_address_bytes(offset).to_vec();
+    match method {
+        ReadMethod::CombinedWriteRead => {
+            vec![Transaction::write_read(
+                address,
+                memory_address,
+                data.to_vec(),
+            )]
+        }
+        ReadMethod::WriteStopRead => vec![
+            Transaction::write(address, memory_address),
+            Transaction::read(address, data.to_vec()),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Address, At24Cx};
+    use embedded_hal_mock::eh1::{delay::NoopDelay, i2c::Mock as I2cMock};
+    use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+    #[tokio::test]
+    async fn matches_a_write_across_blocks() {
+        let data: Vec<u8> = (0..0x120).map(|i| i as u8).collect();
+        let expectations = [
+            expect_write(0x52, 0xFFF0, &data),
+            expect_read(0x52, 0x10000, &data[0x10..0x20]),
+        ]
+        .concat();
+        // Three pages, the last two in the second block
+        assert_eq!(expectations.len(), 3 * 2 + 1);
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(1, 0), 17, NoopDelay::new());
+        eeprom.write(0xFFF0, &data).await.unwrap();
+        let mut buf = [0; 0x10];
+        eeprom.read(0x10000, &mut buf).await.unwrap();
+        assert_eq!(buf[..], data[0x10..0x20]);
+        eeprom.i2c.done();
+    }
+}
@@ -0,0 +1,28 @@
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+use embedded_hal_async::delay::DelayNs;
+
+/// Runs `future` to completion unless `timeout_us` microseconds of `delay` pass first, in
+/// which case it is dropped and `None` returned. Without a timeout it simply awaits.
+pub(crate) async fn with_timeout<F: Future, D: DelayNs>(
+    delay: &mut D,
+    timeout_us: Option<u32>,
+    future: F,
+) -> Option<F::Output> {
+    let Some(timeout_us) = timeout_us else {
+        return Some(future.await);
+    };
+    let mut future = pin!(future);
+    let mut timer = pin!(delay.delay_us(timeout_us));
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        if timer.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,576 @@
+//! Tag-length-value record store in a region of the device.
+//!
+//! Values are opaque bytes identified by a 16-bit tag, so firmware revisions that know
+//! different sets of tags can share the store. Tags a revision doesn't know are kept as they
+//! are, including through [`compact`](TlvStore::compact).
+//!
+//! The region is split into two halves of which one is active, like the
+//! [key-value store](crate::kv). The active half starts with a header (magic `TLV1`, a
+//! generation number and a CRC-32) followed by an append-only log of records:
+//!
+//! | bytes     | content                                         |
+//! |-----------|-------------------------------------------------|
+//! | 0         | status, 0xFF while live, 0x00 once replaced     |
+//! | 1..3      | generation of the half it was written in        |
+//! | 3..5      | tag                                             |
+//! | 5..7      | value length                                    |
+//! | 7..7+v    | value                                           |
+//! | ..+4      | CRC-32 over everything before it but the status |
+//!
+//! All integers are little endian. [`put`](TlvStore::put) appends the new record and then
+//! clears the status of the one it replaces. If power is lost in between, both records are
+//! intact and the newer one wins. Mounting scans the log and stops at the first record that
+//! doesn't check out, so a record torn by a power loss is ignored and overwritten by the
+//! next one.
+
+use crate::crc::crc32_update;
+use crate::{EepromPartition, Error};
+use core::cmp::min;
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Longest value accepted by the store
+pub const MAX_VALUE_LEN: usize = 256;
+
+const MAGIC: [u8; 4] = *b"TLV1";
+const HALF_HEADER_SIZE: u32 = 10;
+const RECORD_HEADER_SIZE: usize = 7;
+const RECORD_OVERHEAD: u32 = RECORD_HEADER_SIZE as u32 + 4;
+const STATUS_LIVE: u8 = 0xFF;
+const STATUS_REPLACED: u8 = 0x00;
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct RecordHeader {
+    status: u8,
+    generation: u16,
+    tag: u16,
+    len: u16,
+}
+
+impl RecordHeader {
+    fn encode(&self) -> [u8; RECORD_HEADER_SIZE] {
+        let mut out = [0; RECORD_HEADER_SIZE];
+        out[0] = self.status;
+        out[1..3].copy_from_slice(&self.generation.to_le_bytes());
+        out[3..5].copy_from_slice(&self.tag.to_le_bytes());
+        out[5..7].copy_from_slice(&self.len.to_le_bytes());
+        out
+    }
+
+    fn decode(bytes: &[u8; RECORD_HEADER_SIZE]) -> Self {
+        Self {
+            status: bytes[0],
+            generation: u16::from_le_bytes([bytes[1], bytes[2]]),
+            tag: u16::from_le_bytes([bytes[3], bytes[4]]),
+            len: u16::from_le_bytes([bytes[5], bytes[6]]),
+        }
+    }
+
+    /// Size of the whole record on the device
+    fn size(&self) -> u32 {
+        RECORD_OVERHEAD + self.len as u32
+    }
+}
+
+/// Tag-length-value store over a partition, see the [module docs](self)
+pub struct TlvStore<'a, I2C, D> {
+    region: EepromPartition<'a, I2C, D>,
+    half_len: u32,
+    active: u32,
+    generation: u16,
+    /// End of the log, relative to the start of the active half
+    end: u32,
+}
+
+impl<'a, I2C, E: Debug, D: DelayNs> TlvStore<'a, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Opens the store in `region`, initializing it if neither half has a valid header.
+    ///
+    /// Returns `InvalidArgument` if the region is too small to hold the largest record.
+    pub async fn mount(mut region: EepromPartition<'a, I2C, D>) -> Result<Self, Error<E>> {
+        let half_len = region.capacity() as u32 / 2;
+        if half_len < HALF_HEADER_SIZE + RECORD_OVERHEAD + MAX_VALUE_LEN as u32 {
+            return Err(Error::InvalidArgument);
+        }
+        let first = read_half_header(&mut region, 0).await?;
+        let second = read_half_header(&mut region, half_len).await?;
+        let (active, generation) = match (first, second) {
+            (Some(a), Some(b)) if (b.wrapping_sub(a) as i16) > 0 => (1, b),
+            (Some(a), _) => (0, a),
+            (None, Some(b)) => (1, b),
+            (None, None) => {
+                write_half_header(&mut region, 0, 0).await?;
+                (0, 0)
+            }
+        };
+
+        let mut store = Self {
+            region,
+            half_len,
+            active,
+            generation,
+            end: HALF_HEADER_SIZE,
+        };
+        while let Some(header) = store.read_valid_record(store.end).await? {
+            store.end += header.size();
+        }
+        Ok(store)
+    }
+
+    /// Bytes left in the active half. [`compact`](Self::compact) may free more.
+    pub fn free_bytes(&self) -> usize {
+        (self.half_len - self.end) as usize
+    }
+
+    /// Reads the value stored for `tag` into the start of `buf`, returning its length.
+    ///
+    /// Returns `NotFound` if the tag isn't set and `OutOfBounds` if `buf` is too small.
+    pub async fn get(&mut self, tag: u16, buf: &mut [u8]) -> Result<usize, Error<E>> {
+        let (position, header) = self.find(tag).await?.ok_or(Error::NotFound)?;
+        self.read_value(position, header, buf).await
+    }
+
+    /// Stores `value` for `tag`, replacing any previous value.
+    ///
+    /// Values longer than [`MAX_VALUE_LEN`] are rejected with `InvalidArgument`. Returns
+    /// `Full` if the live records don't leave room for this one even after compacting.
+    pub async fn put(&mut self, tag: u16, value: &[u8]) -> Result<(), Error<E>> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        let header = RecordHeader {
+            status: STATUS_LIVE,
+            generation: self.generation,
+            tag,
+            len: value.len() as u16,
+        };
+        if header.size() > self.half_len - self.end {
+            self.compact().await?;
+            if header.size() > self.half_len - self.end {
+                return Err(Error::Full);
+            }
+        }
+        let old = self.find(tag).await?;
+
+        let header = RecordHeader {
+            generation: self.generation,
+            ..header
+        }
+        .encode();
+        let crc = !crc32_update(crc32_update(!0, &header[1..]), value);
+        let mut offset = self.end;
+        self.write(offset, &header).await?;
+        offset += header.len() as u32;
+        self.write(offset, value).await?;
+        offset += value.len() as u32;
+        self.write(offset, &crc.to_le_bytes()).await?;
+        self.end = offset + 4;
+
+        if let Some((position, _)) = old {
+            self.write(position, &[STATUS_REPLACED]).await?;
+        }
+        Ok(())
+    }
+
+    /// Streams the live records in the order they were written
+    pub fn iter(&mut self) -> Records<'_, 'a, I2C, D> {
+        Records {
+            store: self,
+            position: HALF_HEADER_SIZE,
+        }
+    }
+
+    /// Rewrites the live records into the other half, dropping replaced values
+    pub async fn compact(&mut self) -> Result<(), Error<E>> {
+        let target = (1 - self.active) * self.half_len;
+        let generation = self.generation.wrapping_add(1);
+        let mut out = HALF_HEADER_SIZE;
+        let mut position = HALF_HEADER_SIZE;
+        while position < self.end {
+            let header = self.read_header(position).await?;
+            if self.is_live(position, header).await? {
+                self.copy_record(position, header, target + out, generation)
+                    .await?;
+                out += header.size();
+            }
+            position += header.size();
+        }
+        write_half_header(&mut self.region, target, generation).await?;
+        self.active = 1 - self.active;
+        self.generation = generation;
+        self.end = out;
+        Ok(())
+    }
+
+    /// Position and header of the newest live record for `tag`
+    async fn find(&mut self, tag: u16) -> Result<Option<(u32, RecordHeader)>, Error<E>> {
+        let mut found = None;
+        let mut position = HALF_HEADER_SIZE;
+        while position < self.end {
+            let header = self.read_header(position).await?;
+            if header.tag == tag && header.status == STATUS_LIVE {
+                found = Some((position, header));
+            }
+            position += header.size();
+        }
+        Ok(found)
+    }
+
+    /// Whether the record at `position` is neither marked as replaced nor followed by a
+    /// live record with its tag, which happens when a put was interrupted before it could
+    /// mark the old record
+    async fn is_live(&mut self, position: u32, header: RecordHeader) -> Result<bool, Error<E>> {
+        if header.status != STATUS_LIVE {
+            return Ok(false);
+        }
+        let mut later = position + header.size();
+        while later < self.end {
+            let other = self.read_header(later).await?;
+            if other.tag == header.tag && other.status == STATUS_LIVE {
+                return Ok(false);
+            }
+            later += other.size();
+        }
+        Ok(true)
+    }
+
+    async fn read_value(
+        &mut self,
+        position: u32,
+        header: RecordHeader,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        let len = header.len as usize;
+        if len > buf.len() {
+            return Err(Error::OutOfBounds);
+        }
+        self.read(position + RECORD_HEADER_SIZE as u32, &mut buf[..len])
+            .await?;
+        Ok(len)
+    }
+
+    /// Copies a live record to an absolute offset in the region, rewriting its generation
+    async fn copy_record(
+        &mut self,
+        position: u32,
+        header: RecordHeader,
+        destination: u32,
+        generation: u16,
+    ) -> Result<(), Error<E>> {
+        let source = self.active * self.half_len + position;
+        let new_header = RecordHeader {
+            generation,
+            ..header
+        }
+        .encode();
+        self.region.write(destination, &new_header).await?;
+        let mut crc = crc32_update(!0, &new_header[1..]);
+
+        let body_len = header.len as u32;
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut done = 0;
+        while done < body_len {
+            let len = min(CHUNK_SIZE as u32, body_len - done);
+            let chunk = &mut chunk[..len as usize];
+            let from = source + RECORD_HEADER_SIZE as u32 + done;
+            self.region.read(from, chunk).await?;
+            let to = destination + RECORD_HEADER_SIZE as u32 + done;
+            self.region.write(to, chunk).await?;
+            crc = crc32_update(crc, chunk);
+            done += len;
+        }
+        let crc_offset = destination + RECORD_HEADER_SIZE as u32 + body_len;
+        self.region.write(crc_offset, &(!crc).to_le_bytes()).await
+    }
+
+    /// Header of a record known to be valid
+    async fn read_header(&mut self, position: u32) -> Result<RecordHeader, Error<E>> {
+        let mut bytes = [0; RECORD_HEADER_SIZE];
+        self.read(position, &mut bytes).await?;
+        Ok(RecordHeader::decode(&bytes))
+    }
+
+    /// Header of the record at `position` if there is a complete, intact one
+    async fn read_valid_record(&mut self, position: u32) -> Result<Option<RecordHeader>, Error<E>> {
+        if position + RECORD_OVERHEAD > self.half_len {
+            return Ok(None);
+        }
+        let mut bytes = [0; RECORD_HEADER_SIZE];
+        self.read(position, &mut bytes).await?;
+        let header = RecordHeader::decode(&bytes);
+        let plausible = header.generation == self.generation
+            && (header.status == STATUS_LIVE || header.status == STATUS_REPLACED)
+            && header.len as usize <= MAX_VALUE_LEN
+            && header.size() <= self.half_len - position;
+        if !plausible {
+            return Ok(None);
+        }
+
+        let mut crc = crc32_update(!0, &bytes[1..]);
+        let body_len = header.len as u32;
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut done = 0;
+        while done < body_len {
+            let len = min(CHUNK_SIZE as u32, body_len - done);
+            let chunk = &mut chunk[..len as usize];
+            self.read(position + RECORD_HEADER_SIZE as u32 + done, chunk)
+                .await?;
+            crc = crc32_update(crc, chunk);
+            done += len;
+        }
+        let mut stored = [0; 4];
+        self.read(position + RECORD_HEADER_SIZE as u32 + body_len, &mut stored)
+            .await?;
+        Ok((!crc == u32::from_le_bytes(stored)).then_some(header))
+    }
+
+    /// Reads relative to the start of the active half
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        let start = self.active * self.half_len;
+        self.region.read(start + offset, bytes).await
+    }
+
+    /// Writes relative to the start of the active half
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error<E>> {
+        let start = self.active * self.half_len;
+        self.region.write(start + offset, bytes).await
+    }
+}
+
+/// Streaming iterator over the live records of a [`TlvStore`], see [`TlvStore::iter`]
+pub struct Records<'s, 'a, I2C, D> {
+    store: &'s mut TlvStore<'a, I2C, D>,
+    position: u32,
+}
+
+impl<I2C, E: Debug, D: DelayNs> Records<'_, '_, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Reads the next live record's value into the start of `buf` and returns its tag and
+    /// length, `None` after the last record.
+    ///
+    /// Returns `OutOfBounds` if `buf` is too small, the record is skipped then.
+    pub async fn next(&mut self, buf: &mut [u8]) -> Result<Option<(u16, usize)>, Error<E>> {
+        while self.position < self.store.end {
+            let position = self.position;
+            let header = self.store.read_header(position).await?;
+            self.position += header.size();
+            if self.store.is_live(position, header).await? {
+                let len = self.store.read_value(position, header, buf).await?;
+                return Ok(Some((header.tag, len)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The generation in the header of the half at `offset`, if it is valid
+async fn read_half_header<I2C, E, D>(
+    region: &mut EepromPartition<'_, I2C, D>,
+    offset: u32,
+) -> Result<Option<u16>, Error<E>>
+where
+    I2C: I2c<Error = E>,
+    E: Debug,
+    D: DelayNs,
+{
+    let mut header = [0; HALF_HEADER_SIZE as usize];
+    region.read(offset, &mut header).await?;
+    let crc = !crc32_update(!0, &header[..6]);
+    if header[0..4] != MAGIC
+        || crc != u32::from_le_bytes([header[6], header[7], header[8], header[9]])
+    {
+        return Ok(None);
+    }
+    Ok(Some(u16::from_le_bytes([header[4], header[5]])))
+}
+
+async fn write_half_header<I2C, E, D>(
+    region: &mut EepromPartition<'_, I2C, D>,
+    offset: u32,
+    generation: u16,
+) -> Result<(), Error<E>>
+where
+    I2C: I2c<Error = E>,
+    E: Debug,
+    D: DelayNs,
+{
+    let mut header = [0; HALF_HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&generation.to_le_bytes());
+    let crc = !crc32_update(!0, &header[..6]);
+    header[6..10].copy_from_slice(&crc.to_le_bytes());
+    region.write(offset, &header).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec::Vec;
+
+    const REGION: core::ops::Range<u32> = 0x1000..0x1400;
+
+    fn driver(memory: Option<&[u8]>) -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        if let Some(memory) = memory {
+            bus.memory_mut().copy_from_slice(memory);
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    async fn mount(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> TlvStore<'_, SimBus, NoopDelay> {
+        TlvStore::mount(eeprom.partition(REGION).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn records(eeprom: &mut At24Cx<SimBus, NoopDelay>) -> Vec<(u16, Vec<u8>)> {
+        let mut store = mount(eeprom).await;
+        let mut records = store.iter();
+        let mut buf = [0; MAX_VALUE_LEN];
+        let mut all = Vec::new();
+        while let Some((tag, len)) = records.next(&mut buf).await.unwrap() {
+            all.push((tag, buf[..len].to_vec()));
+        }
+        all
+    }
+
+    #[tokio::test]
+    async fn put_replaces() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        let free = store.free_bytes();
+        store.put(1, &[3]).await.unwrap();
+        store.put(2, b"synth").await.unwrap();
+        store.put(1, &[7]).await.unwrap();
+        assert_eq!(store.free_bytes(), free - 12 - 16 - 12);
+        let mut buf = [0; 4];
+        assert!(matches!(
+            store.get(2, &mut buf).await,
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(store.get(1, &mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 7);
+        assert!(matches!(store.get(3, &mut buf).await, Err(Error::NotFound)));
+        // The replaced record is marked
+        assert_eq!(eeprom.i2c.memory()[REGION.start as usize + 10], 0x00);
+
+        assert_eq!(
+            records(&mut eeprom).await,
+            [(2, b"synth".to_vec()), (1, std::vec![7])]
+        );
+        let mut store = mount(&mut eeprom).await;
+        assert!(matches!(
+            store.put(1, &[0; MAX_VALUE_LEN + 1]).await,
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[tokio::test]
+    async fn compaction_keeps_every_tag() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        // Tags an older firmware doesn't know about are opaque to the store anyway
+        store.put(0x8001, &[1; 20]).await.unwrap();
+        store.put(2, &[2; 20]).await.unwrap();
+        store.put(0x8001, &[3; 20]).await.unwrap();
+        store.put(0xFFFF, &[]).await.unwrap();
+        let expected = [
+            (2, std::vec![2; 20]),
+            (0x8001, std::vec![3; 20]),
+            (0xFFFF, std::vec![]),
+        ];
+        assert_eq!(records(&mut eeprom).await, expected);
+
+        let mut store = mount(&mut eeprom).await;
+        let free = store.free_bytes();
+        store.compact().await.unwrap();
+        assert_eq!(store.free_bytes(), free + 31);
+        assert_eq!(records(&mut eeprom).await, expected);
+
+        // Replacing one tag forever keeps compacting
+        let mut store = mount(&mut eeprom).await;
+        for i in 0..20u8 {
+            store.put(2, &[i; 200]).await.unwrap();
+        }
+        assert!(matches!(
+            store.put(3, &[0; MAX_VALUE_LEN]).await,
+            Err(Error::Full)
+        ));
+        let mut buf = [0; 200];
+        assert_eq!(store.get(2, &mut buf).await.unwrap(), 200);
+        assert_eq!(buf, [19; 200]);
+        assert_eq!(records(&mut eeprom).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn power_loss_during_put() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        store.put(5, &[1; 40]).await.unwrap();
+        store.put(6, &[9; 4]).await.unwrap();
+        let snapshot = eeprom.i2c.memory().to_vec();
+
+        // The record and the status byte of the old one
+        for cut in 0..=11 + 40 + 1 {
+            let mut eeprom = driver(Some(&snapshot));
+            // Mounting an initialized store only reads
+            eeprom.i2c.cut_power_after(cut);
+            let mut store = mount(&mut eeprom).await;
+            let result = store.put(5, &[2; 40]).await;
+            assert_eq!(result.is_ok(), cut == 11 + 40 + 1);
+            eeprom.i2c.restore_power();
+
+            let all = records(&mut eeprom).await;
+            let expected = if cut < 11 + 40 {
+                [(5, std::vec![1; 40]), (6, std::vec![9; 4])]
+            } else {
+                [(6, std::vec![9; 4]), (5, std::vec![2; 40])]
+            };
+            assert_eq!(all, expected, "cut after {cut} bytes");
+
+            // The torn record is overwritten by the next one
+            let mut store = mount(&mut eeprom).await;
+            store.put(5, &[3; 40]).await.unwrap();
+            let mut buf = [0; 40];
+            store.get(5, &mut buf).await.unwrap();
+            assert_eq!(buf, [3; 40]);
+            store.compact().await.unwrap();
+            assert_eq!(records(&mut eeprom).await.len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn power_loss_during_compaction() {
+        let mut eeprom = driver(None);
+        let mut store = mount(&mut eeprom).await;
+        store.put(1, &[1; 30]).await.unwrap();
+        store.put(2, &[2; 30]).await.unwrap();
+        store.put(1, &[3; 30]).await.unwrap();
+        store.put(0x4242, &[4; 30]).await.unwrap();
+        let snapshot = eeprom.i2c.memory().to_vec();
+        let expected = [
+            (2, std::vec![2; 30]),
+            (1, std::vec![3; 30]),
+            (0x4242, std::vec![4; 30]),
+        ];
+
+        // Three records of 41 bytes and the half header
+        for cut in 0..3 * 41 + 10 {
+            let mut eeprom = driver(Some(&snapshot));
+            // Mounting an initialized store only reads
+            eeprom.i2c.cut_power_after(cut);
+            let mut store = mount(&mut eeprom).await;
+            assert!(store.compact().await.is_err());
+            eeprom.i2c.restore_power();
+            assert_eq!(records(&mut eeprom).await, expected);
+        }
+    }
+}
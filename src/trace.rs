@@ -0,0 +1,414 @@
+//! Recording what the driver puts on the bus and replaying it in tests.
+//!
+//! A [`Recorder`] wraps a bus and logs every transaction into a [`Trace`]. A [`Replayer`]
+//! plays a trace back as the bus of another run: every transaction has to match the trace
+//! byte for byte, reads get the recorded data and errors are returned as recorded. It panics
+//! with the expected and actual transaction on the first difference.
+//!
+//! Traces are stored as text, one transaction per line: the device address, then the
+//! operations as `w:` with the written bytes or `r:` with the bytes read, and `!` with the
+//! error if the transaction failed. Bytes are written as hex without separators. Empty
+//! lines and lines starting with `#` are skipped.
+//!
+//! ```text
+//! # Page write and ACK polling
+//! 50 w:00f0000102
+//! 50 w:00 !nack-address
+//! 50 w:00
+//! 50 w:00f0 r:000102
+//! ```
+
+use crate::sim::SimError;
+use core::fmt;
+use core::str::FromStr;
+use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use std::format;
+use std::string::String;
+use std::vec::Vec;
+
+/// One operation of a [`TracedTransaction`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TracedOperation {
+    /// The bytes written
+    Write(Vec<u8>),
+    /// The bytes read
+    Read(Vec<u8>),
+}
+
+/// One transaction on the bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedTransaction {
+    pub address: u8,
+    pub operations: Vec<TracedOperation>,
+    /// The error the transaction failed with
+    pub error: Option<ErrorKind>,
+}
+
+/// The transactions of a run, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub transactions: Vec<TracedTransaction>,
+}
+
+/// A line of a trace that couldn't be parsed, counting from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParseError {
+    pub line: usize,
+}
+
+const ERRORS: [(&str, ErrorKind); 6] = [
+    (
+        "nack-address",
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+    ),
+    (
+        "nack-data",
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data),
+    ),
+    (
+        "nack",
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+    ),
+    ("bus", ErrorKind::Bus),
+    ("arbitration", ErrorKind::ArbitrationLoss),
+    ("overrun", ErrorKind::Overrun),
+];
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))
+}
+
+impl fmt::Display for TracedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}", self.address)?;
+        for operation in &self.operations {
+            match operation {
+                TracedOperation::Write(bytes) => {
+                    f.write_str(" w:")?;
+                    write_hex(f, bytes)?;
+                }
+                TracedOperation::Read(bytes) => {
+                    f.write_str(" r:")?;
+                    write_hex(f, bytes)?;
+                }
+            }
+        }
+        if let Some(error) = self.error {
+            let name = ERRORS
+                .iter()
+                .find(|(_, kind)| *kind == error)
+                .map_or("other", |(name, _)| name);
+            write!(f, " !{name}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.transactions
+            .iter()
+            .try_for_each(|transaction| writeln!(f, "{transaction}"))
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_transaction(line: &str) -> Option<TracedTransaction> {
+    let mut tokens = line.split_whitespace();
+    let address = u8::from_str_radix(tokens.next()?, 16).ok()?;
+    let mut transaction = TracedTransaction {
+        address,
+        operations: Vec::new(),
+        error: None,
+    };
+    for token in tokens {
+        if transaction.error.is_some() {
+            return None;
+        }
+        if let Some(hex) = token.strip_prefix("w:") {
+            let bytes = parse_hex(hex)?;
+            transaction.operations.push(TracedOperation::Write(bytes));
+        } else if let Some(hex) = token.strip_prefix("r:") {
+            let bytes = parse_hex(hex)?;
+            transaction.operations.push(TracedOperation::Read(bytes));
+        } else {
+            let name = token.strip_prefix('!')?;
+            transaction.error = Some(match ERRORS.iter().find(|(n, _)| *n == name) {
+                Some((_, kind)) => *kind,
+                None if name == "other" => ErrorKind::Other,
+                None => return None,
+            });
+        }
+    }
+    Some(transaction)
+}
+
+impl FromStr for Trace {
+    type Err = TraceParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut trace = Trace::default();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let transaction = parse_transaction(line).ok_or(TraceParseError { line: i + 1 })?;
+            trace.transactions.push(transaction);
+        }
+        Ok(trace)
+    }
+}
+
+/// Wraps a bus and records every transaction on it
+pub struct Recorder<I2C> {
+    i2c: I2C,
+    trace: Trace,
+}
+
+impl<I2C> Recorder<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self {
+            i2c,
+            trace: Trace::default(),
+        }
+    }
+
+    /// The transactions recorded so far
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    pub fn inner(&self) -> &I2C {
+        &self.i2c
+    }
+
+    pub fn inner_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+
+    /// Releases the bus and the trace
+    pub fn into_parts(self) -> (I2C, Trace) {
+        (self.i2c, self.trace)
+    }
+}
+
+impl<I2C: ErrorType> ErrorType for Recorder<I2C> {
+    type Error = I2C::Error;
+}
+
+impl<I2C: I2c> I2c for Recorder<I2C> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let result = self.i2c.transaction(address, operations).await;
+        let operations = operations
+            .iter()
+            .map(|operation| match operation {
+                Operation::Write(bytes) => TracedOperation::Write(bytes.to_vec()),
+                Operation::Read(buffer) => TracedOperation::Read(buffer.to_vec()),
+            })
+            .collect();
+        self.trace.transactions.push(TracedTransaction {
+            address,
+            operations,
+            error: result.as_ref().err().map(Error::kind),
+        });
+        result
+    }
+}
+
+/// A bus that expects a driver to reproduce a [`Trace`], see the [module](self)
+pub struct Replayer {
+    trace: Trace,
+    next: usize,
+}
+
+impl Replayer {
+    pub fn new(trace: Trace) -> Self {
+        Self { trace, next: 0 }
+    }
+
+    /// Panics unless the whole trace was replayed
+    pub fn done(&mut self) {
+        if let Some(expected) = self.trace.transactions.get(self.next) {
+            panic!(
+                "{} of {} transactions replayed, next expected:\n  {expected}",
+                self.next,
+                self.trace.transactions.len()
+            );
+        }
+    }
+}
+
+/// A transaction as the driver issued it, with `..` for every byte it wants to read
+fn describe(address: u8, operations: &[Operation<'_>]) -> String {
+    let mut line = format!("{address:02x}");
+    for operation in operations {
+        match operation {
+            Operation::Write(bytes) => {
+                line.push_str(" w:");
+                bytes
+                    .iter()
+                    .for_each(|b| line.push_str(&format!("{b:02x}")));
+            }
+            Operation::Read(buffer) => {
+                line.push_str(" r:");
+                line.push_str(&"..".repeat(buffer.len()));
+            }
+        }
+    }
+    line
+}
+
+fn matches(expected: &TracedTransaction, address: u8, operations: &[Operation<'_>]) -> bool {
+    expected.address == address
+        && expected.operations.len() == operations.len()
+        && expected
+            .operations
+            .iter()
+            .zip(operations)
+            .all(|(expected, actual)| match (expected, actual) {
+                (TracedOperation::Write(e), Operation::Write(a)) => e[..] == a[..],
+                (TracedOperation::Read(e), Operation::Read(a)) => e.len() == a.len(),
+                _ => false,
+            })
+}
+
+impl ErrorType for Replayer {
+    type Error = SimError;
+}
+
+impl I2c for Replayer {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let index = self.next;
+        let Some(expected) = self.trace.transactions.get(index) else {
+            panic!(
+                "transaction {index} is past the end of the trace:\n  actual:   {}",
+                describe(address, operations)
+            );
+        };
+        if !matches(expected, address, operations) {
+            panic!(
+                "transaction {index} differs from the trace:\n  expected: {expected}\n  actual:   {}",
+                describe(address, operations)
+            );
+        }
+        self.next += 1;
+        for (expected, actual) in expected.operations.iter().zip(operations.iter_mut()) {
+            if let (TracedOperation::Read(data), Operation::Read(buffer)) = (expected, actual) {
+                buffer.copy_from_slice(data);
+            }
+        }
+        match expected.error {
+            Some(kind) => Err(SimError(kind)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, At24Cx};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+    const PAGE_WRITE: &str = include_str!("../tests/traces/page_write.trace");
+    const BOUNDARY_READ: &str = include_str!("../tests/traces/boundary_read.trace");
+
+    async fn page_write<I2C: I2c>(eeprom: &mut At24Cx<I2C, NoopDelay>) {
+        let data: Vec<u8> = (0..40).collect();
+        eeprom.write(0xF0, &data).await.unwrap();
+    }
+
+    async fn boundary_read<I2C: I2c>(eeprom: &mut At24Cx<I2C, NoopDelay>) -> [u8; 8] {
+        let mut buf = [0; 8];
+        eeprom.read(0xFFFC, &mut buf).await.unwrap();
+        buf
+    }
+
+    fn recording() -> At24Cx<Recorder<SimBus>, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        for (i, byte) in bus.memory_mut().iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        At24Cx::new(Recorder::new(bus), Address(0, 0), 17, NoopDelay::new())
+    }
+
+    fn replaying(fixture: &str) -> At24Cx<Replayer, NoopDelay> {
+        let trace = fixture.parse().unwrap();
+        At24Cx::new(Replayer::new(trace), Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn page_write_matches_its_golden_trace() {
+        let mut eeprom = recording();
+        page_write(&mut eeprom).await;
+        assert_eq!(*eeprom.i2c.trace(), PAGE_WRITE.parse().unwrap());
+
+        let mut eeprom = replaying(PAGE_WRITE);
+        page_write(&mut eeprom).await;
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn boundary_read_matches_its_golden_trace() {
+        let mut eeprom = recording();
+        let recorded = boundary_read(&mut eeprom).await;
+        assert_eq!(*eeprom.i2c.trace(), BOUNDARY_READ.parse().unwrap());
+
+        let mut eeprom = replaying(BOUNDARY_READ);
+        assert_eq!(boundary_read(&mut eeprom).await, recorded);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_errors() {
+        let mut eeprom = replaying("50 w:000001 !nack-data\n");
+        assert!(matches!(
+            eeprom.write(0, &[1]).await,
+            Err(crate::Error::NoAcknowledge(e))
+                if e.kind() == ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data)
+        ));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "transaction 0 differs from the trace:\n  \
+                               expected: 50 w:0010 r:0102\n  actual:   50 w:0011 r:....")]
+    async fn panics_with_a_diff() {
+        let mut eeprom = replaying("50 w:0010 r:0102");
+        let _ = eeprom.read(0x11, &mut [0; 2]).await;
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = "50 w:00f0000102\n51 w:fff0 r:00ff\n50 w:00 !nack-address\n50 w: !other\n";
+        let trace: Trace = text.parse().unwrap();
+        assert_eq!(trace.to_string(), text);
+        assert_eq!(
+            "# comment\n\n50 w:0\n".parse::<Trace>(),
+            Err(TraceParseError { line: 3 })
+        );
+        assert_eq!(
+            "50 !nack w:00".parse::<Trace>(),
+            Err(TraceParseError { line: 1 })
+        );
+    }
+}
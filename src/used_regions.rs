@@ -0,0 +1,103 @@
+use crate::{At24Cx, Error, PAGE_SIZE};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Streams the `(offset, len)` spans of the device that hold something else than
+    /// `blank`, in ascending order. The device is read a page at a time.
+    ///
+    /// Spans are as exact as a byte: a `blank` byte in the middle of stored data splits it
+    /// in two, merge spans closer than some gap if that matters.
+    pub fn used_regions(&mut self, blank: u8) -> UsedRegions<'_, I2C, D> {
+        UsedRegions {
+            eeprom: self,
+            blank,
+            offset: 0,
+            page: [0; PAGE_SIZE],
+            loaded: None,
+        }
+    }
+}
+
+/// Streaming iterator over the used spans of a device, see [`At24Cx::used_regions`]
+pub struct UsedRegions<'a, I2C, D> {
+    eeprom: &'a mut At24Cx<I2C, D>,
+    blank: u8,
+    /// Where the scan continues
+    offset: u32,
+    page: [u8; PAGE_SIZE],
+    /// Offset of the page in `page`
+    loaded: Option<u32>,
+}
+
+impl<I2C, E: Debug, D: DelayNs> UsedRegions<'_, I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Next used span as its offset and length, `None` after the last one
+    pub async fn next(&mut self) -> Result<Option<(u32, usize)>, Error<E>> {
+        let capacity = self.eeprom.capacity() as u32;
+        let mut start = None;
+        while self.offset < capacity {
+            let page_start = self.offset - self.offset % PAGE_SIZE as u32;
+            if self.loaded != Some(page_start) {
+                self.eeprom.read(page_start, &mut self.page).await?;
+                self.loaded = Some(page_start);
+            }
+            let used = self.page[(self.offset - page_start) as usize] != self.blank;
+            match start {
+                None if used => start = Some(self.offset),
+                Some(start) if !used => {
+                    return Ok(Some((start, (self.offset - start) as usize)));
+                }
+                _ => {}
+            }
+            self.offset += 1;
+        }
+        Ok(start.map(|start| (start, (capacity - start) as usize)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBus;
+    use crate::Address;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_storage_async::nor_flash::NorFlash;
+    use std::vec::Vec;
+
+    async fn collect(eeprom: &mut At24Cx<SimBus, NoopDelay>, blank: u8) -> Vec<(u32, usize)> {
+        let mut regions = eeprom.used_regions(blank);
+        let mut spans = Vec::new();
+        while let Some(span) = regions.next().await.unwrap() {
+            spans.push(span);
+        }
+        spans
+    }
+
+    #[tokio::test]
+    async fn finds_used_spans() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(collect(&mut eeprom, 0xFF).await, []);
+
+        eeprom.write(0, &[1, 2]).await.unwrap();
+        // Across a page boundary, split by a blank byte
+        eeprom.write(0x1F0, &[3; 0x20]).await.unwrap();
+        eeprom.write(0x200, &[0xFF]).await.unwrap();
+        eeprom.write(0x1FFFE, &[0, 0]).await.unwrap();
+        assert_eq!(
+            collect(&mut eeprom, 0xFF).await,
+            [(0, 2), (0x1F0, 0x10), (0x201, 0xF), (0x1FFFE, 2)]
+        );
+
+        // Everything else is used with another blank byte
+        let spans = collect(&mut eeprom, 0).await;
+        assert_eq!(spans, [(0, 0x1FFFE)]);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::timeout::with_timeout;
+use crate::{bus_error, check_read, memory_address_bytes, At24Cx, Error, ReadMethod};
+use core::fmt::Debug;
+use embedded_hal_async::{
+    delay::DelayNs,
+    i2c::{I2c, Operation},
+};
+use embedded_storage_async::nor_flash::ReadNorFlash;
+use heapless::Vec;
+
+/// Slices read in one bus transaction, each needs an `Operation` on the stack
+const SLICES_PER_TRANSACTION: usize = 8;
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Reads the region at `offset` into `slices`, one after the other.
+    ///
+    /// The slices are filled by adjacent reads of a single transaction, which the bus runs
+    /// without a stop or repeated start between them, so the data lands in place with one
+    /// address phase for every 8 slices. Like [`read`](ReadNorFlash::read) the device runs on
+    /// into its next 64KiB block by itself. The combined length is bounds checked before the
+    /// bus is touched. With bad pages, [read padding](Self::set_min_read_len) or across the
+    /// seam to an [upper half address](Self::set_upper_half_address) the slices are read one
+    /// at a time instead.
+    pub async fn read_vectored(
+        &mut self,
+        offset: u32,
+        slices: &mut [&mut [u8]],
+    ) -> Result<(), Error<E>> {
+        let total = slices.iter().map(|slice| slice.len()).sum();
+        check_read(self, offset, total).map_err(Error::from_kind)?;
+        if !self.bad_pages.is_empty()
+            || self.padded_read(offset, total).is_some()
+            || self.below_seam(offset, total).is_some()
+        {
+            let mut offset = offset;
+            for slice in slices {
+                self.read(offset, slice).await?;
+                offset += slice.len() as u32;
+            }
+            return Ok(());
+        }
+
+        self.flush().await?;
+        let mut offset = offset;
+        let mut slices = slices
+            .iter_mut()
+            .filter(|slice| !slice.is_empty())
+            .peekable();
+        while slices.peek().is_some() {
+            let mut group: Vec<&mut [u8], SLICES_PER_TRANSACTION> = Vec::new();
+            while !group.is_full() {
+                let Some(slice) = slices.next() else {
+                    break;
+                };
+                // Not full, checked above
+                let _ = group.push(&mut **slice);
+            }
+            let len: usize = group.iter().map(|slice| slice.len()).sum();
+            self.read_transfer_vectored(offset, &mut group).await?;
+            offset += len as u32;
+        }
+        Ok(())
+    }
+
+    /// The transfers of a read into `slices`
+    async fn read_transfer_vectored(
+        &mut self,
+        offset: u32,
+        slices: &mut [&mut [u8]],
+    ) -> Result<(), Error<E>> {
+        let device_address = self.get_device_address(offset)?;
+        let memaddr = memory_address_bytes(offset);
+        let method = self.read_method;
+        let mut operations: Vec<Operation<'_>, { SLICES_PER_TRANSACTION + 1 }> = Vec::new();
+        if method == ReadMethod::CombinedWriteRead {
+            let _ = operations.push(Operation::Write(&memaddr));
+        }
+        for slice in slices {
+            let _ = operations.push(Operation::Read(slice));
+        }
+        let timeout_us = self.read_timeout_us;
+        if method == ReadMethod::WriteStopRead {
+            self.guard_delay().await;
+            let transfer = self.i2c.write(device_address, &memaddr);
+            with_timeout(&mut self.delay, timeout_us, transfer)
+                .await
+                .ok_or(Error::Timeout)?
+                .map_err(bus_error::<I2C>)?;
+        }
+        self.guard_delay().await;
+        let transfer = self.i2c.transaction(device_address, &mut operations);
+        with_timeout(&mut self.delay, timeout_us, transfer)
+            .await
+            .ok_or(Error::Timeout)?
+            .map_err(bus_error::<I2C>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBus;
+    use crate::{Address, BadPage};
+    use embedded_hal_mock::eh1::{
+        delay::NoopDelay,
+        i2c::{Mock as I2cMock, Transaction},
+    };
+    use std::vec;
+
+    fn driver() -> At24Cx<SimBus, NoopDelay> {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        for (i, byte) in bus.memory_mut().iter_mut().enumerate() {
+            *byte = (i * 7 + (i >> 8)) as u8;
+        }
+        At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new())
+    }
+
+    #[tokio::test]
+    async fn scatters_one_read() {
+        let expectations = [
+            Transaction::transaction_start(0x51),
+            Transaction::write(0x51, vec![0xFF, 0xFD]),
+            Transaction::read(0x51, vec![1]),
+            Transaction::read(0x51, vec![2, 3]),
+            Transaction::transaction_end(0x51),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        let (mut a, mut b) = ([0; 1], [0; 2]);
+        eeprom
+            .read_vectored(0x1FFFD, &mut [&mut a, &mut [], &mut b])
+            .await
+            .unwrap();
+        assert_eq!((a, b), ([1], [2, 3]));
+        let result = eeprom.read_vectored(0x1FFFE, &mut [&mut a, &mut b]).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn reads_across_blocks_into_many_slices() {
+        let mut eeprom = driver();
+        let memory = eeprom.i2c.memory().to_vec();
+        let mut bufs = [[0; 5]; 10];
+        let mut slices: std::vec::Vec<&mut [u8]> = bufs.iter_mut().map(|b| &mut b[..]).collect();
+        let before = eeprom.i2c.transaction_count();
+        eeprom.read_vectored(0xFFE0, &mut slices).await.unwrap();
+        // Eight slices to a transaction
+        assert_eq!(eeprom.i2c.transaction_count() - before, 2);
+        assert_eq!(bufs.concat(), memory[0xFFE0..0x10012]);
+
+        eeprom.set_read_method(ReadMethod::WriteStopRead);
+        let (mut a, mut b) = ([0; 0x20], [0; 0x30]);
+        eeprom
+            .read_vectored(0x1FFB0, &mut [&mut a, &mut b])
+            .await
+            .unwrap();
+        assert_eq!(a, memory[0x1FFB0..0x1FFD0]);
+        assert_eq!(b, memory[0x1FFD0..]);
+    }
+
+    #[tokio::test]
+    async fn remaps_bad_pages() {
+        let mut eeprom = driver();
+        let memory = eeprom.i2c.memory().to_vec();
+        eeprom
+            .set_bad_pages(&[BadPage {
+                page: 1,
+                remap: Some(9),
+            }])
+            .unwrap();
+        let (mut a, mut b) = ([0; 0x10], [0; 0x10]);
+        eeprom
+            .read_vectored(0xF8, &mut [&mut a, &mut b])
+            .await
+            .unwrap();
+        assert_eq!(a[..8], memory[0xF8..0x100]);
+        assert_eq!(a[8..], memory[0x900..0x908]);
+        assert_eq!(b, memory[0x908..0x918]);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::crc::{crc32_update, crc32_update_with, CRC32_IEEE};
+use crate::{check_read, At24Cx, Error, PAGE_SIZE};
+use core::cmp::min;
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Compares the device contents starting at `offset` against `expected`, page by page,
+    /// in the [current page size](Self::set_page_size).
+    ///
+    /// Bit `i` of `mismatch_bits` (LSB first within each byte) is set if the `i`-th page
+    /// touched by the range differs and cleared otherwise, bits past the last page are left
+    /// alone. Page 0 is the page containing `offset`. Returns `OutOfBounds` if the bitmap is
+    /// too small for the number of pages.
+    pub async fn verify_pages(
+        &mut self,
+        offset: u32,
+        expected: &[u8],
+        mismatch_bits: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        check_read(self, offset, expected.len()).map_err(Error::from_kind)?;
+        let page_size = self.page_size;
+        let first_page = offset as usize / page_size;
+        let pages = (offset as usize + expected.len()).div_ceil(page_size) - first_page;
+        if expected.is_empty() {
+            return Ok(());
+        }
+        if mismatch_bits.len() * 8 < pages {
+            return Err(Error::OutOfBounds);
+        }
+
+        let mut scratch = [0; PAGE_SIZE];
+        let mut address = offset;
+        let mut remaining = expected;
+        for page in 0..pages {
+            let chunk_size = min(remaining.len(), page_size - address as usize % page_size);
+            let chunk = &mut scratch[..chunk_size];
+            self.read(address, chunk).await?;
+            if chunk != &remaining[..chunk_size] {
+                mismatch_bits[page / 8] |= 1 << (page % 8);
+            } else {
+                mismatch_bits[page / 8] &= !(1 << (page % 8));
+            }
+            address += chunk_size as u32;
+            remaining = &remaining[chunk_size..];
+        }
+        Ok(())
+    }
+
+    /// Number of set bits in `len` bytes starting at `offset`, read one page at a time.
+    /// A fully erased range has `len * 8` of them, a zeroized one none.
+    pub async fn count_set_bits(&mut self, offset: u32, len: usize) -> Result<u32, Error<E>> {
+        check_read(self, offset, len).map_err(Error::from_kind)?;
+        let mut scratch = [0; PAGE_SIZE];
+        let mut address = offset;
+        let mut remaining = len;
+        let mut count = 0;
+        while remaining > 0 {
+            let chunk_size = min(remaining, PAGE_SIZE - address as usize % PAGE_SIZE);
+            let chunk = &mut scratch[..chunk_size];
+            self.read(address, chunk).await?;
+            count += chunk.iter().map(|b| b.count_ones()).sum::<u32>();
+            address += chunk_size as u32;
+            remaining -= chunk_size;
+        }
+        Ok(count)
+    }
+
+    /// CRC-32 (IEEE) of the whole device, read one page at a time. Meant to be compared
+    /// against a golden CRC taken at manufacturing time.
+    pub async fn crc32_all(&mut self) -> Result<u32, Error<E>> {
+        self.crc32_all_with(CRC32_IEEE).await
+    }
+
+    /// [`crc32_all`](Self::crc32_all) with another reflected polynomial, such as
+    /// [`CRC32_CASTAGNOLI`](crate::CRC32_CASTAGNOLI).
+    pub async fn crc32_all_with(&mut self, poly: u32) -> Result<u32, Error<E>> {
+        let mut scratch = [0; PAGE_SIZE];
+        let mut crc = !0;
+        for page in (0..self.capacity()).step_by(PAGE_SIZE) {
+            let chunk = &mut scratch[..min(PAGE_SIZE, self.capacity() - page)];
+            self.read(page as u32, chunk).await?;
+            crc = crc32_update_with(poly, crc, chunk);
+        }
+        Ok(!crc)
+    }
+
+    /// Seals the device: writes the CRC-32 (IEEE) of everything outside the
+    /// [footer](Self::set_seal_footer) to the footer, little endian. Any later change to the
+    /// sealed bytes makes [`verify_seal`](Self::verify_seal) fail.
+    pub async fn seal(&mut self) -> Result<(), Error<E>> {
+        let crc = self.seal_crc().await?;
+        let (footer, _) = self.seal_footer();
+        self.write(footer, &crc.to_le_bytes()).await
+    }
+
+    /// Whether the device still matches the CRC [`seal`](Self::seal) stored in the footer.
+    /// An unsealed device almost certainly doesn't.
+    pub async fn verify_seal(&mut self) -> Result<bool, Error<E>> {
+        let crc = self.seal_crc().await?;
+        let (footer, _) = self.seal_footer();
+        let mut stored = [0; 4];
+        self.read(footer, &mut stored).await?;
+        Ok(u32::from_le_bytes(stored) == crc)
+    }
+
+    /// CRC-32 (IEEE) of the device without the seal footer, read one page at a time
+    async fn seal_crc(&mut self) -> Result<u32, Error<E>> {
+        let (footer, footer_len) = self.seal_footer();
+        let footer = footer as usize..footer as usize + footer_len;
+        let mut scratch = [0; PAGE_SIZE];
+        let mut crc = !0;
+        for page in (0..self.capacity()).step_by(PAGE_SIZE) {
+            let chunk = &mut scratch[..min(PAGE_SIZE, self.capacity() - page)];
+            self.read(page as u32, chunk).await?;
+            let end = page + chunk.len();
+            let before = &chunk[..footer.start.clamp(page, end) - page];
+            let after = &chunk[footer.end.clamp(page, end) - page..];
+            crc = crc32_update(crc32_update(crc, before), after);
+        }
+        Ok(!crc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address, DeviceKind};
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+
+    #[tokio::test]
+    async fn marks_mismatched_pages() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        let image: [u8; 0x600] = core::array::from_fn(|i| i as u8);
+        bus.memory_mut()[0x80..0x680].copy_from_slice(&image);
+        bus.memory_mut()[0x90] ^= 1;
+        bus.memory_mut()[0x400] ^= 1;
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+
+        // Range touches 7 pages: 0x000, 0x100, ..., 0x600
+        let mut bits = [0xFF; 2];
+        eeprom.verify_pages(0x80, &image, &mut bits).await.unwrap();
+        assert_eq!(bits, [0b1001_0001, 0xFF]);
+
+        let result = eeprom.verify_pages(0x80, &image, &mut []).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+        let result = eeprom.verify_pages(0x1FF00, &image, &mut bits).await;
+        assert!(matches!(result, Err(Error::OutOfBounds)));
+    }
+
+    #[tokio::test]
+    async fn marks_small_pages() {
+        let address = Address::pins(1, 1, 1);
+        let mut bus = SimBus::new(address, 12).with_page_size(32);
+        let image: [u8; 0x80] = core::array::from_fn(|i| i as u8);
+        bus.memory_mut()[0x10..0x90].copy_from_slice(&image);
+        bus.memory_mut()[0x45] ^= 1;
+        let mut eeprom =
+            At24Cx::with_device(bus, address, DeviceKind::At24c32, NoopDelay::new()).unwrap();
+
+        // Range touches 5 pages: 0x00, 0x20, ..., 0x80
+        let mut bits = [0xFF];
+        eeprom.verify_pages(0x10, &image, &mut bits).await.unwrap();
+        assert_eq!(bits, [0b1110_0100]);
+    }
+
+    #[tokio::test]
+    async fn matching_image_clears_bits() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let mut bits = [0xFF];
+        eeprom
+            .verify_pages(0x200, &[0xFF; 0x300], &mut bits)
+            .await
+            .unwrap();
+        assert_eq!(bits, [0xF8]);
+    }
+
+    #[tokio::test]
+    async fn counts_set_bits() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.count_set_bits(0xF0, 0x220).await.unwrap(), 0x220 * 8);
+        eeprom.fill(0xF0, 0x220, 0).await.unwrap();
+        assert_eq!(eeprom.count_set_bits(0xF0, 0x220).await.unwrap(), 0);
+        eeprom.i2c.memory_mut()[0x200] = 0b1011_0000;
+        // 0x10 erased bytes on either side of the zeroized range
+        assert_eq!(
+            eeprom.count_set_bits(0xE0, 0x240).await.unwrap(),
+            0x20 * 8 + 3
+        );
+        assert_eq!(eeprom.count_set_bits(0x1FFFF, 0).await.unwrap(), 0);
+        assert!(matches!(
+            eeprom.count_set_bits(0x1FFFF, 2).await,
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[tokio::test]
+    async fn crc_of_whole_device() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        for (i, byte) in bus.memory_mut().iter_mut().enumerate() {
+            *byte = (i ^ ((i >> 16) * 3)) as u8;
+        }
+        let image = bus.memory().to_vec();
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.crc32_all().await.unwrap(), crate::crc::crc32(&image));
+        assert_eq!(
+            eeprom
+                .crc32_all_with(crate::CRC32_CASTAGNOLI)
+                .await
+                .unwrap(),
+            !crc32_update_with(crate::CRC32_CASTAGNOLI, !0, &image)
+        );
+        // A flipped bit past the 64KiB boundary shows
+        eeprom.i2c.memory_mut()[0x18000] ^= 1;
+        assert_ne!(eeprom.crc32_all().await.unwrap(), crate::crc::crc32(&image));
+    }
+
+    #[tokio::test]
+    async fn seals_the_device() {
+        let mut bus = SimBus::new(Address(0, 0), 17);
+        for (i, byte) in bus.memory_mut().iter_mut().enumerate() {
+            *byte = (i * 7) as u8;
+        }
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert_eq!(eeprom.seal_footer(), (0x1FFFC, 4));
+        assert!(!eeprom.verify_seal().await.unwrap());
+
+        eeprom.seal().await.unwrap();
+        let mut image = eeprom.i2c.memory().to_vec();
+        let stored = image.split_off(0x1FFFC);
+        assert_eq!(stored, crate::crc::crc32(&image).to_le_bytes());
+        assert!(eeprom.verify_seal().await.unwrap());
+
+        eeprom.i2c.memory_mut()[0x10000] ^= 0x80;
+        assert!(!eeprom.verify_seal().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn footer_anywhere() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        assert!(matches!(
+            eeprom.set_seal_footer(0, 3),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            eeprom.set_seal_footer(0x1FFFD, 4),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            eeprom.set_seal_footer(u32::MAX, 4),
+            Err(Error::InvalidArgument)
+        ));
+
+        // Straddling a page boundary, with reserved bytes after the CRC
+        eeprom.set_seal_footer(0x1FE, 0x10).unwrap();
+        assert_eq!(eeprom.seal_footer(), (0x1FE, 0x10));
+        eeprom.seal().await.unwrap();
+        assert!(eeprom.verify_seal().await.unwrap());
+        // The reserved bytes aren't sealed, the byte after the footer is
+        eeprom.write(0x20A, &[0]).await.unwrap();
+        assert!(eeprom.verify_seal().await.unwrap());
+        eeprom.write(0x20E, &[0]).await.unwrap();
+        assert!(!eeprom.verify_seal().await.unwrap());
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{At24Cx, Error};
+use core::fmt::Debug;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+impl<I2C, E: Debug, D: DelayNs> At24Cx<I2C, D>
+where
+    I2C: I2c<Error = E>,
+{
+    /// Reads `buf.len()` bytes treating the device as a circular buffer: a read past the
+    /// end continues from offset 0. `offset` is taken modulo the capacity. Returns
+    /// `OutOfBounds` if `buf` is larger than the device.
+    pub async fn read_wrapping(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Error<E>> {
+        let (offset, first) = self.wrap(offset, buf.len())?;
+        let (head, tail) = buf.split_at_mut(first);
+        self.read(offset, head).await?;
+        self.read(0, tail).await
+    }
+
+    /// Writes `data` treating the device as a circular buffer: a write past the end
+    /// continues from offset 0. `offset` is taken modulo the capacity. Returns `OutOfBounds`
+    /// if `data` is larger than the device.
+    pub async fn write_wrapping(&mut self, offset: u32, data: &[u8]) -> Result<(), Error<E>> {
+        let (offset, first) = self.wrap(offset, data.len())?;
+        let (head, tail) = data.split_at(first);
+        self.write(offset, head).await?;
+        self.write(0, tail).await
+    }
+
+    /// The offset within the device and how many of the `len` bytes fit before its end
+    fn wrap(&self, offset: u32, len: usize) -> Result<(u32, usize), Error<E>> {
+        let capacity = self.capacity();
+        if len > capacity {
+            return Err(Error::OutOfBounds);
+        }
+        let offset = offset as usize % capacity;
+        Ok((offset as u32, len.min(capacity - offset)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBus;
+    use crate::Address;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use std::vec::Vec;
+
+    #[tokio::test]
+    async fn wraps_around_the_end() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let data: Vec<u8> = (0..0x10100).map(|i| i as u8).collect();
+        // 0x100 bytes before the end, then over the 64KiB boundary at 0x10000
+        eeprom.write_wrapping(0x1FF00, &data).await.unwrap();
+
+        let memory = eeprom.i2c.memory();
+        assert_eq!(memory[0x1FF00..], data[..0x100]);
+        assert_eq!(memory[..0x10000], data[0x100..0x10100]);
+        assert!(memory[0x10000..0x1FF00].iter().all(|&b| b == 0xFF));
+
+        let mut buf = std::vec![0; 0x200];
+        // Given modulo the capacity
+        eeprom.read_wrapping(0x3FF80, &mut buf).await.unwrap();
+        assert_eq!(buf, data[0x80..0x280]);
+
+        let too_long = std::vec![0; 0x20001];
+        assert!(matches!(
+            eeprom.write_wrapping(0, &too_long).await,
+            Err(Error::OutOfBounds)
+        ));
+        eeprom.write_wrapping(0x20000, &[]).await.unwrap();
+    }
+}
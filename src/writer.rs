@@ -0,0 +1,318 @@
+//! Deferring writes to a background task.
+//!
+//! A [`WriteQueue`] holds up to `N` pending writes of up to `MAX` bytes each. Any task can
+//! queue writes through a [`WriteSender`] without waiting for the EEPROM, while
+//! [`writer_task`] drains the queue in order. Queued writes to the same
+//! [page](At24Cx::set_page_size) that touch or overlap each other are coalesced into a single
+//! page write.
+//!
+//! `writer_task` is generic and can't be an `#[embassy_executor::task]` itself, wrap it in a
+//! task with your concrete types:
+//!
+//! ```ignore
+//! static QUEUE: WriteQueue<'static, CriticalSectionRawMutex, 8, 32> = WriteQueue::new();
+//!
+//! #[embassy_executor::task]
+//! async fn eeprom_writer(mut eeprom: At24Cx<I2c<'static, Async>, Delay>) -> ! {
+//!     writer_task(&mut eeprom, QUEUE.receiver()).await
+//! }
+//! ```
+
+pub use crate::EnqueueError;
+use crate::{At24Cx, PAGE_SIZE};
+use core::fmt::Debug;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use embedded_storage_async::nor_flash::NorFlash;
+use heapless::Vec;
+
+/// Signalled by the writer once a request was processed: `true` if it was written, `false`
+/// if the write failed.
+pub type Completion<M> = Signal<M, bool>;
+
+struct WriteRequest<'a, M: RawMutex, const MAX: usize> {
+    offset: u32,
+    data: Vec<u8, MAX>,
+    done: Option<&'a Completion<M>>,
+}
+
+/// Bounded queue of pending writes, usually placed in a `static`
+pub struct WriteQueue<'a, M: RawMutex, const N: usize, const MAX: usize> {
+    channel: Channel<M, WriteRequest<'a, M, MAX>, N>,
+}
+
+impl<'a, M: RawMutex, const N: usize, const MAX: usize> WriteQueue<'a, M, N, MAX> {
+    pub const fn new() -> Self {
+        Self {
+            channel: Channel::new(),
+        }
+    }
+
+    pub fn sender(&self) -> WriteSender<'_, 'a, M, N, MAX> {
+        WriteSender { queue: self }
+    }
+
+    pub fn receiver(&self) -> WriteReceiver<'_, 'a, M, N, MAX> {
+        WriteReceiver { queue: self }
+    }
+}
+
+impl<M: RawMutex, const N: usize, const MAX: usize> Default for WriteQueue<'_, M, N, MAX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queues writes for [`writer_task`]
+pub struct WriteSender<'q, 'a, M: RawMutex, const N: usize, const MAX: usize> {
+    queue: &'q WriteQueue<'a, M, N, MAX>,
+}
+
+impl<M: RawMutex, const N: usize, const MAX: usize> Clone for WriteSender<'_, '_, M, N, MAX> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M: RawMutex, const N: usize, const MAX: usize> Copy for WriteSender<'_, '_, M, N, MAX> {}
+
+impl<'a, M: RawMutex, const N: usize, const MAX: usize> WriteSender<'_, 'a, M, N, MAX> {
+    /// Queues a write without waiting, failing with `Full` if there is no room.
+    /// `done` is signalled once the write was processed.
+    pub fn try_enqueue(
+        &self,
+        offset: u32,
+        data: &[u8],
+        done: Option<&'a Completion<M>>,
+    ) -> Result<(), EnqueueError> {
+        let request = Self::request(offset, data, done)?;
+        self.queue
+            .channel
+            .try_send(request)
+            .map_err(|_| EnqueueError::Full)
+    }
+
+    /// Queues a write, waiting for room in the queue if it is full.
+    /// `done` is signalled once the write was processed.
+    pub async fn enqueue(
+        &self,
+        offset: u32,
+        data: &[u8],
+        done: Option<&'a Completion<M>>,
+    ) -> Result<(), EnqueueError> {
+        let request = Self::request(offset, data, done)?;
+        self.queue.channel.send(request).await;
+        Ok(())
+    }
+
+    fn request(
+        offset: u32,
+        data: &[u8],
+        done: Option<&'a Completion<M>>,
+    ) -> Result<WriteRequest<'a, M, MAX>, EnqueueError> {
+        let data = Vec::from_slice(data).map_err(|_| EnqueueError::TooLarge)?;
+        Ok(WriteRequest { offset, data, done })
+    }
+}
+
+/// The receiving end of a [`WriteQueue`], handed to [`writer_task`]
+pub struct WriteReceiver<'q, 'a, M: RawMutex, const N: usize, const MAX: usize> {
+    queue: &'q WriteQueue<'a, M, N, MAX>,
+}
+
+/// Writes queued requests to the EEPROM, forever.
+///
+/// Requests are written in the order they were queued. A write that fails is reported
+/// through its [`Completion`] and doesn't stop the task.
+pub async fn writer_task<I2C, E, D, M, const N: usize, const MAX: usize>(
+    eeprom: &mut At24Cx<I2C, D>,
+    receiver: WriteReceiver<'_, '_, M, N, MAX>,
+) -> !
+where
+    I2C: I2c<Error = E>,
+    E: Debug,
+    D: DelayNs,
+    M: RawMutex,
+{
+    let channel = &receiver.queue.channel;
+    let mut next = None;
+    loop {
+        let request = match next.take() {
+            Some(request) => request,
+            None => channel.receive().await,
+        };
+        let page_size = eeprom.page_size();
+        let page_offset = request.offset as usize % page_size;
+        if page_offset + request.data.len() > page_size {
+            let result = eeprom.write(request.offset, &request.data).await;
+            if let Some(done) = request.done {
+                done.signal(result.is_ok());
+            }
+            continue;
+        }
+
+        // Merge following requests into this page for as long as the result stays contiguous
+        let page = request.offset - page_offset as u32;
+        let mut buf = [0; PAGE_SIZE];
+        let mut start = page_offset;
+        let mut end = page_offset + request.data.len();
+        buf[start..end].copy_from_slice(&request.data);
+        let mut completions: Vec<&Completion<M>, N> = Vec::new();
+        if let Some(done) = request.done {
+            let _ = completions.push(done);
+        }
+        while let Ok(request) = channel.try_receive() {
+            let offset = request.offset.wrapping_sub(page) as usize;
+            let fits = request.offset >= page
+                && offset + request.data.len() <= page_size
+                && offset <= end
+                && offset + request.data.len() >= start
+                && (request.done.is_none() || !completions.is_full());
+            if !fits {
+                next = Some(request);
+                break;
+            }
+            buf[offset..offset + request.data.len()].copy_from_slice(&request.data);
+            start = start.min(offset);
+            end = end.max(offset + request.data.len());
+            if let Some(done) = request.done {
+                let _ = completions.push(done);
+            }
+        }
+
+        let result = eeprom.write(page + start as u32, &buf[start..end]).await;
+        for done in completions {
+            done.signal(result.is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimBus, Address};
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embedded_hal_mock::eh1::{
+        delay::NoopDelay,
+        i2c::{Mock as I2cMock, Transaction},
+    };
+
+    type Queue<'a, const N: usize> = WriteQueue<'a, CriticalSectionRawMutex, N, 8>;
+
+    /// Runs the writer until `done` is signalled
+    async fn run_until<I2C, E, D, const N: usize>(
+        eeprom: &mut At24Cx<I2C, D>,
+        queue: &Queue<'_, N>,
+        done: &Completion<CriticalSectionRawMutex>,
+    ) -> bool
+    where
+        I2C: I2c<Error = E>,
+        E: Debug,
+        D: DelayNs,
+    {
+        tokio::select! {
+            _ = writer_task(eeprom, queue.receiver()) => unreachable!(),
+            written = done.wait() => written,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_in_queue_order() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let first = Completion::new();
+        let last = Completion::new();
+        let queue = Queue::<4>::new();
+        let sender = queue.sender();
+        sender.try_enqueue(0xFC, &[1; 8], Some(&first)).unwrap();
+        sender.try_enqueue(0x500, &[2; 4], None).unwrap();
+        sender.try_enqueue(0xFE, &[3; 4], Some(&last)).unwrap();
+
+        assert!(run_until(&mut eeprom, &queue, &last).await);
+        assert!(first.signaled());
+        assert_eq!(&eeprom.i2c.memory()[0xFC..0x104], &[1, 1, 3, 3, 3, 3, 1, 1]);
+        assert_eq!(&eeprom.i2c.memory()[0x500..0x504], &[2; 4]);
+    }
+
+    #[tokio::test]
+    async fn coalesces_same_page_requests() {
+        let expectations = [
+            Transaction::write(0x50, std::vec![0x01, 0x10, 1, 1, 3, 3, 3, 3, 2, 2]),
+            Transaction::write(0x50, std::vec![0]),
+            Transaction::write(0x50, std::vec![0x02, 0x00, 4]),
+            Transaction::write(0x50, std::vec![0]),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        let done = Completion::new();
+        let queue = Queue::<4>::new();
+        let sender = queue.sender();
+        sender.try_enqueue(0x110, &[1; 4], None).unwrap();
+        sender.try_enqueue(0x114, &[2; 4], None).unwrap();
+        sender.try_enqueue(0x112, &[3; 4], None).unwrap();
+        // Another page, written separately
+        sender.try_enqueue(0x200, &[4], Some(&done)).unwrap();
+
+        assert!(run_until(&mut eeprom, &queue, &done).await);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn coalesces_within_small_pages() {
+        let expectations = [
+            Transaction::write(0x50, std::vec![0x00, 0x1C, 1, 1, 1, 1]),
+            Transaction::write(0x50, std::vec![0]),
+            Transaction::write(0x50, std::vec![0x00, 0x20, 2, 2, 2, 2]),
+            Transaction::write(0x50, std::vec![0]),
+        ];
+        let i2c = I2cMock::new(&expectations);
+        let mut eeprom = At24Cx::new(i2c, Address(0, 0), 17, NoopDelay::new());
+        eeprom.set_page_size(32).unwrap();
+        let done = Completion::new();
+        let queue = Queue::<4>::new();
+        let sender = queue.sender();
+        // Touching, but on either side of a 32 byte page boundary
+        sender.try_enqueue(0x1C, &[1; 4], None).unwrap();
+        sender.try_enqueue(0x20, &[2; 4], Some(&done)).unwrap();
+
+        assert!(run_until(&mut eeprom, &queue, &done).await);
+        eeprom.i2c.done();
+    }
+
+    #[tokio::test]
+    async fn full_queue_rejects_requests() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let done = Completion::new();
+        let queue = Queue::<2>::new();
+        let sender = queue.sender();
+        assert_eq!(
+            sender.try_enqueue(0, &[0; 9], None),
+            Err(EnqueueError::TooLarge)
+        );
+        sender.try_enqueue(0x300, &[1], None).unwrap();
+        sender.try_enqueue(0x400, &[2], Some(&done)).unwrap();
+        assert_eq!(
+            sender.try_enqueue(0x500, &[3], None),
+            Err(EnqueueError::Full)
+        );
+
+        assert!(run_until(&mut eeprom, &queue, &done).await);
+        sender.enqueue(0x500, &[3], None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_write_is_reported() {
+        let bus = SimBus::new(Address(0, 0), 17);
+        let mut eeprom = At24Cx::new(bus, Address(0, 0), 17, NoopDelay::new());
+        let done = Completion::new();
+        let queue = Queue::<2>::new();
+        queue
+            .sender()
+            .try_enqueue(0x20000, &[1], Some(&done))
+            .unwrap();
+        assert!(!run_until(&mut eeprom, &queue, &done).await);
+    }
+}
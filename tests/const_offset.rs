@@ -0,0 +1,8 @@
+#[test]
+fn out_of_range_constant_accesses_fail_to_compile() {
+    let cases = trybuild::TestCases::new();
+    // The bounds are checked when the accessors are instantiated, which `cargo check`
+    // doesn't do. A passing case makes trybuild build instead.
+    cases.pass("tests/ui/const_offset_in_range.rs");
+    cases.compile_fail("tests/ui/const_offset_out_of_range.rs");
+}
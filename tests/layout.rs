@@ -0,0 +1,7 @@
+#![cfg(feature = "bytemuck")]
+
+#[test]
+fn invalid_layouts_fail_to_compile() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/layout_*.rs");
+}
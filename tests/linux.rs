@@ -0,0 +1,30 @@
+#![cfg(feature = "linux")]
+//! Runs against a real device when `AT24CX_LINUX_BUS` names its bus, like `/dev/i2c-1`.
+//! The device has to be an AT24CM01 at `Address(0, 0)`. The last byte of the device is
+//! overwritten and restored.
+
+use at24cx::{Address, At24Cx};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+#[tokio::test]
+async fn write_and_read_back() {
+    let Ok(path) = std::env::var("AT24CX_LINUX_BUS") else {
+        eprintln!("AT24CX_LINUX_BUS is not set, skipping");
+        return;
+    };
+    let mut eeprom = At24Cx::new_linux(path, Address(0, 0), 17).unwrap();
+    let offset = eeprom.capacity() as u32 - 1;
+    let mut original = [0];
+    eeprom.read(offset, &mut original).await.unwrap();
+
+    let value = !original[0];
+    eeprom.write(offset, &[value]).await.unwrap();
+    let mut buf = [0];
+    eeprom.read(offset, &mut buf).await.unwrap();
+    assert_eq!(buf[0], value);
+
+    // Busy right after the write, so this goes through ACK polling
+    eeprom.write(offset, &original).await.unwrap();
+    eeprom.read(offset, &mut buf).await.unwrap();
+    assert_eq!(buf, original);
+}
@@ -0,0 +1,18 @@
+use at24cx::{Address, At24Cx};
+use embedded_hal_mock::eh1::{
+    delay::NoopDelay,
+    i2c::{Mock as I2cMock, Transaction},
+};
+
+fn main() {
+    let expectations = [Transaction::write_read(0x51, vec![0xFF, 0xFE], vec![1, 2])];
+    let mut eeprom = At24Cx::new(I2cMock::new(&expectations), Address(0, 0), 17, NoopDelay::new());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    // The last two bytes of 128KiB
+    let bytes = runtime.block_on(eeprom.read_at::<0x1FFFE, 2>()).unwrap();
+    assert_eq!(bytes, [1, 2]);
+    let (mut i2c, _) = eeprom.into_parts();
+    i2c.done();
+}
@@ -0,0 +1,11 @@
+use at24cx::{Address, At24Cx};
+use embedded_hal_mock::eh1::{delay::NoopDelay, i2c::Mock as I2cMock};
+
+fn main() {
+    let mut eeprom = At24Cx::new(I2cMock::new(&[]), Address(0, 0), 17, NoopDelay::new());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    // Ends one byte past 128KiB
+    let _ = runtime.block_on(eeprom.read_at::<0x1FFFF, 2>());
+}
@@ -0,0 +1,10 @@
+use at24cx::eeprom_layout;
+
+eeprom_layout! {
+    pub struct Layout(capacity = 0x8000) {
+        version: u16 @ 0x000,
+        log: region(0x100, 0x8100),
+    }
+}
+
+fn main() {}
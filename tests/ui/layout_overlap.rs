@@ -0,0 +1,10 @@
+use at24cx::eeprom_layout;
+
+eeprom_layout! {
+    pub struct Layout(capacity = 0x8000) {
+        version: u16 @ 0x000,
+        serial: [u8; 12] @ 0x001,
+    }
+}
+
+fn main() {}